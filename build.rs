@@ -0,0 +1,23 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "c-header")]
+    write_header();
+    #[cfg(feature = "node")]
+    napi_build::setup();
+}
+
+// writes rln.h next to Cargo.toml from the extern functions of the ffi module
+#[cfg(feature = "c-header")]
+fn write_header() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir))
+        .expect("cannot read cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("cannot generate rln.h")
+        .write_to_file(format!("{}/rln.h", crate_dir));
+}
@@ -16,6 +16,7 @@ mod test_keys {
     pub fn export<E: Engine>(merkle_depth: usize) {
         use rand::{SeedableRng, XorShiftRng};
         use rln::circuit::poseidon::PoseidonCircuit;
+        use rln::circuit::progress::{ProgressCircuit, SetupPhase};
         use rln::circuit::rln::{RLNCircuit, RLNInputs};
         use rln::poseidon::PoseidonParams;
         use sapling_crypto::bellman::groth16::generate_random_parameters;
@@ -28,7 +29,20 @@ mod test_keys {
             inputs: RLNInputs::<E>::empty(merkle_depth),
             hasher: hasher.clone(),
         };
+        let num_constraints = circuit.clone().shape().unwrap().num_constraints;
+        let mut progress = |phase: SetupPhase, percent: u8| {
+            eprint!("\r{:?} {:>3}%", phase, percent);
+            if phase == SetupPhase::Parameters && percent == 100 {
+                eprintln!();
+            }
+        };
+        let circuit = ProgressCircuit {
+            circuit,
+            num_constraints,
+            progress: &mut progress,
+        };
         let parameters = generate_random_parameters(circuit, &mut rng).unwrap();
+        progress(SetupPhase::Parameters, 100);
         let mut file_vk = File::create("verifier.key").unwrap();
         let vk = parameters.vk.clone();
         vk.write(&mut file_vk).unwrap();
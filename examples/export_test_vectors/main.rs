@@ -0,0 +1,14 @@
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    use rln::vectors::TestVectors;
+    use std::fs::File;
+
+    let vectors = TestVectors::generate().unwrap();
+    let file = File::create("test_vectors.json").unwrap();
+    serde_json::to_writer_pretty(file, &vectors).unwrap();
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    panic!("should not be run in wasm");
+}
@@ -0,0 +1,296 @@
+//! Versioned envelope for proof bundles.
+//!
+//! A bundle is the `generate_proof` output prefixed with a header
+//! [ magic<4> | version<1> | curve_id<1> | circuit_id<2> | proof_format<1> ]
+//! where `circuit_id` is [ arity<1> | merkle_depth<1> ]. Readers reject versions
+//! they do not know instead of misreading payloads at shifted offsets.
+//!
+//! `RLNProofBundle` reads the public values of a bundle without checking the proof,
+//! so messages can be routed by epoch or nullifier before any pairing is computed.
+
+use crate::circuit::rln::RLNInputs;
+use crate::merkle::TreeArity;
+use crate::utils::{fr_to_hex, ProofFormat};
+use bellman::pairing::bn256::Fr as Bn256Fr;
+use bellman::pairing::ff::{PrimeField, PrimeFieldRepr};
+use bellman::pairing::Engine;
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{self, Read, Write};
+
+pub const BUNDLE_MAGIC: &[u8; 4] = b"RLNP";
+pub const BUNDLE_VERSION: u8 = 1;
+pub const BUNDLE_HEADER_SIZE: usize = 9;
+
+pub const CURVE_ID_UNKNOWN: u8 = 0;
+pub const CURVE_ID_BN256: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BundleHeader {
+    pub version: u8,
+    pub curve_id: u8,
+    pub arity: TreeArity,
+    pub merkle_depth: u8,
+    pub proof_format: ProofFormat,
+}
+
+impl BundleHeader {
+    /// header of the current version for the given engine and circuit
+    pub fn new<E: Engine>(
+        arity: TreeArity,
+        merkle_depth: usize,
+        proof_format: ProofFormat,
+    ) -> io::Result<BundleHeader> {
+        if merkle_depth > u8::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "merkle depth does not fit the bundle header",
+            ));
+        }
+        Ok(BundleHeader {
+            version: BUNDLE_VERSION,
+            curve_id: curve_id::<E>(),
+            arity,
+            merkle_depth: merkle_depth as u8,
+            proof_format,
+        })
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let arity = match self.arity {
+            TreeArity::Binary => 2u8,
+            TreeArity::Quad => 4u8,
+        };
+        let proof_format = match self.proof_format {
+            ProofFormat::Uncompressed => 0u8,
+            ProofFormat::Compressed => 1u8,
+        };
+        writer.write_all(BUNDLE_MAGIC)?;
+        writer.write_all(&[
+            self.version,
+            self.curve_id,
+            arity,
+            self.merkle_depth,
+            proof_format,
+        ])?;
+        Ok(())
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> io::Result<BundleHeader> {
+        let mut buf = [0u8; BUNDLE_HEADER_SIZE];
+        reader.read_exact(&mut buf)?;
+        if &buf[..4] != BUNDLE_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a proof bundle",
+            ));
+        }
+        if buf[4] != BUNDLE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported proof bundle version",
+            ));
+        }
+        let arity = match buf[6] {
+            2 => TreeArity::Binary,
+            4 => TreeArity::Quad,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unknown tree arity",
+                ))
+            }
+        };
+        let proof_format = match buf[8] {
+            0 => ProofFormat::Uncompressed,
+            1 => ProofFormat::Compressed,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unknown proof format",
+                ))
+            }
+        };
+        Ok(BundleHeader {
+            version: buf[4],
+            curve_id: buf[5],
+            arity,
+            merkle_depth: buf[7],
+            proof_format,
+        })
+    }
+}
+
+/// public values of a proof, read without verifying it
+pub struct RLNProofBundle<E: Engine> {
+    /// `None` for plain `generate_proof` output
+    pub header: Option<BundleHeader>,
+    /// proof bytes as written, points are not decoded
+    pub proof: Vec<u8>,
+    /// signal following the public inputs, if any
+    pub signal: Option<Vec<u8>>,
+    // [ root, epoch, share_x, share_y, nullifier ]
+    public_inputs: Vec<E::Fr>,
+}
+
+impl<E: Engine> RLNProofBundle<E> {
+    /// reads a bundle, or plain `generate_proof` output in the uncompressed format
+    /// * expect `bytes` is serialized as
+    /// [ header<9>? | proof<var> | root<32> | epoch<32> | share_x<32> | share_y<32> | nullifier<32> ]
+    /// optionally followed by [ signal_len<8> | signal<var> ]
+    pub fn parse(bytes: &[u8]) -> io::Result<RLNProofBundle<E>> {
+        Self::parse_with_format(bytes, ProofFormat::default())
+    }
+
+    /// same as `parse`, `proof_format` applies to input without a header
+    pub fn parse_with_format(
+        mut bytes: &[u8],
+        proof_format: ProofFormat,
+    ) -> io::Result<RLNProofBundle<E>> {
+        let header = if bytes.starts_with(BUNDLE_MAGIC) {
+            Some(BundleHeader::read(&mut bytes)?)
+        } else {
+            None
+        };
+        let proof_format = header.map_or(proof_format, |header| header.proof_format);
+        let proof_size = proof_format.proof_size::<E>();
+        if bytes.len() < proof_size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "proof is truncated",
+            ));
+        }
+        let proof = bytes[..proof_size].to_vec();
+        bytes = &bytes[proof_size..];
+        let public_inputs = RLNInputs::<E>::read_public_inputs(&mut bytes)?;
+        let signal = if bytes.is_empty() {
+            None
+        } else {
+            let n = bytes.read_u64::<LittleEndian>()?;
+            if n != bytes.len() as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "signal length does not match the remaining bytes",
+                ));
+            }
+            Some(bytes.to_vec())
+        };
+        Ok(RLNProofBundle {
+            header,
+            proof,
+            signal,
+            public_inputs,
+        })
+    }
+
+    pub fn root(&self) -> E::Fr {
+        self.public_inputs[0]
+    }
+
+    pub fn epoch(&self) -> E::Fr {
+        self.public_inputs[1]
+    }
+
+    pub fn share_x(&self) -> E::Fr {
+        self.public_inputs[2]
+    }
+
+    pub fn share_y(&self) -> E::Fr {
+        self.public_inputs[3]
+    }
+
+    pub fn nullifier(&self) -> E::Fr {
+        self.public_inputs[4]
+    }
+
+    /// [ root, epoch, share_x, share_y, nullifier ]
+    pub fn public_inputs(&self) -> &[E::Fr] {
+        &self.public_inputs
+    }
+
+    /// hex strings as `fr_to_hex` writes them
+    pub fn root_hex(&self) -> String {
+        fr_to_hex(&self.root())
+    }
+
+    pub fn epoch_hex(&self) -> String {
+        fr_to_hex(&self.epoch())
+    }
+
+    pub fn share_x_hex(&self) -> String {
+        fr_to_hex(&self.share_x())
+    }
+
+    pub fn share_y_hex(&self) -> String {
+        fr_to_hex(&self.share_y())
+    }
+
+    pub fn nullifier_hex(&self) -> String {
+        fr_to_hex(&self.nullifier())
+    }
+}
+
+/// identifies the pairing engine by its scalar field modulus
+pub fn curve_id<E: Engine>() -> u8 {
+    if E::Fr::char().as_ref() == Bn256Fr::char().as_ref() {
+        CURVE_ID_BN256
+    } else {
+        CURVE_ID_UNKNOWN
+    }
+}
+
+#[test]
+fn test_bundle_header() {
+    use bellman::pairing::bn256::Bn256;
+    let header = BundleHeader::new::<Bn256>(TreeArity::Quad, 16, ProofFormat::Compressed).unwrap();
+    assert_eq!(header.curve_id, CURVE_ID_BN256);
+
+    let mut buf: Vec<u8> = Vec::new();
+    header.write(&mut buf).unwrap();
+    assert_eq!(buf.len(), BUNDLE_HEADER_SIZE);
+    assert_eq!(BundleHeader::read(buf.as_slice()).unwrap(), header);
+
+    let mut future = buf.clone();
+    future[4] = BUNDLE_VERSION + 1;
+    assert!(BundleHeader::read(future.as_slice()).is_err());
+    let mut garbage = buf.clone();
+    garbage[0] = 0;
+    assert!(BundleHeader::read(garbage.as_slice()).is_err());
+}
+
+#[test]
+fn test_parse_proof_bundle() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    use byteorder::WriteBytesExt;
+
+    let fr = |n: u64| Fr::from_str(&n.to_string()).unwrap();
+    let mut proof_data = vec![7u8; ProofFormat::Uncompressed.proof_size::<Bn256>()];
+    for i in 1..=5 {
+        fr(i).into_repr().write_le(&mut proof_data).unwrap();
+    }
+
+    let parsed = RLNProofBundle::<Bn256>::parse(&proof_data).unwrap();
+    assert!(parsed.header.is_none() && parsed.signal.is_none());
+    assert_eq!(parsed.proof, vec![7u8; 256]);
+    assert_eq!(parsed.epoch(), fr(2));
+    assert_eq!(parsed.nullifier(), fr(5));
+    assert_eq!(parsed.share_x_hex(), fr_to_hex(&fr(3)));
+
+    let header =
+        BundleHeader::new::<Bn256>(TreeArity::Binary, 20, ProofFormat::Uncompressed).unwrap();
+    let mut bundle: Vec<u8> = Vec::new();
+    header.write(&mut bundle).unwrap();
+    bundle.extend_from_slice(&proof_data);
+    bundle.write_u64::<LittleEndian>(3).unwrap();
+    bundle.extend_from_slice(b"rln");
+    let parsed = RLNProofBundle::<Bn256>::parse(&bundle).unwrap();
+    assert_eq!(parsed.header, Some(header));
+    assert_eq!(parsed.root(), fr(1));
+    assert_eq!(parsed.signal, Some(b"rln".to_vec()));
+
+    assert!(RLNProofBundle::<Bn256>::parse(&bundle[..bundle.len() - 1]).is_err());
+    assert!(RLNProofBundle::<Bn256>::parse(&proof_data[..300]).is_err());
+    let compressed = &proof_data[128..];
+    let parsed =
+        RLNProofBundle::<Bn256>::parse_with_format(compressed, ProofFormat::Compressed).unwrap();
+    assert_eq!(parsed.share_y(), fr(4));
+}
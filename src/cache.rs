@@ -0,0 +1,130 @@
+//! Results of recently verified proofs.
+//!
+//! Entries are keyed by a blake2s digest of the verified bytes and the least
+//! recently used one is evicted once the cache is full, so a message delivered
+//! by several peers is checked once.
+
+use blake2::{Blake2s, Digest};
+use std::collections::{BTreeMap, HashMap};
+
+pub type ProofDigest = [u8; 32];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub len: usize,
+}
+
+pub struct VerificationCache {
+    capacity: usize,
+    // result and last use of each digest
+    entries: HashMap<ProofDigest, (bool, u64)>,
+    // digests by last use, oldest first
+    order: BTreeMap<u64, ProofDigest>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl VerificationCache {
+    /// `capacity` is clamped to at least one entry
+    pub fn new(capacity: usize) -> VerificationCache {
+        VerificationCache {
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn digest(data: &[u8]) -> ProofDigest {
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&Blake2s::digest(data));
+        digest
+    }
+
+    /// cached result of `digest`, counted as a hit or a miss
+    pub fn get(&mut self, digest: &ProofDigest) -> Option<bool> {
+        let tick = self.next_tick();
+        match self.entries.get_mut(digest) {
+            Some((result, last_use)) => {
+                self.order.remove(last_use);
+                self.order.insert(tick, *digest);
+                *last_use = tick;
+                self.hits += 1;
+                Some(*result)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, digest: ProofDigest, result: bool) {
+        let tick = self.next_tick();
+        if let Some((_, last_use)) = self.entries.insert(digest, (result, tick)) {
+            self.order.remove(&last_use);
+        }
+        self.order.insert(tick, digest);
+        while self.entries.len() > self.capacity {
+            let oldest = *self.order.keys().next().unwrap();
+            let evicted = self.order.remove(&oldest).unwrap();
+            self.entries.remove(&evicted);
+        }
+    }
+
+    /// drops every entry, counters are kept
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            len: self.entries.len(),
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[test]
+fn test_verification_cache() {
+    let mut cache = VerificationCache::new(2);
+    let a = VerificationCache::digest(b"a");
+    let b = VerificationCache::digest(b"b");
+    let c = VerificationCache::digest(b"c");
+    assert_ne!(a, b);
+
+    assert_eq!(cache.get(&a), None);
+    cache.insert(a, true);
+    cache.insert(b, false);
+    assert_eq!(cache.get(&a), Some(true));
+    assert_eq!(cache.get(&b), Some(false));
+    // a is the least recently used entry
+    cache.insert(c, true);
+    assert_eq!(cache.get(&a), None);
+    assert_eq!(cache.get(&b), Some(false));
+    assert_eq!(cache.get(&c), Some(true));
+    assert_eq!(
+        cache.stats(),
+        CacheStats {
+            hits: 4,
+            misses: 2,
+            len: 2
+        }
+    );
+
+    cache.clear();
+    assert_eq!(cache.get(&c), None);
+    assert_eq!(cache.stats().len, 0);
+}
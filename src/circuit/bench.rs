@@ -1,5 +1,5 @@
-use crate::circuit::rln::{RLNCircuit, RLNInputs};
-use crate::merkle::MerkleTree;
+use crate::circuit::rln::{external_nullifier, IdentityScheme, RLNCircuit, RLNInputs};
+use crate::merkle::{MerkleTree, TreeArity};
 use crate::poseidon::{Poseidon as PoseidonHasher, PoseidonParams};
 use crate::{circuit::poseidon::PoseidonCircuit, public::RLNSignal};
 use byteorder::{LittleEndian, WriteBytesExt};
@@ -15,34 +15,126 @@ use std::time::{Duration, Instant};
 use std::{error::Error, hash::Hash};
 
 use crate::public::RLN;
-
-pub struct ProverBenchResult {
+use crate::test_utils;
+
+/// measurements of a single rln configuration
+/// * times are in seconds, sizes in bytes
+/// * `peak_memory` is the resident set high water mark of the process, when the platform reports it
+#[derive(Clone, Debug, Default)]
+pub struct BenchResult {
+    pub merkle_depth: usize,
+    pub poseidon_width: usize,
+    pub full_rounds: usize,
+    pub partial_rounds: usize,
+    pub num_constraints: usize,
+    pub setup_time: f64,
     pub prover_key_size: usize,
     pub prover_time: f64,
+    pub verifier_time: f64,
+    pub proof_size: usize,
+    pub peak_memory: Option<usize>,
 }
 
-impl ProverBenchResult {
-    pub fn new() -> ProverBenchResult {
-        ProverBenchResult {
-            prover_key_size: 0,
-            prover_time: 0f64,
-        }
+impl BenchResult {
+    pub fn new() -> BenchResult {
+        BenchResult::default()
+    }
+
+    pub fn to_json(&self) -> String {
+        let peak_memory = match self.peak_memory {
+            Some(peak_memory) => peak_memory.to_string(),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"merkle_depth\":{},\"poseidon_width\":{},\"full_rounds\":{},\"partial_rounds\":{},\"num_constraints\":{},\"setup_time\":{},\"prover_key_size\":{},\"prover_time\":{},\"verifier_time\":{},\"proof_size\":{},\"peak_memory\":{}}}",
+            self.merkle_depth,
+            self.poseidon_width,
+            self.full_rounds,
+            self.partial_rounds,
+            self.num_constraints,
+            self.setup_time,
+            self.prover_key_size,
+            self.prover_time,
+            self.verifier_time,
+            self.proof_size,
+            peak_memory,
+        )
+    }
+
+    pub fn csv_header() -> &'static str {
+        "merkle_depth,poseidon_width,full_rounds,partial_rounds,num_constraints,setup_time,prover_key_size,prover_time,verifier_time,proof_size,peak_memory"
+    }
+
+    pub fn to_csv(&self) -> String {
+        let peak_memory = match self.peak_memory {
+            Some(peak_memory) => peak_memory.to_string(),
+            None => String::new(),
+        };
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            self.merkle_depth,
+            self.poseidon_width,
+            self.full_rounds,
+            self.partial_rounds,
+            self.num_constraints,
+            self.setup_time,
+            self.prover_key_size,
+            self.prover_time,
+            self.verifier_time,
+            self.proof_size,
+            peak_memory,
+        )
     }
 }
 
+// VmHWM of /proc/self/status, only available on linux
+fn peak_memory() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
 pub fn run_rln_prover_bench<E: Engine>(
     merkle_depth: usize,
     poseidon_params: PoseidonParams<E>,
-) -> ProverBenchResult {
+) -> BenchResult {
     RLNTest::new(merkle_depth, Some(poseidon_params)).run_prover_bench()
 }
 
+/// benches every combination of merkle depth and poseidon parameters,
+/// one row per combination with depths varying fastest
+pub fn run_rln_bench_sweep<E: Engine>(
+    depths: &[usize],
+    poseidon_variants: &[PoseidonParams<E>],
+) -> Vec<BenchResult> {
+    let mut results = Vec::with_capacity(depths.len() * poseidon_variants.len());
+    for poseidon_params in poseidon_variants.iter() {
+        for merkle_depth in depths.iter() {
+            results.push(run_rln_prover_bench(*merkle_depth, poseidon_params.clone()));
+        }
+    }
+    results
+}
+
+/// csv table of bench results with a header line
+pub fn bench_results_to_csv(results: &[BenchResult]) -> String {
+    let mut csv = String::from(BenchResult::csv_header());
+    csv.push('\n');
+    for result in results.iter() {
+        csv.push_str(&result.to_csv());
+        csv.push('\n');
+    }
+    csv
+}
+
 pub struct RLNTest<E>
 where
     E: Engine,
 {
     pub rln: RLN<E>,
     merkle_depth: usize,
+    setup_time: f64,
 }
 
 impl<E> RLNTest<E>
@@ -70,9 +162,21 @@ where
     }
 
     pub fn new(merkle_depth: usize, poseidon_params: Option<PoseidonParams<E>>) -> RLNTest<E> {
+        Self::new_with_arity(merkle_depth, TreeArity::Binary, poseidon_params)
+    }
+
+    pub fn new_with_arity(
+        merkle_depth: usize,
+        arity: TreeArity,
+        poseidon_params: Option<PoseidonParams<E>>,
+    ) -> RLNTest<E> {
+        let now = Instant::now();
+        let rln = RLN::new(merkle_depth, arity, poseidon_params, None);
+        let setup_time = now.elapsed().as_millis() as f64 / 1000.0;
         let mut rln_test = RLNTest {
-            rln: RLN::new(merkle_depth, poseidon_params),
+            rln,
             merkle_depth,
+            setup_time,
         };
         rln_test.insert_public_key();
         rln_test
@@ -86,65 +190,24 @@ where
         let mut rng = Self::rng();
         let hasher = self.rln.hasher();
 
-        // Initialize empty merkle tree
-        let merkle_depth = self.merkle_depth;
-        let mut membership_tree = MerkleTree::empty(hasher.clone(), merkle_depth);
-
-        // A. setup an identity
-
-        let secret_key = E::Fr::rand(&mut rng);
-        let id_comm: E::Fr = hasher.hash(vec![secret_key.clone()]);
-
-        // B. insert to the membership tree
-
-        let id_index = 6; // any number below 2^depth will work
-        membership_tree.update(id_index, id_comm).unwrap();
-
-        // C.1 get membership witness
-
-        let auth_path = membership_tree.get_witness(id_index).unwrap();
-        assert!(membership_tree
-            .check_inclusion(auth_path.clone(), id_index)
-            .unwrap());
-
-        // C.2 prepare sss
-
-        // get current epoch
+        // any index below 2^depth will work
+        let id_index = 6;
+        let tree = test_utils::fixture_tree(&hasher, self.merkle_depth, self.rln.arity(), 7);
         let epoch = E::Fr::rand(&mut rng);
-
         let signal_hash = E::Fr::rand(&mut rng);
-        // evaluation point is the signal_hash
-        let share_x = signal_hash.clone();
-
-        // calculate current line equation
-        let a_0 = secret_key.clone();
-        let a_1: E::Fr = hasher.hash(vec![a_0, epoch]);
-
-        // evaluate line equation
-        let mut share_y = a_1.clone();
-        share_y.mul_assign(&share_x);
-        share_y.add_assign(&a_0);
-
-        // calculate nullfier
-        let nullifier = hasher.hash(vec![a_1]);
-
-        // compose the circuit
-
-        let inputs = RLNInputs::<E> {
-            share_x: Some(share_x),
-            share_y: Some(share_y),
-            epoch: Some(epoch),
-            nullifier: Some(nullifier),
-            root: Some(membership_tree.get_root()),
-            id_key: Some(secret_key),
-            auth_path: auth_path.into_iter().map(|w| Some(w)).collect(),
-        };
-
-        inputs
+        test_utils::valid_inputs(
+            &hasher,
+            &tree,
+            test_utils::fixture_secret::<E>(id_index),
+            id_index as u64,
+            epoch,
+            signal_hash,
+            self.rln.rln_identifier(),
+        )
     }
 
     pub fn synthesize(&self) -> usize {
-        let hasher = PoseidonCircuit::new(self.rln.poseidon_params());
+        let hasher = PoseidonCircuit::from_hasher(&self.rln.hasher());
         let inputs = self.valid_inputs();
         let circuit = RLNCircuit::<E> {
             inputs: inputs.clone(),
@@ -172,7 +235,7 @@ where
         cs.num_constraints()
     }
 
-    pub fn run_prover_bench(&self) -> ProverBenchResult {
+    pub fn run_prover_bench(&self) -> BenchResult {
         let now = Instant::now();
 
         let secret_key = Self::secret_key();
@@ -211,7 +274,9 @@ where
 
         input_data.write(&signal[..]).unwrap();
 
+        let now = Instant::now();
         assert!(self.rln.verify(input_data.as_slice()).unwrap());
+        let verifier_time = now.elapsed().as_millis() as f64 / 1000.0;
 
         let mut circuit_parameters: Vec<u8> = Vec::new();
         self.rln
@@ -219,9 +284,25 @@ where
             .unwrap();
         let prover_key_size = circuit_parameters.len();
 
-        ProverBenchResult {
-            prover_time,
+        let circuit = RLNCircuit::<E> {
+            inputs: RLNInputs::<E>::empty_with_arity(self.merkle_depth, self.rln.arity()),
+            hasher: PoseidonCircuit::from_hasher(&self.rln.hasher()),
+        };
+        let num_constraints = circuit.shape().unwrap().num_constraints;
+
+        let poseidon_params = self.rln.poseidon_params();
+        BenchResult {
+            merkle_depth: self.merkle_depth,
+            poseidon_width: poseidon_params.width(),
+            full_rounds: poseidon_params.full_round_half_len() * 2,
+            partial_rounds: poseidon_params.partial_round_len(),
+            num_constraints,
+            setup_time: self.setup_time,
             prover_key_size,
+            prover_time,
+            verifier_time,
+            proof_size: self.rln.proof_format().proof_size::<E>(),
+            peak_memory: peak_memory(),
         }
     }
 
@@ -229,3 +310,14 @@ where
         self.rln.export_circuit_parameters(w)
     }
 }
+
+#[test]
+fn test_rln_constraint_count() {
+    use sapling_crypto::bellman::pairing::bn256::Bn256;
+    let poseidon_params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let rln_test = RLNTest::<Bn256>::new(4, Some(poseidon_params));
+    // 8 hashes of 3 * (7 * 3 + 55 + 1) constraints: leaf, 4 levels,
+    // external nullifier, a_1 and nullifier
+    // 3 for each level position, 6 public inputs, 2 for the line and 3 equalities
+    assert_eq!(rln_test.synthesize(), 8 * 231 + 4 * 3 + 6 + 2 + 3);
+}
@@ -0,0 +1,238 @@
+//! Merkle path gadgets over a `ZkHasherGadget`, the poseidon circuit by default.
+//!
+//! Paths use the witness layout of `crate::merkle::MerkleTree`,
+//! `get_witness` for binary trees and `get_path` for quad trees.
+
+use crate::circuit::poseidon::PoseidonCircuit;
+use crate::hasher::ZkHasherGadget;
+use sapling_crypto::bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr};
+use sapling_crypto::bellman::pairing::Engine;
+use sapling_crypto::bellman::{ConstraintSystem, SynthesisError};
+use sapling_crypto::circuit::{boolean, num, Assignment};
+
+/// computes the root from `leaf` following the binary path then the quad path
+/// * only one of the paths is expected to be non empty
+/// * elements are `None` when synthesizing without a witness
+pub fn merkle_root<CS, E, H>(
+    mut cs: CS,
+    hasher: &H,
+    leaf: &num::AllocatedNum<E>,
+    auth_path: &[Option<(E::Fr, bool)>],
+    quad_auth_path: &[Option<(Vec<E::Fr>, usize)>],
+) -> Result<num::AllocatedNum<E>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+    H: ZkHasherGadget<E>,
+{
+    let mut acc = leaf.clone();
+    for (i, e) in auth_path.iter().enumerate() {
+        acc = binary_level(
+            cs.namespace(|| format!("auth path {}", i)),
+            hasher,
+            &acc,
+            e.clone(),
+        )?;
+    }
+    for (i, e) in quad_auth_path.iter().enumerate() {
+        let (siblings, position) = match e.clone() {
+            Some((siblings, position)) => (Some(siblings), Some(position)),
+            None => (None, None),
+        };
+        acc = quad_level(
+            cs.namespace(|| format!("quad auth path {}", i)),
+            hasher,
+            &acc,
+            siblings,
+            position,
+        )?;
+    }
+    Ok(acc)
+}
+
+/// ascends one level of a binary tree
+/// * `sibling` is the path element and whether it is the right child,
+/// i.e. true when `acc` is the left child as in the witness of `get_witness`
+pub fn binary_level<CS, E, H>(
+    mut cs: CS,
+    hasher: &H,
+    acc: &num::AllocatedNum<E>,
+    sibling: Option<(E::Fr, bool)>,
+) -> Result<num::AllocatedNum<E>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+    H: ZkHasherGadget<E>,
+{
+    let position = boolean::Boolean::from(boolean::AllocatedBit::alloc(
+        cs.namespace(|| "position bit"),
+        sibling.map(|e| e.1),
+    )?);
+    let path_element =
+        num::AllocatedNum::alloc(cs.namespace(|| "path element"), || Ok(sibling.get()?.0))?;
+
+    let (xr, xl) = num::AllocatedNum::conditionally_reverse(
+        cs.namespace(|| "conditional reversal of preimage"),
+        acc,
+        &path_element,
+        &position,
+    )?;
+
+    hasher.alloc(cs.namespace(|| "hash couple"), vec![xl, xr])
+}
+
+// ascends one level of a quad tree
+// `siblings` are the three other children of the parent in order
+// `position` is the index of `acc` among the four children
+// children are placed with position bits b0 (low) and b1 (high):
+// c_0 = s_0 + e_0 * (acc - s_0)
+// c_1 = s_1 + e_0 * (s_0 - s_1) + e_1 * (acc - s_1)
+// c_2 = s_2 + (1 - b_1) * (s_1 - s_2) + e_2 * (acc - s_2)
+// c_3 = s_2 + e_3 * (acc - s_2)
+// where e_i is the indicator of position == i
+pub fn quad_level<CS, E, H>(
+    mut cs: CS,
+    hasher: &H,
+    acc: &num::AllocatedNum<E>,
+    siblings: Option<Vec<E::Fr>>,
+    position: Option<usize>,
+) -> Result<num::AllocatedNum<E>, SynthesisError>
+where
+    E: Engine,
+    CS: ConstraintSystem<E>,
+    H: ZkHasherGadget<E>,
+{
+    if let Some(siblings) = siblings.as_ref() {
+        assert_eq!(siblings.len(), 3);
+    }
+    if let Some(position) = position {
+        assert!(position < 4);
+    }
+
+    let b0 = boolean::AllocatedBit::alloc(
+        cs.namespace(|| "position bit 0"),
+        position.map(|p| p & 1 == 1),
+    )?;
+    let b1 = boolean::AllocatedBit::alloc(
+        cs.namespace(|| "position bit 1"),
+        position.map(|p| p & 2 == 2),
+    )?;
+    let b01 = boolean::AllocatedBit::and(cs.namespace(|| "position bits and"), &b0, &b1)?;
+
+    let mut s: Vec<num::AllocatedNum<E>> = Vec::with_capacity(3);
+    for i in 0..3 {
+        let sibling = num::AllocatedNum::alloc(cs.namespace(|| format!("sibling {}", i)), || {
+            Ok(siblings.get()?[i])
+        })?;
+        s.push(sibling);
+    }
+
+    let children: Option<Vec<E::Fr>> = match (siblings, position, acc.get_value()) {
+        (Some(mut siblings), Some(position), Some(acc)) => {
+            siblings.insert(position, acc);
+            Some(siblings)
+        }
+        _ => None,
+    };
+    let child = |i: usize| -> Result<E::Fr, SynthesisError> { Ok(children.get()?[i]) };
+
+    // c_0
+    let c0 = num::AllocatedNum::alloc(cs.namespace(|| "child 0"), || child(0))?;
+    cs.enforce(
+        || "enforce child 0",
+        |lc| lc + acc.get_variable() - s[0].get_variable(),
+        |lc| lc + CS::one() - b0.get_variable() - b1.get_variable() + b01.get_variable(),
+        |lc| lc + c0.get_variable() - s[0].get_variable(),
+    );
+
+    // c_1
+    let t1 = num::AllocatedNum::alloc(cs.namespace(|| "child 1 term"), || {
+        let mut t = *s[0].get_value().get()?;
+        t.sub_assign(s[1].get_value().get()?);
+        if position.get()? != &0 {
+            t = E::Fr::zero();
+        }
+        Ok(t)
+    })?;
+    cs.enforce(
+        || "enforce child 1 term",
+        |lc| lc + s[0].get_variable() - s[1].get_variable(),
+        |lc| lc + CS::one() - b0.get_variable() - b1.get_variable() + b01.get_variable(),
+        |lc| lc + t1.get_variable(),
+    );
+    let c1 = num::AllocatedNum::alloc(cs.namespace(|| "child 1"), || child(1))?;
+    cs.enforce(
+        || "enforce child 1",
+        |lc| lc + acc.get_variable() - s[1].get_variable(),
+        |lc| lc + b0.get_variable() - b01.get_variable(),
+        |lc| lc + c1.get_variable() - s[1].get_variable() - t1.get_variable(),
+    );
+
+    // c_2
+    let t2 = num::AllocatedNum::alloc(cs.namespace(|| "child 2 term"), || {
+        let mut t = *s[1].get_value().get()?;
+        t.sub_assign(s[2].get_value().get()?);
+        if position.get()? & 2 == 2 {
+            t = E::Fr::zero();
+        }
+        Ok(t)
+    })?;
+    cs.enforce(
+        || "enforce child 2 term",
+        |lc| lc + s[1].get_variable() - s[2].get_variable(),
+        |lc| lc + CS::one() - b1.get_variable(),
+        |lc| lc + t2.get_variable(),
+    );
+    let c2 = num::AllocatedNum::alloc(cs.namespace(|| "child 2"), || child(2))?;
+    cs.enforce(
+        || "enforce child 2",
+        |lc| lc + acc.get_variable() - s[2].get_variable(),
+        |lc| lc + b1.get_variable() - b01.get_variable(),
+        |lc| lc + c2.get_variable() - s[2].get_variable() - t2.get_variable(),
+    );
+
+    // c_3
+    let c3 = num::AllocatedNum::alloc(cs.namespace(|| "child 3"), || child(3))?;
+    cs.enforce(
+        || "enforce child 3",
+        |lc| lc + acc.get_variable() - s[2].get_variable(),
+        |lc| lc + b01.get_variable(),
+        |lc| lc + c3.get_variable() - s[2].get_variable(),
+    );
+
+    hasher.alloc(cs.namespace(|| "hash children"), vec![c0, c1, c2, c3])
+}
+
+#[test]
+fn test_merkle_root_gadget() {
+    use crate::merkle::{MerkleTree, TreeArity};
+    use crate::poseidon::{Poseidon as PoseidonHasher, PoseidonParams};
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    use sapling_crypto::circuit::test::TestConstraintSystem;
+
+    for (arity, width) in [(TreeArity::Binary, 3), (TreeArity::Quad, 5)].iter() {
+        let params = PoseidonParams::<Bn256>::new(8, 55, *width, None, None, None);
+        let mut tree = MerkleTree::empty_with_arity(PoseidonHasher::new(params.clone()), 2, *arity);
+        let leaf = Fr::from_str("7").unwrap();
+        tree.update(5, leaf).unwrap();
+        let (auth_path, quad_auth_path) = match arity {
+            TreeArity::Binary => (tree.get_witness(5).unwrap(), vec![]),
+            TreeArity::Quad => (vec![], tree.get_path(5).unwrap()),
+        };
+        let auth_path: Vec<_> = auth_path.into_iter().map(Some).collect();
+        let quad_auth_path: Vec<_> = quad_auth_path.into_iter().map(Some).collect();
+
+        let mut cs = TestConstraintSystem::<Bn256>::new();
+        let leaf = num::AllocatedNum::alloc(cs.namespace(|| "leaf"), || Ok(leaf)).unwrap();
+        let root = merkle_root(
+            cs.namespace(|| "root"),
+            &PoseidonCircuit::new(params),
+            &leaf,
+            &auth_path,
+            &quad_auth_path,
+        )
+        .unwrap();
+        assert_eq!(root.get_value().unwrap(), tree.get_root());
+        assert!(cs.is_satisfied());
+    }
+}
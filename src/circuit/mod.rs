@@ -1,6 +1,10 @@
+pub mod merkle;
 mod polynomial;
 pub mod poseidon;
+#[cfg(feature = "prover")]
+pub mod progress;
 pub mod rln;
+pub mod withdrawal;
 
 #[cfg(any(test, feature = "bench"))]
 pub mod bench;
@@ -1,363 +1,277 @@
+use crate::poseidon::circom::CircomPoseidon;
+use crate::poseidon::{OptimizedRounds, SparseMatrix};
 use crate::poseidon::{Poseidon as PoseidonHasher, PoseidonParams};
 use sapling_crypto::bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr};
 use sapling_crypto::bellman::pairing::Engine;
 use sapling_crypto::bellman::{Circuit, ConstraintSystem, LinearCombination, SynthesisError};
 use sapling_crypto::circuit::{boolean, ecc, num, Assignment};
+use std::sync::Arc;
 
-#[derive(Clone)]
-struct Element<E>
-where
-    E: Engine,
-{
-    an: Option<num::AllocatedNum<E>>,
-    nu: Option<num::Num<E>>,
-}
-
-enum RoundType {
-    Full,
-    Partial,
-    Exhausted,
-}
-
-struct RoundCtx<'a, E>
-where
-    E: Engine,
-{
-    number: usize,
-    params: &'a PoseidonParams<E>,
-}
-
-struct State<E>
-where
-    E: Engine,
-{
-    elements: Vec<Element<E>>,
-}
-
+/// poseidon hash gadget, constrains the same permutation as `crate::poseidon::Poseidon`
+/// so circuits built on it agree with native hashes of the same parameters
+/// * rounds follow `OptimizedRounds`, linear layers are free and every sbox
+/// takes three constraints over the linear combination of its input
 #[derive(Clone)]
 pub struct PoseidonCircuit<E>
 where
     E: Engine,
 {
-    params: PoseidonParams<E>,
+    params: Arc<PoseidonParams<E>>,
+    rounds: Arc<OptimizedRounds<E>>,
 }
 
-impl<E> Element<E>
+impl<E> PoseidonCircuit<E>
 where
     E: Engine,
 {
-    pub fn new_from_alloc(an: num::AllocatedNum<E>) -> Self {
-        return Element {
-            an: Some(an),
-            nu: None,
-        };
-    }
-
-    pub fn new_from_num(nu: num::Num<E>) -> Self {
-        return Element {
-            an: None,
-            nu: Some(nu),
-        };
-    }
-
-    pub fn is_allocated(&self) -> bool {
-        return self.an.is_some();
-    }
-
-    pub fn is_number(&self) -> bool {
-        return self.nu.is_some();
-    }
-
-    pub fn update_with_allocated(&mut self, an: num::AllocatedNum<E>) {
-        self.an = Some(an);
-        self.nu = None;
-    }
-
-    pub fn update_with_num(&mut self, nu: num::Num<E>) {
-        self.nu = Some(nu);
-        self.an = None;
-    }
-
-    pub fn num(&self) -> num::Num<E> {
-        if let Some(nu) = self.nu.clone() {
-            nu
-        } else {
-            match self.an.clone() {
-                Some(an) => num::Num::from(an),
-                None => panic!("element not exist"),
-            }
-        }
-    }
-
-    pub fn allocate<CS: ConstraintSystem<E>>(
-        &self,
-        mut cs: CS,
-    ) -> Result<num::AllocatedNum<E>, SynthesisError> {
-        match self.nu.clone() {
-            Some(nu) => {
-                let v = num::AllocatedNum::alloc(cs.namespace(|| "allocate num"), || {
-                    nu.get_value()
-                        .ok_or_else(|| SynthesisError::AssignmentMissing)
-                })?;
-                cs.enforce(
-                    || format!("enforce allocated"),
-                    |_| nu.lc(E::Fr::one()),
-                    |lc| lc + CS::one(),
-                    |lc| lc + v.get_variable(),
-                );
-                Ok(v)
-            }
-            None => panic!(""),
+    pub fn new(params: PoseidonParams<E>) -> Self {
+        let rounds = Arc::new(params.optimized_rounds());
+        Self {
+            params: Arc::new(params),
+            rounds,
         }
     }
 
-    pub fn allocated(&self) -> Option<num::AllocatedNum<E>> {
-        self.an.clone()
+    /// gadget of the native hasher, sharing its precomputed rounds
+    pub fn from_hasher(hasher: &PoseidonHasher<E>) -> Self {
+        let (params, rounds) = hasher.shared_params();
+        Self { params, rounds }
     }
-}
 
-impl<'a, E> RoundCtx<'a, E>
-where
-    E: Engine,
-{
-    pub fn new(params: &'a PoseidonParams<E>) -> Self {
-        RoundCtx {
-            params,
-            number: 0usize,
-        }
+    pub fn params(&self) -> &PoseidonParams<E> {
+        &self.params
     }
 
     pub fn width(&self) -> usize {
         self.params.width()
     }
 
-    pub fn round_number(&self) -> usize {
-        self.number
-    }
-
-    pub fn is_full_round(&self) -> bool {
-        match self.round_type() {
-            RoundType::Full => true,
-            _ => false,
+    /// allocates `inputs` as witnesses and hashes them
+    /// * values are `None` when synthesizing without a witness
+    pub fn hash<CS: ConstraintSystem<E>>(
+        &self,
+        mut cs: CS,
+        inputs: &[Option<E::Fr>],
+    ) -> Result<num::AllocatedNum<E>, SynthesisError> {
+        let mut allocated: Vec<num::AllocatedNum<E>> = Vec::with_capacity(inputs.len());
+        for (i, input) in inputs.iter().enumerate() {
+            let input = num::AllocatedNum::alloc(cs.namespace(|| format!("input {}", i)), || {
+                Ok(*input.get()?)
+            })?;
+            allocated.push(input);
         }
+        self.alloc(cs.namespace(|| "hash"), allocated)
     }
 
-    pub fn is_exhausted(&self) -> bool {
-        match self.round_type() {
-            RoundType::Exhausted => true,
-            _ => false,
-        }
-    }
+    /// hashes already allocated inputs
+    /// * number of inputs must be less than the width of the permutation
+    pub fn alloc<CS: ConstraintSystem<E>>(
+        &self,
+        mut cs: CS,
+        input: Vec<num::AllocatedNum<E>>,
+    ) -> Result<num::AllocatedNum<E>, SynthesisError> {
+        assert!(input.len() < self.params.width());
 
-    pub fn is_last_round(&self) -> bool {
-        self.number == self.params.total_rounds() - 1
-    }
+        let mut state: Vec<num::Num<E>> = input.into_iter().map(num::Num::from).collect();
+        state.resize(self.width(), num::Num::zero());
 
-    pub fn in_transition(&self) -> bool {
         let a1 = self.params.full_round_half_len();
         let a2 = a1 + self.params.partial_round_len();
-        self.number == a1 - 1 || self.number == a2 - 1
-    }
-
-    pub fn round_constant(&self) -> E::Fr {
-        self.params.round_constant(self.number)
-    }
+        let last_round = self.params.total_rounds() - 1;
+        for round in 0..last_round {
+            let mut cs = cs.namespace(|| format!("round {}", round));
+            if round >= a1 && round < a2 {
+                let i = round - a1;
+                let s_0 = sbox(
+                    cs.namespace(|| "sbox 0"),
+                    &state[0],
+                    self.rounds.partial_constants[i],
+                )?;
+                state[0] = num::Num::from(s_0);
+                mul_sparse_matrix(&self.rounds.sparse_matrices[i], &mut state);
+            } else {
+                let mut s: Vec<num::Num<E>> = Vec::with_capacity(state.len());
+                for (i, el) in state.iter().enumerate() {
+                    let constant = self.full_round_constant(round, i);
+                    let s_i = sbox(cs.namespace(|| format!("sbox {}", i)), el, constant)?;
+                    s.push(num::Num::from(s_i));
+                }
+                state = if round == a1 - 1 {
+                    mul_matrix(&self.rounds.pre_sparse_matrix, &s)
+                } else {
+                    mul_matrix(self.params.mds_matrix_ref(), &s)
+                };
+            }
+        }
 
-    pub fn mds_matrix_row(&self, i: usize) -> Vec<E::Fr> {
-        let w = self.width();
-        let matrix = self.params.mds_matrix();
-        matrix[i * w..(i + 1) * w].to_vec()
+        // only the first element of the last round is the output
+        let constant = self.full_round_constant(last_round, 0);
+        sbox(cs.namespace(|| "result"), &state[0], constant)
     }
 
-    pub fn round_type(&self) -> RoundType {
-        let a1 = self.params.full_round_half_len();
-        let (a2, a3) = (
-            a1 + self.params.partial_round_len(),
-            self.params.total_rounds(),
-        );
-        if self.number < a1 {
-            RoundType::Full
-        } else if self.number >= a1 && self.number < a2 {
-            RoundType::Partial
-        } else if self.number >= a2 && self.number < a3 {
-            RoundType::Full
-        } else {
-            RoundType::Exhausted
+    fn full_round_constant(&self, round: usize, i: usize) -> E::Fr {
+        let mut constant = self.params.round_constant(round);
+        if round == self.params.full_round_half_len() + self.params.partial_round_len() {
+            constant.add_assign(&self.rounds.post_partial_constants[i]);
         }
+        constant
     }
+}
 
-    pub fn round_end(&mut self) {
-        self.number += 1;
-    }
+/// gadget of `CircomPoseidon`, round by round
+/// * constants are carried next to the state until an sbox takes them,
+/// so only the sboxes and the output cost constraints
+#[derive(Clone)]
+pub struct CircomPoseidonCircuit<E: Engine> {
+    hasher: CircomPoseidon<E>,
 }
 
-impl<E> State<E>
-where
-    E: Engine,
-{
-    pub fn new(elements: Vec<Element<E>>) -> Self {
-        Self { elements }
+impl<E: Engine> CircomPoseidonCircuit<E> {
+    pub fn new(hasher: CircomPoseidon<E>) -> Self {
+        Self { hasher }
     }
 
-    pub fn first_allocated<CS: ConstraintSystem<E>>(
-        &mut self,
-        mut cs: CS,
-    ) -> Result<num::AllocatedNum<E>, SynthesisError> {
-        let el = match self.elements[0].allocated() {
-            Some(an) => an,
-            None => self.elements[0].allocate(cs.namespace(|| format!("alloc first")))?,
-        };
-        Ok(el)
+    pub fn width(&self) -> usize {
+        self.hasher.width()
     }
 
-    fn sbox<CS: ConstraintSystem<E>>(
-        &mut self,
+    /// hashes already allocated inputs
+    /// * the number of inputs must be the one of the hasher
+    pub fn alloc<CS: ConstraintSystem<E>>(
+        &self,
         mut cs: CS,
-        ctx: &mut RoundCtx<E>,
-    ) -> Result<(), SynthesisError> {
-        assert_eq!(ctx.width(), self.elements.len());
-
-        for i in 0..if ctx.is_full_round() { ctx.width() } else { 1 } {
-            let round_constant = ctx.round_constant();
-            let si = {
-                match self.elements[i].allocated() {
-                    Some(an) => an,
-                    None => self.elements[i]
-                        .allocate(cs.namespace(|| format!("alloc sbox input {}", i)))?,
-                }
-            };
-            let si2 = num::AllocatedNum::alloc(
-                cs.namespace(|| format!("square with round constant {}", i)),
-                || {
-                    let mut val = *si.get_value().get()?;
-                    val.add_assign(&round_constant);
-                    val.square();
-                    Ok(val)
-                },
-            )?;
-            cs.enforce(
-                || format!("constraint square with round constant {}", i),
-                |lc| lc + si.get_variable() + (round_constant, CS::one()),
-                |lc| lc + si.get_variable() + (round_constant, CS::one()),
-                |lc| lc + si2.get_variable(),
-            );
-            let si4 = si2.square(cs.namespace(|| format!("si^4 {}", i)))?;
-            let si5 = num::AllocatedNum::alloc(cs.namespace(|| format!("si^5 {}", i)), || {
-                let mut val = *si4.get_value().get()?;
-                let mut si_val = *si.get_value().get()?;
-                si_val.add_assign(&round_constant);
-                val.mul_assign(&si_val);
-                Ok(val)
-            })?;
-            cs.enforce(
-                || format!("constraint sbox result {}", i),
-                |lc| lc + si.get_variable() + (round_constant, CS::one()),
-                |lc| lc + si4.get_variable(),
-                |lc| lc + si5.get_variable(),
-            );
-            self.elements[i].update_with_allocated(si5);
-        }
-
-        Ok(())
-    }
-
-    fn mul_mds_matrix<CS: ConstraintSystem<E>>(
-        &mut self,
-        ctx: &mut RoundCtx<E>,
-    ) -> Result<(), SynthesisError> {
-        assert_eq!(ctx.width(), self.elements.len());
-
-        if !ctx.is_last_round() {
-            // skip mds multiplication in last round
-
-            let mut new_state: Vec<num::Num<E>> = Vec::new();
-            let w = ctx.width();
-
-            for i in 0..w {
-                let row = ctx.mds_matrix_row(i);
-                let mut acc = num::Num::<E>::zero();
-                for j in 0..w {
-                    let mut r = self.elements[j].num();
-                    r.scale(row[j]);
-                    acc.add_assign(&r);
-                }
-                new_state.push(acc);
-            }
-
-            // round ends here
-            let is_full_round = ctx.is_full_round();
-            let in_transition = ctx.in_transition();
-            ctx.round_end();
-
-            // add round constants just after mds if
-            // first full round has just ended
-            // or in partial rounds expect the last one.
-            if in_transition == is_full_round {
-                // add round constants for elements in {1, t}
-                let round_constant = ctx.round_constant();
-                for i in 1..w {
-                    let mut constant_as_num = num::Num::<E>::zero();
-                    constant_as_num = constant_as_num.add_bool_with_coeff(
-                        CS::one(),
-                        &boolean::Boolean::Constant(true),
-                        round_constant,
-                    );
-                    new_state[i].add_assign(&constant_as_num);
-                }
+        input: Vec<num::AllocatedNum<E>>,
+    ) -> Result<num::AllocatedNum<E>, SynthesisError> {
+        let t = self.hasher.width();
+        assert_eq!(input.len() + 1, t);
+
+        let mut state: Vec<num::Num<E>> = vec![num::Num::zero()];
+        state.extend(input.into_iter().map(num::Num::from));
+        let mut constants = vec![E::Fr::zero(); t];
+        for round in 0..self.hasher.total_rounds() {
+            let mut cs = cs.namespace(|| format!("round {}", round));
+            for (i, c) in constants.iter_mut().enumerate() {
+                c.add_assign(&self.hasher.round_constant(round, i));
             }
-
-            for (s0, s1) in self.elements.iter_mut().zip(new_state) {
-                s0.update_with_num(s1);
+            let sboxes = if self.hasher.is_full_round(round) {
+                t
+            } else {
+                1
+            };
+            for i in 0..sboxes {
+                let s_i = sbox(
+                    cs.namespace(|| format!("sbox {}", i)),
+                    &state[i],
+                    constants[i],
+                )?;
+                state[i] = num::Num::from(s_i);
+                constants[i] = E::Fr::zero();
             }
-        } else {
-            // terminates hades
-            ctx.round_end();
+            state = mul_matrix(self.hasher.mds_matrix_ref(), &state);
+            constants = mul_constants::<E>(self.hasher.mds_matrix_ref(), &constants);
         }
-        Ok(())
+
+        let value = state[0].get_value().map(|mut v| {
+            v.add_assign(&constants[0]);
+            v
+        });
+        let result = num::AllocatedNum::alloc(cs.namespace(|| "result"), || Ok(*value.get()?))?;
+        cs.enforce(
+            || "result constraint",
+            |_| state[0].lc(E::Fr::one()) + (constants[0], CS::one()),
+            |lc| lc + CS::one(),
+            |lc| lc + result.get_variable(),
+        );
+        Ok(result)
     }
 }
 
-impl<E> PoseidonCircuit<E>
+// (x + c)^5 in three constraints, `x` stays a linear combination
+fn sbox<E, CS>(
+    mut cs: CS,
+    x: &num::Num<E>,
+    round_constant: E::Fr,
+) -> Result<num::AllocatedNum<E>, SynthesisError>
 where
     E: Engine,
+    CS: ConstraintSystem<E>,
 {
-    pub fn new(params: PoseidonParams<E>) -> Self {
-        Self { params: params }
-    }
-
-    pub fn width(&self) -> usize {
-        self.params.width()
-    }
-
-    pub fn alloc<CS: ConstraintSystem<E>>(
-        &self,
-        mut cs: CS,
-        input: Vec<num::AllocatedNum<E>>,
-    ) -> Result<num::AllocatedNum<E>, SynthesisError> {
-        assert!(input.len() < self.params.width());
+    let value = x.get_value().map(|mut v| {
+        v.add_assign(&round_constant);
+        v
+    });
+    let x2 = num::AllocatedNum::alloc(cs.namespace(|| "x^2"), || {
+        let mut v = *value.get()?;
+        v.square();
+        Ok(v)
+    })?;
+    cs.enforce(
+        || "x^2 constraint",
+        |_| x.lc(E::Fr::one()) + (round_constant, CS::one()),
+        |_| x.lc(E::Fr::one()) + (round_constant, CS::one()),
+        |lc| lc + x2.get_variable(),
+    );
+    let x4 = x2.square(cs.namespace(|| "x^4"))?;
+    let x5 = num::AllocatedNum::alloc(cs.namespace(|| "x^5"), || {
+        let mut v = *x4.get_value().get()?;
+        v.mul_assign(value.get()?);
+        Ok(v)
+    })?;
+    cs.enforce(
+        || "x^5 constraint",
+        |_| x.lc(E::Fr::one()) + (round_constant, CS::one()),
+        |lc| lc + x4.get_variable(),
+        |lc| lc + x5.get_variable(),
+    );
+    Ok(x5)
+}
 
-        let mut elements: Vec<Element<E>> = input
-            .iter()
-            .map(|el| Element::new_from_alloc(el.clone()))
-            .collect();
-        elements.resize(self.width(), Element::new_from_num(num::Num::zero()));
+fn mul_matrix<E: Engine>(matrix: &[E::Fr], state: &[num::Num<E>]) -> Vec<num::Num<E>> {
+    let w = state.len();
+    (0..w)
+        .map(|i| {
+            let mut acc = num::Num::<E>::zero();
+            for (j, s) in state.iter().enumerate() {
+                let mut r = s.clone();
+                r.scale(matrix[i * w + j]);
+                acc.add_assign(&r);
+            }
+            acc
+        })
+        .collect()
+}
 
-        let mut state = State::new(elements);
-        let mut ctx = RoundCtx::new(&self.params);
-        loop {
-            match ctx.round_type() {
-                RoundType::Exhausted => {
-                    break;
-                }
-                _ => {
-                    let round_number = ctx.round_number();
-                    state.sbox(cs.namespace(|| format!("sbox {}", round_number)), &mut ctx)?;
-                    state.mul_mds_matrix::<CS>(&mut ctx)?;
-                }
+fn mul_constants<E: Engine>(matrix: &[E::Fr], constants: &[E::Fr]) -> Vec<E::Fr> {
+    let w = constants.len();
+    (0..w)
+        .map(|i| {
+            let mut acc = E::Fr::zero();
+            for (j, c) in constants.iter().enumerate() {
+                let mut tmp = *c;
+                tmp.mul_assign(&matrix[i * w + j]);
+                acc.add_assign(&tmp);
             }
-        }
-        state.first_allocated(cs.namespace(|| format!("allocate result")))
+            acc
+        })
+        .collect()
+}
+
+fn mul_sparse_matrix<E: Engine>(matrix: &SparseMatrix<E>, state: &mut [num::Num<E>]) {
+    let x_0 = state[0].clone();
+    let mut y_0 = x_0.clone();
+    y_0.scale(matrix.m_00);
+    for (s, (r, c)) in state[1..]
+        .iter_mut()
+        .zip(matrix.row.iter().zip(matrix.column.iter()))
+    {
+        let mut tmp = s.clone();
+        tmp.scale(*r);
+        y_0.add_assign(&tmp);
+        let mut tmp = x_0.clone();
+        tmp.scale(*c);
+        s.add_assign(&tmp);
     }
+    state[0] = y_0;
 }
 
 #[test]
@@ -393,6 +307,17 @@ fn test_poseidon_circuit() {
 
     assert_eq!(result, expected);
     assert!(cs.is_satisfied());
+    // three constraints for each sbox, the last round only keeps the first element
+    let rf = params.full_round_half_len() * 2;
+    let sboxes = (rf - 1) * params.width() + params.partial_round_len() + 1;
+    assert_eq!(cs.num_constraints(), 3 * sboxes);
+
+    let values: Vec<Option<Fr>> = inputs.iter().map(|e| Some(*e)).collect();
+    let res_hashed = circuit
+        .hash(cs.namespace(|| "hash values"), &values)
+        .unwrap();
+    assert_eq!(res_hashed.get_value().unwrap(), expected);
+    assert!(cs.is_satisfied());
     println!(
         "number of constraints for (t: {}, rf: {}, rp: {}), {}",
         params.width(),
@@ -401,3 +326,29 @@ fn test_poseidon_circuit() {
         cs.num_constraints()
     );
 }
+
+#[test]
+fn test_circom_poseidon_circuit() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    use sapling_crypto::circuit::test::TestConstraintSystem;
+
+    for num_inputs in 1..3 {
+        let hasher = CircomPoseidon::<Bn256>::new(num_inputs).unwrap();
+        let inputs: Vec<Fr> = (0..num_inputs)
+            .map(|i| Fr::from_str(&format!("{}", i + 1)).unwrap())
+            .collect();
+        let mut cs = TestConstraintSystem::<Bn256>::new();
+        let allocated = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                num::AllocatedNum::alloc(cs.namespace(|| format!("input {}", i)), || Ok(*input))
+                    .unwrap()
+            })
+            .collect();
+        let circuit = CircomPoseidonCircuit::new(hasher.clone());
+        let hash = circuit.alloc(cs.namespace(|| "hash"), allocated).unwrap();
+        assert!(cs.is_satisfied());
+        assert_eq!(hash.get_value().unwrap(), hasher.hash(&inputs));
+    }
+}
@@ -0,0 +1,166 @@
+//! Progress of circuit parameter and proof generation.
+//!
+//! `generate_random_parameters` synthesizes the circuit once and then evaluates the
+//! QAP polynomials in the exponent, both without any feedback. `ProgressCircuit` wraps
+//! a circuit and reports the share of constraints synthesized so far. The multiexps
+//! that follow run inside bellman, so that phase is only reported when it starts and
+//! when the parameters are ready.
+//! `create_random_proof` has the same shape, the witness is synthesized and then the
+//! ffts and multiexps of the proof run, `ProofPhase` names those two phases.
+
+use sapling_crypto::bellman::pairing::Engine;
+use sapling_crypto::bellman::{
+    Circuit, ConstraintSystem, LinearCombination, SynthesisError, Variable,
+};
+
+/// phases of parameter generation, with stable discriminants for the c api
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetupPhase {
+    /// constraints of the circuit are synthesized
+    Synthesis = 0,
+    /// query points are computed, most of the time goes here
+    Parameters = 1,
+}
+
+/// phases of proof generation, with stable discriminants for the wasm api
+/// * `ProgressCircuit` reports them as `SetupPhase::Synthesis` and `SetupPhase::Parameters`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProofPhase {
+    /// the witness is assigned constraint by constraint
+    Synthesis = 0,
+    /// ffts and multiexps of the proof, most of the time goes here
+    Proving = 1,
+}
+
+impl From<SetupPhase> for ProofPhase {
+    fn from(phase: SetupPhase) -> Self {
+        match phase {
+            SetupPhase::Synthesis => ProofPhase::Synthesis,
+            SetupPhase::Parameters => ProofPhase::Proving,
+        }
+    }
+}
+
+/// reports the progress of synthesizing `circuit` to `progress`
+/// as a phase and a percentage
+/// * `num_constraints` is the constraint count of the circuit, e.g. `RLNCircuit::shape`
+/// * `Synthesis` goes from 0 to 100, then `Parameters` is reported at 0;
+/// the caller reports `Parameters` at 100 once the parameters are returned
+pub struct ProgressCircuit<'a, C> {
+    pub circuit: C,
+    pub num_constraints: usize,
+    pub progress: &'a mut dyn FnMut(SetupPhase, u8),
+}
+
+impl<'a, E: Engine, C: Circuit<E>> Circuit<E> for ProgressCircuit<'a, C> {
+    fn synthesize<CS: ConstraintSystem<E>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let progress = self.progress;
+        progress(SetupPhase::Synthesis, 0);
+        let mut cs = ProgressCS {
+            cs,
+            num_constraints: self.num_constraints.max(1),
+            enforced: 0,
+            percent: 0,
+            progress: &mut *progress,
+        };
+        self.circuit.synthesize(&mut cs)?;
+        progress(SetupPhase::Synthesis, 100);
+        progress(SetupPhase::Parameters, 0);
+        Ok(())
+    }
+}
+
+struct ProgressCS<'a, 'b, CS> {
+    cs: &'a mut CS,
+    num_constraints: usize,
+    enforced: usize,
+    percent: u8,
+    progress: &'b mut dyn FnMut(SetupPhase, u8),
+}
+
+impl<'a, 'b, E: Engine, CS: ConstraintSystem<E>> ConstraintSystem<E> for ProgressCS<'a, 'b, CS> {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, annotation: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.cs.alloc(annotation, f)
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, annotation: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.cs.alloc_input(annotation, f)
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, annotation: A, a: LA, b: LB, c: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LB: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LC: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+    {
+        self.cs.enforce(annotation, a, b, c);
+        self.enforced += 1;
+        // 100 is left for the end of synthesis
+        let percent = (self.enforced * 100 / self.num_constraints).min(99) as u8;
+        if percent > self.percent {
+            self.percent = percent;
+            (self.progress)(SetupPhase::Synthesis, percent);
+        }
+    }
+
+    fn push_namespace<NR, N>(&mut self, name_fn: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+        self.cs.get_root().push_namespace(name_fn);
+    }
+
+    fn pop_namespace(&mut self) {
+        self.cs.get_root().pop_namespace();
+    }
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+#[test]
+fn test_progress_circuit() {
+    use crate::circuit::poseidon::PoseidonCircuit;
+    use crate::circuit::rln::{RLNCircuit, RLNInputs};
+    use crate::poseidon::PoseidonParams;
+    use sapling_crypto::bellman::pairing::bn256::Bn256;
+    use sapling_crypto::circuit::test::TestConstraintSystem;
+
+    let circuit = RLNCircuit::<Bn256> {
+        inputs: RLNInputs::<Bn256>::empty(3),
+        hasher: PoseidonCircuit::new(PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None)),
+    };
+    let num_constraints = circuit.clone().shape().unwrap().num_constraints;
+    let mut reports: Vec<(SetupPhase, u8)> = Vec::new();
+    let mut cs = TestConstraintSystem::<Bn256>::new();
+    ProgressCircuit {
+        circuit,
+        num_constraints,
+        progress: &mut |phase, percent| reports.push((phase, percent)),
+    }
+    .synthesize(&mut cs)
+    .unwrap();
+    assert_eq!(cs.num_constraints(), num_constraints);
+    assert_eq!(reports.first(), Some(&(SetupPhase::Synthesis, 0)));
+    assert_eq!(reports.last(), Some(&(SetupPhase::Parameters, 0)));
+    assert!(reports.contains(&(SetupPhase::Synthesis, 50)));
+    assert!(reports
+        .windows(2)
+        .all(|w| w[0].0 != w[1].0 || w[0].1 < w[1].1));
+}
@@ -1,9 +1,18 @@
+use crate::circuit::merkle::merkle_root;
 use crate::circuit::polynomial::allocate_add_with_coeff;
-use crate::circuit::poseidon::PoseidonCircuit;
+use crate::circuit::poseidon::{CircomPoseidonCircuit, PoseidonCircuit};
+use crate::hash_to_field::{expand_message_xmd, hash_to_field_xmd};
+use crate::hasher::{ZkHasher, ZkHasherGadget};
+use crate::merkle::TreeArity;
+use crate::poseidon::circom::CircomPoseidon;
 use crate::poseidon::{Poseidon as PoseidonHasher, PoseidonParams};
+use crate::utils::read_fr;
+use rand::{Rand, Rng};
 use sapling_crypto::bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr};
 use sapling_crypto::bellman::pairing::Engine;
-use sapling_crypto::bellman::{Circuit, ConstraintSystem, SynthesisError, Variable};
+use sapling_crypto::bellman::{
+    Circuit, ConstraintSystem, Index, LinearCombination, SynthesisError, Variable,
+};
 use sapling_crypto::circuit::{boolean, ecc, num, Assignment};
 use sapling_crypto::jubjub::{JubjubEngine, JubjubParams, PrimeOrder};
 
@@ -11,7 +20,59 @@ use std::io::{self, Read, Write};
 
 // Rate Limit Nullifier
 
+/// how the secret `a_0` of a member relates to its leaf
+/// * `Single` leaves are `hash(a_0)` for a random `a_0`
+/// * `Semaphore` leaves are the identity commitments of semaphore, `a_0` is the secret
+/// `poseidon(nullifier, trapdoor)` with the circomlib poseidon, see `identity_secret`,
+/// the tree is a binary one hashed with the circomlib poseidon as well,
+/// see `semaphore_tree_hasher`
+/// * `Staked` leaves are `hash(hash(a_0), stake)`, see `staked_leaf`
+/// * `PublicStake` leaves are the same as `Staked` ones,
+/// the stake is the last public input, after `rln_identifier`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IdentityScheme {
+    Single,
+    Semaphore,
+    Staked,
+    PublicStake,
+}
+
+impl IdentityScheme {
+    /// leaves of the scheme commit to a stake
+    pub fn is_staked(&self) -> bool {
+        match self {
+            IdentityScheme::Staked | IdentityScheme::PublicStake => true,
+            IdentityScheme::Single | IdentityScheme::Semaphore => false,
+        }
+    }
+
+    /// number of secrets of an identity, see `key_gen`
+    pub fn num_secrets(&self) -> usize {
+        match self {
+            IdentityScheme::Semaphore => 2,
+            _ => 1,
+        }
+    }
+
+    /// number of public inputs of a proof, see `RLNInputs::public_inputs`
+    pub fn num_public_inputs(&self) -> usize {
+        match self {
+            IdentityScheme::PublicStake => 7,
+            _ => 6,
+        }
+    }
+}
+
+impl Default for IdentityScheme {
+    fn default() -> Self {
+        IdentityScheme::Single
+    }
+}
+
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
 pub struct RLNInputs<E>
 where
     E: Engine,
@@ -21,28 +82,175 @@ where
     // share, (x, y),
     // where x should be hash of the signal
     // and y is the evaluation
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr_option"))]
     pub share_x: Option<E::Fr>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr_option"))]
     pub share_y: Option<E::Fr>,
 
-    // epoch is the external nullifier
+    // epoch and rln_identifier hash to the external nullifier
     // we derive the line equation and the nullifier from epoch
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr_option"))]
     pub epoch: Option<E::Fr>,
 
     // nullifier
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr_option"))]
     pub nullifier: Option<E::Fr>,
 
     // root is the current state of membership set
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr_option"))]
     pub root: Option<E::Fr>,
 
+    // rln_identifier is a per deployment constant mixed into the external nullifier,
+    // so the same key and epoch give unrelated nullifiers across deployments
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr_option"))]
+    pub rln_identifier: Option<E::Fr>,
+
     // Private inputs
 
     // id_key must be a preimage of a leaf in membership tree.
     // id_key also together with epoch will be used to construct
     // a secret line equation together with the epoch
+    // with semaphore identities id_key is the trapdoor
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr_option"))]
     pub id_key: Option<E::Fr>,
 
+    // nullifier of a semaphore identity, unused by single secret identities
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, with = "crate::serde_utils::fr_option")
+    )]
+    pub id_nullifier: Option<E::Fr>,
+
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub identity_scheme: IdentityScheme,
+
+    // stake the leaf commits to, unused by schemes without stake
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, with = "crate::serde_utils::fr_option")
+    )]
+    pub stake: Option<E::Fr>,
+
     // authentication path of the member
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::auth_path"))]
     pub auth_path: Vec<Option<(E::Fr, bool)>>,
+
+    // authentication path of the member in a quad tree,
+    // three siblings and position at each level.
+    // when it is not empty `auth_path` is left empty
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::quad_auth_path"))]
+    pub quad_auth_path: Vec<Option<(Vec<E::Fr>, usize)>>,
+}
+
+/// secret `a_0` of a semaphore identity, `poseidon(nullifier, trapdoor)` of circomlib
+/// * semaphore identities are over bn256 only, other engines fail with `InvalidInput`
+pub fn identity_secret<E: Engine>(trapdoor: E::Fr, id_nullifier: E::Fr) -> io::Result<E::Fr> {
+    Ok(semaphore_hasher::<E>(2)?.hash(&[id_nullifier, trapdoor]))
+}
+
+/// leaf of a semaphore identity with the secret `a_0`, `poseidon(a_0)` of circomlib
+pub fn identity_commitment<E: Engine>(secret: E::Fr) -> io::Result<E::Fr> {
+    Ok(semaphore_hasher::<E>(1)?.hash(&[secret]))
+}
+
+/// hasher of semaphore membership trees, the circomlib poseidon of two inputs
+pub fn semaphore_tree_hasher<E: Engine>() -> io::Result<CircomPoseidon<E>> {
+    semaphore_hasher(2)
+}
+
+fn semaphore_hasher<E: Engine>(num_inputs: usize) -> io::Result<CircomPoseidon<E>> {
+    CircomPoseidon::new(num_inputs).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "semaphore identities are over bn256",
+        )
+    })
+}
+
+// gadget of `semaphore_hasher`, unsatisfiable over engines other than bn256
+fn semaphore_gadget<E: Engine>(
+    num_inputs: usize,
+) -> Result<CircomPoseidonCircuit<E>, SynthesisError> {
+    CircomPoseidon::new(num_inputs)
+        .map(CircomPoseidonCircuit::new)
+        .ok_or(SynthesisError::Unsatisfiable)
+}
+
+/// leaf of a member with `public_key` holding `stake`, `hash(public_key, stake)`
+pub fn staked_leaf<E: Engine, H: ZkHasher<E>>(
+    hasher: &H,
+    public_key: E::Fr,
+    stake: E::Fr,
+) -> E::Fr {
+    hasher.hash(vec![public_key, stake])
+}
+
+/// random identity of `identity_scheme` and the leaf it is registered with
+/// * secrets are `[a_0]` for `Single` and `[trapdoor, nullifier]` for `Semaphore`
+/// * staked schemes get the public key `hash(a_0)`, see `staked_leaf` for their leaf
+/// * semaphore identities fail with `InvalidInput` over engines other than bn256
+pub fn key_gen<E: Engine, H: ZkHasher<E>, R: Rng>(
+    hasher: &H,
+    identity_scheme: IdentityScheme,
+    rng: &mut R,
+) -> io::Result<(Vec<E::Fr>, E::Fr)> {
+    let secrets = (0..identity_scheme.num_secrets())
+        .map(|_| E::Fr::rand(rng))
+        .collect();
+    identity_of(hasher, identity_scheme, secrets)
+}
+
+/// domain separation tag of the chain of `derive_identity`
+pub const IDENTITY_CHAIN_DST: &[u8] = b"RLN-V1-IDENTITY-CHAIN_XMD:SHA-256_";
+/// domain separation tag `derive_identity` hashes the chain to secrets with
+pub const IDENTITY_SECRET_DST: &[u8] = b"RLN-V1-IDENTITY_XMD:SHA-256_HASH_TO_FIELD_";
+
+/// identity of `identity_scheme` derived from `master_seed` along `path`,
+/// in the layout of `key_gen`
+/// * the chain starts at `expand_message_xmd(master_seed)` and every index of the path
+/// is hashed into it, `expand_message_xmd(chain | index<4>)` with big endian indices
+/// * secrets are `hash_to_field_xmd(chain)`, so children of one seed are unrelated
+/// to each other and to the seed
+pub fn derive_identity<E: Engine, H: ZkHasher<E>>(
+    hasher: &H,
+    identity_scheme: IdentityScheme,
+    master_seed: &[u8],
+    path: &[u32],
+) -> io::Result<(Vec<E::Fr>, E::Fr)> {
+    let mut chain = expand_message_xmd(master_seed, IDENTITY_CHAIN_DST, 32);
+    for index in path.iter() {
+        chain.extend_from_slice(&index.to_be_bytes());
+        chain = expand_message_xmd(&chain, IDENTITY_CHAIN_DST, 32);
+    }
+    let secrets =
+        hash_to_field_xmd::<E>(&chain, IDENTITY_SECRET_DST, identity_scheme.num_secrets())?;
+    identity_of(hasher, identity_scheme, secrets)
+}
+
+// secrets and public key of an identity of `identity_scheme`
+fn identity_of<E: Engine, H: ZkHasher<E>>(
+    hasher: &H,
+    identity_scheme: IdentityScheme,
+    secrets: Vec<E::Fr>,
+) -> io::Result<(Vec<E::Fr>, E::Fr)> {
+    let public = match identity_scheme {
+        IdentityScheme::Single | IdentityScheme::Staked | IdentityScheme::PublicStake => {
+            hasher.hash(vec![secrets[0]])
+        }
+        IdentityScheme::Semaphore => {
+            identity_commitment::<E>(identity_secret::<E>(secrets[0], secrets[1])?)?
+        }
+    };
+    Ok((secrets, public))
+}
+
+/// epoch bound to the deployment, `hash(epoch, rln_identifier)`
+pub fn external_nullifier<E: Engine, H: ZkHasher<E>>(
+    hasher: &H,
+    epoch: E::Fr,
+    rln_identifier: E::Fr,
+) -> E::Fr {
+    hasher.hash(vec![epoch, rln_identifier])
 }
 
 impl<E> RLNInputs<E>
@@ -50,28 +258,62 @@ where
     E: Engine,
 {
     pub fn public_inputs(&self) -> Vec<E::Fr> {
-        vec![
+        let mut public_inputs = vec![
             self.root.unwrap(),
             self.epoch.unwrap(),
             self.share_x.unwrap(),
             self.share_y.unwrap(),
             self.nullifier.unwrap(),
-        ]
+            self.rln_identifier.unwrap(),
+        ];
+        if self.identity_scheme == IdentityScheme::PublicStake {
+            public_inputs.push(self.stake.unwrap());
+        }
+        public_inputs
     }
 
     pub fn merkle_depth(&self) -> usize {
-        self.auth_path.len()
+        self.auth_path.len() + self.quad_auth_path.len()
+    }
+
+    pub fn arity(&self) -> TreeArity {
+        if self.quad_auth_path.is_empty() {
+            TreeArity::Binary
+        } else {
+            TreeArity::Quad
+        }
     }
 
     pub fn empty(merkle_depth: usize) -> RLNInputs<E> {
+        Self::empty_with_arity(merkle_depth, TreeArity::Binary)
+    }
+
+    pub fn empty_with_arity(merkle_depth: usize, arity: TreeArity) -> RLNInputs<E> {
+        Self::empty_with_identity(merkle_depth, arity, IdentityScheme::Single)
+    }
+
+    pub fn empty_with_identity(
+        merkle_depth: usize,
+        arity: TreeArity,
+        identity_scheme: IdentityScheme,
+    ) -> RLNInputs<E> {
+        let (auth_path, quad_auth_path) = match arity {
+            TreeArity::Binary => (vec![None; merkle_depth], vec![]),
+            TreeArity::Quad => (vec![], vec![None; merkle_depth]),
+        };
         RLNInputs::<E> {
             share_x: None,
             share_y: None,
             epoch: None,
             nullifier: None,
             root: None,
+            rln_identifier: None,
             id_key: None,
-            auth_path: vec![None; merkle_depth],
+            id_nullifier: None,
+            identity_scheme,
+            stake: None,
+            auth_path,
+            quad_auth_path,
         }
     }
 
@@ -98,15 +340,53 @@ where
         buf.read_le(&mut reader)?;
         let id_key =
             E::Fr::from_repr(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        buf.read_le(&mut reader)?;
+        let rln_identifier =
+            E::Fr::from_repr(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut byte_buf = [0u8; 1];
+        reader.read_exact(&mut byte_buf)?;
+        let (identity_scheme, id_nullifier) = match byte_buf[0] {
+            0 => (IdentityScheme::Single, None),
+            1 => {
+                buf.read_le(&mut reader)?;
+                let id_nullifier = E::Fr::from_repr(buf)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                (IdentityScheme::Semaphore, Some(id_nullifier))
+            }
+            2 => (IdentityScheme::Staked, None),
+            3 => (IdentityScheme::PublicStake, None),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "unknown identity scheme",
+                ))
+            }
+        };
+        let stake = if identity_scheme.is_staked() {
+            Some(read_fr::<_, E>(&mut reader, 1)?[0])
+        } else {
+            None
+        };
         let auth_path = Self::decode_auth_path(&mut reader)?;
+        // an empty binary path is followed by a quad path
+        let quad_auth_path = if auth_path.is_empty() {
+            Self::decode_quad_auth_path(&mut reader)?
+        } else {
+            vec![]
+        };
         Ok(RLNInputs {
             share_x: Some(share_x),
             share_y: Some(share_y),
             epoch: Some(epoch),
             nullifier: Some(nullifier),
             root: Some(root),
+            rln_identifier: Some(rln_identifier),
             id_key: Some(id_key),
+            id_nullifier,
+            identity_scheme,
+            stake,
             auth_path,
+            quad_auth_path,
         })
     }
 
@@ -141,28 +421,38 @@ where
             .into_repr()
             .write_le(&mut writer)
             .unwrap();
+        self.rln_identifier
+            .unwrap()
+            .into_repr()
+            .write_le(&mut writer)
+            .unwrap();
+        match self.identity_scheme {
+            IdentityScheme::Single => writer.write_all(&[0])?,
+            IdentityScheme::Semaphore => {
+                writer.write_all(&[1])?;
+                self.id_nullifier
+                    .unwrap()
+                    .into_repr()
+                    .write_le(&mut writer)?;
+            }
+            IdentityScheme::Staked => writer.write_all(&[2])?,
+            IdentityScheme::PublicStake => writer.write_all(&[3])?,
+        }
+        if self.identity_scheme.is_staked() {
+            self.stake.unwrap().into_repr().write_le(&mut writer)?;
+        }
         Self::encode_auth_path(&mut writer, self.auth_path.clone()).unwrap();
+        if self.auth_path.is_empty() {
+            Self::encode_quad_auth_path(&mut writer, self.quad_auth_path.clone())?;
+        }
         Ok(())
     }
 
-    pub fn read_public_inputs<R: Read>(mut reader: R) -> io::Result<Vec<E::Fr>> {
-        let mut buf = <E::Fr as PrimeField>::Repr::default();
-        buf.read_le(&mut reader)?;
-        let root =
-            E::Fr::from_repr(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        buf.read_le(&mut reader)?;
-        let epoch =
-            E::Fr::from_repr(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        buf.read_le(&mut reader)?;
-        let share_x =
-            E::Fr::from_repr(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        buf.read_le(&mut reader)?;
-        let share_y =
-            E::Fr::from_repr(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        buf.read_le(&mut reader)?;
-        let nullifier =
-            E::Fr::from_repr(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        Ok(vec![root, epoch, share_x, share_y, nullifier])
+    /// reads the public inputs that follow a proof,
+    /// `rln_identifier` is not part of proof data as verifiers know it upfront
+    /// * non canonical field elements are rejected
+    pub fn read_public_inputs<R: Read>(reader: R) -> io::Result<Vec<E::Fr>> {
+        read_fr::<_, E>(reader, 5)
     }
 
     pub fn write_public_inputs<W: Write>(&self, mut writer: W) -> io::Result<()> {
@@ -198,7 +488,8 @@ where
         let mut auth_path: Vec<Option<(E::Fr, bool)>> = vec![];
         reader.read_exact(&mut byte_buf)?;
         let path_len = byte_buf[0];
-        if path_len < 2 {
+        // zero length is allowed and marks that a quad path follows
+        if path_len == 1 {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
                 "invalid path length",
@@ -223,20 +514,195 @@ where
         }
         Ok(auth_path)
     }
+
+    pub fn encode_quad_auth_path<W: Write>(
+        mut writer: W,
+        auth_path: Vec<Option<(Vec<E::Fr>, usize)>>,
+    ) -> io::Result<()> {
+        let path_len = auth_path.len() as u8;
+        writer.write(&[path_len])?;
+        for el in auth_path.into_iter() {
+            let (siblings, position) = el.unwrap();
+            writer.write(&[position as u8])?;
+            for sibling in siblings.iter() {
+                sibling.into_repr().write_le(&mut writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn decode_quad_auth_path<R: Read>(
+        mut reader: R,
+    ) -> io::Result<Vec<Option<(Vec<E::Fr>, usize)>>> {
+        let mut byte_buf = vec![0u8; 1];
+        let mut el_buf = <E::Fr as PrimeField>::Repr::default();
+        let mut auth_path: Vec<Option<(Vec<E::Fr>, usize)>> = vec![];
+        reader.read_exact(&mut byte_buf)?;
+        let path_len = byte_buf[0];
+        if path_len < 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid path length",
+            ));
+        }
+        for _ in 0..path_len {
+            reader.read_exact(&mut byte_buf)?;
+            let position = byte_buf[0] as usize;
+            if position > 3 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "invalid path position",
+                ));
+            }
+            let mut siblings: Vec<E::Fr> = Vec::with_capacity(3);
+            for _ in 0..3 {
+                el_buf.read_le(&mut reader)?;
+                let node = E::Fr::from_repr(el_buf)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                siblings.push(node);
+            }
+            auth_path.push(Some((siblings, position)));
+        }
+        Ok(auth_path)
+    }
 }
 
+/// `hasher` is the gadget of the hash the membership tree is built with
 #[derive(Clone)]
-pub struct RLNCircuit<E>
+pub struct RLNCircuit<E, H = PoseidonCircuit<E>>
 where
     E: Engine,
+    H: ZkHasherGadget<E>,
 {
     pub inputs: RLNInputs<E>,
-    pub hasher: PoseidonCircuit<E>,
+    pub hasher: H,
+}
+
+/// size of the constraint system a circuit synthesizes to
+/// * `num_inputs` includes the constant one input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircuitShape {
+    pub num_inputs: usize,
+    pub num_aux: usize,
+    pub num_constraints: usize,
+}
+
+impl CircuitShape {
+    /// evaluation domain groth16 parameters are generated over,
+    /// one input constraint is added for each public input
+    pub fn domain_size(&self) -> usize {
+        (self.num_constraints + self.num_inputs).next_power_of_two()
+    }
+}
+
+impl<E, H> RLNCircuit<E, H>
+where
+    E: Engine,
+    H: ZkHasherGadget<E>,
+{
+    /// synthesizes the circuit without witness values and counts variables and constraints
+    pub fn shape(self) -> Result<CircuitShape, SynthesisError> {
+        let mut cs = ShapeCS {
+            shape: CircuitShape {
+                num_inputs: 1,
+                num_aux: 0,
+                num_constraints: 0,
+            },
+        };
+        self.synthesize(&mut cs)?;
+        Ok(cs.shape)
+    }
+
+    /// synthesizes the circuit with its witness and reports what does not hold
+    #[cfg(feature = "debug")]
+    pub fn diagnose(self) -> Result<CircuitDiagnostics, SynthesisError> {
+        use sapling_crypto::circuit::test::TestConstraintSystem;
+        let mut cs = TestConstraintSystem::<E>::new();
+        self.synthesize(&mut cs)?;
+        Ok(CircuitDiagnostics {
+            num_constraints: cs.num_constraints(),
+            unsatisfied: cs.which_is_unsatisfied().map(|path| path.to_string()),
+            unconstrained: cs.find_unconstrained(),
+        })
+    }
+}
+
+/// result of `RLNCircuit::diagnose`
+/// * `unsatisfied` is the path of the first constraint that does not hold
+/// * `unconstrained` lists variables no constraint refers to, empty if there are none
+#[cfg(feature = "debug")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CircuitDiagnostics {
+    pub num_constraints: usize,
+    pub unsatisfied: Option<String>,
+    pub unconstrained: String,
+}
+
+#[cfg(feature = "debug")]
+impl CircuitDiagnostics {
+    pub fn is_ok(&self) -> bool {
+        self.unsatisfied.is_none() && self.unconstrained.is_empty()
+    }
+}
+
+struct ShapeCS {
+    shape: CircuitShape,
 }
 
-impl<E> Circuit<E> for RLNCircuit<E>
+impl<E: Engine> ConstraintSystem<E> for ShapeCS {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, _: A, _: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.shape.num_aux += 1;
+        Ok(Variable::new_unchecked(Index::Aux(self.shape.num_aux - 1)))
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, _: A, _: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.shape.num_inputs += 1;
+        Ok(Variable::new_unchecked(Index::Input(
+            self.shape.num_inputs - 1,
+        )))
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, _: A, _: LA, _: LB, _: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LB: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LC: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+    {
+        self.shape.num_constraints += 1;
+    }
+
+    fn push_namespace<NR, N>(&mut self, _: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+    }
+
+    fn pop_namespace(&mut self) {}
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+impl<E, H> Circuit<E> for RLNCircuit<E, H>
 where
     E: Engine,
+    H: ZkHasherGadget<E>,
 {
     fn synthesize<CS: ConstraintSystem<E>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
         // 1. Part
@@ -249,44 +715,79 @@ where
         })?;
         root.inputize(cs.namespace(|| "root is public"))?;
 
-        let preimage = num::AllocatedNum::alloc(cs.namespace(|| "preimage"), || {
+        let id_key = num::AllocatedNum::alloc(cs.namespace(|| "preimage"), || {
             let value = self.inputs.id_key;
             Ok(*value.get()?)
         })?;
 
-        // identity is a leaf of membership tree
+        // identity is a leaf of membership tree,
+        // semaphore identities derive the secret from trapdoor and nullifier
+        // and commit to it with the circomlib poseidon
 
-        let identity = self
-            .hasher
-            .alloc(cs.namespace(|| "identity"), vec![preimage.clone()])?;
-
-        // accumulator up to the root
-
-        let mut acc = identity.clone();
+        let (preimage, identity) = match self.inputs.identity_scheme {
+            IdentityScheme::Single | IdentityScheme::Staked | IdentityScheme::PublicStake => {
+                let identity = self
+                    .hasher
+                    .alloc(cs.namespace(|| "identity"), vec![id_key.clone()])?;
+                (id_key, identity)
+            }
+            IdentityScheme::Semaphore => {
+                let id_nullifier =
+                    num::AllocatedNum::alloc(cs.namespace(|| "identity nullifier"), || {
+                        let value = self.inputs.id_nullifier;
+                        Ok(*value.get()?)
+                    })?;
+                let secret = semaphore_gadget(2)?.alloc(
+                    cs.namespace(|| "identity secret"),
+                    vec![id_nullifier, id_key],
+                )?;
+                let identity = semaphore_gadget(1)?
+                    .alloc(cs.namespace(|| "identity"), vec![secret.clone()])?;
+                (secret, identity)
+            }
+        };
 
-        // ascend the tree
+        // staked leaves commit to the stake as well
 
-        let auth_path_witness = self.inputs.auth_path.clone();
-        for (i, e) in auth_path_witness.into_iter().enumerate() {
-            let cs = &mut cs.namespace(|| format!("auth path {}", i));
-            let position = boolean::Boolean::from(boolean::AllocatedBit::alloc(
-                cs.namespace(|| "position bit"),
-                e.map(|e| e.1),
-            )?);
-            let path_element =
-                num::AllocatedNum::alloc(cs.namespace(|| "path element"), || Ok(e.get()?.0))?;
+        let stake = if self.inputs.identity_scheme.is_staked() {
+            Some(num::AllocatedNum::alloc(cs.namespace(|| "stake"), || {
+                let value = self.inputs.stake;
+                Ok(*value.get()?)
+            })?)
+        } else {
+            None
+        };
+        let identity = match stake.clone() {
+            Some(stake) => self
+                .hasher
+                .alloc(cs.namespace(|| "staked identity"), vec![identity, stake])?,
+            None => identity,
+        };
 
-            let (xr, xl) = num::AllocatedNum::conditionally_reverse(
-                cs.namespace(|| "conditional reversal of preimage"),
-                &acc,
-                &path_element,
-                &position,
-            )?;
+        // accumulator up to the root,
+        // semaphore trees are binary and hashed with the circomlib poseidon
 
-            acc = self
-                .hasher
-                .alloc(cs.namespace(|| "hash couple"), vec![xl, xr])?;
-        }
+        let acc = match self.inputs.identity_scheme {
+            IdentityScheme::Semaphore => {
+                if !self.inputs.quad_auth_path.is_empty() {
+                    return Err(SynthesisError::Unsatisfiable);
+                }
+                merkle_root(
+                    cs.namespace(|| "merkle root"),
+                    &semaphore_gadget(2)?,
+                    &identity,
+                    &self.inputs.auth_path,
+                    &[],
+                )?
+            }
+            _ => merkle_root(
+                cs.namespace(|| "merkle root"),
+                &self.hasher,
+                &identity,
+                &self.inputs.auth_path,
+                &self.inputs.quad_auth_path,
+            )?,
+        };
 
         // see if it is a member
 
@@ -299,7 +800,7 @@ where
 
         // 2. Part
         // Line Equation Constaints
-        // a_1 = hash(a_0, epoch)
+        // a_1 = hash(a_0, hash(epoch, rln_identifier))
         // share_y == a_0 + a_1 * share_x
 
         let epoch = num::AllocatedNum::alloc(cs.namespace(|| "epoch"), || {
@@ -308,13 +809,25 @@ where
         })?;
         epoch.inputize(cs.namespace(|| "epoch is public"))?;
 
+        // inputized after the nullifier, so it is the last public input
+        let rln_identifier = num::AllocatedNum::alloc(cs.namespace(|| "rln identifier"), || {
+            let value = self.inputs.rln_identifier.clone();
+            Ok(*value.get()?)
+        })?;
+
+        let external_nullifier = self.hasher.alloc(
+            cs.namespace(|| "external nullifier"),
+            vec![epoch, rln_identifier.clone()],
+        )?;
+
         let a_0 = preimage.clone();
 
-        // a_1 == h(a_0, epoch)
+        // a_1 == h(a_0, external_nullifier)
 
-        let a_1 = self
-            .hasher
-            .alloc(cs.namespace(|| "a_1"), vec![a_0.clone(), epoch])?;
+        let a_1 = self.hasher.alloc(
+            cs.namespace(|| "a_1"),
+            vec![a_0.clone(), external_nullifier],
+        )?;
 
         let share_x = num::AllocatedNum::alloc(cs.namespace(|| "share x"), || {
             let value = self.inputs.share_x.clone();
@@ -345,7 +858,7 @@ where
         // Nullifier constraints
 
         // hashing secret twice with epoch ingredient
-        // a_1 == hash(a_0, epoch) is already constrained
+        // a_1 == hash(a_0, external_nullifier) is already constrained
 
         // nullifier == hash(a_1)
 
@@ -368,6 +881,42 @@ where
             |lc| lc + nullifier.get_variable(),
         );
 
+        rln_identifier.inputize(cs.namespace(|| "rln identifier is public"))?;
+
+        if let (IdentityScheme::PublicStake, Some(stake)) = (self.inputs.identity_scheme, stake) {
+            stake.inputize(cs.namespace(|| "stake is public"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// several messages in one proof, each synthesized as an `RLNCircuit`
+/// * members and epochs may differ between messages
+/// * public inputs are the ones of `RLNCircuit` for each message in order
+#[derive(Clone)]
+pub struct RLNBatchCircuit<E, H = PoseidonCircuit<E>>
+where
+    E: Engine,
+    H: ZkHasherGadget<E>,
+{
+    pub inputs: Vec<RLNInputs<E>>,
+    pub hasher: H,
+}
+
+impl<E, H> Circuit<E> for RLNBatchCircuit<E, H>
+where
+    E: Engine,
+    H: ZkHasherGadget<E>,
+{
+    fn synthesize<CS: ConstraintSystem<E>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        for (i, inputs) in self.inputs.into_iter().enumerate() {
+            let circuit = RLNCircuit {
+                inputs,
+                hasher: self.hasher.clone(),
+            };
+            circuit.synthesize(&mut cs.namespace(|| format!("message {}", i)))?;
+        }
         Ok(())
     }
 }
@@ -437,9 +986,94 @@ mod test {
             println!("number of constatins:\t{}", num_constraints);
             println!("prover key size:\t{}", result.prover_key_size);
             println!("prover time:\t{}", result.prover_time);
+            println!("verifier time:\t{}", result.verifier_time);
+            assert_eq!(result.num_constraints, num_constraints);
+            assert!(result.to_json().contains("\"proof_size\":256"));
+            assert_eq!(
+                result.to_csv().split(',').count(),
+                bench::BenchResult::csv_header().split(',').count()
+            );
+        }
+    }
+
+    #[test]
+    fn test_rln_bench_sweep() {
+        use sapling_crypto::bellman::pairing::bn256::Bn256;
+        let poseidon_variants = vec![
+            PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None),
+            PoseidonParams::<Bn256>::new(8, 57, 5, None, None, None),
+        ];
+        let results = bench::run_rln_bench_sweep(&[2, 3], &poseidon_variants);
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[1].merkle_depth, 3);
+        assert_eq!(results[2].poseidon_width, 5);
+        assert!(results[0].num_constraints < results[1].num_constraints);
+        let csv = bench::bench_results_to_csv(&results);
+        assert_eq!(csv.lines().count(), 5);
+        println!("{}", csv);
+    }
+
+    #[test]
+    fn test_rln_quad_bn() {
+        use crate::merkle::TreeArity;
+        use sapling_crypto::bellman::pairing::bn256::Bn256;
+        let poseidon_parameters = PoseidonParams::<Bn256>::new(8, 60, 5, None, None, None);
+        for merkle_depth in [2usize, 8].iter() {
+            let rln_test = bench::RLNTest::<Bn256>::new_with_arity(
+                *merkle_depth,
+                TreeArity::Quad,
+                Some(poseidon_parameters.clone()),
+            );
+            let num_constraints = rln_test.synthesize();
+            let result = rln_test.run_prover_bench();
+            println!("bn256, t: 5, quad merkle depth: {}", merkle_depth);
+            println!("number of constatins:\t{}", num_constraints);
+            println!("prover time:\t{}", result.prover_time);
         }
     }
 
+    #[test]
+    fn test_derive_identity() {
+        use super::{derive_identity, IdentityScheme};
+        use crate::poseidon::{Poseidon, PoseidonParams};
+        use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+        use sapling_crypto::bellman::pairing::ff::PrimeField;
+
+        let hasher = Poseidon::new(PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None));
+        let seed = b"correct horse battery staple";
+        let derive =
+            |path: &[u32]| derive_identity(&hasher, IdentityScheme::Single, seed, path).unwrap();
+        let (secrets, public) = derive(&[0, 1]);
+        // pins the derivation scheme, changing it loses every backed up identity
+        assert_eq!(
+            secrets,
+            vec![Fr::from_str(
+                "3780322781838693255975357783562270221363609175761021670451863190898296093787"
+            )
+            .unwrap()]
+        );
+        assert_eq!(
+            public,
+            Fr::from_str(
+                "4106605879850729834895911769813316174778023880660140003069835291275163677854"
+            )
+            .unwrap()
+        );
+        assert_eq!((secrets.clone(), public), derive(&[0, 1]));
+        assert_eq!(hasher.hash(secrets.clone()), public);
+        assert_ne!(derive(&[0, 2]).1, public);
+        assert_ne!(derive(&[0]).1, public);
+        assert_ne!(derive(&[1, 0]).1, public);
+        let other =
+            derive_identity(&hasher, IdentityScheme::Single, b"other seed", &[0, 1]).unwrap();
+        assert_ne!(other.1, public);
+
+        let (secrets, _) =
+            derive_identity(&hasher, IdentityScheme::Semaphore, seed, &[0, 1]).unwrap();
+        assert_eq!(secrets.len(), 2);
+        assert_ne!(secrets[0], secrets[1]);
+    }
+
     #[test]
     fn test_input_serialization() {
         use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
@@ -450,6 +1084,7 @@ mod test {
         let nullifier = Fr::from_str("4").unwrap();
         let root = Fr::from_str("5").unwrap();
         let id_key = Fr::from_str("6").unwrap();
+        let rln_identifier = Fr::from_str("7").unwrap();
         let auth_path = vec![
             Some((Fr::from_str("20").unwrap(), false)),
             Some((Fr::from_str("21").unwrap(), true)),
@@ -462,8 +1097,13 @@ mod test {
             epoch: Some(epoch),
             nullifier: Some(nullifier),
             root: Some(root),
+            rln_identifier: Some(rln_identifier),
             id_key: Some(id_key),
+            id_nullifier: Some(Fr::from_str("8").unwrap()),
+            identity_scheme: super::IdentityScheme::Semaphore,
+            stake: None,
             auth_path,
+            quad_auth_path: vec![],
         };
         let mut raw_inputs: Vec<u8> = Vec::new();
         input0.write(&mut raw_inputs).unwrap();
@@ -475,6 +1115,9 @@ mod test {
         assert_eq!(input0.nullifier, input1.nullifier);
         assert_eq!(input0.root, input1.root);
         assert_eq!(input0.id_key, input1.id_key);
+        assert_eq!(input0.rln_identifier, input1.rln_identifier);
+        assert_eq!(input0.id_nullifier, input1.id_nullifier);
+        assert_eq!(input0.identity_scheme, input1.identity_scheme);
         assert_eq!(input0.auth_path, input1.auth_path);
     }
 }
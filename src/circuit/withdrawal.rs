@@ -0,0 +1,123 @@
+//! Exit of a member from the membership set.
+//!
+//! Proves knowledge of the secret `a_0` behind a public key, `public_key == hash(a_0)`,
+//! without a root, epoch or nullifier, so it links to none of the messages of the member.
+//! The receiver of the deposit is a public input, a proof taken from the mempool
+//! can not be replayed to another receiver.
+
+use crate::circuit::poseidon::PoseidonCircuit;
+use crate::hasher::ZkHasherGadget;
+use sapling_crypto::bellman::pairing::Engine;
+use sapling_crypto::bellman::{Circuit, ConstraintSystem, SynthesisError};
+use sapling_crypto::circuit::{num, Assignment};
+
+#[derive(Clone)]
+pub struct WithdrawalInputs<E: Engine> {
+    // Public inputs
+
+    // public key of the member, `hash(a_0)`
+    pub public_key: Option<E::Fr>,
+
+    // e.g. the address deposits are sent to
+    pub receiver: Option<E::Fr>,
+
+    // Private inputs
+    pub secret: Option<E::Fr>,
+}
+
+impl<E: Engine> WithdrawalInputs<E> {
+    pub fn empty() -> WithdrawalInputs<E> {
+        WithdrawalInputs {
+            public_key: None,
+            receiver: None,
+            secret: None,
+        }
+    }
+
+    /// [ public_key, receiver ]
+    pub fn public_inputs(&self) -> Vec<E::Fr> {
+        vec![self.public_key.unwrap(), self.receiver.unwrap()]
+    }
+}
+
+#[derive(Clone)]
+pub struct WithdrawalCircuit<E, H = PoseidonCircuit<E>>
+where
+    E: Engine,
+    H: ZkHasherGadget<E>,
+{
+    pub inputs: WithdrawalInputs<E>,
+    pub hasher: H,
+}
+
+impl<E, H> Circuit<E> for WithdrawalCircuit<E, H>
+where
+    E: Engine,
+    H: ZkHasherGadget<E>,
+{
+    fn synthesize<CS: ConstraintSystem<E>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let secret = num::AllocatedNum::alloc(cs.namespace(|| "secret"), || {
+            let value = self.inputs.secret;
+            Ok(*value.get()?)
+        })?;
+
+        let public_key_calculated = self
+            .hasher
+            .alloc(cs.namespace(|| "calculated public key"), vec![secret])?;
+
+        let public_key = num::AllocatedNum::alloc(cs.namespace(|| "public key"), || {
+            let value = self.inputs.public_key;
+            Ok(*value.get()?)
+        })?;
+        public_key.inputize(cs.namespace(|| "public key is public"))?;
+
+        cs.enforce(
+            || "enforce public key",
+            |lc| lc + public_key_calculated.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc + public_key.get_variable(),
+        );
+
+        let receiver = num::AllocatedNum::alloc(cs.namespace(|| "receiver"), || {
+            let value = self.inputs.receiver;
+            Ok(*value.get()?)
+        })?;
+        receiver.inputize(cs.namespace(|| "receiver is public"))?;
+
+        // ties the receiver to the proof with a constraint of its own
+        receiver.square(cs.namespace(|| "receiver squared"))?;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_withdrawal_circuit() {
+    use crate::poseidon::{Poseidon, PoseidonParams};
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    use sapling_crypto::bellman::pairing::ff::{Field, PrimeField};
+    use sapling_crypto::circuit::test::TestConstraintSystem;
+
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Poseidon::new(params.clone());
+    let secret = Fr::from_str("1001").unwrap();
+    let inputs = WithdrawalInputs::<Bn256> {
+        public_key: Some(hasher.hash(vec![secret])),
+        receiver: Some(Fr::from_str("7").unwrap()),
+        secret: Some(secret),
+    };
+    let satisfied = |inputs: WithdrawalInputs<Bn256>| {
+        let mut cs = TestConstraintSystem::<Bn256>::new();
+        let circuit = WithdrawalCircuit {
+            inputs,
+            hasher: PoseidonCircuit::new(params.clone()),
+        };
+        circuit.synthesize(&mut cs).unwrap();
+        assert_eq!(cs.num_inputs(), 3);
+        cs.is_satisfied()
+    };
+    assert!(satisfied(inputs.clone()));
+    let mut wrong = inputs;
+    wrong.secret.as_mut().unwrap().add_assign(&Fr::one());
+    assert!(!satisfied(wrong));
+}
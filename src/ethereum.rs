@@ -0,0 +1,120 @@
+//! Calldata for standard Groth16 Solidity verifiers over bn256.
+//!
+//! Verifiers generated by snarkjs or ZoKrates take
+//! `(uint256[2] a, uint256[2][2] b, uint256[2] c, uint256[k] inputs)`.
+//! G2 coordinates are written with the imaginary part first, which is the order
+//! the EIP-197 pairing precompile expects.
+
+use crate::bundle::{BundleHeader, CURVE_ID_BN256};
+use crate::circuit::rln::RLNInputs;
+use crate::utils::{read_proof, ProofFormat};
+use crate::verifier_core::PublicInputLayout;
+use bellman::groth16::Proof;
+use bellman::pairing::bn256::{Bn256, Fq, Fr, G1Affine, G2Affine};
+use bellman::pairing::ff::{PrimeField, PrimeFieldRepr};
+use bellman::pairing::CurveAffine;
+use std::io::{self, Read};
+
+/// abi encodes a proof and its public inputs, 32 bytes per word, without a function selector
+pub fn encode_groth16_calldata(proof: &Proof<Bn256>, public_inputs: &[Fr]) -> Vec<u8> {
+    let mut calldata: Vec<u8> = Vec::with_capacity((8 + public_inputs.len()) * 32);
+    write_g1(&mut calldata, &proof.a);
+    write_g2(&mut calldata, &proof.b);
+    write_g1(&mut calldata, &proof.c);
+    for input in public_inputs.iter() {
+        input.into_repr().write_be(&mut calldata).unwrap();
+    }
+    calldata
+}
+
+/// abi encodes a `generate_proof` output,
+/// inputs are [ root, epoch, share_x, share_y, nullifier, rln_identifier ]
+/// in the order of the circuit
+pub fn encode_proof_calldata<R: Read>(
+    proof_data: R,
+    proof_format: ProofFormat,
+    rln_identifier: Fr,
+) -> io::Result<Vec<u8>> {
+    encode_proof_calldata_with_layout(
+        proof_data,
+        proof_format,
+        rln_identifier,
+        &PublicInputLayout::native(),
+    )
+}
+
+/// same as `encode_proof_calldata` with the inputs in the order of `layout`,
+/// e.g. for verifiers exported from a circom deployment
+pub fn encode_proof_calldata_with_layout<R: Read>(
+    mut proof_data: R,
+    proof_format: ProofFormat,
+    rln_identifier: Fr,
+    layout: &PublicInputLayout,
+) -> io::Result<Vec<u8>> {
+    let proof = read_proof::<_, Bn256>(&mut proof_data, proof_format)?;
+    let mut public_inputs = RLNInputs::<Bn256>::read_public_inputs(proof_data)?;
+    public_inputs.push(rln_identifier);
+    let public_inputs = layout.arrange(&public_inputs)?;
+    Ok(encode_groth16_calldata(&proof, &public_inputs))
+}
+
+/// abi encodes a `generate_proof_bundle` output
+pub fn encode_bundle_calldata<R: Read>(mut bundle: R, rln_identifier: Fr) -> io::Result<Vec<u8>> {
+    let header = BundleHeader::read(&mut bundle)?;
+    if header.curve_id != CURVE_ID_BN256 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "solidity verifiers expect bn256 proofs",
+        ));
+    }
+    encode_proof_calldata(bundle, header.proof_format, rln_identifier)
+}
+
+fn write_fq(calldata: &mut Vec<u8>, fq: Fq) {
+    fq.into_repr().write_be(calldata).unwrap();
+}
+
+fn write_g1(calldata: &mut Vec<u8>, point: &G1Affine) {
+    let (x, y) = point.into_xy_unchecked();
+    write_fq(calldata, x);
+    write_fq(calldata, y);
+}
+
+// [[x.c1, x.c0], [y.c1, y.c0]]
+fn write_g2(calldata: &mut Vec<u8>, point: &G2Affine) {
+    let (x, y) = point.into_xy_unchecked();
+    write_fq(calldata, x.c1);
+    write_fq(calldata, x.c0);
+    write_fq(calldata, y.c1);
+    write_fq(calldata, y.c0);
+}
+
+#[test]
+fn test_calldata_layout() {
+    use bellman::pairing::ff::Field;
+    let proof = Proof::<Bn256> {
+        a: G1Affine::one(),
+        b: G2Affine::one(),
+        c: G1Affine::one(),
+    };
+    let public_inputs = vec![Fr::one(), Fr::zero()];
+    let calldata = encode_groth16_calldata(&proof, &public_inputs);
+    assert_eq!(calldata.len(), 10 * 32);
+
+    let word = |i: usize| &calldata[i * 32..(i + 1) * 32];
+    let mut one = [0u8; 32];
+    one[31] = 1;
+    let mut two = [0u8; 32];
+    two[31] = 2;
+    // bn256 g1 generator is (1, 2)
+    assert_eq!(word(0), &one[..]);
+    assert_eq!(word(1), &two[..]);
+    assert_eq!(word(6), &one[..]);
+    assert_eq!(word(8), &one[..]);
+    assert_eq!(word(9), &[0u8; 32][..]);
+
+    let (x, _) = G2Affine::one().into_xy_unchecked();
+    let mut x_c1: Vec<u8> = Vec::new();
+    x.c1.into_repr().write_be(&mut x_c1).unwrap();
+    assert_eq!(word(2), x_c1.as_slice());
+}
@@ -1,7 +1,19 @@
-use crate::{circuit::rln, public::RLN};
+use crate::{
+    circuit::rln,
+    hash_to_field::SignalHasher,
+    merkle::{MemberEvent, TreeArity},
+    nullifier::LogStatus,
+    public::{self, RLNVerifier, RLN},
+    utils::{read_fr, seeded_rng, ProofFormat},
+};
 use bellman::pairing::bn256::Bn256;
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
 use std::slice;
 
+// `rln.h` is generated from this module with the `c-header` feature,
+// `RLN<Bn256>` handles become the opaque `RLN_Bn256` struct there.
+
 /// Buffer struct is taken from
 /// https://github.com/celo-org/celo-threshold-bls-rs/blob/master/crates/threshold-bls-ffi/src/ffi.rs
 
@@ -15,7 +27,7 @@ pub struct Buffer {
 impl From<&[u8]> for Buffer {
     fn from(src: &[u8]) -> Self {
         Self {
-            ptr: &src[0] as *const u8,
+            ptr: src.as_ptr(),
             len: src.len(),
         }
     }
@@ -23,6 +35,10 @@ impl From<&[u8]> for Buffer {
 
 impl<'a> From<&Buffer> for &'a [u8] {
     fn from(src: &Buffer) -> &'a [u8] {
+        // callers may pass a null pointer for empty input
+        if src.ptr.is_null() || src.len == 0 {
+            return &[];
+        }
         unsafe { slice::from_raw_parts(src.ptr, src.len) }
     }
 }
@@ -30,7 +46,7 @@ impl<'a> From<&Buffer> for &'a [u8] {
 #[derive(Clone, Debug, PartialEq)]
 pub struct Auth {
     secret_buffer: *const Buffer,
-    pub index: usize,
+    pub index: u64,
 }
 
 impl Auth {
@@ -47,7 +63,37 @@ pub extern "C" fn new_circuit_from_params(
     ctx: *mut *mut RLN<Bn256>,
 ) -> bool {
     let buffer = <&[u8]>::from(unsafe { &*parameters_buffer });
-    let rln = match RLN::<Bn256>::new_with_raw_params(merkle_depth, buffer, None) {
+    let rln = match RLN::<Bn256>::new_with_raw_params(
+        merkle_depth,
+        TreeArity::Binary,
+        buffer,
+        None,
+        None,
+    ) {
+        Ok(rln) => rln,
+        Err(_) => return false,
+    };
+    unsafe { *ctx = Box::into_raw(Box::new(rln)) };
+    true
+}
+
+/// same as `new_circuit_from_params` with the parameters read from the file at `path`,
+/// a nul terminated utf-8 string, see `RLN::new_with_params_file`
+#[no_mangle]
+pub extern "C" fn new_circuit_from_params_file(
+    merkle_depth: usize,
+    path: *const c_char,
+    ctx: *mut *mut RLN<Bn256>,
+) -> bool {
+    if path.is_null() {
+        return false;
+    }
+    let path = match unsafe { CStr::from_ptr(path) }.to_str() {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    let rln = match RLN::<Bn256>::new_with_params_file(merkle_depth, TreeArity::Binary, path, None)
+    {
         Ok(rln) => rln,
         Err(_) => return false,
     };
@@ -55,6 +101,153 @@ pub extern "C" fn new_circuit_from_params(
     true
 }
 
+/// verifier only handle built from `export_verifier_key` output
+#[no_mangle]
+pub extern "C" fn new_verifier_from_vk(
+    vk_buffer: *const Buffer,
+    ctx: *mut *mut RLNVerifier<Bn256>,
+) -> bool {
+    let buffer = <&[u8]>::from(unsafe { &*vk_buffer });
+    let verifier = match RLN::<Bn256>::new_verifier_from_vk(buffer) {
+        Ok(verifier) => verifier,
+        Err(_) => return false,
+    };
+    unsafe { *ctx = Box::into_raw(Box::new(verifier)) };
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn free_verifier(ctx: *mut RLNVerifier<Bn256>) {
+    if !ctx.is_null() {
+        drop(unsafe { Box::from_raw(ctx) });
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn verifier_set_rln_identifier(
+    ctx: *mut RLNVerifier<Bn256>,
+    input_buffer: *const Buffer,
+) -> bool {
+    let verifier = unsafe { &mut *ctx };
+    let input_data = <&[u8]>::from(unsafe { &*input_buffer });
+    match read_fr::<_, Bn256>(input_data, 1) {
+        Ok(rln_identifier) => {
+            verifier.set_rln_identifier(rln_identifier[0]);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// same as `verify` with a verifier only handle
+#[no_mangle]
+pub extern "C" fn verifier_verify(
+    ctx: *const RLNVerifier<Bn256>,
+    proof_buffer: *const Buffer,
+    result_ptr: *mut u32,
+) -> bool {
+    let verifier = unsafe { &*ctx };
+    let proof_data = <&[u8]>::from(unsafe { &*proof_buffer });
+    if match verifier.verify(proof_data) {
+        Ok(verified) => verified,
+        Err(_) => return false,
+    } {
+        unsafe { *result_ptr = 0 };
+    } else {
+        unsafe { *result_ptr = 1 };
+    };
+    true
+}
+
+/// generates fresh circuit parameters, meant for tests and development setups
+#[cfg(feature = "prover")]
+#[no_mangle]
+pub extern "C" fn new_circuit(merkle_depth: usize, ctx: *mut *mut RLN<Bn256>) -> bool {
+    let rln = RLN::<Bn256>::new(merkle_depth, TreeArity::Binary, None, None);
+    unsafe { *ctx = Box::into_raw(Box::new(rln)) };
+    true
+}
+
+/// same as `new_circuit` calling `progress(phase, percent, user_data)` during generation
+/// * `phase` is 0 while the circuit is synthesized and 1 while query points are computed,
+/// see `SetupPhase`
+#[cfg(feature = "prover")]
+#[no_mangle]
+pub extern "C" fn new_circuit_with_progress(
+    merkle_depth: usize,
+    progress: extern "C" fn(phase: u32, percent: u8, user_data: *mut c_void),
+    user_data: *mut c_void,
+    ctx: *mut *mut RLN<Bn256>,
+) -> bool {
+    let rln = RLN::<Bn256>::new_with_progress(
+        merkle_depth,
+        TreeArity::Binary,
+        None,
+        None,
+        |phase, percent| progress(phase as u32, percent, user_data),
+    );
+    unsafe { *ctx = Box::into_raw(Box::new(rln)) };
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn set_signal_hasher(ctx: *mut RLN<Bn256>, keccak: bool) -> bool {
+    let rln = unsafe { &mut *ctx };
+    if keccak {
+        rln.set_signal_hasher(SignalHasher::Keccak256);
+    } else {
+        rln.set_signal_hasher(SignalHasher::Sha256);
+    }
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn set_rln_identifier(ctx: *mut RLN<Bn256>, input_buffer: *const Buffer) -> bool {
+    let rln = unsafe { &mut *ctx };
+    let input_data = <&[u8]>::from(unsafe { &*input_buffer });
+    match read_fr::<_, Bn256>(input_data, 1) {
+        Ok(rln_identifier) => {
+            rln.set_rln_identifier(rln_identifier[0]);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn set_proof_format(ctx: *mut RLN<Bn256>, compressed: bool) -> bool {
+    let rln = unsafe { &mut *ctx };
+    if compressed {
+        rln.set_proof_format(ProofFormat::Compressed);
+    } else {
+        rln.set_proof_format(ProofFormat::Uncompressed);
+    }
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn set_prover_threads(ctx: *mut RLN<Bn256>, prover_threads: usize) -> bool {
+    let rln = unsafe { &mut *ctx };
+    let prover_threads = if prover_threads == 0 {
+        None
+    } else {
+        Some(prover_threads)
+    };
+    rln.set_prover_threads(prover_threads).is_ok()
+}
+
+/// zero accepts signals of any length
+#[no_mangle]
+pub extern "C" fn set_max_signal_size(ctx: *mut RLN<Bn256>, max_signal_size: u64) -> bool {
+    let rln = unsafe { &mut *ctx };
+    if max_signal_size == 0 {
+        rln.set_max_signal_size(None);
+    } else {
+        rln.set_max_signal_size(Some(max_signal_size));
+    }
+    true
+}
+
 #[no_mangle]
 pub extern "C" fn get_root(ctx: *const RLN<Bn256>, output_buffer: *mut Buffer) -> bool {
     let rln = unsafe { &*ctx };
@@ -79,7 +272,48 @@ pub extern "C" fn update_next_member(ctx: *mut RLN<Bn256>, input_buffer: *const
 }
 
 #[no_mangle]
-pub extern "C" fn delete_member(ctx: *mut RLN<Bn256>, index: usize) -> bool {
+pub extern "C" fn apply_membership_events(
+    ctx: *mut RLN<Bn256>,
+    input_buffer: *const Buffer,
+    output_buffer: *mut Buffer,
+) -> bool {
+    let rln = unsafe { &mut *ctx };
+    let input_data = <&[u8]>::from(unsafe { &*input_buffer });
+    let events = match MemberEvent::<Bn256>::read_events(input_data) {
+        Ok(events) => events,
+        Err(_) => return false,
+    };
+    let root = match rln.apply_membership_events(events.into_iter()) {
+        Ok(root) => root,
+        Err(_) => return false,
+    };
+    let mut output_data: Vec<u8> = Vec::new();
+    if root.into_repr().write_le(&mut output_data).is_err() {
+        return false;
+    }
+    unsafe { *output_buffer = Buffer::from(&output_data[..]) };
+    std::mem::forget(output_data);
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn get_merkle_proof(
+    ctx: *const RLN<Bn256>,
+    index: u64,
+    output_buffer: *mut Buffer,
+) -> bool {
+    let rln = unsafe { &*ctx };
+    let mut output_data: Vec<u8> = Vec::new();
+    if rln.get_witness(index, &mut output_data).is_err() {
+        return false;
+    }
+    unsafe { *output_buffer = Buffer::from(&output_data[..]) };
+    std::mem::forget(output_data);
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn delete_member(ctx: *mut RLN<Bn256>, index: u64) -> bool {
     let rln = unsafe { &mut *ctx };
     match rln.delete_member(index) {
         Ok(_) => true,
@@ -106,6 +340,34 @@ pub extern "C" fn generate_proof(
     true
 }
 
+#[no_mangle]
+pub extern "C" fn generate_proof_with_seed(
+    ctx: *const RLN<Bn256>,
+    input_buffer: *const Buffer,
+    seed_buffer: *const Buffer,
+    output_buffer: *mut Buffer,
+) -> bool {
+    let rln = unsafe { &*ctx };
+    let input_data = <&[u8]>::from(unsafe { &*input_buffer });
+    let seed = <&[u8]>::from(unsafe { &*seed_buffer });
+    let mut output_data: Vec<u8> = Vec::new();
+
+    match rln.generate_proof_with_rng(input_data, &mut output_data, &mut seeded_rng(seed)) {
+        Ok(proof_data) => proof_data,
+        Err(_) => return false,
+    };
+    unsafe { *output_buffer = Buffer::from(&output_data[..]) };
+    std::mem::forget(output_data);
+    true
+}
+
+/// verifies a proof bundle followed by its signal,
+/// binding the signal hash to share_x just like `RLN::verify`
+/// * `proof_buffer` is serialized as
+/// [ proof<256 or 128>| root<32>| epoch<32>| share_x<32>| share_y<32>| nullifier<32> | signal_len<8> | signal<var> ]
+/// * proofs are 256 bytes uncompressed and 128 bytes after `set_proof_format(ctx, true)`
+/// * `result_ptr` is set to 0 if the proof is valid and to 1 otherwise
+/// * returns false for malformed input and for a signal that does not match share_x
 #[no_mangle]
 pub extern "C" fn verify(
     ctx: *const RLN<Bn256>,
@@ -125,6 +387,77 @@ pub extern "C" fn verify(
     true
 }
 
+/// recovers a member secret from two proofs of the same epoch and nullifier
+/// * `share1_buffer` and `share2_buffer` are `generate_proof` outputs
+/// * `output_buffer` is set to the secret in 32 bytes
+#[no_mangle]
+pub extern "C" fn recover_secret(
+    ctx: *const RLN<Bn256>,
+    share1_buffer: *const Buffer,
+    share2_buffer: *const Buffer,
+    output_buffer: *mut Buffer,
+) -> bool {
+    let rln = unsafe { &*ctx };
+    let share1_data = <&[u8]>::from(unsafe { &*share1_buffer });
+    let share2_data = <&[u8]>::from(unsafe { &*share2_buffer });
+    let mut output_data: Vec<u8> = Vec::new();
+    match rln.recover_secret(share1_data, share2_data, &mut output_data) {
+        Ok(_) => (),
+        Err(_) => return false,
+    };
+    unsafe { *output_buffer = Buffer::from(&output_data[..]) };
+    std::mem::forget(output_data);
+    true
+}
+
+/// logs the share of a `generate_proof` output in the nullifier log
+/// * `result_ptr` is set to 0 for a new share, 1 for a duplicate and 2 if the rate limit is breached
+/// * `secret_buffer` is set to the recovered secret only on a breach
+#[no_mangle]
+pub extern "C" fn nullifier_log_insert(
+    ctx: *const RLN<Bn256>,
+    proof_buffer: *const Buffer,
+    result_ptr: *mut u32,
+    secret_buffer: *mut Buffer,
+) -> bool {
+    let rln = unsafe { &*ctx };
+    let proof_data = <&[u8]>::from(unsafe { &*proof_buffer });
+    let status = match rln.nullifier_log_insert(proof_data) {
+        Ok(status) => status,
+        Err(_) => return false,
+    };
+    write_log_status(status, result_ptr, secret_buffer);
+    true
+}
+
+/// same as `nullifier_log_insert` without logging the share
+#[no_mangle]
+pub extern "C" fn nullifier_log_check(
+    ctx: *const RLN<Bn256>,
+    proof_buffer: *const Buffer,
+    result_ptr: *mut u32,
+    secret_buffer: *mut Buffer,
+) -> bool {
+    let rln = unsafe { &*ctx };
+    let proof_data = <&[u8]>::from(unsafe { &*proof_buffer });
+    let status = match rln.nullifier_log_check(proof_data) {
+        Ok(status) => status,
+        Err(_) => return false,
+    };
+    write_log_status(status, result_ptr, secret_buffer);
+    true
+}
+
+fn write_log_status(status: LogStatus<Bn256>, result_ptr: *mut u32, secret_buffer: *mut Buffer) {
+    unsafe { *result_ptr = status.code() };
+    if let LogStatus::Breach(secret) = status {
+        let mut secret_data: Vec<u8> = Vec::new();
+        secret.into_repr().write_le(&mut secret_data).unwrap();
+        unsafe { *secret_buffer = Buffer::from(&secret_data[..]) };
+        std::mem::forget(secret_data);
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn signal_to_field(
     ctx: *const RLN<Bn256>,
@@ -157,6 +490,201 @@ pub extern "C" fn key_gen(ctx: *const RLN<Bn256>, input_buffer: *mut Buffer) ->
     true
 }
 
+/// leaf of a member with a secret, see `public::commitment`,
+/// with the default poseidon parameters of binary trees
+/// * `input_buffer` is the secret in 32 bytes, `output_buffer` is set to the leaf in 32 bytes
+#[no_mangle]
+pub extern "C" fn commitment(input_buffer: *const Buffer, output_buffer: *mut Buffer) -> bool {
+    let input_data = <&[u8]>::from(unsafe { &*input_buffer });
+    let secret = match read_fr::<_, Bn256>(input_data, 1) {
+        Ok(secret) => secret[0],
+        Err(_) => return false,
+    };
+    let mut output_data: Vec<u8> = Vec::new();
+    if public::commitment::<Bn256>(secret)
+        .into_repr()
+        .write_le(&mut output_data)
+        .is_err()
+    {
+        return false;
+    }
+    unsafe { *output_buffer = Buffer::from(&output_data[..]) };
+    std::mem::forget(output_data);
+    true
+}
+
+/// restores a key pair from its secrets, see `RLN::import_key`
+/// * `output_buffer` is [ secret<32> | leaf<32> ], followed by `index<8>`
+/// with `check_member`, which fails if the key is not registered
+#[no_mangle]
+pub extern "C" fn import_key(
+    ctx: *const RLN<Bn256>,
+    input_buffer: *const Buffer,
+    check_member: bool,
+    output_buffer: *mut Buffer,
+) -> bool {
+    let rln = unsafe { &*ctx };
+    let input_data = <&[u8]>::from(unsafe { &*input_buffer });
+    let (secret, leaf, index) = if check_member {
+        match rln.import_member_key(input_data) {
+            Ok((secret, leaf, index)) => (secret, leaf, Some(index)),
+            Err(_) => return false,
+        }
+    } else {
+        match rln.import_key(input_data) {
+            Ok((secret, leaf)) => (secret, leaf, None),
+            Err(_) => return false,
+        }
+    };
+    let mut output_data: Vec<u8> = Vec::new();
+    for fr in [secret, leaf].iter() {
+        if fr.into_repr().write_le(&mut output_data).is_err() {
+            return false;
+        }
+    }
+    if let Some(index) = index {
+        output_data.extend_from_slice(&index.to_le_bytes());
+    }
+    unsafe { *output_buffer = Buffer::from(&output_data[..]) };
+    std::mem::forget(output_data);
+    true
+}
+
+/// serialized circuit parameters of the instance,
+/// restored with `new_circuit_from_params` or `new_circuit_from_params_file`
+#[no_mangle]
+pub extern "C" fn export_circuit_parameters(
+    ctx: *const RLN<Bn256>,
+    output_buffer: *mut Buffer,
+) -> bool {
+    let rln = unsafe { &*ctx };
+    let mut output_data: Vec<u8> = Vec::new();
+    if rln.export_circuit_parameters(&mut output_data).is_err() {
+        return false;
+    }
+    unsafe { *output_buffer = Buffer::from(&output_data[..]) };
+    std::mem::forget(output_data);
+    true
+}
+
+/// serialized verifying key of the instance, e.g. for a verifier service,
+/// restored with `new_verifier_from_vk`
+#[no_mangle]
+pub extern "C" fn export_verifier_key(ctx: *const RLN<Bn256>, output_buffer: *mut Buffer) -> bool {
+    let rln = unsafe { &*ctx };
+    let mut output_data: Vec<u8> = Vec::new();
+    if rln.export_verifier_key(&mut output_data).is_err() {
+        return false;
+    }
+    unsafe { *output_buffer = Buffer::from(&output_data[..]) };
+    std::mem::forget(output_data);
+    true
+}
+
+/// result codes of the calls writing into caller allocated memory
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RLNStatus {
+    Ok = 0,
+    Error = 1,
+    BufferTooSmall = 2,
+}
+
+// Variants below write into caller allocated memory following the two call convention.
+// `output_len` is always set to the length of the output and the output is copied only
+// if it fits `capacity`, with `output_len` zero on errors.
+// Calling with a zero capacity first gives the size to allocate, a null `output_ptr`
+// is only accepted for such size queries.
+
+#[no_mangle]
+pub extern "C" fn generate_proof_into(
+    ctx: *const RLN<Bn256>,
+    input_buffer: *const Buffer,
+    output_ptr: *mut u8,
+    capacity: usize,
+    output_len: *mut usize,
+) -> RLNStatus {
+    let rln = unsafe { &*ctx };
+    // the size is known from the proof format, size queries do not prove
+    let proof_len = rln.proof_output_len();
+    if proof_len > capacity {
+        unsafe { *output_len = proof_len };
+        return RLNStatus::BufferTooSmall;
+    }
+    let input_data = <&[u8]>::from(unsafe { &*input_buffer });
+    let mut output_data: Vec<u8> = Vec::new();
+    let result = rln.generate_proof(input_data, &mut output_data);
+    write_output(result, &output_data, output_ptr, capacity, output_len)
+}
+
+#[no_mangle]
+pub extern "C" fn key_gen_into(
+    ctx: *const RLN<Bn256>,
+    output_ptr: *mut u8,
+    capacity: usize,
+    output_len: *mut usize,
+) -> RLNStatus {
+    let rln = unsafe { &*ctx };
+    // secrets of the scheme and the public key, size queries do not generate keys
+    let key_len = (rln.identity_scheme().num_secrets() + 1) * 32;
+    if key_len > capacity {
+        unsafe { *output_len = key_len };
+        return RLNStatus::BufferTooSmall;
+    }
+    let mut output_data: Vec<u8> = Vec::new();
+    let result = rln.key_gen(&mut output_data);
+    write_output(result, &output_data, output_ptr, capacity, output_len)
+}
+
+#[no_mangle]
+pub extern "C" fn export_circuit_parameters_into(
+    ctx: *const RLN<Bn256>,
+    output_ptr: *mut u8,
+    capacity: usize,
+    output_len: *mut usize,
+) -> RLNStatus {
+    let rln = unsafe { &*ctx };
+    let mut output_data: Vec<u8> = Vec::new();
+    let result = rln.export_circuit_parameters(&mut output_data);
+    write_output(result, &output_data, output_ptr, capacity, output_len)
+}
+
+#[no_mangle]
+pub extern "C" fn export_verifier_key_into(
+    ctx: *const RLN<Bn256>,
+    output_ptr: *mut u8,
+    capacity: usize,
+    output_len: *mut usize,
+) -> RLNStatus {
+    let rln = unsafe { &*ctx };
+    let mut output_data: Vec<u8> = Vec::new();
+    let result = rln.export_verifier_key(&mut output_data);
+    write_output(result, &output_data, output_ptr, capacity, output_len)
+}
+
+fn write_output(
+    result: io::Result<()>,
+    output_data: &[u8],
+    output_ptr: *mut u8,
+    capacity: usize,
+    output_len: *mut usize,
+) -> RLNStatus {
+    if result.is_err() {
+        unsafe { *output_len = 0 };
+        return RLNStatus::Error;
+    }
+    unsafe { *output_len = output_data.len() };
+    if output_data.len() > capacity {
+        return RLNStatus::BufferTooSmall;
+    }
+    if output_ptr.is_null() {
+        unsafe { *output_len = 0 };
+        return RLNStatus::Error;
+    }
+    unsafe { std::ptr::copy_nonoverlapping(output_data.as_ptr(), output_ptr, output_data.len()) };
+    RLNStatus::Ok
+}
+
 use sapling_crypto::bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr};
 use sapling_crypto::bellman::pairing::Engine;
 use std::io::{self, Read, Write};
@@ -177,8 +705,8 @@ mod tests {
         4usize
     }
 
-    fn index() -> usize {
-        13usize
+    fn index() -> u64 {
+        13u64
     }
 
     fn rln_test() -> bench::RLNTest<Bn256> {
@@ -203,6 +731,17 @@ mod tests {
         rln_pointer
     }
 
+    #[test]
+    fn test_empty_buffer() {
+        let buffer = Buffer::from(&[][..]);
+        assert!(<&[u8]>::from(&buffer).is_empty());
+        let null = Buffer {
+            ptr: std::ptr::null(),
+            len: 4,
+        };
+        assert!(<&[u8]>::from(&null).is_empty());
+    }
+
     #[test]
     fn test_proof_ffi() {
         let mut rng = XorShiftRng::from_seed([0x3dbe6258, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
@@ -257,20 +796,9 @@ mod tests {
             let signal = b"rln signal test xyz abc";
 
             // serialize input
-            let mut input_data: Vec<u8> = Vec::new();
-            id_key.into_repr().write_le(&mut input_data).unwrap();
-            if fail {
-                input_data
-                    .write_u64::<LittleEndian>(index as u64 - 1)
-                    .unwrap();
-            } else {
-                input_data.write_u64::<LittleEndian>(index as u64).unwrap();
-            }
-            epoch.into_repr().write_le(&mut input_data).unwrap();
-            input_data
-                .write_u64::<LittleEndian>(signal.len() as u64)
-                .unwrap();
-            input_data.write(&signal[..]).unwrap();
+            let id_index = if fail { index - 1 } else { index };
+            let input_data =
+                crate::test_utils::proof_input::<Bn256>(id_key, id_index, epoch, &signal[..]);
 
             let input_buffer = &Buffer::from(input_data.as_ref());
 
@@ -299,6 +827,14 @@ mod tests {
             } else {
                 assert_eq!(0, result);
             }
+
+            // tampered signal must not verify
+            let mut tampered_data = input_data.clone();
+            let last = tampered_data.len() - 1;
+            tampered_data[last] ^= 1;
+            let tampered_buffer = &Buffer::from(tampered_data.as_ref());
+            let success = verify(rln_pointer, tampered_buffer, result_ptr);
+            assert!(!success, "signal hash mismatch should fail");
         };
 
         gen_proof_and_verify(rln_pointer, false);
@@ -310,6 +846,155 @@ mod tests {
 
         gen_proof_and_verify(rln_pointer, false);
         gen_proof_and_verify(rln_pointer, true);
+
+        // switch to compressed proofs
+        let success = set_proof_format(rln_pointer, true);
+        assert!(success, "set proof format call failed");
+
+        gen_proof_and_verify(rln_pointer, false);
+        gen_proof_and_verify(rln_pointer, true);
+    }
+
+    #[test]
+    fn test_verifier_ffi() {
+        let rln = RLN::<Bn256>::new(merkle_depth(), TreeArity::Binary, None, None);
+        let id_key = Fr::from_str("1001").unwrap();
+        let signal = b"rln signal";
+        let input_data = crate::test_utils::register_member(&rln, id_key, Fr::one(), signal);
+        let mut proof_data: Vec<u8> = Vec::new();
+        rln.generate_proof(input_data.as_slice(), &mut proof_data)
+            .unwrap();
+        proof_data
+            .write_u64::<LittleEndian>(signal.len() as u64)
+            .unwrap();
+        proof_data.write(&signal[..]).unwrap();
+
+        let mut vk: Vec<u8> = Vec::new();
+        rln.export_verifier_key(&mut vk).unwrap();
+        let mut verifier_pointer = MaybeUninit::<*mut RLNVerifier<Bn256>>::uninit();
+        let vk_buffer = &Buffer::from(vk.as_ref());
+        assert!(new_verifier_from_vk(
+            vk_buffer,
+            verifier_pointer.as_mut_ptr()
+        ));
+        let verifier_pointer = unsafe { verifier_pointer.assume_init() };
+
+        let proof_buffer = &Buffer::from(proof_data.as_ref());
+        let mut result = 1u32;
+        assert!(verifier_verify(
+            verifier_pointer,
+            proof_buffer,
+            &mut result as *mut u32
+        ));
+        assert_eq!(result, 0);
+
+        // a verifier of another deployment rejects the proof
+        let mut rln_identifier: Vec<u8> = Vec::new();
+        Fr::from_str("7")
+            .unwrap()
+            .into_repr()
+            .write_le(&mut rln_identifier)
+            .unwrap();
+        let identifier_buffer = &Buffer::from(rln_identifier.as_ref());
+        assert!(verifier_set_rln_identifier(
+            verifier_pointer,
+            identifier_buffer
+        ));
+        assert!(verifier_verify(
+            verifier_pointer,
+            proof_buffer,
+            &mut result as *mut u32
+        ));
+        assert_eq!(result, 1);
+        free_verifier(verifier_pointer);
+
+        let truncated = &Buffer::from(&vk[..vk.len() - 1]);
+        let mut verifier_pointer = MaybeUninit::<*mut RLNVerifier<Bn256>>::uninit();
+        assert!(!new_verifier_from_vk(
+            truncated,
+            verifier_pointer.as_mut_ptr()
+        ));
+    }
+
+    #[test]
+    fn test_recover_secret_ffi() {
+        let rln_test = rln_test();
+        let mut circuit_parameters: Vec<u8> = Vec::new();
+        rln_test
+            .export_circuit_parameters(&mut circuit_parameters)
+            .unwrap();
+        let rln_pointer = rln_pointer(circuit_parameters);
+        let rln_pointer = unsafe { rln_pointer.assume_init() };
+
+        let mut keypair_buffer = MaybeUninit::<Buffer>::uninit();
+        let success = key_gen(rln_pointer, keypair_buffer.as_mut_ptr());
+        assert!(success, "key generation call failed");
+        let keypair_buffer = unsafe { keypair_buffer.assume_init() };
+        let keypair_data = <&[u8]>::from(&keypair_buffer);
+        let success = update_next_member(rln_pointer, &Buffer::from(&keypair_data[32..]));
+        assert!(success, "update with new pubkey call failed");
+
+        let epoch = Fr::from_str("1").unwrap();
+        let gen_proof = |signal: &[u8]| {
+            let mut input_data: Vec<u8> = Vec::new();
+            input_data.write_all(&keypair_data[..32]).unwrap();
+            input_data.write_u64::<LittleEndian>(0).unwrap();
+            epoch.into_repr().write_le(&mut input_data).unwrap();
+            input_data
+                .write_u64::<LittleEndian>(signal.len() as u64)
+                .unwrap();
+            input_data.write_all(signal).unwrap();
+            let mut proof_buffer = MaybeUninit::<Buffer>::uninit();
+            let success = generate_proof(
+                rln_pointer,
+                &Buffer::from(input_data.as_ref()),
+                proof_buffer.as_mut_ptr(),
+            );
+            assert!(success, "proof generation call failed");
+            <&[u8]>::from(&unsafe { proof_buffer.assume_init() }).to_vec()
+        };
+        let proof_1 = gen_proof(b"first message");
+        let proof_2 = gen_proof(b"second message");
+
+        let mut status = 0u32;
+        let mut secret_buffer = MaybeUninit::<Buffer>::uninit();
+        let success = nullifier_log_insert(
+            rln_pointer,
+            &Buffer::from(proof_1.as_ref()),
+            &mut status as *mut u32,
+            secret_buffer.as_mut_ptr(),
+        );
+        assert!(success, "nullifier log call failed");
+        assert_eq!(status, 0);
+        let success = nullifier_log_insert(
+            rln_pointer,
+            &Buffer::from(proof_1.as_ref()),
+            &mut status as *mut u32,
+            secret_buffer.as_mut_ptr(),
+        );
+        assert!(success, "nullifier log call failed");
+        assert_eq!(status, 1);
+        let success = nullifier_log_check(
+            rln_pointer,
+            &Buffer::from(proof_2.as_ref()),
+            &mut status as *mut u32,
+            secret_buffer.as_mut_ptr(),
+        );
+        assert!(success, "nullifier log call failed");
+        assert_eq!(status, 2);
+        let secret_buffer = unsafe { secret_buffer.assume_init() };
+        assert_eq!(<&[u8]>::from(&secret_buffer), &keypair_data[..32]);
+
+        let mut output_buffer = MaybeUninit::<Buffer>::uninit();
+        let success = recover_secret(
+            rln_pointer,
+            &Buffer::from(proof_1.as_ref()),
+            &Buffer::from(proof_2.as_ref()),
+            output_buffer.as_mut_ptr(),
+        );
+        assert!(success, "recover secret call failed");
+        let output_buffer = unsafe { output_buffer.assume_init() };
+        assert_eq!(<&[u8]>::from(&output_buffer), &keypair_data[..32]);
     }
 
     #[test]
@@ -324,7 +1009,7 @@ mod tests {
 
         let signal = b"rln signal test xyz abc";
 
-        let expected = hash_to_field::<Bn256>(&signal[..]);
+        let expected = hash_to_field::<Bn256>(&signal[..]).unwrap();
         let mut expected_data: Vec<u8> = Vec::new();
         expected.into_repr().write_le(&mut expected_data).unwrap();
 
@@ -377,6 +1062,140 @@ mod tests {
         assert_eq!(public, expected_public);
     }
 
+    #[test]
+    fn test_new_circuit_with_progress_ffi() {
+        extern "C" fn record(phase: u32, percent: u8, user_data: *mut c_void) {
+            let reports = unsafe { &mut *(user_data as *mut Vec<(u32, u8)>) };
+            reports.push((phase, percent));
+        }
+
+        let mut reports: Vec<(u32, u8)> = Vec::new();
+        let mut rln_pointer = MaybeUninit::<*mut RLN<Bn256>>::uninit();
+        assert!(new_circuit_with_progress(
+            merkle_depth(),
+            record,
+            &mut reports as *mut Vec<(u32, u8)> as *mut c_void,
+            rln_pointer.as_mut_ptr()
+        ));
+        drop(unsafe { Box::from_raw(rln_pointer.assume_init()) });
+        assert_eq!(reports.first(), Some(&(0, 0)));
+        assert_eq!(reports.last(), Some(&(1, 100)));
+    }
+
+    #[test]
+    fn test_new_circuit_ffi() {
+        let mut rln_pointer = MaybeUninit::<*mut RLN<Bn256>>::uninit();
+        assert!(new_circuit(merkle_depth(), rln_pointer.as_mut_ptr()));
+        let rln = unsafe { &*rln_pointer.assume_init() };
+
+        let mut parameters_buffer = MaybeUninit::<Buffer>::uninit();
+        let success = export_circuit_parameters(rln, parameters_buffer.as_mut_ptr());
+        assert!(success, "export circuit parameters call failed");
+        let parameters_buffer = unsafe { parameters_buffer.assume_init() };
+        let mut vk_buffer = MaybeUninit::<Buffer>::uninit();
+        let success = export_verifier_key(rln, vk_buffer.as_mut_ptr());
+        assert!(success, "export verifier key call failed");
+        let vk_buffer = unsafe { vk_buffer.assume_init() };
+
+        // parameters restore an instance with the same verifier key
+        let parameters = <&[u8]>::from(&parameters_buffer).to_vec();
+        let restored = unsafe { &*rln_pointer(parameters).assume_init() };
+        let mut restored_vk: Vec<u8> = Vec::new();
+        restored.export_verifier_key(&mut restored_vk).unwrap();
+        assert_eq!(<&[u8]>::from(&vk_buffer), restored_vk.as_slice());
+    }
+
+    #[test]
+    fn test_new_circuit_from_params_file() {
+        use std::ffi::CString;
+        let rln_test = rln_test();
+        let mut circuit_parameters: Vec<u8> = Vec::new();
+        rln_test
+            .export_circuit_parameters(&mut circuit_parameters)
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("rln-params-{}", std::process::id()));
+        std::fs::write(&path, &circuit_parameters).unwrap();
+
+        let path_cstr = CString::new(path.to_str().unwrap()).unwrap();
+        let mut rln_pointer = MaybeUninit::<*mut RLN<Bn256>>::uninit();
+        let success = new_circuit_from_params_file(
+            merkle_depth(),
+            path_cstr.as_ptr(),
+            rln_pointer.as_mut_ptr(),
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(success, "cannot init rln instance from file");
+        let rln = unsafe { Box::from_raw(rln_pointer.assume_init()) };
+        let mut restored: Vec<u8> = Vec::new();
+        rln.export_circuit_parameters(&mut restored).unwrap();
+        assert_eq!(restored, circuit_parameters);
+
+        let mut rln_pointer = MaybeUninit::<*mut RLN<Bn256>>::uninit();
+        assert!(!new_circuit_from_params_file(
+            merkle_depth(),
+            path_cstr.as_ptr(),
+            rln_pointer.as_mut_ptr()
+        ));
+        assert!(!new_circuit_from_params_file(
+            merkle_depth(),
+            std::ptr::null(),
+            rln_pointer.as_mut_ptr()
+        ));
+    }
+
+    #[test]
+    fn test_caller_allocated_output() {
+        let rln_test = rln_test();
+        let mut circuit_parameters: Vec<u8> = Vec::new();
+        rln_test
+            .export_circuit_parameters(&mut circuit_parameters)
+            .unwrap();
+        let rln_pointer = rln_pointer(circuit_parameters);
+        let rln_pointer = unsafe { &*rln_pointer.assume_init() };
+
+        // first call only reports the size
+        let mut output_len = 0usize;
+        let status = key_gen_into(rln_pointer, std::ptr::null_mut(), 0, &mut output_len);
+        assert_eq!(status, RLNStatus::BufferTooSmall);
+        assert_eq!(output_len, 64);
+
+        let mut output = vec![0u8; output_len];
+        let status = key_gen_into(
+            rln_pointer,
+            output.as_mut_ptr(),
+            output.len(),
+            &mut output_len,
+        );
+        assert_eq!(status, RLNStatus::Ok);
+        let hasher = rln_test.hasher();
+        let secret = read_fr::<_, Bn256>(&output[..32], 1).unwrap()[0];
+        let public = read_fr::<_, Bn256>(&output[32..], 1).unwrap()[0];
+        assert_eq!(public, hasher.hash(vec![secret]));
+
+        // proof sizes come from the proof format, the empty input is never proved
+        let empty: &[u8] = &[];
+        let input_buffer = &Buffer::from(empty);
+        let status = generate_proof_into(
+            rln_pointer,
+            input_buffer,
+            std::ptr::null_mut(),
+            0,
+            &mut output_len,
+        );
+        assert_eq!(status, RLNStatus::BufferTooSmall);
+        assert_eq!(output_len, 416);
+
+        // a null output is refused once the output fits
+        let status = export_verifier_key_into(
+            rln_pointer,
+            std::ptr::null_mut(),
+            usize::MAX,
+            &mut output_len,
+        );
+        assert_eq!(status, RLNStatus::Error);
+        assert_eq!(output_len, 0);
+    }
+
     #[test]
     #[ignore]
     fn test_parameters_from_file() {
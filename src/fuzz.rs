@@ -0,0 +1,79 @@
+//! Entry points of the cargo-fuzz targets in `fuzz/`, built with the `fuzzing` feature.
+//!
+//! Each entry feeds arbitrary bytes to a reader of peer input. Errors are expected,
+//! a target only fails if the reader panics.
+
+use crate::circuit::rln::RLNInputs;
+use crate::ffi::{self, Buffer};
+use crate::hash_to_field::SignalHasher;
+use crate::merkle::TreeArity;
+use crate::public::{RLNSignal, RLN};
+use crate::utils::{read_proof, read_signal_hash, ProofFormat};
+use bellman::pairing::bn256::Bn256;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref VERIFIER: RLN<Bn256> = RLN::new(3, TreeArity::Binary, None, None);
+}
+
+const PROOF_FORMATS: [ProofFormat; 2] = [ProofFormat::Uncompressed, ProofFormat::Compressed];
+
+const SIGNAL_HASHERS: [SignalHasher; 2] = [SignalHasher::Sha256, SignalHasher::Keccak256];
+
+pub fn proof(data: &[u8]) {
+    for format in PROOF_FORMATS.iter() {
+        let _ = read_proof::<_, Bn256>(data, *format);
+    }
+}
+
+pub fn inputs(data: &[u8]) {
+    let _ = RLNInputs::<Bn256>::read(data);
+    let _ = RLNInputs::<Bn256>::read_public_inputs(data);
+}
+
+pub fn signal(data: &[u8]) {
+    for signal_hasher in SIGNAL_HASHERS.iter() {
+        let _ = RLNSignal::<Bn256>::read(data, *signal_hasher);
+        let _ = read_signal_hash::<_, Bn256>(data, *signal_hasher);
+    }
+}
+
+/// `verify` and `nullifier_log_check` of the c api
+pub fn ffi_buffer(data: &[u8]) {
+    let ctx: *const RLN<Bn256> = &*VERIFIER;
+    let buffer = Buffer::from(data);
+    let mut result = 0u32;
+    let mut secret = Buffer {
+        ptr: std::ptr::null(),
+        len: 0,
+    };
+    ffi::verify(ctx, &buffer, &mut result);
+    ffi::nullifier_log_check(ctx, &buffer, &mut result, &mut secret);
+}
+
+#[test]
+fn test_fuzz_entries() {
+    let inputs_data: Vec<Vec<u8>> = vec![
+        vec![],
+        vec![0u8; 1],
+        vec![0xffu8; 64],
+        vec![0u8; 300],
+        vec![0xffu8; 600],
+    ];
+    for data in inputs_data.iter() {
+        proof(data);
+        inputs(data);
+        signal(data);
+        ffi_buffer(data);
+    }
+
+    // the signal "40" hashes to a value shorter than 32 bytes, it used to panic on decoding
+    let mut short_hash = 2u64.to_le_bytes().to_vec();
+    short_hash.extend_from_slice(b"40");
+    signal(&short_hash);
+    assert!(read_signal_hash::<_, Bn256>(short_hash.as_slice(), SignalHasher::Sha256).is_ok());
+    let mut short_signal = vec![0u8; 32];
+    short_signal.extend_from_slice(&short_hash);
+    signal(&short_signal);
+    assert!(RLNSignal::<Bn256>::read(short_signal.as_slice(), SignalHasher::Sha256).is_ok());
+}
@@ -1,21 +1,232 @@
+//! Hashing of arbitrary bytes to scalar field elements.
+//!
+//! `hash_to_field` follows RFC 9380 (section 5) with `expand_message_xmd` over sha256,
+//! reducing 48 bytes per element so the result is statistically close to uniform.
+//! The previous construction is kept as `hash_to_field_legacy` and is used by default
+//! with the `legacy-hash-to-field` feature, for deployments whose signals were hashed with it.
+
 use bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr, ScalarEngine};
 use bellman::pairing::Engine;
 use digest::{FixedOutput, VariableOutput, XofReader};
 use num_bigint::BigUint;
 use num_traits::{Num, One, Zero};
 use sha2::{Digest, Sha256};
+use sha3::Keccak256;
 use std::convert::TryInto;
+use std::io::{self, Read};
 use std::str::EncodeUtf16;
 
+/// domain separation tag used by `hash_to_field`
+pub const DEFAULT_DST: &[u8] = b"RLN-V1_XMD:SHA-256_HASH_TO_FIELD_";
+
+// bytes reduced per field element, ceil((ceil(log2(p)) + k) / 8) with k = 128
+const L: usize = 48;
+// sha256 output and block sizes
+const B_IN_BYTES: usize = 32;
+const S_IN_BYTES: usize = 64;
+
+// chunk size signals are read in by `SignalHasher::hash_reader`
+const READ_CHUNK_SIZE: usize = 4096;
+
 const PREFIX_RLN_HASH_TO_FIELD: &[u8; 17] = b"rln_hash_to_field";
 const PREFIX_RLN_HASH_TO_FIELD_LO: &[u8; 20] = b"rln_hash_to_field_lo";
 const PREFIX_RLN_HASH_TO_FIELD_HI: &[u8; 20] = b"rln_hash_to_field_hi";
 
-pub fn hash_to_field<E: Engine>(data: &[u8]) -> <E as ScalarEngine>::Fr {
+/// hash function turning a signal into `share_x`
+/// * `Sha256` uses `hash_to_field`
+/// * `Keccak256` reads keccak256(signal) as a big endian integer reduced mod r,
+/// the same as `uint256(keccak256(signal)) % r` in solidity, so on-chain verifiers
+/// can recompute it cheaply
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignalHasher {
+    Sha256,
+    Keccak256,
+}
+
+impl Default for SignalHasher {
+    fn default() -> Self {
+        SignalHasher::Sha256
+    }
+}
+
+impl SignalHasher {
+    pub fn hash<E: Engine>(&self, data: &[u8]) -> io::Result<E::Fr> {
+        let mut state = self.start();
+        state.update(data);
+        state.finish::<E>()
+    }
+
+    /// starts an incremental hash, signal bytes are fed with `SignalHashState::update`
+    pub fn start(&self) -> SignalHashState {
+        match self {
+            SignalHasher::Sha256 => SignalHashState::Sha256(hash_to_field_start()),
+            SignalHasher::Keccak256 => SignalHashState::Keccak256(Keccak256::new()),
+        }
+    }
+
+    /// hashes exactly `len` bytes of `reader` in fixed size chunks,
+    /// fails with `UnexpectedEof` if the reader ends early
+    pub fn hash_reader<E: Engine, R: Read>(&self, mut reader: R, len: u64) -> io::Result<E::Fr> {
+        let mut state = self.start();
+        let mut buf = [0u8; READ_CHUNK_SIZE];
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = std::cmp::min(remaining, READ_CHUNK_SIZE as u64) as usize;
+            reader.read_exact(&mut buf[..n])?;
+            state.update(&buf[..n]);
+            remaining -= n as u64;
+        }
+        state.finish::<E>()
+    }
+}
+
+/// incremental state of a `SignalHasher`
+#[derive(Clone)]
+pub enum SignalHashState {
+    Sha256(Sha256),
+    Keccak256(Keccak256),
+}
+
+impl SignalHashState {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            SignalHashState::Sha256(hasher) => hasher.update(data),
+            SignalHashState::Keccak256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finish<E: Engine>(self) -> io::Result<E::Fr> {
+        match self {
+            SignalHashState::Sha256(hasher) => hash_to_field_finish::<E>(hasher),
+            SignalHashState::Keccak256(hasher) => {
+                big_to_fr::<E>(BigUint::from_bytes_be(&hasher.finalize()[..]))
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "legacy-hash-to-field"))]
+fn hash_to_field_start() -> Sha256 {
+    xmd_start()
+}
+
+#[cfg(not(feature = "legacy-hash-to-field"))]
+fn hash_to_field_finish<E: Engine>(msg_hasher: Sha256) -> io::Result<E::Fr> {
+    let uniform_bytes = xmd_finish(msg_hasher, DEFAULT_DST, L);
+    big_to_fr::<E>(BigUint::from_bytes_be(&uniform_bytes[..]))
+}
+
+#[cfg(feature = "legacy-hash-to-field")]
+fn hash_to_field_start() -> Sha256 {
+    legacy_start()
+}
+
+#[cfg(feature = "legacy-hash-to-field")]
+fn hash_to_field_finish<E: Engine>(hasher: Sha256) -> io::Result<E::Fr> {
+    legacy_finish::<E>(hasher)
+}
+
+/// hashes data to a single field element with the default domain separation tag
+#[cfg(not(feature = "legacy-hash-to-field"))]
+pub fn hash_to_field<E: Engine>(data: &[u8]) -> io::Result<<E as ScalarEngine>::Fr> {
+    Ok(hash_to_field_xmd::<E>(data, DEFAULT_DST, 1)?[0])
+}
+
+/// hashes data to a single field element with the legacy construction
+#[cfg(feature = "legacy-hash-to-field")]
+pub fn hash_to_field<E: Engine>(data: &[u8]) -> io::Result<<E as ScalarEngine>::Fr> {
+    hash_to_field_legacy::<E>(data)
+}
+
+/// RFC 9380 hash_to_field with expand_message_xmd over sha256
+/// * `dst` is the domain separation tag, tags longer than 255 bytes are hashed down
+/// * `count` elements are derived, at most 170
+pub fn hash_to_field_xmd<E: Engine>(
+    data: &[u8],
+    dst: &[u8],
+    count: usize,
+) -> io::Result<Vec<E::Fr>> {
+    let uniform_bytes = expand_message_xmd(data, dst, count * L);
+    uniform_bytes
+        .chunks(L)
+        .map(|chunk| big_to_fr::<E>(BigUint::from_bytes_be(chunk)))
+        .collect()
+}
+
+/// expand_message_xmd of RFC 9380 section 5.3.1 with sha256
+pub fn expand_message_xmd(msg: &[u8], dst: &[u8], len_in_bytes: usize) -> Vec<u8> {
+    let mut msg_hasher = xmd_start();
+    msg_hasher.update(msg);
+    xmd_finish(msg_hasher, dst, len_in_bytes)
+}
+
+// msg_prime starts with a zero block, the message follows
+fn xmd_start() -> Sha256 {
     let mut hasher = Sha256::new();
-    hasher.update(PREFIX_RLN_HASH_TO_FIELD);
+    hasher.update(&[0u8; S_IN_BYTES][..]);
+    hasher
+}
+
+fn xmd_finish(mut msg_hasher: Sha256, dst: &[u8], len_in_bytes: usize) -> Vec<u8> {
+    let ell = (len_in_bytes + B_IN_BYTES - 1) / B_IN_BYTES;
+    assert!(
+        ell <= 255 && len_in_bytes <= 65535,
+        "requested output is too long"
+    );
+
+    let oversize_dst: Vec<u8>;
+    let dst = if dst.len() > 255 {
+        let mut hasher = Sha256::new();
+        hasher.update(b"H2C-OVERSIZE-DST-");
+        hasher.update(dst);
+        oversize_dst = hasher.finalize().to_vec();
+        &oversize_dst[..]
+    } else {
+        dst
+    };
+    let mut dst_prime = dst.to_vec();
+    dst_prime.push(dst.len() as u8);
+
+    msg_hasher.update(&(len_in_bytes as u16).to_be_bytes()[..]);
+    msg_hasher.update(&[0u8][..]);
+    msg_hasher.update(&dst_prime[..]);
+    let b_0 = msg_hasher.finalize();
+
+    let mut hasher = Sha256::new();
+    hasher.update(&b_0[..]);
+    hasher.update(&[1u8][..]);
+    hasher.update(&dst_prime[..]);
+    let mut b_i = hasher.finalize();
+
+    let mut uniform_bytes: Vec<u8> = Vec::with_capacity(ell * B_IN_BYTES);
+    uniform_bytes.extend_from_slice(&b_i[..]);
+    for i in 2..=ell {
+        let xored: Vec<u8> = b_0.iter().zip(b_i.iter()).map(|(a, b)| a ^ b).collect();
+        let mut hasher = Sha256::new();
+        hasher.update(&xored[..]);
+        hasher.update(&[i as u8][..]);
+        hasher.update(&dst_prime[..]);
+        b_i = hasher.finalize();
+        uniform_bytes.extend_from_slice(&b_i[..]);
+    }
+    uniform_bytes.truncate(len_in_bytes);
+    uniform_bytes
+}
+
+/// the original construction, two prefixed sha256 outputs combined into 512 bits
+pub fn hash_to_field_legacy<E: Engine>(data: &[u8]) -> io::Result<<E as ScalarEngine>::Fr> {
+    let mut hasher = legacy_start();
     hasher.update(data);
+    legacy_finish::<E>(hasher)
+}
 
+fn legacy_start() -> Sha256 {
+    let mut hasher = Sha256::new();
+    hasher.update(PREFIX_RLN_HASH_TO_FIELD);
+    hasher
+}
+
+fn legacy_finish<E: Engine>(hasher: Sha256) -> io::Result<E::Fr> {
     let mut hasher_to_lo = hasher.clone();
     let mut hasher_to_hi = hasher.clone();
 
@@ -28,7 +239,6 @@ pub fn hash_to_field<E: Engine>(data: &[u8]) -> <E as ScalarEngine>::Fr {
     let lo = &BigUint::from_bytes_le(&result_1[..]);
     let hi = &BigUint::from_bytes_le(&result_2[..]);
 
-    // FIX: use const R size
     let combined: BigUint = lo + hi * (BigUint::from(1usize) << 256);
 
     big_to_fr::<E>(combined)
@@ -42,10 +252,130 @@ fn big_modulus<E: Engine>() -> BigUint {
     modulus
 }
 
-fn big_to_fr<E: Engine>(e: BigUint) -> E::Fr {
+// reduced values shorter than the repr, zero among them, are padded before decoding
+fn big_to_fr<E: Engine>(e: BigUint) -> io::Result<E::Fr> {
     let e = e % big_modulus::<E>();
-    let e = e.to_bytes_le();
     let mut buf = <E::Fr as PrimeField>::Repr::default();
-    buf.read_le(&e[..]).unwrap();
-    E::Fr::from_repr(buf).unwrap()
+    let mut bytes = e.to_bytes_le();
+    bytes.resize(buf.as_ref().len() * 8, 0);
+    buf.read_le(&bytes[..])?;
+    E::Fr::from_repr(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bellman::pairing::bn256::{Bn256, Fr};
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_expand_message_xmd_vectors() {
+        // RFC 9380 appendix K.1
+        let dst = b"QUUX-V01-CS02-with-expander-SHA256-128";
+        assert_eq!(
+            to_hex(&expand_message_xmd(b"", dst, 0x20)),
+            "68a985b87eb6b46952128911f2a4412bbc302a9d759667f87f7a21d803f07235"
+        );
+        assert_eq!(
+            to_hex(&expand_message_xmd(b"abc", dst, 0x20)),
+            "d8ccab23b5985ccea865c6c97b6e5b8350e794e603b4b97902f53a8a0d605615"
+        );
+        assert_eq!(expand_message_xmd(b"", dst, 0x80).len(), 0x80);
+    }
+
+    #[test]
+    fn test_hash_to_field_vectors() {
+        let cases: Vec<(&[u8], &str)> = vec![
+            (
+                b"",
+                "9802270570539168606989653623293394230603548938366763962423680177609042147666",
+            ),
+            (
+                b"abc",
+                "12994108163428249607403171774930370188192325501946856132364883626336331137177",
+            ),
+            // reduces to fewer than 32 bytes
+            (
+                b"40",
+                "416121538368702336377814395164532534466555979147241785652344105910103440017",
+            ),
+        ];
+        for (data, expected) in cases.into_iter() {
+            let result = hash_to_field_xmd::<Bn256>(data, DEFAULT_DST, 1).unwrap();
+            assert_eq!(result, vec![Fr::from_str(expected).unwrap()]);
+        }
+
+        let result = hash_to_field_xmd::<Bn256>(b"rln signal", DEFAULT_DST, 2).unwrap();
+        assert_eq!(
+            result,
+            vec![
+                Fr::from_str(
+                    "11233422463527651314863930272238365443272722240432932608577290871749450495969"
+                )
+                .unwrap(),
+                Fr::from_str(
+                    "9109944484448166381131599140820088035237087782316284845423839835816532968606"
+                )
+                .unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keccak_signal_hasher() {
+        // keccak256("") = c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470
+        assert_eq!(
+            SignalHasher::Keccak256.hash::<Bn256>(b"").unwrap(),
+            Fr::from_str(
+                "1924180730567573949438414972962865885128629851683618892617351438379423999084"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            SignalHasher::Sha256.hash::<Bn256>(b"abc").unwrap(),
+            hash_to_field::<Bn256>(b"abc").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_signal_hash_reader() {
+        let signal: Vec<u8> = (0..10000u32).map(|i| i as u8).collect();
+        for signal_hasher in [SignalHasher::Sha256, SignalHasher::Keccak256].iter() {
+            let expected = signal_hasher.hash::<Bn256>(&signal[..]).unwrap();
+            let result = signal_hasher
+                .hash_reader::<Bn256, _>(&signal[..], signal.len() as u64)
+                .unwrap();
+            assert_eq!(result, expected);
+
+            let err = signal_hasher
+                .hash_reader::<Bn256, _>(&signal[..], signal.len() as u64 + 1)
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+        assert_eq!(
+            SignalHasher::Sha256.hash::<Bn256>(&signal[..]).unwrap(),
+            hash_to_field::<Bn256>(&signal[..]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_hash_to_field_legacy_vectors() {
+        assert_eq!(
+            hash_to_field_legacy::<Bn256>(b"").unwrap(),
+            Fr::from_str(
+                "8780301782033286752304992985948920097078544564950506122935615813722664705229"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            hash_to_field_legacy::<Bn256>(b"abc").unwrap(),
+            Fr::from_str(
+                "13576398779358764978950534715314731125464794410383248374618176620935937593706"
+            )
+            .unwrap()
+        );
+    }
 }
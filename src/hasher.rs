@@ -0,0 +1,279 @@
+//! Hashes the membership tree and the circuit can be built over.
+//!
+//! A `ZkHasher` pairs a native hash with the gadget computing the same function in
+//! the circuit. The poseidon of the crate and the one of circomlib are shipped,
+//! other arithmetization friendly hashes such as MiMC or Rescue plug into `MerkleTree`
+//! and `RLNCircuit` by implementing both traits.
+//! `TreeHasher` picks one of the two shipped ones at runtime, `RLN` builds semaphore
+//! trees with the circomlib poseidon so their roots agree with the ones of semaphore.
+
+use crate::circuit::poseidon::{CircomPoseidonCircuit, PoseidonCircuit};
+use crate::poseidon::circom::CircomPoseidon;
+use crate::poseidon::Poseidon;
+use sapling_crypto::bellman::pairing::Engine;
+use sapling_crypto::bellman::{ConstraintSystem, SynthesisError};
+use sapling_crypto::circuit::num;
+
+pub trait ZkHasher<E: Engine>: Clone + Send + Sync {
+    type Gadget: ZkHasherGadget<E>;
+
+    /// hashes less than `width` inputs
+    fn hash(&self, inputs: Vec<E::Fr>) -> E::Fr;
+
+    /// hashes each input independently
+    fn hash_batch(&self, inputs: &[Vec<E::Fr>]) -> Vec<E::Fr> {
+        inputs
+            .iter()
+            .map(|input| self.hash(input.clone()))
+            .collect()
+    }
+
+    /// one more than the maximum number of inputs of a single hash
+    fn width(&self) -> usize;
+
+    /// digest identifying the hash function, trees over hashers of the same digest
+    /// share their empty nodes, `None` computes them for every tree
+    fn digest(&self) -> Option<[u8; 32]> {
+        None
+    }
+
+    /// circuit computing the same hash
+    fn gadget(&self) -> Self::Gadget;
+}
+
+pub trait ZkHasherGadget<E: Engine>: Clone {
+    /// hashes already allocated inputs
+    fn alloc<CS: ConstraintSystem<E>>(
+        &self,
+        cs: CS,
+        inputs: Vec<num::AllocatedNum<E>>,
+    ) -> Result<num::AllocatedNum<E>, SynthesisError>;
+
+    fn width(&self) -> usize;
+}
+
+impl<E: Engine> ZkHasher<E> for Poseidon<E> {
+    type Gadget = PoseidonCircuit<E>;
+
+    fn hash(&self, inputs: Vec<E::Fr>) -> E::Fr {
+        Poseidon::hash(self, inputs)
+    }
+
+    fn hash_batch(&self, inputs: &[Vec<E::Fr>]) -> Vec<E::Fr> {
+        Poseidon::hash_batch(self, inputs)
+    }
+
+    fn width(&self) -> usize {
+        Poseidon::width(self)
+    }
+
+    fn digest(&self) -> Option<[u8; 32]> {
+        Some(Poseidon::digest(self))
+    }
+
+    fn gadget(&self) -> PoseidonCircuit<E> {
+        PoseidonCircuit::from_hasher(self)
+    }
+}
+
+impl<E: Engine> ZkHasherGadget<E> for PoseidonCircuit<E> {
+    fn alloc<CS: ConstraintSystem<E>>(
+        &self,
+        cs: CS,
+        inputs: Vec<num::AllocatedNum<E>>,
+    ) -> Result<num::AllocatedNum<E>, SynthesisError> {
+        PoseidonCircuit::alloc(self, cs, inputs)
+    }
+
+    fn width(&self) -> usize {
+        PoseidonCircuit::width(self)
+    }
+}
+
+impl<E: Engine> ZkHasher<E> for CircomPoseidon<E> {
+    type Gadget = CircomPoseidonCircuit<E>;
+
+    /// hashes exactly `width - 1` inputs, circomlib does not pad
+    fn hash(&self, inputs: Vec<E::Fr>) -> E::Fr {
+        CircomPoseidon::hash(self, &inputs)
+    }
+
+    fn width(&self) -> usize {
+        CircomPoseidon::width(self)
+    }
+
+    fn digest(&self) -> Option<[u8; 32]> {
+        Some(CircomPoseidon::digest(self))
+    }
+
+    fn gadget(&self) -> CircomPoseidonCircuit<E> {
+        CircomPoseidonCircuit::new(self.clone())
+    }
+}
+
+impl<E: Engine> ZkHasherGadget<E> for CircomPoseidonCircuit<E> {
+    /// fails with `Unsatisfiable` unless there are exactly `width - 1` inputs
+    fn alloc<CS: ConstraintSystem<E>>(
+        &self,
+        cs: CS,
+        inputs: Vec<num::AllocatedNum<E>>,
+    ) -> Result<num::AllocatedNum<E>, SynthesisError> {
+        if inputs.len() + 1 != CircomPoseidonCircuit::width(self) {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+        CircomPoseidonCircuit::alloc(self, cs, inputs)
+    }
+
+    fn width(&self) -> usize {
+        CircomPoseidonCircuit::width(self)
+    }
+}
+
+/// one of the hashers shipped with the crate, chosen at runtime
+#[derive(Clone)]
+pub enum TreeHasher<E: Engine> {
+    Poseidon(Poseidon<E>),
+    Circom(CircomPoseidon<E>),
+}
+
+/// gadget of a `TreeHasher`
+#[derive(Clone)]
+pub enum TreeHasherGadget<E: Engine> {
+    Poseidon(PoseidonCircuit<E>),
+    Circom(CircomPoseidonCircuit<E>),
+}
+
+impl<E: Engine> ZkHasher<E> for TreeHasher<E> {
+    type Gadget = TreeHasherGadget<E>;
+
+    fn hash(&self, inputs: Vec<E::Fr>) -> E::Fr {
+        match self {
+            TreeHasher::Poseidon(hasher) => ZkHasher::hash(hasher, inputs),
+            TreeHasher::Circom(hasher) => ZkHasher::hash(hasher, inputs),
+        }
+    }
+
+    fn hash_batch(&self, inputs: &[Vec<E::Fr>]) -> Vec<E::Fr> {
+        match self {
+            TreeHasher::Poseidon(hasher) => ZkHasher::hash_batch(hasher, inputs),
+            TreeHasher::Circom(hasher) => ZkHasher::hash_batch(hasher, inputs),
+        }
+    }
+
+    fn width(&self) -> usize {
+        match self {
+            TreeHasher::Poseidon(hasher) => ZkHasher::width(hasher),
+            TreeHasher::Circom(hasher) => ZkHasher::width(hasher),
+        }
+    }
+
+    fn digest(&self) -> Option<[u8; 32]> {
+        match self {
+            TreeHasher::Poseidon(hasher) => ZkHasher::digest(hasher),
+            TreeHasher::Circom(hasher) => ZkHasher::digest(hasher),
+        }
+    }
+
+    fn gadget(&self) -> TreeHasherGadget<E> {
+        match self {
+            TreeHasher::Poseidon(hasher) => TreeHasherGadget::Poseidon(hasher.gadget()),
+            TreeHasher::Circom(hasher) => TreeHasherGadget::Circom(hasher.gadget()),
+        }
+    }
+}
+
+impl<E: Engine> ZkHasherGadget<E> for TreeHasherGadget<E> {
+    fn alloc<CS: ConstraintSystem<E>>(
+        &self,
+        cs: CS,
+        inputs: Vec<num::AllocatedNum<E>>,
+    ) -> Result<num::AllocatedNum<E>, SynthesisError> {
+        match self {
+            TreeHasherGadget::Poseidon(gadget) => ZkHasherGadget::alloc(gadget, cs, inputs),
+            TreeHasherGadget::Circom(gadget) => ZkHasherGadget::alloc(gadget, cs, inputs),
+        }
+    }
+
+    fn width(&self) -> usize {
+        match self {
+            TreeHasherGadget::Poseidon(gadget) => ZkHasherGadget::width(gadget),
+            TreeHasherGadget::Circom(gadget) => ZkHasherGadget::width(gadget),
+        }
+    }
+}
+
+#[test]
+fn test_poseidon_gadget() {
+    use crate::poseidon::PoseidonParams;
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    use sapling_crypto::bellman::pairing::ff::PrimeField;
+    use sapling_crypto::circuit::test::TestConstraintSystem;
+
+    let hasher = Poseidon::<Bn256>::new(PoseidonParams::new(8, 55, 3, None, None, None));
+    let inputs = vec![Fr::from_str("1").unwrap(), Fr::from_str("2").unwrap()];
+    let expected = ZkHasher::hash(&hasher, inputs.clone());
+
+    let mut cs = TestConstraintSystem::<Bn256>::new();
+    let allocated = inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| {
+            num::AllocatedNum::alloc(cs.namespace(|| format!("input {}", i)), || Ok(*input))
+                .unwrap()
+        })
+        .collect();
+    let gadget = hasher.gadget();
+    let hash = gadget.alloc(cs.namespace(|| "hash"), allocated).unwrap();
+    assert!(cs.is_satisfied());
+    assert_eq!(hash.get_value().unwrap(), expected);
+}
+
+#[test]
+fn test_circom_poseidon_tree() {
+    use crate::merkle::MerkleTree;
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    use sapling_crypto::bellman::pairing::ff::{Field, PrimeField};
+    use sapling_crypto::circuit::test::TestConstraintSystem;
+
+    let hasher = CircomPoseidon::<Bn256>::new(2).unwrap();
+    // `poseidon([0, 0])` of circomlibjs, the first empty node of semaphore trees
+    let zero = Fr::from_str(
+        "14744269619966411208579211824598458697587494354926760081771325075741142829156",
+    )
+    .unwrap();
+    assert_eq!(ZkHasher::hash(&hasher, vec![Fr::zero(), Fr::zero()]), zero);
+    assert_ne!(
+        ZkHasher::digest(&hasher),
+        ZkHasher::digest(&TreeHasher::Poseidon(Poseidon::<Bn256>::new(
+            crate::poseidon::PoseidonParams::new(8, 55, 3, None, None, None)
+        )))
+    );
+
+    let tree_hasher = TreeHasher::Circom(hasher.clone());
+    let mut tree = MerkleTree::<Bn256, _>::empty(tree_hasher.clone(), 2);
+    assert_eq!(tree.get_root(), hasher.hash(&[zero, zero]));
+    let leaf = Fr::from_str("7").unwrap();
+    tree.update(1, leaf).unwrap();
+    assert_eq!(
+        tree.get_root(),
+        hasher.hash(&[hasher.hash(&[Fr::zero(), leaf]), zero])
+    );
+
+    let mut cs = TestConstraintSystem::<Bn256>::new();
+    let allocated = vec![Fr::zero(), leaf]
+        .into_iter()
+        .enumerate()
+        .map(|(i, input)| {
+            num::AllocatedNum::alloc(cs.namespace(|| format!("input {}", i)), || Ok(input)).unwrap()
+        })
+        .collect::<Vec<_>>();
+    let gadget = tree_hasher.gadget();
+    let hash = gadget
+        .alloc(cs.namespace(|| "hash"), allocated.clone())
+        .unwrap();
+    assert!(cs.is_satisfied());
+    assert_eq!(hash.get_value().unwrap(), hasher.hash(&[Fr::zero(), leaf]));
+    assert!(gadget
+        .alloc(cs.namespace(|| "too many"), vec![allocated[0].clone(); 4])
+        .is_err());
+}
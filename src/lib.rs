@@ -1,15 +1,64 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
 
+// without `verifier` only the verifier core is built
+pub mod verifier_core;
+
+#[cfg(feature = "verifier")]
+pub mod bundle;
+#[cfg(feature = "verifier")]
+pub mod cache;
+#[cfg(feature = "verifier")]
 pub mod circuit;
-mod hash_to_field;
+#[cfg(feature = "verifier")]
+pub mod ethereum;
+#[cfg(all(feature = "fuzzing", not(target_arch = "wasm32")))]
+pub mod fuzz;
+#[cfg(feature = "verifier")]
+pub mod hash_to_field;
+#[cfg(feature = "verifier")]
+pub mod hasher;
+#[cfg(feature = "verifier")]
+pub mod manager;
+#[cfg(feature = "verifier")]
 pub mod merkle;
+#[cfg(feature = "verifier")]
+pub mod metrics;
+#[cfg(feature = "verifier")]
+pub mod nullifier;
+#[cfg(feature = "verifier")]
 pub mod poseidon;
+#[cfg(feature = "verifier")]
 pub mod public;
-mod utils;
+#[cfg(feature = "verifier")]
+pub mod rate_limit;
+#[cfg(feature = "ethereum")]
+pub mod registry;
+#[cfg(all(feature = "verifier", feature = "serde"))]
+pub mod serde_utils;
+#[cfg(feature = "server")]
+pub mod service;
+#[cfg(feature = "prover")]
+pub mod setup;
+#[cfg(feature = "snarkjs")]
+pub mod snarkjs;
+#[cfg(feature = "verifier")]
+mod telemetry;
+#[cfg(all(feature = "verifier", any(test, feature = "test_utils")))]
+pub mod test_utils;
+#[cfg(feature = "verifier")]
+pub mod utils;
+#[cfg(feature = "prover")]
+pub mod vectors;
+#[cfg(feature = "verifier")]
+pub mod waku;
 
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(all(feature = "ffi", not(target_arch = "wasm32")))]
 pub mod ffi;
+#[cfg(all(feature = "mobile", not(target_arch = "wasm32")))]
+pub mod mobile;
+#[cfg(all(feature = "node", not(target_arch = "wasm32")))]
+pub mod node;
 
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(feature = "wasm-verifier", target_arch = "wasm32"))]
 mod wasm;
@@ -0,0 +1,214 @@
+//! Several membership groups over one set of circuit parameters.
+//!
+//! Every group is an `RLN` instance with its own tree, rln identifier and nullifier log,
+//! all of them hold the same `Arc` of the proving key, so a relay serving many
+//! applications or shards keeps a single copy of it in memory.
+
+use crate::merkle::TreeArity;
+use crate::nullifier::LogStatus;
+use crate::poseidon::PoseidonParams;
+use crate::public::RLN;
+use bellman::groth16::{prepare_verifying_key, Parameters, PreparedVerifyingKey};
+use bellman::pairing::Engine;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, PoisonError, RwLock};
+
+pub struct RLNManager<E: Engine> {
+    merkle_depth: usize,
+    arity: TreeArity,
+    circuit_parameters: Arc<Parameters<E>>,
+    verifying_key: Arc<PreparedVerifyingKey<E>>,
+    poseidon_params: Option<PoseidonParams<E>>,
+    groups: RwLock<HashMap<String, Arc<RLN<E>>>>,
+}
+
+impl<E: Engine> RLNManager<E> {
+    /// groups are created with `new_with_shared_keys` of the same arguments
+    pub fn new(
+        merkle_depth: usize,
+        arity: TreeArity,
+        circuit_parameters: Arc<Parameters<E>>,
+        poseidon_params: Option<PoseidonParams<E>>,
+    ) -> RLNManager<E> {
+        let verifying_key = Arc::new(prepare_verifying_key(&circuit_parameters.vk));
+        RLNManager {
+            merkle_depth,
+            arity,
+            circuit_parameters,
+            verifying_key,
+            poseidon_params,
+            groups: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn new_with_raw_params<R: Read>(
+        merkle_depth: usize,
+        arity: TreeArity,
+        raw_circuit_parameters: R,
+        poseidon_params: Option<PoseidonParams<E>>,
+    ) -> io::Result<RLNManager<E>> {
+        let circuit_parameters = Parameters::<E>::read(raw_circuit_parameters, true)?;
+        Ok(Self::new(
+            merkle_depth,
+            arity,
+            Arc::new(circuit_parameters),
+            poseidon_params,
+        ))
+    }
+
+    pub fn circuit_parameters(&self) -> &Arc<Parameters<E>> {
+        &self.circuit_parameters
+    }
+
+    /// creates an empty group bound to `rln_identifier`
+    /// * fails if a group of the same name exists
+    pub fn add_group(&self, name: &str, rln_identifier: E::Fr) -> io::Result<Arc<RLN<E>>> {
+        let mut groups = self.groups.write().unwrap_or_else(PoisonError::into_inner);
+        if groups.contains_key(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "group already exists",
+            ));
+        }
+        let mut rln = RLN::new_with_shared_keys(
+            self.merkle_depth,
+            self.arity,
+            self.circuit_parameters.clone(),
+            self.verifying_key.clone(),
+            self.poseidon_params.clone(),
+        )?;
+        rln.set_rln_identifier(rln_identifier);
+        let rln = Arc::new(rln);
+        groups.insert(name.to_string(), rln.clone());
+        Ok(rln)
+    }
+
+    /// drops the group from the manager, it lives on while other handles to it exist
+    pub fn remove_group(&self, name: &str) -> Option<Arc<RLN<E>>> {
+        let mut groups = self.groups.write().unwrap_or_else(PoisonError::into_inner);
+        groups.remove(name)
+    }
+
+    pub fn group(&self, name: &str) -> Option<Arc<RLN<E>>> {
+        let groups = self.groups.read().unwrap_or_else(PoisonError::into_inner);
+        groups.get(name).cloned()
+    }
+
+    /// names of all groups in no particular order
+    pub fn group_names(&self) -> Vec<String> {
+        let groups = self.groups.read().unwrap_or_else(PoisonError::into_inner);
+        groups.keys().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// see `RLN::update_next_member`
+    pub fn update_next_member<R: Read>(&self, name: &str, input_data: R) -> io::Result<u64> {
+        self.get(name)?.update_next_member(input_data)
+    }
+
+    /// see `RLN::generate_proof`
+    pub fn generate_proof<R: Read, W: Write>(
+        &self,
+        name: &str,
+        input_data: R,
+        result_data: W,
+    ) -> io::Result<()> {
+        self.get(name)?.generate_proof(input_data, result_data)
+    }
+
+    /// see `RLN::verify`
+    pub fn verify<R: Read>(&self, name: &str, input_data: R) -> io::Result<bool> {
+        self.get(name)?.verify(input_data)
+    }
+
+    /// see `RLN::nullifier_log_insert`
+    pub fn nullifier_log_insert<R: Read>(
+        &self,
+        name: &str,
+        proof_data: R,
+    ) -> io::Result<LogStatus<E>> {
+        self.get(name)?.nullifier_log_insert(proof_data)
+    }
+
+    fn get(&self, name: &str) -> io::Result<Arc<RLN<E>>> {
+        self.group(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unknown group"))
+    }
+}
+
+#[test]
+fn test_manager() {
+    use crate::test_utils::{proof_input, register_member};
+    use bellman::pairing::bn256::{Bn256, Fr};
+    use bellman::pairing::ff::{Field, PrimeField};
+    use byteorder::{LittleEndian, WriteBytesExt};
+
+    let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let mut raw_params: Vec<u8> = Vec::new();
+    rln.export_circuit_parameters(&mut raw_params).unwrap();
+    let manager =
+        RLNManager::<Bn256>::new_with_raw_params(3, TreeArity::Binary, raw_params.as_slice(), None)
+            .unwrap();
+    manager
+        .add_group("chat", Fr::from_str("1").unwrap())
+        .unwrap();
+    manager
+        .add_group("votes", Fr::from_str("2").unwrap())
+        .unwrap();
+    assert!(manager.add_group("chat", Fr::one()).is_err());
+    assert_eq!(manager.len(), 2);
+    assert_eq!(Arc::strong_count(manager.circuit_parameters()), 3);
+    assert_eq!(Arc::strong_count(&manager.verifying_key), 3);
+
+    let id_key = Fr::from_str("1001").unwrap();
+    let signal = b"hello";
+    let input_data = proof_input::<Bn256>(id_key, 0, Fr::one(), signal);
+    for name in ["chat", "votes"].iter() {
+        let group = manager.get(name).unwrap();
+        assert_eq!(
+            register_member(&group, id_key, Fr::one(), signal),
+            input_data
+        );
+    }
+
+    let mut proof: Vec<u8> = Vec::new();
+    manager
+        .generate_proof("chat", input_data.as_slice(), &mut proof)
+        .unwrap();
+    let mut verify_data = proof.clone();
+    verify_data
+        .write_u64::<LittleEndian>(signal.len() as u64)
+        .unwrap();
+    verify_data.write_all(signal).unwrap();
+    assert!(manager.verify("chat", verify_data.as_slice()).unwrap());
+    assert!(!manager.verify("votes", verify_data.as_slice()).unwrap());
+
+    let status = manager
+        .nullifier_log_insert("chat", proof.as_slice())
+        .unwrap();
+    assert_eq!(status, LogStatus::New);
+    let status = manager
+        .nullifier_log_insert("chat", proof.as_slice())
+        .unwrap();
+    assert_eq!(status, LogStatus::Duplicate);
+    let status = manager
+        .nullifier_log_insert("votes", proof.as_slice())
+        .unwrap();
+    assert_eq!(status, LogStatus::New);
+
+    assert!(manager.remove_group("votes").is_some());
+    let err = manager.verify("votes", verify_data.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert_eq!(manager.group_names(), vec!["chat".to_string()]);
+}
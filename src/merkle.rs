@@ -1,59 +1,503 @@
+use crate::hasher::ZkHasher;
 use crate::poseidon::{Poseidon as Hasher, PoseidonParams};
+use crate::telemetry;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use lazy_static::lazy_static;
 use sapling_crypto::bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr};
 use sapling_crypto::bellman::pairing::Engine;
-use std::io::{self, Error, ErrorKind};
-use std::{collections::HashMap, hash::Hash};
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::{
+    any::{Any, TypeId},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    hash::Hash,
+    mem,
+    sync::{Mutex, PoisonError},
+};
 
 enum SyncMode {
     Bootstarp,
     Maintain,
 }
 
-pub struct IncrementalMerkleTree<E>
+/// number of children of each node
+/// * `Binary` trees hash couples with a poseidon of width 3
+/// * `Quad` trees hash four children with a poseidon of width 5
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TreeArity {
+    Binary,
+    Quad,
+}
+
+impl Default for TreeArity {
+    fn default() -> Self {
+        TreeArity::Binary
+    }
+}
+
+impl TreeArity {
+    pub fn width(&self) -> usize {
+        match self {
+            TreeArity::Binary => 2,
+            TreeArity::Quad => 4,
+        }
+    }
+
+    fn log_width(&self) -> u32 {
+        match self {
+            TreeArity::Binary => 1,
+            TreeArity::Quad => 2,
+        }
+    }
+}
+
+/// membership change as logged by a registry contract
+#[derive(Clone, Debug)]
+pub enum MemberEvent<E: Engine> {
+    Insert { index: u64, commitment: E::Fr },
+    Delete { index: u64 },
+}
+
+impl<E: Engine> MemberEvent<E> {
+    /// reads a list of events
+    /// * serialized as [ n<8> | event * n ] where an event is
+    /// [ 0<1> | index<8> | commitment<32> ] for inserts and [ 1<1> | index<8> ] for deletes
+    pub fn read_events<R: Read>(mut reader: R) -> io::Result<Vec<MemberEvent<E>>> {
+        let n = reader.read_u64::<LittleEndian>()?;
+        let mut events = Vec::new();
+        for _ in 0..n {
+            let kind = reader.read_u8()?;
+            let index = reader.read_u64::<LittleEndian>()?;
+            let event = match kind {
+                0 => {
+                    let mut repr = <E::Fr as PrimeField>::Repr::default();
+                    repr.read_le(&mut reader)?;
+                    let commitment = E::Fr::from_repr(repr)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    MemberEvent::Insert { index, commitment }
+                }
+                1 => MemberEvent::Delete { index },
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unknown member event",
+                    ))
+                }
+            };
+            events.push(event);
+        }
+        Ok(events)
+    }
+}
+
+pub struct IncrementalMerkleTree<E, H = Hasher<E>>
 where
     E: Engine,
+    H: ZkHasher<E>,
 {
-    pub current_index: usize,
-    merkle_tree: MerkleTree<E>,
+    pub current_index: u64,
+    merkle_tree: MerkleTree<E, H>,
+    // leaf indexes changed since the last `take_changes`, when tracked
+    changes: Option<BTreeSet<u64>>,
+    // indexes of occupied leaves by leaf, for `index_of`
+    leaf_indexes: HashMap<Vec<u64>, BTreeSet<u64>>,
+    // latest roots, current one last
+    roots: VecDeque<E::Fr>,
+    root_history_size: usize,
+}
+
+// field, hasher digest, arity and default leaf of a ladder of empty nodes
+type EmptyNodesKey = (TypeId, [u8; 32], usize, Vec<u8>);
+
+lazy_static! {
+    // empty nodes of each level leaf first, shared by all trees of the same key
+    // and extended when a deeper tree needs more levels
+    static ref EMPTY_NODES: Mutex<HashMap<EmptyNodesKey, Box<dyn Any + Send + Sync>>> =
+        Mutex::new(HashMap::new());
 }
 
-impl<E> IncrementalMerkleTree<E>
+/// least number of subtrees batch writes are split into, see `MerkleTree::write_leaves`
+const MIN_SUBTREES: u64 = 16;
+
+/// number of latest roots an `IncrementalMerkleTree` accepts unless changed,
+/// so proofs made just before a membership change still check
+pub const DEFAULT_ROOT_HISTORY_SIZE: usize = 5;
+
+impl<E, H> IncrementalMerkleTree<E, H>
 where
     E: Engine,
+    H: ZkHasher<E>,
 {
-    pub fn empty(hasher: Hasher<E>, depth: usize) -> Self {
-        let mut zero: Vec<E::Fr> = Vec::with_capacity(depth + 1);
-        zero.push(E::Fr::from_str("0").unwrap());
-        for i in 0..depth {
-            zero.push(hasher.hash([zero[i]; 2].to_vec()));
-        }
-        zero.reverse();
-        let merkle_tree = MerkleTree {
-            hasher: hasher,
-            zero: zero.clone(),
-            depth: depth,
-            nodes: HashMap::new(),
-        };
-        let current_index: usize = 0;
-        IncrementalMerkleTree {
+    pub fn empty(hasher: H, depth: usize) -> Self {
+        Self::empty_with_arity(hasher, depth, TreeArity::Binary)
+    }
+
+    pub fn empty_with_arity(hasher: H, depth: usize, arity: TreeArity) -> Self {
+        Self::empty_with_default_leaf(hasher, depth, arity, E::Fr::zero())
+    }
+
+    /// empty tree whose empty slots hold `default_leaf`, see `MerkleTree::empty_with_default_leaf`
+    pub fn empty_with_default_leaf(
+        hasher: H,
+        depth: usize,
+        arity: TreeArity,
+        default_leaf: E::Fr,
+    ) -> Self {
+        let merkle_tree = MerkleTree::empty_with_default_leaf(hasher, depth, arity, default_leaf);
+        let current_index: u64 = 0;
+        let mut tree = IncrementalMerkleTree {
             current_index,
             merkle_tree,
-        }
+            changes: None,
+            leaf_indexes: HashMap::new(),
+            roots: VecDeque::new(),
+            root_history_size: DEFAULT_ROOT_HISTORY_SIZE,
+        };
+        tree.record_root();
+        tree
+    }
+
+    /// rebuilds a tree with the leaves at indexes `0..leaves.len()`
+    pub fn from_leaves(
+        hasher: H,
+        depth: usize,
+        arity: TreeArity,
+        leaves: &[E::Fr],
+    ) -> io::Result<Self> {
+        let merkle_tree = MerkleTree::from_leaves(hasher, depth, arity, leaves)?;
+        let mut tree = IncrementalMerkleTree {
+            current_index: leaves.len() as u64,
+            merkle_tree,
+            changes: None,
+            leaf_indexes: HashMap::new(),
+            roots: VecDeque::new(),
+            root_history_size: DEFAULT_ROOT_HISTORY_SIZE,
+        };
+        tree.reindex_leaves();
+        tree.record_root();
+        Ok(tree)
     }
 
-    pub fn update_next(&mut self, leaf: E::Fr) -> io::Result<()> {
-        self.merkle_tree.update(self.current_index, leaf)?;
+    /// appends the leaf and returns the index it is assigned to
+    pub fn update_next(&mut self, leaf: E::Fr) -> io::Result<u64> {
+        let _step = telemetry::step(telemetry::TREE_UPDATE);
+        let index = self.current_index;
+        self.merkle_tree.update(index, leaf)?;
         self.current_index += 1;
+        self.index_leaf(index);
+        self.record_change(index);
+        self.record_root();
+        Ok(index)
+    }
+
+    /// applies the events in order and returns the new root
+    /// * inserts may land past the incremental index, which then moves after them
+    /// * the whole batch is checked before any leaf is written
+    /// and nodes above changed leaves are hashed once per batch
+    pub fn apply_events<I: IntoIterator<Item = MemberEvent<E>>>(
+        &mut self,
+        events: I,
+    ) -> io::Result<E::Fr> {
+        let mut occupied: HashMap<u64, bool> = HashMap::new();
+        let mut leaves: HashMap<u64, Option<E::Fr>> = HashMap::new();
+        let mut current_index = self.current_index;
+        for event in events.into_iter() {
+            let index = match event {
+                MemberEvent::Insert { index, .. } => index,
+                MemberEvent::Delete { index } => index,
+            };
+            self.merkle_tree.check_index(index)?;
+            let is_occupied = *occupied
+                .get(&index)
+                .unwrap_or(&self.merkle_tree.is_occupied(index));
+            match event {
+                MemberEvent::Insert { index, commitment } => {
+                    if is_occupied {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            "slot is occupied",
+                        ));
+                    }
+                    occupied.insert(index, true);
+                    leaves.insert(index, Some(commitment));
+                    current_index = current_index.max(index + 1);
+                }
+                MemberEvent::Delete { index } => {
+                    if !is_occupied {
+                        return Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            "slot is not occupied",
+                        ));
+                    }
+                    occupied.insert(index, false);
+                    leaves.insert(index, None);
+                }
+            }
+        }
+        let _step = telemetry::step(telemetry::TREE_UPDATE);
+        let indexes: Vec<u64> = leaves.keys().copied().collect();
+        for index in indexes.iter() {
+            self.unindex_leaf(*index);
+            self.record_change(*index);
+        }
+        self.merkle_tree.write_leaves(leaves);
+        for index in indexes {
+            self.index_leaf(index);
+        }
+        self.current_index = current_index;
+        self.record_root();
+        Ok(self.get_root())
+    }
+
+    /// sets a leaf below the incremental index,
+    /// occupied slots are only overwritten with `force`
+    pub fn update(&mut self, index: u64, leaf: E::Fr, force: bool) -> io::Result<()> {
+        if index >= self.current_index {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index exceeds incremental index",
+            ));
+        }
+        let _step = telemetry::step(telemetry::TREE_UPDATE);
+        let previous = self.indexed_leaf(index);
+        self.merkle_tree.insert(index, leaf, force)?;
+        if let Some(previous) = previous {
+            self.remove_leaf_index(previous, index);
+        }
+        self.index_leaf(index);
+        self.record_change(index);
+        self.record_root();
+        Ok(())
+    }
+
+    /// empties the slot at the index and marks it reusable
+    pub fn delete(&mut self, index: u64) -> io::Result<()> {
+        let _step = telemetry::step(telemetry::TREE_UPDATE);
+        let previous = self.indexed_leaf(index);
+        self.merkle_tree.delete(index)?;
+        if let Some(previous) = previous {
+            self.remove_leaf_index(previous, index);
+        }
+        self.record_change(index);
+        self.record_root();
+        Ok(())
+    }
+
+    /// starts recording changed leaves for `take_changes`, so the tree can be
+    /// persisted incrementally and brought back with `restore`
+    pub fn track_changes(&mut self) {
+        if self.changes.is_none() {
+            self.changes = Some(BTreeSet::new());
+        }
+    }
+
+    /// records leaves as changed again, e.g. after persisting them failed
+    pub fn requeue_changes(&mut self, indexes: &[u64]) {
+        for index in indexes {
+            self.record_change(*index);
+        }
+    }
+
+    /// leaves changed since the last call, `None` for emptied slots
+    pub fn take_changes(&mut self) -> Vec<(u64, Option<E::Fr>)> {
+        let changes = match self.changes.as_mut() {
+            Some(changes) => std::mem::take(changes),
+            None => return vec![],
+        };
+        changes
+            .into_iter()
+            .map(|index| {
+                let leaf = if self.merkle_tree.is_occupied(index) {
+                    Some(self.merkle_tree.get_node(self.merkle_tree.depth, index))
+                } else {
+                    None
+                };
+                (index, leaf)
+            })
+            .collect()
+    }
+
+    /// replaces the content of the tree with persisted occupied leaves
+    pub fn restore(&mut self, current_index: u64, leaves: &[(u64, E::Fr)]) -> io::Result<()> {
+        if current_index > self.merkle_tree.set_size() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index exceeds set size",
+            ));
+        }
+        if leaves.iter().any(|(index, _)| *index >= current_index) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index exceeds incremental index",
+            ));
+        }
+        let mut merkle_tree = MerkleTree::empty_with_default_leaf(
+            self.merkle_tree.hasher.clone(),
+            self.merkle_tree.depth,
+            self.merkle_tree.arity,
+            self.merkle_tree.default_leaf(),
+        );
+        merkle_tree.write_leaves(leaves.iter().map(|(i, leaf)| (*i, Some(*leaf))).collect());
+        self.merkle_tree = merkle_tree;
+        self.current_index = current_index;
+        self.reindex_leaves();
+        if let Some(changes) = self.changes.as_mut() {
+            changes.clear();
+        }
+        self.roots.clear();
+        self.record_root();
         Ok(())
     }
 
-    pub fn delete(&mut self, index: usize) -> io::Result<()> {
-        let zero = E::Fr::from_str("0").unwrap();
-        self.merkle_tree.update(index, zero)?;
+    /// proves that the tree extends its state at incremental index `old_size` by appends,
+    /// see `verify_consistency`
+    /// * fails if the earlier state filled the tree, no append can follow it
+    pub fn consistency_proof(&self, old_size: u64) -> io::Result<ConsistencyProof<E>> {
+        if old_size > self.current_index {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index exceeds incremental index",
+            ));
+        }
+        let path = self.merkle_tree.get_path(old_size)?;
+        Ok(ConsistencyProof {
+            old_size,
+            leaf: self.merkle_tree.get_node(self.merkle_tree.depth, old_size),
+            siblings: path.into_iter().map(|(siblings, _)| siblings).collect(),
+        })
+    }
+
+    /// occupied leaves with their index, in index order
+    pub fn iter_leaves(&self) -> impl Iterator<Item = (u64, E::Fr)> + '_ {
+        let mut indexes: Vec<u64> = self.merkle_tree.occupied.iter().copied().collect();
+        indexes.sort_unstable();
+        let depth = self.merkle_tree.depth;
+        indexes
+            .into_iter()
+            .map(move |i| (i, self.merkle_tree.get_node(depth, i)))
+    }
+
+    /// writes the occupied leaves, enough for a peer to rebuild the tree with `import_leaves`
+    /// * serialized as [ current_index<8> | n<8> | (index<8> | leaf<32>) * n ] in index order
+    pub fn export_leaves<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u64::<LittleEndian>(self.current_index)?;
+        writer.write_u64::<LittleEndian>(self.member_count() as u64)?;
+        for (index, leaf) in self.iter_leaves() {
+            writer.write_u64::<LittleEndian>(index)?;
+            leaf.into_repr().write_le(&mut writer)?;
+        }
         Ok(())
     }
 
-    pub fn get_witness(&self, index: usize) -> io::Result<Vec<(E::Fr, bool)>> {
+    /// replaces the content of the tree with `export_leaves` output, see `restore`
+    /// * leaves must be in strictly increasing index order
+    pub fn import_leaves<R: Read>(&mut self, mut reader: R) -> io::Result<()> {
+        let current_index = reader.read_u64::<LittleEndian>()?;
+        let n = reader.read_u64::<LittleEndian>()?;
+        if n > current_index {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "more leaves than the incremental index",
+            ));
+        }
+        let mut leaves: Vec<(u64, E::Fr)> = Vec::new();
+        for _ in 0..n {
+            let index = reader.read_u64::<LittleEndian>()?;
+            if leaves.last().map_or(false, |(last, _)| *last >= index) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "leaves are not in index order",
+                ));
+            }
+            leaves.push((index, read_field::<E, _>(&mut reader)?));
+        }
+        self.restore(current_index, &leaves)
+    }
+
+    // leaf of an occupied slot
+    fn indexed_leaf(&self, index: u64) -> Option<E::Fr> {
+        if self.merkle_tree.is_occupied(index) {
+            Some(self.merkle_tree.get_node(self.merkle_tree.depth, index))
+        } else {
+            None
+        }
+    }
+
+    fn index_leaf(&mut self, index: u64) {
+        if let Some(leaf) = self.indexed_leaf(index) {
+            self.leaf_indexes
+                .entry(leaf_key::<E>(leaf))
+                .or_insert_with(BTreeSet::new)
+                .insert(index);
+        }
+    }
+
+    fn unindex_leaf(&mut self, index: u64) {
+        if let Some(leaf) = self.indexed_leaf(index) {
+            self.remove_leaf_index(leaf, index);
+        }
+    }
+
+    fn remove_leaf_index(&mut self, leaf: E::Fr, index: u64) {
+        let key = leaf_key::<E>(leaf);
+        if let Some(indexes) = self.leaf_indexes.get_mut(&key) {
+            indexes.remove(&index);
+            if indexes.is_empty() {
+                self.leaf_indexes.remove(&key);
+            }
+        }
+    }
+
+    fn reindex_leaves(&mut self) {
+        let mut leaf_indexes: HashMap<Vec<u64>, BTreeSet<u64>> = HashMap::new();
+        for (index, leaf) in self.iter_leaves() {
+            leaf_indexes
+                .entry(leaf_key::<E>(leaf))
+                .or_insert_with(BTreeSet::new)
+                .insert(index);
+        }
+        self.leaf_indexes = leaf_indexes;
+    }
+
+    fn record_change(&mut self, index: u64) {
+        if let Some(changes) = self.changes.as_mut() {
+            changes.insert(index);
+        }
+    }
+
+    /// sets the number of latest roots `is_recent_root` accepts, at least one
+    pub fn set_root_history_size(&mut self, root_history_size: usize) {
+        self.root_history_size = root_history_size.max(1);
+        while self.roots.len() > self.root_history_size {
+            self.roots.pop_front();
+        }
+    }
+
+    pub fn root_history_size(&self) -> usize {
+        self.root_history_size
+    }
+
+    /// latest roots, current one last
+    pub fn recent_roots(&self) -> Vec<E::Fr> {
+        self.roots.iter().cloned().collect()
+    }
+
+    /// whether the root is the current one or one of the roots before it
+    pub fn is_recent_root(&self, root: E::Fr) -> bool {
+        self.roots.contains(&root)
+    }
+
+    fn record_root(&mut self) {
+        let root = self.get_root();
+        if self.roots.back() == Some(&root) {
+            return;
+        }
+        self.roots.push_back(root);
+        if self.roots.len() > self.root_history_size {
+            self.roots.pop_front();
+        }
+    }
+
+    pub fn get_witness(&self, index: u64) -> io::Result<Vec<(E::Fr, bool)>> {
         if index >= self.current_index {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
@@ -63,14 +507,87 @@ where
         self.merkle_tree.get_witness(index)
     }
 
+    /// see `MerkleTree::merkle_proof`
+    pub fn merkle_proof(&self, index: u64) -> io::Result<MerkleProof<E>> {
+        if index >= self.current_index {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index exceeds incremental index",
+            ));
+        }
+        self.merkle_tree.merkle_proof(index)
+    }
+
+    pub fn get_path(&self, index: u64) -> io::Result<Vec<(Vec<E::Fr>, usize)>> {
+        if index >= self.current_index {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index exceeds incremental index",
+            ));
+        }
+        self.merkle_tree.get_path(index)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.merkle_tree.depth
+    }
+
+    pub fn arity(&self) -> TreeArity {
+        self.merkle_tree.arity
+    }
+
+    pub fn get_leaf(&self, index: u64) -> io::Result<E::Fr> {
+        if index >= self.current_index {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index exceeds incremental index",
+            ));
+        }
+        self.merkle_tree.get_leaf(index)
+    }
+
+    /// returns the lowest occupied index holding the given leaf
+    pub fn index_of(&self, leaf: E::Fr) -> Option<u64> {
+        self.leaf_indexes
+            .get(&leaf_key::<E>(leaf))
+            .and_then(|indexes| indexes.iter().next().copied())
+    }
+
+    /// returns all leaves up to the incremental index, deleted ones are zero
+    pub fn leaves(&self) -> Vec<E::Fr> {
+        let depth = self.merkle_tree.depth;
+        (0..self.current_index)
+            .map(|i| self.merkle_tree.get_node(depth, i))
+            .collect()
+    }
+
+    /// number of inserted leaves that are not deleted
+    pub fn member_count(&self) -> usize {
+        self.merkle_tree.occupied_count()
+    }
+
+    /// see `MerkleTree::compact`
+    pub fn compact(&mut self) -> usize {
+        self.merkle_tree.compact()
+    }
+
+    /// see `MerkleTree::memory_usage`
+    pub fn memory_usage(&self) -> usize {
+        self.merkle_tree.memory_usage()
+    }
+
     pub fn hash(&self, inputs: Vec<E::Fr>) -> E::Fr {
         self.merkle_tree.hasher.hash(inputs)
     }
 
+    pub fn hasher(&self) -> &H {
+        &self.merkle_tree.hasher
+    }
+
     pub fn check_inclusion(
         &self,
         witness: Vec<(E::Fr, bool)>,
-        leaf_index: usize,
+        leaf_index: u64,
     ) -> io::Result<bool> {
         if leaf_index >= self.current_index {
             return Err(io::Error::new(
@@ -86,144 +603,1567 @@ where
     }
 }
 
-pub struct MerkleTree<E>
-where
-    E: Engine,
-{
-    pub hasher: Hasher<E>,
-    pub depth: usize,
-    zero: Vec<E::Fr>,
-    nodes: HashMap<(usize, usize), E::Fr>,
+// key of a leaf in `IncrementalMerkleTree::leaf_indexes`
+fn leaf_key<E: Engine>(leaf: E::Fr) -> Vec<u64> {
+    leaf.into_repr().as_ref().to_vec()
 }
 
-impl<E> MerkleTree<E>
-where
-    E: Engine,
-{
-    pub fn empty(hasher: Hasher<E>, depth: usize) -> Self {
-        let mut zero: Vec<E::Fr> = Vec::with_capacity(depth + 1);
-        zero.push(E::Fr::from_str("0").unwrap());
-        for i in 0..depth {
-            zero.push(hasher.hash([zero[i]; 2].to_vec()));
+/// checks that the leaf at the index hashes up to the root with the binary witness
+pub fn verify_inclusion<E: Engine, H: ZkHasher<E>>(
+    hasher: &H,
+    root: E::Fr,
+    index: u64,
+    leaf: E::Fr,
+    witness: &[(E::Fr, bool)],
+) -> bool {
+    if index.checked_shr(witness.len() as u32).unwrap_or(0) != 0 {
+        return false;
+    }
+    let mut acc = leaf;
+    for (i, (node, right)) in witness.iter().enumerate() {
+        // sibling is on the right iff the node is a left child
+        if *right != (index.checked_shr(i as u32).unwrap_or(0) & 1 == 0) {
+            return false;
         }
-        zero.reverse();
-        MerkleTree {
-            hasher: hasher,
-            zero: zero.clone(),
-            depth: depth,
-            nodes: HashMap::new(),
+        if *right {
+            acc = hasher.hash(vec![acc, *node]);
+        } else {
+            acc = hasher.hash(vec![*node, acc]);
         }
     }
+    acc == root
+}
 
-    pub fn set_size(&self) -> usize {
-        1 << self.depth
-    }
+/// binary membership proof of a leaf
+/// * `path_bits` are the bits of `leaf_index` leaf level first, a bit is set
+/// when the node is a right child and its sibling is on the left
+/// * `get_witness` pairs each sibling with the opposite bit, true when the sibling is on the right
+#[derive(Clone)]
+pub struct MerkleProof<E: Engine> {
+    pub leaf_index: u64,
+    pub leaf: E::Fr,
+    /// siblings at each level, leaf level first
+    pub siblings: Vec<E::Fr>,
+    pub path_bits: Vec<bool>,
+}
 
-    pub fn update(&mut self, index: usize, leaf: E::Fr) -> io::Result<()> {
-        if index >= self.set_size() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "index exceeds set size",
-            ));
+impl<E: Engine> MerkleProof<E> {
+    /// proof from `get_witness` output
+    pub fn from_witness(leaf_index: u64, leaf: E::Fr, witness: &[(E::Fr, bool)]) -> Self {
+        MerkleProof {
+            leaf_index,
+            leaf,
+            siblings: witness.iter().map(|(sibling, _)| *sibling).collect(),
+            path_bits: witness.iter().map(|(_, right)| !right).collect(),
         }
-        self.nodes.insert((self.depth, index), leaf);
-        self.recalculate_from(index);
-        Ok(())
     }
 
-    pub fn check_inclusion(&self, witness: Vec<(E::Fr, bool)>, index: usize) -> io::Result<bool> {
-        if index >= self.set_size() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "index exceeds set size",
-            ));
-        }
-        let mut acc = self.get_node(self.depth, index);
+    /// witness in the convention of `get_witness`
+    pub fn witness(&self) -> Vec<(E::Fr, bool)> {
+        self.siblings
+            .iter()
+            .zip(self.path_bits.iter())
+            .map(|(sibling, bit)| (*sibling, !bit))
+            .collect()
+    }
 
-        for w in witness.into_iter() {
-            if w.1 {
-                acc = self.hasher.hash(vec![acc, w.0]);
+    /// proof from the `auth_path` of `RLNInputs`, fails if a level is unassigned
+    pub fn from_auth_path(
+        leaf_index: u64,
+        leaf: E::Fr,
+        auth_path: &[Option<(E::Fr, bool)>],
+    ) -> io::Result<Self> {
+        let witness = auth_path
+            .iter()
+            .cloned()
+            .collect::<Option<Vec<(E::Fr, bool)>>>()
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "auth path is not assigned")
+            })?;
+        Ok(Self::from_witness(leaf_index, leaf, &witness))
+    }
+
+    /// `auth_path` of `RLNInputs` for the circuit
+    pub fn auth_path(&self) -> Vec<Option<(E::Fr, bool)>> {
+        self.witness().into_iter().map(Some).collect()
+    }
+
+    /// root the leaf hashes up to
+    pub fn root<H: ZkHasher<E>>(&self, hasher: &H) -> E::Fr {
+        let mut acc = self.leaf;
+        for (sibling, bit) in self.siblings.iter().zip(self.path_bits.iter()) {
+            acc = if *bit {
+                hasher.hash(vec![*sibling, acc])
             } else {
-                acc = self.hasher.hash(vec![w.0, acc]);
-            }
+                hasher.hash(vec![acc, *sibling])
+            };
         }
-        Ok(acc.eq(&self.get_root()))
+        acc
     }
 
-    pub fn get_root(&self) -> E::Fr {
-        return self.get_node(0, 0);
+    /// checks that the path follows `leaf_index` and hashes up to `root`, see `verify_inclusion`
+    pub fn verify<H: ZkHasher<E>>(&self, hasher: &H, root: E::Fr) -> bool {
+        self.siblings.len() == self.path_bits.len()
+            && verify_inclusion(hasher, root, self.leaf_index, self.leaf, &self.witness())
     }
 
-    pub fn get_witness(&self, index: usize) -> io::Result<Vec<(E::Fr, bool)>> {
-        if index >= self.set_size() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "index exceeds set size",
-            ));
+    /// writes the path in the format of `utils::write_witness`,
+    /// [ direction<1> | sibling<32> ] for each level, leaf level first
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for (sibling, bit) in self.siblings.iter().zip(self.path_bits.iter()) {
+            writer.write_u8(!bit as u8)?;
+            sibling.into_repr().write_le(&mut writer)?;
         }
-        let mut witness = Vec::<(E::Fr, bool)>::with_capacity(self.depth);
-        let mut i = index;
-        let mut depth = self.depth;
-        loop {
-            i ^= 1;
-            witness.push((self.get_node(depth, i), (i & 1 == 1)));
-            i >>= 1;
-            depth -= 1;
-            if depth == 0 {
-                break;
+        Ok(())
+    }
+
+    /// reads a path of `depth` levels written by `write`
+    /// * fails if the directions do not follow `leaf_index`
+    pub fn read<R: Read>(
+        mut reader: R,
+        leaf_index: u64,
+        leaf: E::Fr,
+        depth: usize,
+    ) -> io::Result<Self> {
+        let mut siblings = Vec::with_capacity(depth);
+        let mut path_bits = Vec::with_capacity(depth);
+        for level in 0..depth {
+            let bit = match reader.read_u8()? {
+                0 => true,
+                1 => false,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid path direction",
+                    ))
+                }
+            };
+            if bit != (leaf_index.checked_shr(level as u32).unwrap_or(0) & 1 == 1) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "path does not match the leaf index",
+                ));
             }
+            siblings.push(read_field::<E, _>(&mut reader)?);
+            path_bits.push(bit);
         }
-        assert_eq!(i, 0);
-        Ok(witness)
+        Ok(MerkleProof {
+            leaf_index,
+            leaf,
+            siblings,
+            path_bits,
+        })
     }
+}
 
-    fn get_node(&self, depth: usize, index: usize) -> E::Fr {
-        let node = *self
-            .nodes
-            .get(&(depth, index))
-            .unwrap_or_else(|| &self.zero[depth]);
-        node
-    }
+fn read_field<E: Engine, R: Read>(mut reader: R) -> io::Result<E::Fr> {
+    let mut repr = <E::Fr as PrimeField>::Repr::default();
+    repr.read_le(&mut reader)?;
+    E::Fr::from_repr(repr).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// evidence that a tree extends an earlier state of itself by appends only,
+/// see `IncrementalMerkleTree::consistency_proof`
+/// * the path of the first slot after the earlier state holds on its left the nodes
+/// committing to every earlier leaf and on its right the appended ones
+#[derive(Clone)]
+pub struct ConsistencyProof<E: Engine> {
+    /// incremental index of the earlier state
+    pub old_size: u64,
+    /// leaf at `old_size` in the later state
+    pub leaf: E::Fr,
+    /// siblings of `old_size` in the later state at each level, leaf level first
+    pub siblings: Vec<Vec<E::Fr>>,
+}
 
-    fn get_leaf(&self, index: usize) -> E::Fr {
-        self.get_node(self.depth, index)
+impl<E: Engine> ConsistencyProof<E> {
+    /// serialized as [ old_size<8> | leaf<32> | depth<1> | siblings<(width - 1) * 32> * depth ],
+    /// positions follow from `old_size`
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u64::<LittleEndian>(self.old_size)?;
+        self.leaf.into_repr().write_le(&mut writer)?;
+        writer.write_u8(self.siblings.len() as u8)?;
+        for node in self.siblings.iter().flatten() {
+            node.into_repr().write_le(&mut writer)?;
+        }
+        Ok(())
     }
 
-    fn hash_couple(&mut self, depth: usize, index: usize) -> E::Fr {
-        let b = index & !1;
-        self.hasher
-            .hash([self.get_node(depth, b), self.get_node(depth, b + 1)].to_vec())
+    pub fn read<R: Read>(mut reader: R, arity: TreeArity) -> io::Result<ConsistencyProof<E>> {
+        let old_size = reader.read_u64::<LittleEndian>()?;
+        let leaf = read_field::<E, _>(&mut reader)?;
+        let depth = reader.read_u8()?;
+        let mut siblings = Vec::with_capacity(depth as usize);
+        for _ in 0..depth {
+            let level = (1..arity.width())
+                .map(|_| read_field::<E, _>(&mut reader))
+                .collect::<io::Result<Vec<E::Fr>>>()?;
+            siblings.push(level);
+        }
+        Ok(ConsistencyProof {
+            old_size,
+            leaf,
+            siblings,
+        })
     }
+}
 
-    fn recalculate_from(&mut self, index: usize) {
-        let mut i = index;
-        let mut depth = self.depth;
-        loop {
-            let h = self.hash_couple(depth, i);
-            i >>= 1;
-            depth -= 1;
-            self.nodes.insert((depth, i), h);
-            if depth == 0 {
-                break;
-            }
+/// checks that `new_root` extends `old_root` by appends,
+/// the first `proof.old_size` leaves are the same and the earlier state held no other
+/// * `default_leaf` is the leaf of empty slots, zero unless the tree is built with it
+pub fn verify_consistency<E: Engine, H: ZkHasher<E>>(
+    hasher: &H,
+    arity: TreeArity,
+    default_leaf: E::Fr,
+    old_root: E::Fr,
+    new_root: E::Fr,
+    proof: &ConsistencyProof<E>,
+) -> bool {
+    let depth = proof.siblings.len();
+    let width = arity.width() as u64;
+    let bits = depth as u32 * arity.log_width();
+    if proof.old_size.checked_shr(bits).unwrap_or(0) != 0 {
+        return false;
+    }
+    let zero = MerkleTree::<E, H>::empty_nodes(hasher, depth, arity, default_leaf);
+    let mut old_acc = default_leaf;
+    let mut new_acc = proof.leaf;
+    let mut i = proof.old_size;
+    for (level, siblings) in proof.siblings.iter().enumerate() {
+        if siblings.len() as u64 + 1 != width {
+            return false;
         }
-        assert_eq!(depth, 0);
-        assert_eq!(i, 0);
+        let position = (i % width) as usize;
+        // nodes right of the path are empty in the earlier state
+        let mut old_children = siblings.clone();
+        for node in old_children[position..].iter_mut() {
+            *node = zero[depth - level];
+        }
+        old_children.insert(position, old_acc);
+        old_acc = hasher.hash(old_children);
+        let mut new_children = siblings.clone();
+        new_children.insert(position, new_acc);
+        new_acc = hasher.hash(new_children);
+        i /= width;
     }
+    old_acc == old_root && new_acc == new_root
 }
 
-#[test]
-fn test_merkle_set() {
-    let data: Vec<Fr> = (0..8)
-        .map(|s| Fr::from_str(&format!("{}", s)).unwrap())
-        .collect();
-    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr, FrRepr};
-    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
-    let hasher = Hasher::new(params);
-    let mut set = MerkleTree::empty(hasher.clone(), 3);
-    let leaf_index = 6;
+/// keeps the binary witness of a single member current while the shared tree changes,
+/// without holding the tree
+/// * appended leaves are folded into a frontier of the tree, O(depth) per leaf
+/// * changes to earlier leaves need the witness of the changed leaf, O(depth) as well
+pub struct OwnedMembership<E: Engine, H: ZkHasher<E> = Hasher<E>> {
+    hasher: H,
+    index: u64,
+    leaf: E::Fr,
+    witness: Vec<(E::Fr, bool)>,
+    // empty node of each level, leaf level first
+    zero: Vec<E::Fr>,
+    // left neighbours on the path of the next leaf,
+    // only meaningful at levels where the next index is a right child
+    frontier: Vec<E::Fr>,
+    next_index: u64,
+}
+
+impl<E: Engine, H: ZkHasher<E>> OwnedMembership<E, H> {
+    /// starts tracking the member at the index from the current state of the tree
+    pub fn new(tree: &IncrementalMerkleTree<E, H>, index: u64) -> io::Result<Self> {
+        let witness = tree.get_witness(index)?;
+        let merkle_tree = &tree.merkle_tree;
+        let depth = merkle_tree.depth;
+        let next_index = tree.current_index;
+        let zero: Vec<E::Fr> = merkle_tree.zero.iter().rev().cloned().collect();
+        let frontier = (0..depth)
+            .map(|k| {
+                let i = next_index.checked_shr(k as u32).unwrap_or(0);
+                if i & 1 == 1 {
+                    merkle_tree.get_node(depth - k, i - 1)
+                } else {
+                    zero[k]
+                }
+            })
+            .collect();
+        Ok(OwnedMembership {
+            hasher: merkle_tree.hasher.clone(),
+            index,
+            leaf: merkle_tree.get_node(depth, index),
+            witness,
+            zero,
+            frontier,
+            next_index,
+        })
+    }
+
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    pub fn leaf(&self) -> E::Fr {
+        self.leaf
+    }
+
+    pub fn witness(&self) -> &[(E::Fr, bool)] {
+        &self.witness
+    }
+
+    /// root the witness leads to
+    pub fn root(&self) -> E::Fr {
+        let mut acc = self.leaf;
+        for (node, right) in self.witness.iter() {
+            acc = if *right {
+                self.hasher.hash(vec![acc, *node])
+            } else {
+                self.hasher.hash(vec![*node, acc])
+            };
+        }
+        acc
+    }
+
+    /// follows a leaf appended at the next index of the tree
+    pub fn append(&mut self, leaf: E::Fr) -> io::Result<()> {
+        let depth = self.witness.len();
+        let n = self.next_index;
+        if n.checked_shr(depth as u32).unwrap_or(0) != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index exceeds set size",
+            ));
+        }
+        let divergence = self.divergence(n);
+        let mut node = leaf;
+        for k in 0..depth {
+            if k == divergence {
+                self.witness[k].0 = node;
+            }
+            if n.checked_shr(k as u32).unwrap_or(0) & 1 == 0 {
+                self.frontier[k] = node;
+                node = self.hasher.hash(vec![node, self.zero[k]]);
+            } else {
+                node = self.hasher.hash(vec![self.frontier[k], node]);
+            }
+        }
+        self.next_index += 1;
+        Ok(())
+    }
+
+    /// follows a change of an already inserted leaf, e.g. a deletion
+    /// * `witness` is the witness of the changed leaf, its siblings are not affected by the change
+    pub fn update(&mut self, index: u64, leaf: E::Fr, witness: &[(E::Fr, bool)]) -> io::Result<()> {
+        let depth = self.witness.len();
+        if index >= self.next_index {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index exceeds incremental index",
+            ));
+        }
+        if witness.len() != depth {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "witness does not match tree depth",
+            ));
+        }
+        if index == self.index {
+            self.leaf = leaf;
+            return Ok(());
+        }
+        let divergence = self.divergence(index);
+        let mut node = leaf;
+        for k in 0..depth {
+            if k == divergence {
+                self.witness[k].0 = node;
+            }
+            let i = index.checked_shr(k as u32).unwrap_or(0);
+            let next = self.next_index.checked_shr(k as u32).unwrap_or(0);
+            if next & 1 == 1 && i == next - 1 {
+                self.frontier[k] = node;
+            }
+            let sibling = witness[k].0;
+            node = if i & 1 == 0 {
+                self.hasher.hash(vec![node, sibling])
+            } else {
+                self.hasher.hash(vec![sibling, node])
+            };
+        }
+        Ok(())
+    }
+
+    // level at which the path of the other leaf meets the path of the member
+    fn divergence(&self, other: u64) -> usize {
+        let diff = other ^ self.index;
+        (0u64.leading_zeros() - diff.leading_zeros() - 1) as usize
+    }
+}
+
+pub struct MerkleTree<E, H = Hasher<E>>
+where
+    E: Engine,
+    H: ZkHasher<E>,
+{
+    pub hasher: H,
+    pub depth: usize,
+    pub arity: TreeArity,
+    zero: Vec<E::Fr>,
+    // keyed by depth and index within the level
+    nodes: HashMap<(usize, u64), E::Fr>,
+    occupied: HashSet<u64>,
+}
+
+impl<E, H> MerkleTree<E, H>
+where
+    E: Engine,
+    H: ZkHasher<E>,
+{
+    pub fn empty(hasher: H, depth: usize) -> Self {
+        Self::empty_with_arity(hasher, depth, TreeArity::Binary)
+    }
+
+    pub fn empty_with_arity(hasher: H, depth: usize, arity: TreeArity) -> Self {
+        Self::empty_with_default_leaf(hasher, depth, arity, E::Fr::zero())
+    }
+
+    /// empty tree whose empty and deleted slots hold `default_leaf` instead of zero,
+    /// e.g. `hash(0)` or a deployment constant, to match roots of external registries
+    pub fn empty_with_default_leaf(
+        hasher: H,
+        depth: usize,
+        arity: TreeArity,
+        default_leaf: E::Fr,
+    ) -> Self {
+        assert!(
+            arity.width() < hasher.width(),
+            "hasher is too narrow for tree arity"
+        );
+        let zero = Self::empty_nodes(&hasher, depth, arity, default_leaf);
+        MerkleTree {
+            hasher: hasher,
+            zero: zero.clone(),
+            depth: depth,
+            arity: arity,
+            nodes: HashMap::new(),
+            occupied: HashSet::new(),
+        }
+    }
+
+    // empty node of each level, root first,
+    // taken from the ladders shared by trees of hashers with a digest
+    fn empty_nodes(hasher: &H, depth: usize, arity: TreeArity, default_leaf: E::Fr) -> Vec<E::Fr> {
+        let digest = match hasher.digest() {
+            Some(digest) => digest,
+            None => {
+                let mut zero = vec![default_leaf];
+                Self::extend_empty_nodes(hasher, arity, &mut zero, depth);
+                zero.reverse();
+                return zero;
+            }
+        };
+        let mut leaf = Vec::with_capacity(32);
+        default_leaf.into_repr().write_le(&mut leaf).unwrap();
+        let key = (TypeId::of::<E::Fr>(), digest, arity.width(), leaf);
+        let cached = EMPTY_NODES
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+            .and_then(|zero| zero.downcast_ref::<Vec<E::Fr>>())
+            .cloned();
+        let mut zero = cached.unwrap_or_else(|| vec![default_leaf]);
+        if zero.len() <= depth {
+            Self::extend_empty_nodes(hasher, arity, &mut zero, depth);
+            let mut cache = EMPTY_NODES.lock().unwrap_or_else(PoisonError::into_inner);
+            let longer = cache
+                .get(&key)
+                .and_then(|cached| cached.downcast_ref::<Vec<E::Fr>>())
+                .map_or(true, |cached| cached.len() < zero.len());
+            if longer {
+                cache.insert(key, Box::new(zero.clone()));
+            }
+        }
+        zero.truncate(depth + 1);
+        zero.reverse();
+        zero
+    }
+
+    // hashes empty nodes of the levels above the last one in `zero` up to `depth`, leaf first
+    fn extend_empty_nodes(hasher: &H, arity: TreeArity, zero: &mut Vec<E::Fr>, depth: usize) {
+        while zero.len() <= depth {
+            let node = *zero.last().unwrap();
+            zero.push(hasher.hash(vec![node; arity.width()]));
+        }
+    }
+
+    pub fn default_leaf(&self) -> E::Fr {
+        self.zero[self.depth]
+    }
+
+    /// builds the tree with the leaves at indexes `0..leaves.len()`,
+    /// independent subtrees are hashed in parallel with the `multicore` feature
+    /// * zero leaves are left unoccupied as `delete` would
+    pub fn from_leaves(
+        hasher: H,
+        depth: usize,
+        arity: TreeArity,
+        leaves: &[E::Fr],
+    ) -> io::Result<Self> {
+        Self::from_leaves_with_default_leaf(hasher, depth, arity, E::Fr::zero(), leaves)
+    }
+
+    /// same as `from_leaves` for a tree built with `empty_with_default_leaf`,
+    /// leaves equal to `default_leaf` are left unoccupied
+    pub fn from_leaves_with_default_leaf(
+        hasher: H,
+        depth: usize,
+        arity: TreeArity,
+        default_leaf: E::Fr,
+        leaves: &[E::Fr],
+    ) -> io::Result<Self> {
+        let mut tree = Self::empty_with_default_leaf(hasher, depth, arity, default_leaf);
+        if leaves.len() as u64 > tree.set_size() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many leaves for the tree depth",
+            ));
+        }
+        let leaves = leaves
+            .iter()
+            .enumerate()
+            .filter(|(_, leaf)| **leaf != default_leaf)
+            .map(|(i, leaf)| (i as u64, Some(*leaf)))
+            .collect();
+        tree.write_leaves(leaves);
+        Ok(tree)
+    }
+
+    /// root of a binary tree with the leaves at indexes `0..leaves.len()`,
+    /// see `root_from_leaves_with_arity`
+    pub fn root_from_leaves(hasher: &H, depth: usize, leaves: &[E::Fr]) -> io::Result<E::Fr> {
+        Self::root_from_leaves_with_arity(hasher, depth, TreeArity::Binary, leaves)
+    }
+
+    /// folds the leaves into the root a node at a time without storing the tree,
+    /// only the unfinished siblings of each level are kept
+    pub fn root_from_leaves_with_arity(
+        hasher: &H,
+        depth: usize,
+        arity: TreeArity,
+        leaves: &[E::Fr],
+    ) -> io::Result<E::Fr> {
+        Self::root_from_leaves_with_default_leaf(hasher, depth, arity, E::Fr::zero(), leaves)
+    }
+
+    /// same as `root_from_leaves_with_arity` with empty slots holding `default_leaf`
+    pub fn root_from_leaves_with_default_leaf(
+        hasher: &H,
+        depth: usize,
+        arity: TreeArity,
+        default_leaf: E::Fr,
+        leaves: &[E::Fr],
+    ) -> io::Result<E::Fr> {
+        let width = arity.width();
+        if (leaves.len() as u128) > (1u128 << (depth as u32 * arity.log_width()).min(127)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many leaves for the tree depth",
+            ));
+        }
+        let mut pending: Vec<Vec<E::Fr>> = vec![Vec::with_capacity(width); depth + 1];
+        for leaf in leaves.iter() {
+            pending[0].push(*leaf);
+            let mut level = 0;
+            while level < depth && pending[level].len() == width {
+                let children = std::mem::replace(&mut pending[level], Vec::with_capacity(width));
+                pending[level + 1].push(hasher.hash(children));
+                level += 1;
+            }
+        }
+        if let Some(root) = pending[depth].first() {
+            return Ok(*root);
+        }
+        // pad the rightmost unfinished node of each level with empty subtrees
+        let mut zero = default_leaf;
+        let mut acc: Option<E::Fr> = None;
+        for children in pending.into_iter().take(depth) {
+            let mut children = children;
+            if let Some(node) = acc {
+                children.push(node);
+            }
+            if !children.is_empty() {
+                children.resize(width, zero);
+                acc = Some(hasher.hash(children));
+            }
+            zero = hasher.hash(vec![zero; width]);
+        }
+        Ok(acc.unwrap_or(zero))
+    }
+
+    /// number of leaves, saturates at `u64::MAX` for trees of 2^64 leaves or more
+    pub fn set_size(&self) -> u64 {
+        1u64.checked_shl(self.depth as u32 * self.arity.log_width())
+            .unwrap_or(u64::MAX)
+    }
+
+    fn check_index(&self, index: u64) -> io::Result<()> {
+        let bits = self.depth as u32 * self.arity.log_width();
+        if index.checked_shr(bits).unwrap_or(0) != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index exceeds set size",
+            ));
+        }
+        Ok(())
+    }
+
+    /// sets the leaf at an empty slot
+    pub fn update(&mut self, index: u64, leaf: E::Fr) -> io::Result<()> {
+        self.insert(index, leaf, false)
+    }
+
+    /// sets the leaf, occupied slots are only overwritten with `force`
+    pub fn insert(&mut self, index: u64, leaf: E::Fr, force: bool) -> io::Result<()> {
+        self.check_index(index)?;
+        if !force && self.is_occupied(index) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "slot is occupied",
+            ));
+        }
+        self.set_node(self.depth, index, leaf);
+        self.occupied.insert(index);
+        self.recalculate_from(index);
+        Ok(())
+    }
+
+    /// resets the slot to zero and marks it reusable
+    pub fn delete(&mut self, index: u64) -> io::Result<()> {
+        self.check_index(index)?;
+        if !self.occupied.remove(&index) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "slot is not occupied",
+            ));
+        }
+        let default_leaf = self.default_leaf();
+        self.set_node(self.depth, index, default_leaf);
+        self.recalculate_from(index);
+        Ok(())
+    }
+
+    pub fn is_occupied(&self, index: u64) -> bool {
+        self.occupied.contains(&index)
+    }
+
+    pub fn occupied_count(&self) -> usize {
+        self.occupied.len()
+    }
+
+    pub fn check_inclusion(&self, witness: Vec<(E::Fr, bool)>, index: u64) -> io::Result<bool> {
+        self.ensure_binary()?;
+        self.check_index(index)?;
+        let mut acc = self.get_node(self.depth, index);
+
+        for w in witness.into_iter() {
+            if w.1 {
+                acc = self.hasher.hash(vec![acc, w.0]);
+            } else {
+                acc = self.hasher.hash(vec![w.0, acc]);
+            }
+        }
+        Ok(acc.eq(&self.get_root()))
+    }
+
+    pub fn get_root(&self) -> E::Fr {
+        return self.get_node(0, 0);
+    }
+
+    pub fn get_witness(&self, index: u64) -> io::Result<Vec<(E::Fr, bool)>> {
+        self.ensure_binary()?;
+        self.check_index(index)?;
+        let mut witness = Vec::<(E::Fr, bool)>::with_capacity(self.depth);
+        let mut i = index;
+        for depth in (1..=self.depth).rev() {
+            let sibling = i ^ 1;
+            witness.push((self.get_node(depth, sibling), (sibling & 1 == 1)));
+            i >>= 1;
+        }
+        Ok(witness)
+    }
+
+    /// membership proof of the leaf at the index, binary trees only
+    pub fn merkle_proof(&self, index: u64) -> io::Result<MerkleProof<E>> {
+        let witness = self.get_witness(index)?;
+        Ok(MerkleProof::from_witness(
+            index,
+            self.get_node(self.depth, index),
+            &witness,
+        ))
+    }
+
+    /// returns siblings and position of the node at each level, leaf level first
+    /// * siblings are ordered as the children of the parent with the node itself left out
+    pub fn get_path(&self, index: u64) -> io::Result<Vec<(Vec<E::Fr>, usize)>> {
+        self.check_index(index)?;
+        let width = self.arity.width() as u64;
+        let mut path = Vec::<(Vec<E::Fr>, usize)>::with_capacity(self.depth);
+        let mut i = index;
+        for depth in (1..=self.depth).rev() {
+            let position = i % width;
+            let first = i - position;
+            let siblings = (first..first + width)
+                .filter(|j| *j != i)
+                .map(|j| self.get_node(depth, j))
+                .collect();
+            path.push((siblings, position as usize));
+            i /= width;
+        }
+        assert_eq!(i, 0);
+        Ok(path)
+    }
+
+    pub fn check_path(&self, path: Vec<(Vec<E::Fr>, usize)>, index: u64) -> io::Result<bool> {
+        self.check_index(index)?;
+        let mut acc = self.get_node(self.depth, index);
+        for (mut siblings, position) in path.into_iter() {
+            if siblings.len() + 1 != self.arity.width() || position >= self.arity.width() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "path does not match tree arity",
+                ));
+            }
+            siblings.insert(position, acc);
+            acc = self.hasher.hash(siblings);
+        }
+        Ok(acc.eq(&self.get_root()))
+    }
+
+    fn ensure_binary(&self) -> io::Result<()> {
+        if self.arity != TreeArity::Binary {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "binary witness on a non binary tree, use paths instead",
+            ));
+        }
+        Ok(())
+    }
+
+    // nodes equal to the empty node of their level are not stored
+    fn set_node(&mut self, depth: usize, index: u64, node: E::Fr) {
+        if node == self.zero[depth] {
+            self.nodes.remove(&(depth, index));
+        } else {
+            self.nodes.insert((depth, index), node);
+        }
+    }
+
+    /// drops stored nodes equal to the empty node of their level and releases unused capacity,
+    /// returns the number of nodes dropped
+    pub fn compact(&mut self) -> usize {
+        let before = self.nodes.len();
+        let zero = &self.zero;
+        self.nodes.retain(|(depth, _), node| *node != zero[*depth]);
+        self.nodes.shrink_to_fit();
+        self.occupied.shrink_to_fit();
+        before - self.nodes.len()
+    }
+
+    /// number of nodes held in memory
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// approximate heap bytes held by the tree, counting allocated capacity
+    pub fn memory_usage(&self) -> usize {
+        self.nodes.capacity() * mem::size_of::<((usize, u64), E::Fr)>()
+            + self.occupied.capacity() * mem::size_of::<u64>()
+            + self.zero.capacity() * mem::size_of::<E::Fr>()
+    }
+
+    fn get_node(&self, depth: usize, index: u64) -> E::Fr {
+        let node = *self
+            .nodes
+            .get(&(depth, index))
+            .unwrap_or_else(|| &self.zero[depth]);
+        node
+    }
+
+    pub fn get_leaf(&self, index: u64) -> io::Result<E::Fr> {
+        self.check_index(index)?;
+        Ok(self.get_node(self.depth, index))
+    }
+
+    fn hash_children(&self, depth: usize, index: u64) -> E::Fr {
+        let width = self.arity.width() as u64;
+        let first = index - index % width;
+        let children = (first..first + width)
+            .map(|j| self.get_node(depth, j))
+            .collect();
+        self.hasher.hash(children)
+    }
+
+    // sets leaves without checks, `None` empties the slot, then rehashes every touched parent,
+    // subtrees below `split_level` independently and levels above it a level at a time
+    fn write_leaves(&mut self, leaves: HashMap<u64, Option<E::Fr>>) {
+        let width = self.arity.width() as u64;
+        let top = self.split_level();
+        let shift = (self.depth - top) as u32 * self.arity.log_width();
+        // changed leaves of each subtree, keyed by the index of its root
+        let mut subtrees: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for (index, leaf) in leaves.into_iter() {
+            match leaf {
+                Some(leaf) => {
+                    self.set_node(self.depth, index, leaf);
+                    self.occupied.insert(index);
+                }
+                None => {
+                    let default_leaf = self.default_leaf();
+                    self.set_node(self.depth, index, default_leaf);
+                    self.occupied.remove(&index);
+                }
+            }
+            subtrees
+                .entry(index.checked_shr(shift).unwrap_or(0))
+                .or_default()
+                .push(index);
+        }
+
+        #[cfg(feature = "multicore")]
+        let nodes: Vec<Vec<(usize, u64, E::Fr)>> = {
+            use rayon::prelude::*;
+            let subtrees: Vec<&Vec<u64>> = subtrees.values().collect();
+            subtrees
+                .par_iter()
+                .map(|leaves| self.rehash_subtree(leaves, top))
+                .collect()
+        };
+        #[cfg(not(feature = "multicore"))]
+        let nodes: Vec<Vec<(usize, u64, E::Fr)>> = subtrees
+            .values()
+            .map(|leaves| self.rehash_subtree(leaves, top))
+            .collect();
+        for (depth, index, node) in nodes.into_iter().flatten() {
+            self.set_node(depth, index, node);
+        }
+
+        let mut dirty: BTreeSet<u64> = subtrees.keys().map(|i| i / width).collect();
+        for depth in (1..=top).rev() {
+            let parents: Vec<u64> = dirty.into_iter().collect();
+            let inputs: Vec<Vec<E::Fr>> = parents
+                .iter()
+                .map(|i| {
+                    (i * width..(i + 1) * width)
+                        .map(|j| self.get_node(depth, j))
+                        .collect()
+                })
+                .collect();
+            let hashes = self.hasher.hash_batch(&inputs);
+            for (i, h) in parents.iter().zip(hashes.into_iter()) {
+                self.set_node(depth - 1, *i, h);
+            }
+            dirty = parents.into_iter().map(|i| i / width).collect();
+        }
+    }
+
+    // level of the subtree roots batch writes are split at, the first with
+    // `MIN_SUBTREES` nodes or the leaf level of smaller trees
+    fn split_level(&self) -> usize {
+        let width = self.arity.width() as u64;
+        let mut level = 0;
+        let mut nodes = 1u64;
+        while level < self.depth && nodes < MIN_SUBTREES {
+            nodes *= width;
+            level += 1;
+        }
+        level
+    }
+
+    // hashes the parents of written leaves up to level `top`, siblings are read from the tree
+    // unless hashed here, returns the new nodes
+    fn rehash_subtree(&self, leaves: &[u64], top: usize) -> Vec<(usize, u64, E::Fr)> {
+        let width = self.arity.width() as u64;
+        let mut hashed: HashMap<(usize, u64), E::Fr> = HashMap::new();
+        let mut dirty: BTreeSet<u64> = leaves.iter().map(|i| i / width).collect();
+        for depth in (top + 1..=self.depth).rev() {
+            for parent in dirty.iter() {
+                let children = (parent * width..(parent + 1) * width)
+                    .map(|j| match hashed.get(&(depth, j)) {
+                        Some(node) => *node,
+                        None => self.get_node(depth, j),
+                    })
+                    .collect();
+                hashed.insert((depth - 1, *parent), self.hasher.hash(children));
+            }
+            dirty = dirty.into_iter().map(|i| i / width).collect();
+        }
+        hashed
+            .into_iter()
+            .map(|((depth, index), node)| (depth, index, node))
+            .collect()
+    }
+
+    // expects an index below the set size
+    fn recalculate_from(&mut self, index: u64) {
+        let width = self.arity.width() as u64;
+        let mut i = index;
+        for depth in (1..=self.depth).rev() {
+            let h = self.hash_children(depth, i);
+            i /= width;
+            self.set_node(depth - 1, i, h);
+        }
+    }
+}
+
+#[test]
+fn test_merkle_set() {
+    let data: Vec<Fr> = (0..8)
+        .map(|s| Fr::from_str(&format!("{}", s)).unwrap())
+        .collect();
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr, FrRepr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Hasher::new(params);
+    let mut set = MerkleTree::empty(hasher.clone(), 3);
+    let leaf_index = 6;
     let leaf = hasher.hash(vec![data[0]]);
     set.update(leaf_index, leaf).unwrap();
     let witness = set.get_witness(leaf_index).unwrap();
     assert!(set.check_inclusion(witness, leaf_index).unwrap());
 }
+
+#[test]
+fn test_quad_merkle_set() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 60, 5, None, None, None);
+    let hasher = Hasher::new(params);
+    let mut set = MerkleTree::empty_with_arity(hasher.clone(), 2, TreeArity::Quad);
+    assert_eq!(set.set_size(), 16);
+    let leaf_index = 6;
+    let leaf = hasher.hash(vec![Fr::from_str("1").unwrap()]);
+    set.update(leaf_index, leaf).unwrap();
+    let path = set.get_path(leaf_index).unwrap();
+    assert_eq!(path[0].1, 2);
+    assert_eq!(path[1].1, 1);
+    assert!(set.check_path(path, leaf_index).unwrap());
+    assert!(set.get_witness(leaf_index).is_err());
+}
+
+#[test]
+fn test_wide_indexes() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Hasher::new(params);
+    let mut set = MerkleTree::empty(hasher.clone(), 40);
+    let leaf_index = (1u64 << 39) + 5;
+    let leaf = hasher.hash(vec![Fr::one()]);
+    set.update(leaf_index, leaf).unwrap();
+    let witness = set.get_witness(leaf_index).unwrap();
+    assert!(verify_inclusion(
+        &hasher,
+        set.get_root(),
+        leaf_index,
+        leaf,
+        &witness
+    ));
+    assert!(set.update(1u64 << 40, leaf).is_err());
+    assert!(set.get_witness(u64::MAX).is_err());
+
+    let set = MerkleTree::empty(hasher.clone(), 64);
+    assert_eq!(set.set_size(), u64::MAX);
+    assert!(set.get_witness(u64::MAX).is_ok());
+}
+
+#[test]
+fn test_index_bounds() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 60, 5, None, None, None);
+    let hasher = Hasher::new(params);
+    let leaf = hasher.hash(vec![Fr::one()]);
+    for arity in [TreeArity::Binary, TreeArity::Quad].iter() {
+        let mut set = MerkleTree::empty_with_arity(hasher.clone(), 3, *arity);
+        let last = set.set_size() - 1;
+        set.update(last, leaf).unwrap();
+        assert!(set.check_path(set.get_path(last).unwrap(), last).unwrap());
+        assert_eq!(set.get_leaf(last).unwrap(), leaf);
+        let root = set.get_root();
+
+        let out = set.set_size();
+        assert!(set.update(out, leaf).is_err());
+        assert!(set.insert(out, leaf, true).is_err());
+        assert!(set.delete(out).is_err());
+        assert!(set.get_leaf(out).is_err());
+        assert!(set.get_path(out).is_err());
+        assert!(set.get_witness(out).is_err());
+        assert_eq!(set.get_root(), root);
+    }
+
+    // a tree of depth 0 is its single leaf
+    let mut set = MerkleTree::empty(hasher.clone(), 0);
+    set.update(0, leaf).unwrap();
+    assert_eq!(set.get_root(), leaf);
+    assert!(set.get_witness(0).unwrap().is_empty());
+    assert!(set.update(1, leaf).is_err());
+
+    let mut tree = IncrementalMerkleTree::empty(hasher.clone(), 1);
+    tree.update_next(leaf).unwrap();
+    tree.update_next(leaf).unwrap();
+    assert!(tree.update_next(leaf).is_err());
+    assert_eq!(tree.current_index, 2);
+}
+
+#[test]
+fn test_compact() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Hasher::new(params);
+    let mut tree = MerkleTree::empty(hasher.clone(), 10);
+    let empty_root = tree.get_root();
+    assert_eq!(tree.node_count(), 0);
+
+    for i in 0..32u64 {
+        let leaf = Fr::from_str(&format!("{}", i + 1)).unwrap();
+        tree.update(i, leaf).unwrap();
+    }
+    assert!(tree.node_count() > 32);
+    let usage = tree.memory_usage();
+    for i in 0..32u64 {
+        tree.delete(i).unwrap();
+    }
+    // deletions prune nodes back to the empty ones as they are written
+    assert_eq!(tree.node_count(), 0);
+    assert_eq!(tree.get_root(), empty_root);
+    tree.compact();
+    assert!(tree.memory_usage() < usage);
+
+    tree.update(3, Fr::from_str("3").unwrap()).unwrap();
+    let witness = tree.get_witness(3).unwrap();
+    assert!(tree.check_inclusion(witness, 3).unwrap());
+    assert_eq!(tree.compact(), 0);
+    assert_eq!(tree.node_count(), 11);
+}
+
+#[test]
+fn test_leaf_lookup() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Hasher::new(params);
+    let mut tree = IncrementalMerkleTree::empty(hasher.clone(), 3);
+    let leaves: Vec<Fr> = (1..5)
+        .map(|s| hasher.hash(vec![Fr::from_str(&format!("{}", s)).unwrap()]))
+        .collect();
+    for leaf in leaves.iter() {
+        tree.update_next(*leaf).unwrap();
+    }
+    assert_eq!(tree.get_leaf(2).unwrap(), leaves[2]);
+    assert!(tree.get_leaf(4).is_err());
+    assert_eq!(tree.index_of(leaves[3]), Some(3));
+    assert_eq!(tree.leaves(), leaves);
+    assert_eq!(tree.member_count(), 4);
+
+    tree.delete(1).unwrap();
+    assert_eq!(tree.index_of(leaves[1]), None);
+    assert_eq!(tree.member_count(), 3);
+
+    // the index follows overwrites, events and restores
+    tree.update(1, leaves[3], false).unwrap();
+    assert_eq!(tree.index_of(leaves[3]), Some(1));
+    tree.update(1, leaves[0], true).unwrap();
+    assert_eq!(tree.index_of(leaves[3]), Some(3));
+    assert_eq!(tree.index_of(Fr::zero()), None);
+    tree.apply_events(vec![
+        MemberEvent::Delete { index: 0 },
+        MemberEvent::Insert {
+            index: 5,
+            commitment: leaves[1],
+        },
+    ])
+    .unwrap();
+    assert_eq!(tree.index_of(leaves[0]), Some(1));
+    assert_eq!(tree.index_of(leaves[1]), Some(5));
+    tree.restore(4, &[(2, leaves[2])]).unwrap();
+    assert_eq!(tree.index_of(leaves[2]), Some(2));
+    assert_eq!(tree.index_of(leaves[1]), None);
+}
+
+#[test]
+fn test_occupancy() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Hasher::new(params);
+    let mut tree = IncrementalMerkleTree::empty(hasher.clone(), 3);
+    let leaf_0 = hasher.hash(vec![Fr::from_str("1").unwrap()]);
+    let leaf_1 = hasher.hash(vec![Fr::from_str("2").unwrap()]);
+    assert_eq!(tree.update_next(leaf_0).unwrap(), 0);
+    assert_eq!(tree.update_next(leaf_1).unwrap(), 1);
+
+    assert!(tree.update(0, leaf_1, false).is_err());
+    tree.delete(0).unwrap();
+    assert!(tree.delete(0).is_err());
+    assert_eq!(tree.member_count(), 1);
+
+    tree.update(0, leaf_1, false).unwrap();
+    tree.update(0, leaf_0, true).unwrap();
+    assert_eq!(tree.get_leaf(0).unwrap(), leaf_0);
+    assert_eq!(tree.member_count(), 2);
+}
+
+#[test]
+fn test_verify_inclusion() {
+    use crate::utils::{read_witness, write_witness};
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Hasher::new(params);
+    let mut set = MerkleTree::empty(hasher.clone(), 3);
+    let leaf_index = 5;
+    let leaf = hasher.hash(vec![Fr::from_str("1").unwrap()]);
+    set.update(leaf_index, leaf).unwrap();
+    let witness = set.get_witness(leaf_index).unwrap();
+
+    let mut raw_witness: Vec<u8> = Vec::new();
+    write_witness::<_, Bn256>(&witness, &mut raw_witness).unwrap();
+    assert_eq!(raw_witness.len(), 3 * 33);
+    let witness = read_witness::<_, Bn256>(raw_witness.as_slice(), 3).unwrap();
+
+    let root = set.get_root();
+    assert!(verify_inclusion(&hasher, root, leaf_index, leaf, &witness));
+    assert!(!verify_inclusion(
+        &hasher,
+        root,
+        leaf_index - 1,
+        leaf,
+        &witness
+    ));
+    assert!(!verify_inclusion(
+        &hasher,
+        root,
+        leaf_index + 8,
+        leaf,
+        &witness
+    ));
+}
+
+#[test]
+fn test_from_leaves() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 60, 5, None, None, None);
+    let hasher = Hasher::new(params);
+    let leaves: Vec<Fr> = (1..7)
+        .map(|s| hasher.hash(vec![Fr::from_str(&format!("{}", s)).unwrap()]))
+        .collect();
+    for arity in [TreeArity::Binary, TreeArity::Quad].iter() {
+        let mut expected = IncrementalMerkleTree::empty_with_arity(hasher.clone(), 3, *arity);
+        for leaf in leaves.iter() {
+            expected.update_next(*leaf).unwrap();
+        }
+        expected.delete(2).unwrap();
+        let tree =
+            IncrementalMerkleTree::from_leaves(hasher.clone(), 3, *arity, &expected.leaves())
+                .unwrap();
+        assert_eq!(tree.get_root(), expected.get_root());
+        assert_eq!(tree.member_count(), 5);
+        assert_eq!(tree.current_index, 6);
+    }
+    assert!(MerkleTree::from_leaves(hasher.clone(), 1, TreeArity::Binary, &leaves).is_err());
+}
+
+#[test]
+fn test_root_from_leaves() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 60, 5, None, None, None);
+    let hasher = Hasher::new(params);
+    for arity in [TreeArity::Binary, TreeArity::Quad].iter() {
+        let mut tree = MerkleTree::empty_with_arity(hasher.clone(), 2, *arity);
+        let mut leaves: Vec<Fr> = Vec::new();
+        for i in 0..tree.set_size() {
+            let root =
+                MerkleTree::root_from_leaves_with_arity(&hasher, 2, *arity, &leaves).unwrap();
+            assert_eq!(root, tree.get_root());
+            let leaf = hasher.hash(vec![Fr::from_str(&format!("{}", i + 1)).unwrap()]);
+            tree.update(i, leaf).unwrap();
+            leaves.push(leaf);
+        }
+        let root = MerkleTree::root_from_leaves_with_arity(&hasher, 2, *arity, &leaves).unwrap();
+        assert_eq!(root, tree.get_root());
+        leaves.push(Fr::one());
+        assert!(MerkleTree::root_from_leaves_with_arity(&hasher, 2, *arity, &leaves).is_err());
+    }
+}
+
+#[test]
+fn test_apply_events() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Hasher::new(params);
+    let leaves: Vec<Fr> = (1..6)
+        .map(|s| hasher.hash(vec![Fr::from_str(&format!("{}", s)).unwrap()]))
+        .collect();
+    let mut expected = IncrementalMerkleTree::empty(hasher.clone(), 3);
+    for leaf in leaves.iter() {
+        expected.update_next(*leaf).unwrap();
+    }
+    expected.delete(1).unwrap();
+    expected.update(1, leaves[0], false).unwrap();
+    expected.delete(3).unwrap();
+
+    let mut tree = IncrementalMerkleTree::empty(hasher.clone(), 3);
+    let mut events: Vec<MemberEvent<Bn256>> = leaves
+        .iter()
+        .enumerate()
+        .map(|(index, commitment)| MemberEvent::Insert {
+            index: index as u64,
+            commitment: *commitment,
+        })
+        .collect();
+    events.push(MemberEvent::Delete { index: 1 });
+    events.push(MemberEvent::Insert {
+        index: 1,
+        commitment: leaves[0],
+    });
+    events.push(MemberEvent::Delete { index: 3 });
+    let root = tree.apply_events(events).unwrap();
+    assert_eq!(root, expected.get_root());
+    assert_eq!(tree.current_index, 5);
+    assert_eq!(tree.member_count(), 4);
+
+    // a failing batch leaves the tree untouched
+    let events = vec![
+        MemberEvent::Delete { index: 0 },
+        MemberEvent::Delete { index: 3 },
+    ];
+    assert!(tree.apply_events(events).is_err());
+    assert_eq!(tree.get_root(), root);
+    assert_eq!(tree.member_count(), 4);
+}
+
+#[test]
+fn test_default_leaf() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Hasher::new(params);
+    let default_leaf = hasher.hash(vec![Fr::zero()]);
+    let mut tree =
+        MerkleTree::empty_with_default_leaf(hasher.clone(), 3, TreeArity::Binary, default_leaf);
+    assert!(tree.get_root() != MerkleTree::empty(hasher.clone(), 3).get_root());
+    assert_eq!(tree.get_leaf(5).unwrap(), default_leaf);
+    let empty_root = tree.get_root();
+
+    let leaf = hasher.hash(vec![Fr::one()]);
+    tree.update(2, leaf).unwrap();
+    let leaves = vec![default_leaf, default_leaf, leaf];
+    let root = MerkleTree::root_from_leaves_with_default_leaf(
+        &hasher,
+        3,
+        TreeArity::Binary,
+        default_leaf,
+        &leaves,
+    )
+    .unwrap();
+    assert_eq!(root, tree.get_root());
+    let rebuilt = MerkleTree::from_leaves_with_default_leaf(
+        hasher.clone(),
+        3,
+        TreeArity::Binary,
+        default_leaf,
+        &leaves,
+    )
+    .unwrap();
+    assert_eq!(rebuilt.get_root(), tree.get_root());
+    assert_eq!(rebuilt.occupied_count(), 1);
+
+    tree.delete(2).unwrap();
+    assert_eq!(tree.get_root(), empty_root);
+}
+
+#[test]
+fn test_owned_membership() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Hasher::new(params);
+    let leaf = |i: usize| hasher.hash(vec![Fr::from_str(&format!("{}", i + 1)).unwrap()]);
+    let mut tree = IncrementalMerkleTree::empty(hasher.clone(), 4);
+    for i in 0..5 {
+        tree.update_next(leaf(i)).unwrap();
+    }
+    let mut membership = OwnedMembership::new(&tree, 2).unwrap();
+    assert_eq!(membership.root(), tree.get_root());
+
+    for i in 5..11 {
+        tree.update_next(leaf(i)).unwrap();
+        membership.append(leaf(i)).unwrap();
+        assert_eq!(
+            membership.witness(),
+            tree.get_witness(2).unwrap().as_slice()
+        );
+        assert_eq!(membership.root(), tree.get_root());
+    }
+
+    for index in [9u64, 0, 3].iter() {
+        tree.delete(*index).unwrap();
+        let witness = tree.get_witness(*index).unwrap();
+        membership
+            .update(*index, tree.get_leaf(*index).unwrap(), &witness)
+            .unwrap();
+        assert_eq!(membership.root(), tree.get_root());
+    }
+    // frontier follows the deletion of the last leaf before the next index
+    tree.delete(10).unwrap();
+    let witness = tree.get_witness(10).unwrap();
+    membership
+        .update(10, tree.get_leaf(10).unwrap(), &witness)
+        .unwrap();
+    tree.update_next(leaf(11)).unwrap();
+    membership.append(leaf(11)).unwrap();
+    assert_eq!(
+        membership.witness(),
+        tree.get_witness(2).unwrap().as_slice()
+    );
+    assert_eq!(membership.root(), tree.get_root());
+    assert!(membership.update(12, Fr::zero(), &witness).is_err());
+}
+
+#[test]
+fn test_root_history() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Hasher::new(params);
+    let mut tree = IncrementalMerkleTree::empty(hasher, 4);
+    tree.set_root_history_size(3);
+    let mut roots = vec![tree.get_root()];
+    for i in 0..4u64 {
+        tree.update_next(Fr::from_str(&format!("{}", i + 1)).unwrap())
+            .unwrap();
+        roots.push(tree.get_root());
+    }
+    assert_eq!(tree.recent_roots(), roots[2..].to_vec());
+    assert!(tree.is_recent_root(roots[2]));
+    assert!(!tree.is_recent_root(roots[1]));
+
+    // failed updates leave the history as is
+    assert!(tree.delete(9).is_err());
+    assert_eq!(tree.recent_roots().len(), 3);
+    tree.delete(3).unwrap();
+    assert!(!tree.is_recent_root(roots[2]));
+    assert_eq!(tree.get_root(), tree.recent_roots()[2]);
+
+    tree.restore(1, &[(0, Fr::one())]).unwrap();
+    assert_eq!(tree.recent_roots(), vec![tree.get_root()]);
+}
+
+#[test]
+fn test_tree_changes() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Hasher::new(params);
+    let leaf = |i: usize| hasher.hash(vec![Fr::from_str(&format!("{}", i + 1)).unwrap()]);
+    let mut tree = IncrementalMerkleTree::empty(hasher.clone(), 3);
+    tree.update_next(leaf(0)).unwrap();
+    tree.track_changes();
+    for i in 1..5 {
+        tree.update_next(leaf(i)).unwrap();
+    }
+    tree.delete(2).unwrap();
+    let changes = tree.take_changes();
+    assert_eq!(changes.len(), 4);
+    assert_eq!(changes[0], (1, Some(leaf(1))));
+    assert_eq!(changes[1], (2, None));
+    assert!(tree.take_changes().is_empty());
+    tree.requeue_changes(&[2, 1]);
+    assert_eq!(tree.take_changes(), changes[..2].to_vec());
+
+    // untracked first leaf plus the persisted changes restore the tree
+    let mut leaves = vec![(0, leaf(0))];
+    leaves.extend(changes.iter().filter_map(|(i, l)| l.map(|l| (*i, l))));
+    let mut restored = IncrementalMerkleTree::empty(hasher.clone(), 3);
+    restored.restore(tree.current_index, &leaves).unwrap();
+    assert_eq!(restored.get_root(), tree.get_root());
+    assert_eq!(restored.current_index, 5);
+    assert!(restored.restore(4, &leaves).is_err());
+}
+
+#[test]
+fn test_export_leaves() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Hasher::new(params);
+    let leaf = |i: usize| hasher.hash(vec![Fr::from_str(&format!("{}", i + 1)).unwrap()]);
+    let mut tree = IncrementalMerkleTree::empty(hasher.clone(), 3);
+    for i in 0..6 {
+        tree.update_next(leaf(i)).unwrap();
+    }
+    tree.delete(1).unwrap();
+    tree.delete(4).unwrap();
+    let leaves: Vec<(u64, Fr)> = tree.iter_leaves().collect();
+    assert_eq!(
+        leaves,
+        vec![(0, leaf(0)), (2, leaf(2)), (3, leaf(3)), (5, leaf(5))]
+    );
+
+    let mut exported: Vec<u8> = Vec::new();
+    tree.export_leaves(&mut exported).unwrap();
+    assert_eq!(exported.len(), 16 + 4 * 40);
+    let mut imported = IncrementalMerkleTree::empty(hasher.clone(), 3);
+    imported.import_leaves(exported.as_slice()).unwrap();
+    assert_eq!(imported.get_root(), tree.get_root());
+    assert_eq!(imported.current_index, 6);
+    assert_eq!(imported.member_count(), 4);
+
+    // the second and the third leaf swapped
+    let mut unordered = exported.clone();
+    let (first, second) = unordered[16 + 40..16 + 3 * 40].split_at_mut(40);
+    first.swap_with_slice(second);
+    assert!(imported.import_leaves(unordered.as_slice()).is_err());
+    let truncated = &exported[..exported.len() - 1];
+    assert!(imported.import_leaves(truncated).is_err());
+}
+
+#[test]
+fn test_consistency_proof() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    for (arity, params) in [
+        (
+            TreeArity::Binary,
+            PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None),
+        ),
+        (
+            TreeArity::Quad,
+            PoseidonParams::<Bn256>::new(8, 60, 5, None, None, None),
+        ),
+    ]
+    .iter()
+    {
+        let hasher = Hasher::new(params.clone());
+        let leaf = |i: u64| hasher.hash(vec![Fr::from_str(&format!("{}", i + 1)).unwrap()]);
+        let mut tree = IncrementalMerkleTree::empty_with_arity(hasher.clone(), 3, *arity);
+        let mut roots = vec![tree.get_root()];
+        for i in 0..9 {
+            tree.update_next(leaf(i)).unwrap();
+            roots.push(tree.get_root());
+        }
+        let new_root = tree.get_root();
+        let verify = |old_root: Fr, proof: &ConsistencyProof<Bn256>| {
+            verify_consistency(&hasher, *arity, Fr::zero(), old_root, new_root, proof)
+        };
+        for old_size in 0..=9 {
+            let proof = tree.consistency_proof(old_size).unwrap();
+            assert!(verify(roots[old_size as usize], &proof));
+            let mut raw: Vec<u8> = Vec::new();
+            proof.write(&mut raw).unwrap();
+            let decoded = ConsistencyProof::<Bn256>::read(raw.as_slice(), *arity).unwrap();
+            assert!(verify(roots[old_size as usize], &decoded));
+        }
+        let proof = tree.consistency_proof(5).unwrap();
+        assert!(!verify(roots[4], &proof));
+        assert!(tree.consistency_proof(10).is_err());
+
+        // an earlier leaf changed after the earlier state
+        tree.delete(2).unwrap();
+        let proof = tree.consistency_proof(5).unwrap();
+        assert!(!verify_consistency(
+            &hasher,
+            *arity,
+            Fr::zero(),
+            roots[5],
+            tree.get_root(),
+            &proof
+        ));
+    }
+}
+
+#[test]
+fn test_subtree_rehash() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 60, 5, None, None, None);
+    let hasher = Hasher::new(params);
+    let leaf = |i: u64| hasher.hash(vec![Fr::from_str(&format!("{}", i + 1)).unwrap()]);
+    for (arity, depth) in [(TreeArity::Binary, 7), (TreeArity::Quad, 4)].iter() {
+        let mut expected = IncrementalMerkleTree::empty_with_arity(hasher.clone(), *depth, *arity);
+        let mut events: Vec<MemberEvent<Bn256>> = Vec::new();
+        for i in 0..100 {
+            expected.update_next(leaf(i)).unwrap();
+            events.push(MemberEvent::Insert {
+                index: i,
+                commitment: leaf(i),
+            });
+        }
+        let tree =
+            IncrementalMerkleTree::from_leaves(hasher.clone(), *depth, *arity, &expected.leaves())
+                .unwrap();
+        assert_eq!(tree.get_root(), expected.get_root());
+
+        // writes spread over many subtrees
+        let mut applied = IncrementalMerkleTree::empty_with_arity(hasher.clone(), *depth, *arity);
+        applied.apply_events(events).unwrap();
+        assert_eq!(applied.get_root(), expected.get_root());
+        for i in (0..100).step_by(7) {
+            expected.delete(i).unwrap();
+        }
+        let deletes = (0..100)
+            .step_by(7)
+            .map(|index| MemberEvent::Delete { index });
+        assert_eq!(applied.apply_events(deletes).unwrap(), expected.get_root());
+    }
+}
+
+#[test]
+fn test_empty_nodes_cache() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Hasher::new(params.clone());
+    let empty_root = |depth: usize, default_leaf: Fr| {
+        let mut node = default_leaf;
+        for _ in 0..depth {
+            node = hasher.hash(vec![node, node]);
+        }
+        node
+    };
+    for depth in [4, 9, 2, 9].iter() {
+        let tree = MerkleTree::empty(Hasher::new(params.clone()), *depth);
+        assert_eq!(tree.get_root(), empty_root(*depth, Fr::zero()));
+        assert_eq!(tree.zero.len(), depth + 1);
+    }
+    let default_leaf = Fr::one();
+    let tree =
+        MerkleTree::empty_with_default_leaf(hasher.clone(), 6, TreeArity::Binary, default_leaf);
+    assert_eq!(tree.get_root(), empty_root(6, default_leaf));
+
+    let other = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, Some(b"other".to_vec()));
+    assert_ne!(other.digest(), params.digest());
+    let tree = MerkleTree::empty(Hasher::new(other.clone()), 4);
+    let mut node = Fr::zero();
+    for _ in 0..4 {
+        node = Hasher::new(other.clone()).hash(vec![node, node]);
+    }
+    assert_eq!(tree.get_root(), node);
+}
+
+#[test]
+fn test_merkle_proof() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Hasher::new(params);
+    let mut tree = IncrementalMerkleTree::empty(hasher.clone(), 3);
+    for i in 0..6 {
+        tree.update_next(hasher.hash(vec![Fr::from_str(&format!("{}", i + 1)).unwrap()]))
+            .unwrap();
+    }
+    let proof = tree.merkle_proof(5).unwrap();
+    assert_eq!(proof.path_bits, vec![true, false, true]);
+    assert_eq!(proof.leaf, tree.get_leaf(5).unwrap());
+    assert!(proof.root(&hasher) == tree.get_root());
+    assert!(proof.verify(&hasher, tree.get_root()));
+    assert_eq!(proof.witness(), tree.get_witness(5).unwrap());
+
+    let auth_path = proof.auth_path();
+    let from_auth_path = MerkleProof::from_auth_path(5, proof.leaf, &auth_path).unwrap();
+    assert!(from_auth_path.verify(&hasher, tree.get_root()));
+    assert!(MerkleProof::<Bn256>::from_auth_path(5, proof.leaf, &[None]).is_err());
+
+    let mut raw: Vec<u8> = Vec::new();
+    proof.write(&mut raw).unwrap();
+    let mut witness: Vec<u8> = Vec::new();
+    crate::utils::write_witness::<_, Bn256>(&proof.witness(), &mut witness).unwrap();
+    assert_eq!(raw, witness);
+    let decoded = MerkleProof::<Bn256>::read(raw.as_slice(), 5, proof.leaf, 3).unwrap();
+    assert!(decoded.verify(&hasher, tree.get_root()));
+    assert!(MerkleProof::<Bn256>::read(raw.as_slice(), 4, proof.leaf, 3).is_err());
+
+    let mut wrong_index = proof.clone();
+    wrong_index.leaf_index = 4;
+    assert!(!wrong_index.verify(&hasher, tree.get_root()));
+    assert!(tree.merkle_proof(6).is_err());
+}
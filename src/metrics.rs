@@ -0,0 +1,215 @@
+//! Hooks for operational metrics of an `RLN` instance.
+//!
+//! `RLN` reports generated and verified proofs, slash events and the size of its
+//! membership tree to the `Metrics` set with `RLN::set_metrics`, every hook is a no-op
+//! by default. With the `metrics-prometheus` feature `PrometheusMetrics` exports them
+//! as counters, histograms and a gauge of a prometheus registry.
+
+use std::time::Duration;
+
+pub trait Metrics: Send + Sync {
+    /// a proof was generated in `elapsed`
+    fn proof_generated(&self, _elapsed: Duration) {}
+
+    /// a proof was checked in `elapsed`, `valid` is false for rejected or undecodable proofs
+    fn proof_verified(&self, _valid: bool, _elapsed: Duration) {}
+
+    /// a second share of an epoch revealed the secret of a member
+    fn slash(&self) {}
+
+    /// the membership tree changed and holds `members` members
+    fn tree_size(&self, _members: usize) {}
+}
+
+/// the default metrics, reports nothing
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+#[cfg(feature = "metrics-prometheus")]
+pub use self::prometheus_metrics::PrometheusMetrics;
+
+#[cfg(feature = "metrics-prometheus")]
+mod prometheus_metrics {
+    use super::Metrics;
+    use prometheus::{Histogram, HistogramOpts, IntCounter, IntGauge, Registry};
+    use std::io;
+    use std::time::Duration;
+
+    #[derive(Clone)]
+    pub struct PrometheusMetrics {
+        proofs_generated: IntCounter,
+        proving_seconds: Histogram,
+        proofs_verified: IntCounter,
+        verification_failures: IntCounter,
+        verification_seconds: Histogram,
+        slash_events: IntCounter,
+        tree_size: IntGauge,
+    }
+
+    impl PrometheusMetrics {
+        /// registers the metrics of an instance in `registry`, names start with `rln_`
+        /// * instances sharing a registry need distinct const labels on it
+        pub fn new(registry: &Registry) -> io::Result<PrometheusMetrics> {
+            let metrics = PrometheusMetrics {
+                proofs_generated: IntCounter::new("rln_proofs_generated_total", "proofs generated")
+                    .map_err(to_io_error)?,
+                proving_seconds: Histogram::with_opts(HistogramOpts::new(
+                    "rln_proving_seconds",
+                    "time to generate a proof",
+                ))
+                .map_err(to_io_error)?,
+                proofs_verified: IntCounter::new("rln_proofs_verified_total", "proofs verified")
+                    .map_err(to_io_error)?,
+                verification_failures: IntCounter::new(
+                    "rln_verification_failures_total",
+                    "proofs rejected or not decodable",
+                )
+                .map_err(to_io_error)?,
+                verification_seconds: Histogram::with_opts(HistogramOpts::new(
+                    "rln_verification_seconds",
+                    "time to verify a proof",
+                ))
+                .map_err(to_io_error)?,
+                slash_events: IntCounter::new(
+                    "rln_slash_events_total",
+                    "secrets recovered from double signaling",
+                )
+                .map_err(to_io_error)?,
+                tree_size: IntGauge::new("rln_tree_size", "members of the membership tree")
+                    .map_err(to_io_error)?,
+            };
+            registry
+                .register(Box::new(metrics.proofs_generated.clone()))
+                .map_err(to_io_error)?;
+            registry
+                .register(Box::new(metrics.proving_seconds.clone()))
+                .map_err(to_io_error)?;
+            registry
+                .register(Box::new(metrics.proofs_verified.clone()))
+                .map_err(to_io_error)?;
+            registry
+                .register(Box::new(metrics.verification_failures.clone()))
+                .map_err(to_io_error)?;
+            registry
+                .register(Box::new(metrics.verification_seconds.clone()))
+                .map_err(to_io_error)?;
+            registry
+                .register(Box::new(metrics.slash_events.clone()))
+                .map_err(to_io_error)?;
+            registry
+                .register(Box::new(metrics.tree_size.clone()))
+                .map_err(to_io_error)?;
+            Ok(metrics)
+        }
+    }
+
+    impl Metrics for PrometheusMetrics {
+        fn proof_generated(&self, elapsed: Duration) {
+            self.proofs_generated.inc();
+            self.proving_seconds.observe(elapsed.as_secs_f64());
+        }
+
+        fn proof_verified(&self, valid: bool, elapsed: Duration) {
+            self.proofs_verified.inc();
+            if !valid {
+                self.verification_failures.inc();
+            }
+            self.verification_seconds.observe(elapsed.as_secs_f64());
+        }
+
+        fn slash(&self) {
+            self.slash_events.inc();
+        }
+
+        fn tree_size(&self, members: usize) {
+            self.tree_size.set(members as i64);
+        }
+    }
+
+    fn to_io_error(e: prometheus::Error) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, e)
+    }
+}
+
+#[test]
+fn test_metrics() {
+    use crate::merkle::TreeArity;
+    use crate::nullifier::LogStatus;
+    use crate::public::RLN;
+    use crate::test_utils::{proof_input, register_member};
+    use bellman::pairing::bn256::{Bn256, Fr};
+    use bellman::pairing::ff::{Field, PrimeField};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Default)]
+    struct Counts {
+        generated: AtomicUsize,
+        verified: AtomicUsize,
+        failed: AtomicUsize,
+        slashed: AtomicUsize,
+        members: AtomicUsize,
+    }
+
+    impl Metrics for Counts {
+        fn proof_generated(&self, _elapsed: Duration) {
+            self.generated.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn proof_verified(&self, valid: bool, _elapsed: Duration) {
+            self.verified.fetch_add(1, Ordering::SeqCst);
+            if !valid {
+                self.failed.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        fn slash(&self) {
+            self.slashed.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn tree_size(&self, members: usize) {
+            self.members.store(members, Ordering::SeqCst);
+        }
+    }
+
+    let mut rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let counts = Arc::new(Counts::default());
+    rln.set_metrics(counts.clone());
+
+    let id_key = Fr::from_str("1001").unwrap();
+    let hello_input = register_member(&rln, id_key, Fr::one(), b"hello");
+    assert_eq!(counts.members.load(Ordering::SeqCst), 1);
+
+    let mut proofs = Vec::new();
+    let again_input = proof_input::<Bn256>(id_key, 0, Fr::one(), b"again");
+    for input_data in [hello_input, again_input].iter() {
+        let mut proof: Vec<u8> = Vec::new();
+        rln.generate_proof(input_data.as_slice(), &mut proof)
+            .unwrap();
+        proofs.push(proof);
+    }
+    assert_eq!(counts.generated.load(Ordering::SeqCst), 2);
+
+    assert!(rln.verify_proof(proofs[0].as_slice()).unwrap());
+    let mut tampered = proofs[0].clone();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 1;
+    assert!(!rln.verify_proof(tampered.as_slice()).unwrap_or(false));
+    assert_eq!(counts.verified.load(Ordering::SeqCst), 2);
+    assert_eq!(counts.failed.load(Ordering::SeqCst), 1);
+
+    assert_eq!(
+        rln.nullifier_log_insert(proofs[0].as_slice()).unwrap(),
+        LogStatus::New
+    );
+    assert!(matches!(
+        rln.nullifier_log_insert(proofs[1].as_slice()).unwrap(),
+        LogStatus::Breach(_)
+    ));
+    assert_eq!(counts.slashed.load(Ordering::SeqCst), 1);
+
+    rln.delete_member(0).unwrap();
+    assert_eq!(counts.members.load(Ordering::SeqCst), 0);
+}
@@ -0,0 +1,250 @@
+//! C layer for mobile bindings (JNI, Swift) with string errors.
+//!
+//! Every call returns an `RLNMobileResult` owning either the output bytes or an
+//! error message, which is released with `rln_mobile_free_result`. Panics are caught
+//! and reported as errors instead of unwinding into the host runtime.
+//! The error string `rln_mobile_new` hands out belongs to the caller as well and is
+//! released with `rln_mobile_free_error`.
+//! Byte layouts are the ones of the matching `RLN` methods.
+
+use crate::merkle::TreeArity;
+use crate::public::RLN;
+use bellman::pairing::bn256::Bn256;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::{io, ptr, slice};
+
+#[repr(C)]
+pub struct RLNMobileResult {
+    pub data: *mut u8,
+    pub len: usize,
+    /// nul terminated message, null on success
+    pub error: *mut c_char,
+}
+
+/// loads circuit parameters into a new instance with a binary tree,
+/// `error` is set on failure and the returned handle is null
+/// * the caller owns the message and releases it with `rln_mobile_free_error`
+#[no_mangle]
+pub extern "C" fn rln_mobile_new(
+    merkle_depth: usize,
+    parameters_ptr: *const u8,
+    parameters_len: usize,
+    error: *mut *mut c_char,
+) -> *mut RLN<Bn256> {
+    let parameters = unsafe { bytes(parameters_ptr, parameters_len) };
+    let rln = catch_unwind(|| {
+        RLN::<Bn256>::new_with_raw_params(merkle_depth, TreeArity::Binary, parameters, None, None)
+    });
+    match flatten(rln) {
+        Ok(rln) => Box::into_raw(Box::new(rln)),
+        Err(e) => {
+            unsafe { *error = error_message(e) };
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn rln_mobile_free(ctx: *mut RLN<Bn256>) {
+    if !ctx.is_null() {
+        drop(unsafe { Box::from_raw(ctx) });
+    }
+}
+
+/// releases an error message of `rln_mobile_new`, null is ignored
+/// * messages of an `RLNMobileResult` are released with the result instead
+#[no_mangle]
+pub extern "C" fn rln_mobile_free_error(error: *mut c_char) {
+    if !error.is_null() {
+        drop(unsafe { CString::from_raw(error) });
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn rln_mobile_free_result(result: RLNMobileResult) {
+    if !result.data.is_null() {
+        drop(unsafe { Box::from_raw(slice::from_raw_parts_mut(result.data, result.len)) });
+    }
+    if !result.error.is_null() {
+        drop(unsafe { CString::from_raw(result.error) });
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn rln_mobile_key_gen(ctx: *const RLN<Bn256>) -> RLNMobileResult {
+    let rln = unsafe { &*ctx };
+    run(|| {
+        let mut output: Vec<u8> = Vec::new();
+        rln.key_gen(&mut output)?;
+        Ok(output)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn rln_mobile_root(ctx: *const RLN<Bn256>) -> RLNMobileResult {
+    let rln = unsafe { &*ctx };
+    run(|| {
+        let mut output: Vec<u8> = Vec::new();
+        rln.get_root(&mut output)?;
+        Ok(output)
+    })
+}
+
+/// inserts a public key in 32 bytes, the output is its index in 8 bytes
+#[no_mangle]
+pub extern "C" fn rln_mobile_add_member(
+    ctx: *const RLN<Bn256>,
+    input_ptr: *const u8,
+    input_len: usize,
+) -> RLNMobileResult {
+    let rln = unsafe { &*ctx };
+    let input = unsafe { bytes(input_ptr, input_len) };
+    run(|| {
+        let index = rln.update_next_member(input)?;
+        Ok(index.to_le_bytes().to_vec())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn rln_mobile_delete_member(ctx: *const RLN<Bn256>, index: u64) -> RLNMobileResult {
+    let rln = unsafe { &*ctx };
+    run(|| {
+        rln.delete_member(index)?;
+        Ok(vec![])
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn rln_mobile_prove(
+    ctx: *const RLN<Bn256>,
+    input_ptr: *const u8,
+    input_len: usize,
+) -> RLNMobileResult {
+    let rln = unsafe { &*ctx };
+    let input = unsafe { bytes(input_ptr, input_len) };
+    run(|| {
+        let mut output: Vec<u8> = Vec::new();
+        rln.generate_proof(input, &mut output)?;
+        Ok(output)
+    })
+}
+
+/// the output is a single byte, 1 for a valid proof
+#[no_mangle]
+pub extern "C" fn rln_mobile_verify(
+    ctx: *const RLN<Bn256>,
+    input_ptr: *const u8,
+    input_len: usize,
+) -> RLNMobileResult {
+    let rln = unsafe { &*ctx };
+    let input = unsafe { bytes(input_ptr, input_len) };
+    run(|| Ok(vec![rln.verify(input)? as u8]))
+}
+
+#[no_mangle]
+pub extern "C" fn rln_mobile_recover(
+    ctx: *const RLN<Bn256>,
+    share1_ptr: *const u8,
+    share1_len: usize,
+    share2_ptr: *const u8,
+    share2_len: usize,
+) -> RLNMobileResult {
+    let rln = unsafe { &*ctx };
+    let share1 = unsafe { bytes(share1_ptr, share1_len) };
+    let share2 = unsafe { bytes(share2_ptr, share2_len) };
+    run(|| {
+        let mut output: Vec<u8> = Vec::new();
+        rln.recover_secret(share1, share2, &mut output)?;
+        Ok(output)
+    })
+}
+
+// empty inputs may come with a null pointer from the host
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(ptr, len)
+    }
+}
+
+fn run<F: FnOnce() -> io::Result<Vec<u8>>>(f: F) -> RLNMobileResult {
+    match flatten(catch_unwind(AssertUnwindSafe(f))) {
+        Ok(output) => {
+            let len = output.len();
+            let data = if len == 0 {
+                ptr::null_mut()
+            } else {
+                Box::into_raw(output.into_boxed_slice()) as *mut u8
+            };
+            RLNMobileResult {
+                data,
+                len,
+                error: ptr::null_mut(),
+            }
+        }
+        Err(e) => RLNMobileResult {
+            data: ptr::null_mut(),
+            len: 0,
+            error: error_message(e),
+        },
+    }
+}
+
+fn flatten<T>(result: std::thread::Result<io::Result<T>>) -> io::Result<T> {
+    result.unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "panic".to_string());
+        Err(io::Error::new(io::ErrorKind::Other, message))
+    })
+}
+
+fn error_message(e: io::Error) -> *mut c_char {
+    let message = e.to_string().replace('\0', " ");
+    CString::new(message).unwrap().into_raw()
+}
+
+#[test]
+fn test_mobile_calls() {
+    use crate::circuit::bench;
+    use crate::poseidon::PoseidonParams;
+    use std::ffi::CStr;
+
+    let poseidon_params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let rln_test = bench::RLNTest::<Bn256>::new(4, Some(poseidon_params));
+    let mut parameters: Vec<u8> = Vec::new();
+    rln_test.export_circuit_parameters(&mut parameters).unwrap();
+    let mut error: *mut c_char = ptr::null_mut();
+    let ctx = rln_mobile_new(4, parameters.as_ptr(), parameters.len(), &mut error);
+    assert!(!ctx.is_null());
+
+    let keypair = rln_mobile_key_gen(ctx);
+    assert!(keypair.error.is_null());
+    assert_eq!(keypair.len, 64);
+    let public_key = unsafe { slice::from_raw_parts(keypair.data.add(32), 32) };
+    let index = rln_mobile_add_member(ctx, public_key.as_ptr(), public_key.len());
+    assert_eq!(
+        unsafe { slice::from_raw_parts(index.data, index.len) },
+        &[0u8; 8]
+    );
+    rln_mobile_free_result(keypair);
+    rln_mobile_free_result(index);
+
+    let deleted = rln_mobile_delete_member(ctx, 1 << 10);
+    assert!(deleted.data.is_null());
+    let message = unsafe { CStr::from_ptr(deleted.error) };
+    assert!(!message.to_bytes().is_empty());
+    rln_mobile_free_result(deleted);
+
+    let missing = rln_mobile_new(4, ptr::null(), 0, &mut error);
+    assert!(missing.is_null());
+    assert!(!error.is_null());
+    rln_mobile_free_error(error);
+    rln_mobile_free_error(ptr::null_mut());
+    rln_mobile_free(ctx);
+}
@@ -0,0 +1,416 @@
+//! Native Node.js addon, the counterpart of the wasm bindings for servers.
+//!
+//! Proving and verification run on the libuv thread pool and resolve promises,
+//! so the event loop is not blocked by the multicore prover.
+//! `RLNNode` holds the calls with rust types, the `js` module exports it as the
+//! `RLN` class with napi 1, which builds with the toolchain of the crate.
+
+use crate::hash_to_field::SignalHasher;
+use crate::merkle::TreeArity;
+use crate::public::RLN;
+use crate::utils::{read_fr, ProofFormat};
+use bellman::pairing::bn256::Bn256;
+use std::io;
+use std::sync::Arc;
+
+pub struct RLNNode {
+    api: Arc<RLN<Bn256>>,
+}
+
+impl RLNNode {
+    /// creates an instance with freshly generated circuit parameters
+    pub fn new(merkle_depth: usize) -> RLNNode {
+        RLNNode {
+            api: Arc::new(RLN::new(merkle_depth, TreeArity::Binary, None, None)),
+        }
+    }
+
+    pub fn new_with_raw_params(
+        merkle_depth: usize,
+        raw_circuit_parameters: &[u8],
+    ) -> io::Result<RLNNode> {
+        let api = RLN::new_with_raw_params(
+            merkle_depth,
+            TreeArity::Binary,
+            raw_circuit_parameters,
+            None,
+            None,
+        )?;
+        Ok(RLNNode { api: Arc::new(api) })
+    }
+
+    pub fn set_compressed_proofs(&mut self, compressed: bool) -> io::Result<()> {
+        let proof_format = if compressed {
+            ProofFormat::Compressed
+        } else {
+            ProofFormat::Uncompressed
+        };
+        self.api_mut()?.set_proof_format(proof_format);
+        Ok(())
+    }
+
+    pub fn set_keccak_signal_hash(&mut self, keccak: bool) -> io::Result<()> {
+        let signal_hasher = if keccak {
+            SignalHasher::Keccak256
+        } else {
+            SignalHasher::Sha256
+        };
+        self.api_mut()?.set_signal_hasher(signal_hasher);
+        Ok(())
+    }
+
+    /// zero accepts signals of any length
+    pub fn set_max_signal_size(&mut self, max_signal_size: u32) -> io::Result<()> {
+        let max_signal_size = match max_signal_size {
+            0 => None,
+            n => Some(n as u64),
+        };
+        self.api_mut()?.set_max_signal_size(max_signal_size);
+        Ok(())
+    }
+
+    /// `rln_identifier` is a scalar field element in 32 bytes
+    pub fn set_rln_identifier(&mut self, rln_identifier: &[u8]) -> io::Result<()> {
+        let rln_identifier = read_fr::<_, Bn256>(rln_identifier, 1)?;
+        self.api_mut()?.set_rln_identifier(rln_identifier[0]);
+        Ok(())
+    }
+
+    pub fn key_gen(&self) -> io::Result<Vec<u8>> {
+        let mut output: Vec<u8> = Vec::new();
+        self.api.key_gen(&mut output)?;
+        Ok(output)
+    }
+
+    pub fn get_root(&self) -> io::Result<Vec<u8>> {
+        let mut output: Vec<u8> = Vec::new();
+        self.api.get_root(&mut output)?;
+        Ok(output)
+    }
+
+    /// inserts a public key in 32 bytes and returns its index
+    pub fn update_next_member(&self, public_key: &[u8]) -> io::Result<i64> {
+        let index = self.api.update_next_member(public_key)?;
+        Ok(index as i64)
+    }
+
+    /// `index` is a safe integer, js numbers hold indexes of trees up to depth 53
+    pub fn delete_member(&self, index: i64) -> io::Result<()> {
+        if index < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "negative member index",
+            ));
+        }
+        self.api.delete_member(index as u64)
+    }
+
+    /// task of `generate_proof` to run off the event loop
+    pub fn generate_proof(&self, input: &[u8]) -> ProveTask {
+        ProveTask {
+            api: self.api.clone(),
+            input: input.to_vec(),
+        }
+    }
+
+    /// task of `verify` on a proof followed by its signal, see `RLN::verify`
+    pub fn verify(&self, input: &[u8]) -> VerifyTask {
+        VerifyTask {
+            api: self.api.clone(),
+            input: input.to_vec(),
+        }
+    }
+
+    /// recovers a member secret from two proofs of the same epoch and nullifier
+    pub fn recover_secret(&self, share1: &[u8], share2: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output: Vec<u8> = Vec::new();
+        self.api.recover_secret(share1, share2, &mut output)?;
+        Ok(output)
+    }
+
+    pub fn export_verifier_key(&self) -> io::Result<Vec<u8>> {
+        let mut output: Vec<u8> = Vec::new();
+        self.api.export_verifier_key(&mut output)?;
+        Ok(output)
+    }
+
+    pub fn export_circuit_parameters(&self) -> io::Result<Vec<u8>> {
+        let mut output: Vec<u8> = Vec::new();
+        self.api.export_circuit_parameters(&mut output)?;
+        Ok(output)
+    }
+
+    // settings can not change under a pending proof
+    fn api_mut(&mut self) -> io::Result<&mut RLN<Bn256>> {
+        Arc::get_mut(&mut self.api).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::Other, "instance is busy with a pending task")
+        })
+    }
+}
+
+pub struct ProveTask {
+    api: Arc<RLN<Bn256>>,
+    input: Vec<u8>,
+}
+
+impl ProveTask {
+    pub fn run(&self) -> io::Result<Vec<u8>> {
+        let mut proof: Vec<u8> = Vec::new();
+        self.api.generate_proof(self.input.as_slice(), &mut proof)?;
+        Ok(proof)
+    }
+}
+
+pub struct VerifyTask {
+    api: Arc<RLN<Bn256>>,
+    input: Vec<u8>,
+}
+
+impl VerifyTask {
+    pub fn run(&self) -> io::Result<bool> {
+        self.api.verify(self.input.as_slice())
+    }
+}
+
+// the glue calls into symbols node provides when it loads the addon,
+// test binaries run without node and only link `RLNNode`
+#[cfg(not(test))]
+mod js {
+    use super::{ProveTask, RLNNode, VerifyTask};
+    use napi::{
+        CallContext, Env, Error, JsBoolean, JsBuffer, JsNumber, JsObject, JsUndefined, Property,
+        Result, Task,
+    };
+    use napi_derive::{js_function, module_exports};
+    use std::io;
+
+    #[module_exports]
+    fn init(mut exports: JsObject, env: Env) -> Result<()> {
+        let class = env.define_class(
+            "RLN",
+            constructor,
+            &[
+                Property::new(&env, "setCompressedProofs")?.with_method(set_compressed_proofs),
+                Property::new(&env, "setKeccakSignalHash")?.with_method(set_keccak_signal_hash),
+                Property::new(&env, "setMaxSignalSize")?.with_method(set_max_signal_size),
+                Property::new(&env, "setRlnIdentifier")?.with_method(set_rln_identifier),
+                Property::new(&env, "keyGen")?.with_method(key_gen),
+                Property::new(&env, "getRoot")?.with_method(get_root),
+                Property::new(&env, "updateNextMember")?.with_method(update_next_member),
+                Property::new(&env, "deleteMember")?.with_method(delete_member),
+                Property::new(&env, "generateProof")?.with_method(generate_proof),
+                Property::new(&env, "verify")?.with_method(verify),
+                Property::new(&env, "recoverSecret")?.with_method(recover_secret),
+                Property::new(&env, "exportVerifierKey")?.with_method(export_verifier_key),
+                Property::new(&env, "exportCircuitParameters")?
+                    .with_method(export_circuit_parameters),
+            ],
+        )?;
+        exports.set_named_property("RLN", class)?;
+        Ok(())
+    }
+
+    /// `new RLN(merkleDepth)` generates circuit parameters,
+    /// `new RLN(merkleDepth, rawCircuitParameters)` loads them
+    #[js_function(2)]
+    fn constructor(ctx: CallContext) -> Result<JsUndefined> {
+        let merkle_depth = ctx.get::<JsNumber>(0)?.get_uint32()? as usize;
+        let rln = if ctx.length > 1 {
+            let raw_circuit_parameters = ctx.get::<JsBuffer>(1)?.into_value()?;
+            RLNNode::new_with_raw_params(merkle_depth, &raw_circuit_parameters)
+                .map_err(to_js_error)?
+        } else {
+            RLNNode::new(merkle_depth)
+        };
+        let mut this: JsObject = ctx.this_unchecked();
+        ctx.env.wrap(&mut this, rln)?;
+        ctx.env.get_undefined()
+    }
+
+    #[js_function(1)]
+    fn set_compressed_proofs(ctx: CallContext) -> Result<JsUndefined> {
+        let compressed = ctx.get::<JsBoolean>(0)?.get_value()?;
+        instance(&ctx)?
+            .set_compressed_proofs(compressed)
+            .map_err(to_js_error)?;
+        ctx.env.get_undefined()
+    }
+
+    #[js_function(1)]
+    fn set_keccak_signal_hash(ctx: CallContext) -> Result<JsUndefined> {
+        let keccak = ctx.get::<JsBoolean>(0)?.get_value()?;
+        instance(&ctx)?
+            .set_keccak_signal_hash(keccak)
+            .map_err(to_js_error)?;
+        ctx.env.get_undefined()
+    }
+
+    #[js_function(1)]
+    fn set_max_signal_size(ctx: CallContext) -> Result<JsUndefined> {
+        let max_signal_size = ctx.get::<JsNumber>(0)?.get_uint32()?;
+        instance(&ctx)?
+            .set_max_signal_size(max_signal_size)
+            .map_err(to_js_error)?;
+        ctx.env.get_undefined()
+    }
+
+    #[js_function(1)]
+    fn set_rln_identifier(ctx: CallContext) -> Result<JsUndefined> {
+        let rln_identifier = ctx.get::<JsBuffer>(0)?.into_value()?;
+        instance(&ctx)?
+            .set_rln_identifier(&rln_identifier)
+            .map_err(to_js_error)?;
+        ctx.env.get_undefined()
+    }
+
+    #[js_function(0)]
+    fn key_gen(ctx: CallContext) -> Result<JsBuffer> {
+        let output = instance(&ctx)?.key_gen().map_err(to_js_error)?;
+        buffer(&ctx, output)
+    }
+
+    #[js_function(0)]
+    fn get_root(ctx: CallContext) -> Result<JsBuffer> {
+        let output = instance(&ctx)?.get_root().map_err(to_js_error)?;
+        buffer(&ctx, output)
+    }
+
+    #[js_function(1)]
+    fn update_next_member(ctx: CallContext) -> Result<JsNumber> {
+        let public_key = ctx.get::<JsBuffer>(0)?.into_value()?;
+        let index = instance(&ctx)?
+            .update_next_member(&public_key)
+            .map_err(to_js_error)?;
+        ctx.env.create_int64(index)
+    }
+
+    #[js_function(1)]
+    fn delete_member(ctx: CallContext) -> Result<JsUndefined> {
+        let index = ctx.get::<JsNumber>(0)?.get_int64()?;
+        instance(&ctx)?.delete_member(index).map_err(to_js_error)?;
+        ctx.env.get_undefined()
+    }
+
+    /// resolves to `generate_proof` output
+    #[js_function(1)]
+    fn generate_proof(ctx: CallContext) -> Result<JsObject> {
+        let input = ctx.get::<JsBuffer>(0)?.into_value()?;
+        let task = instance(&ctx)?.generate_proof(&input);
+        Ok(ctx.env.spawn(task)?.promise_object())
+    }
+
+    /// resolves to true for a valid proof followed by its signal
+    #[js_function(1)]
+    fn verify(ctx: CallContext) -> Result<JsObject> {
+        let input = ctx.get::<JsBuffer>(0)?.into_value()?;
+        let task = instance(&ctx)?.verify(&input);
+        Ok(ctx.env.spawn(task)?.promise_object())
+    }
+
+    #[js_function(2)]
+    fn recover_secret(ctx: CallContext) -> Result<JsBuffer> {
+        let share1 = ctx.get::<JsBuffer>(0)?.into_value()?;
+        let share2 = ctx.get::<JsBuffer>(1)?.into_value()?;
+        let output = instance(&ctx)?
+            .recover_secret(&share1, &share2)
+            .map_err(to_js_error)?;
+        buffer(&ctx, output)
+    }
+
+    #[js_function(0)]
+    fn export_verifier_key(ctx: CallContext) -> Result<JsBuffer> {
+        let output = instance(&ctx)?.export_verifier_key().map_err(to_js_error)?;
+        buffer(&ctx, output)
+    }
+
+    #[js_function(0)]
+    fn export_circuit_parameters(ctx: CallContext) -> Result<JsBuffer> {
+        let output = instance(&ctx)?
+            .export_circuit_parameters()
+            .map_err(to_js_error)?;
+        buffer(&ctx, output)
+    }
+
+    impl Task for ProveTask {
+        type Output = Vec<u8>;
+        type JsValue = JsBuffer;
+
+        fn compute(&mut self) -> Result<Self::Output> {
+            self.run().map_err(to_js_error)
+        }
+
+        fn resolve(self, env: Env, output: Self::Output) -> Result<Self::JsValue> {
+            Ok(env.create_buffer_with_data(output)?.into_raw())
+        }
+    }
+
+    impl Task for VerifyTask {
+        type Output = bool;
+        type JsValue = JsBoolean;
+
+        fn compute(&mut self) -> Result<Self::Output> {
+            self.run().map_err(to_js_error)
+        }
+
+        fn resolve(self, env: Env, output: Self::Output) -> Result<Self::JsValue> {
+            env.get_boolean(output)
+        }
+    }
+
+    fn instance<'a>(ctx: &'a CallContext) -> Result<&'a mut RLNNode> {
+        let this: JsObject = ctx.this_unchecked();
+        ctx.env.unwrap::<RLNNode>(&this)
+    }
+
+    fn buffer(ctx: &CallContext, output: Vec<u8>) -> Result<JsBuffer> {
+        Ok(ctx.env.create_buffer_with_data(output)?.into_raw())
+    }
+
+    fn to_js_error(e: io::Error) -> Error {
+        Error::from_reason(e.to_string())
+    }
+}
+
+#[test]
+fn test_node_binding() {
+    use crate::test_utils::proof_input;
+    use bellman::pairing::bn256::Fr;
+    use bellman::pairing::ff::PrimeField;
+
+    let mut rln = RLNNode::new(3);
+    let keypair = rln.key_gen().unwrap();
+    assert_eq!(keypair.len(), 64);
+    let index = rln.update_next_member(&keypair[32..]).unwrap();
+    assert_eq!(index, 0);
+    assert_eq!(rln.get_root().unwrap().len(), 32);
+    assert!(rln.delete_member(-1).is_err());
+
+    let id_key = read_fr::<_, Bn256>(&keypair[..32], 1).unwrap()[0];
+    let epoch = Fr::from_str("1").unwrap();
+    let input = |signal: &[u8]| proof_input::<Bn256>(id_key, index as u64, epoch, signal);
+
+    // settings are refused while a task shares the instance
+    let task = rln.generate_proof(&input(b"signal 0"));
+    assert!(rln.set_compressed_proofs(true).is_err());
+    let proof_0 = task.run().unwrap();
+    drop(task);
+    let mut verify_input = proof_0.clone();
+    verify_input.extend_from_slice(&input(b"signal 0")[72..]);
+    assert!(rln.verify(&verify_input).run().unwrap());
+    assert!(rln.verify(&proof_0).run().is_err());
+
+    let proof_1 = rln.generate_proof(&input(b"signal 1")).run().unwrap();
+    assert_eq!(
+        rln.recover_secret(&proof_0, &proof_1).unwrap(),
+        keypair[..32].to_vec()
+    );
+
+    // an instance loaded from the exported parameters has the same verifying key
+    let parameters = rln.export_circuit_parameters().unwrap();
+    let loaded = RLNNode::new_with_raw_params(3, &parameters).unwrap();
+    assert_eq!(
+        loaded.export_verifier_key().unwrap(),
+        rln.export_verifier_key().unwrap()
+    );
+    assert!(rln.set_compressed_proofs(true).is_ok());
+}
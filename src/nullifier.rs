@@ -0,0 +1,371 @@
+use crate::utils::{epoch_number, read_fr};
+use bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr};
+use bellman::pairing::Engine;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+/// outcome of logging a share
+#[derive(Clone)]
+pub enum LogStatus<E: Engine> {
+    /// first share of the nullifier in this epoch
+    New,
+    /// the same share was seen before, e.g. a re-delivered message
+    Duplicate,
+    /// a second share of the same nullifier, the secret of the sender is recovered
+    Breach(E::Fr),
+}
+
+impl<E: Engine> PartialEq for LogStatus<E> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (LogStatus::New, LogStatus::New) => true,
+            (LogStatus::Duplicate, LogStatus::Duplicate) => true,
+            (LogStatus::Breach(a), LogStatus::Breach(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl<E: Engine> fmt::Debug for LogStatus<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogStatus::New => write!(f, "New"),
+            LogStatus::Duplicate => write!(f, "Duplicate"),
+            LogStatus::Breach(secret) => write!(f, "Breach({:?})", secret),
+        }
+    }
+}
+
+impl<E: Engine> LogStatus<E> {
+    /// status code used over the C ABI, 0 new, 1 duplicate and 2 breach
+    pub fn code(&self) -> u32 {
+        match self {
+            LogStatus::New => 0,
+            LogStatus::Duplicate => 1,
+            LogStatus::Breach(_) => 2,
+        }
+    }
+}
+
+/// share kept by a `NullifierLog`
+#[derive(Clone)]
+pub struct LoggedShare<E: Engine> {
+    pub epoch: E::Fr,
+    pub nullifier: E::Fr,
+    pub share_x: E::Fr,
+    pub share_y: E::Fr,
+}
+
+/// persistence backend of a `NullifierLog`
+pub trait NullifierStore<E: Engine>: Send + Sync {
+    /// every share stored so far
+    fn load(&mut self) -> io::Result<Vec<LoggedShare<E>>>;
+
+    /// stores a share that is new to the log
+    fn append(&mut self, share: &LoggedShare<E>) -> io::Result<()>;
+
+    /// replaces the stored shares, e.g. after pruning
+    fn rewrite(&mut self, shares: &[LoggedShare<E>]) -> io::Result<()>;
+}
+
+/// append only file of shares, serialized as
+/// [ epoch<32> | nullifier<32> | share_x<32> | share_y<32> ] each
+/// * a partially written last share, e.g. after a crash, is ignored on load
+pub struct FileNullifierStore {
+    path: PathBuf,
+}
+
+const LOGGED_SHARE_SIZE: usize = 4 * 32;
+
+impl FileNullifierStore {
+    /// the file is created on the first append if it does not exist
+    pub fn new<P: Into<PathBuf>>(path: P) -> FileNullifierStore {
+        FileNullifierStore { path: path.into() }
+    }
+
+    fn write_share<E: Engine, W: Write>(share: &LoggedShare<E>, mut writer: W) -> io::Result<()> {
+        for fr in [share.epoch, share.nullifier, share.share_x, share.share_y].iter() {
+            fr.into_repr().write_le(&mut writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: Engine> NullifierStore<E> for FileNullifierStore {
+    fn load(&mut self) -> io::Result<Vec<LoggedShare<E>>> {
+        let mut data: Vec<u8> = Vec::new();
+        match File::open(&self.path) {
+            Ok(mut file) => file.read_to_end(&mut data)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        data.chunks_exact(LOGGED_SHARE_SIZE)
+            .map(|chunk| {
+                let values = read_fr::<_, E>(chunk, 4)?;
+                Ok(LoggedShare {
+                    epoch: values[0],
+                    nullifier: values[1],
+                    share_x: values[2],
+                    share_y: values[3],
+                })
+            })
+            .collect()
+    }
+
+    fn append(&mut self, share: &LoggedShare<E>) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        // a torn record from an earlier crash would shift every later one
+        let len = file.metadata()?.len();
+        let torn = len % LOGGED_SHARE_SIZE as u64;
+        if torn != 0 {
+            file.set_len(len - torn)?;
+        }
+        let mut record: Vec<u8> = Vec::with_capacity(LOGGED_SHARE_SIZE);
+        Self::write_share(share, &mut record)?;
+        file.write_all(&record)?;
+        file.sync_data()
+    }
+
+    fn rewrite(&mut self, shares: &[LoggedShare<E>]) -> io::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        {
+            let file = File::create(&tmp_path)?;
+            let mut writer = BufWriter::new(&file);
+            for share in shares.iter() {
+                Self::write_share(share, &mut writer)?;
+            }
+            writer.flush()?;
+            drop(writer);
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+/// shares seen so far for each epoch and nullifier
+pub struct NullifierLog<E: Engine> {
+    entries: HashMap<Vec<u8>, LoggedShare<E>>,
+    store: Option<Box<dyn NullifierStore<E>>>,
+}
+
+impl<E: Engine> Default for NullifierLog<E> {
+    fn default() -> Self {
+        NullifierLog::new()
+    }
+}
+
+impl<E: Engine> NullifierLog<E> {
+    pub fn new() -> NullifierLog<E> {
+        NullifierLog {
+            entries: HashMap::new(),
+            store: None,
+        }
+    }
+
+    /// log that keeps its shares in `store` as well, starting with the shares stored before
+    pub fn with_store(mut store: Box<dyn NullifierStore<E>>) -> io::Result<NullifierLog<E>> {
+        let mut entries = HashMap::new();
+        for share in store.load()?.into_iter() {
+            entries
+                .entry(Self::key(share.epoch, share.nullifier))
+                .or_insert(share);
+        }
+        Ok(NullifierLog {
+            entries,
+            store: Some(store),
+        })
+    }
+
+    /// returns the status the share would have without logging it
+    pub fn check(
+        &self,
+        epoch: E::Fr,
+        nullifier: E::Fr,
+        share_x: E::Fr,
+        share_y: E::Fr,
+    ) -> LogStatus<E> {
+        match self.entries.get(&Self::key(epoch, nullifier)) {
+            None => LogStatus::New,
+            Some(logged) => {
+                if logged.share_x == share_x {
+                    LogStatus::Duplicate
+                } else {
+                    match recover_secret::<E>((logged.share_x, logged.share_y), (share_x, share_y))
+                    {
+                        Some(secret) => LogStatus::Breach(secret),
+                        None => LogStatus::Duplicate,
+                    }
+                }
+            }
+        }
+    }
+
+    /// logs the share and returns its status,
+    /// only the first share of a nullifier is kept as it is enough to recover the secret
+    /// * new shares are written to the store before they are logged
+    pub fn insert(
+        &mut self,
+        epoch: E::Fr,
+        nullifier: E::Fr,
+        share_x: E::Fr,
+        share_y: E::Fr,
+    ) -> io::Result<LogStatus<E>> {
+        let status = self.check(epoch, nullifier, share_x, share_y);
+        if status == LogStatus::New {
+            let share = LoggedShare {
+                epoch,
+                nullifier,
+                share_x,
+                share_y,
+            };
+            if let Some(store) = self.store.as_mut() {
+                store.append(&share)?;
+            }
+            self.entries.insert(Self::key(epoch, nullifier), share);
+        }
+        Ok(status)
+    }
+
+    /// drops shares of epochs before `epoch` and returns how many were dropped
+    /// * epochs that are not integers are kept, see `epoch_number`
+    pub fn prune_before(&mut self, epoch: u64) -> io::Result<usize> {
+        let before = self.entries.len();
+        self.entries
+            .retain(|_, share| epoch_number(share.epoch).map_or(true, |e| e >= epoch));
+        let pruned = before - self.entries.len();
+        if pruned > 0 {
+            if let Some(store) = self.store.as_mut() {
+                let shares: Vec<LoggedShare<E>> = self.entries.values().cloned().collect();
+                store.rewrite(&shares)?;
+            }
+        }
+        Ok(pruned)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn key(epoch: E::Fr, nullifier: E::Fr) -> Vec<u8> {
+        let mut key: Vec<u8> = Vec::new();
+        epoch.into_repr().write_le(&mut key).unwrap();
+        nullifier.into_repr().write_le(&mut key).unwrap();
+        key
+    }
+}
+
+/// recovers `a_0` from two points of the line `y = a_0 + a_1 * x`
+/// * returns `None` if both shares have the same `x`
+pub fn recover_secret<E: Engine>(share1: (E::Fr, E::Fr), share2: (E::Fr, E::Fr)) -> Option<E::Fr> {
+    let (x1, y1) = share1;
+    let (x2, y2) = share2;
+    // a_1 = (y2 - y1) / (x2 - x1)
+    let mut dx = x2;
+    dx.sub_assign(&x1);
+    let dx_inv = dx.inverse()?;
+    let mut a_1 = y2;
+    a_1.sub_assign(&y1);
+    a_1.mul_assign(&dx_inv);
+    // a_0 = y1 - a_1 * x1
+    let mut a_0 = a_1;
+    a_0.mul_assign(&x1);
+    a_0.negate();
+    a_0.add_assign(&y1);
+    Some(a_0)
+}
+
+#[test]
+fn test_nullifier_log() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    use rand::{Rand, SeedableRng, XorShiftRng};
+    let mut rng = XorShiftRng::from_seed([0x3dbe6258, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+    let a_0 = Fr::rand(&mut rng);
+    let a_1 = Fr::rand(&mut rng);
+    let share = |x: Fr| {
+        let mut y = a_1;
+        y.mul_assign(&x);
+        y.add_assign(&a_0);
+        (x, y)
+    };
+    let epoch = Fr::rand(&mut rng);
+    let nullifier = Fr::rand(&mut rng);
+    let (x1, y1) = share(Fr::rand(&mut rng));
+    let (x2, y2) = share(Fr::rand(&mut rng));
+
+    let mut log = NullifierLog::<Bn256>::new();
+    assert_eq!(log.check(epoch, nullifier, x1, y1), LogStatus::New);
+    assert_eq!(
+        log.insert(epoch, nullifier, x1, y1).unwrap(),
+        LogStatus::New
+    );
+    assert_eq!(
+        log.insert(epoch, nullifier, x1, y1).unwrap(),
+        LogStatus::Duplicate
+    );
+    assert_eq!(
+        log.insert(epoch, nullifier, x2, y2).unwrap(),
+        LogStatus::Breach(a_0)
+    );
+    assert_eq!(
+        log.insert(Fr::one(), nullifier, x2, y2).unwrap(),
+        LogStatus::New
+    );
+    assert_eq!(log.len(), 2);
+
+    assert_eq!(recover_secret::<Bn256>((x1, y1), (x2, y2)), Some(a_0));
+    assert_eq!(recover_secret::<Bn256>((x1, y1), (x1, y1)), None);
+}
+
+#[test]
+fn test_nullifier_store() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    let fr = |n: u64| Fr::from_str(&n.to_string()).unwrap();
+    let path = std::env::temp_dir().join(format!("rln-nullifier-log-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let mut log =
+        NullifierLog::<Bn256>::with_store(Box::new(FileNullifierStore::new(&path))).unwrap();
+    for epoch in 1..=3 {
+        let status = log
+            .insert(fr(epoch), fr(100), fr(1), fr(epoch + 7))
+            .unwrap();
+        assert_eq!(status, LogStatus::New);
+    }
+
+    // shares survive a restart, a torn last record is ignored
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[1u8; 17]).unwrap();
+    drop(file);
+    let mut log =
+        NullifierLog::<Bn256>::with_store(Box::new(FileNullifierStore::new(&path))).unwrap();
+    assert_eq!(log.len(), 3);
+    let status = log.insert(fr(2), fr(100), fr(2), fr(11)).unwrap();
+    assert!(matches!(status, LogStatus::Breach(_)));
+
+    assert_eq!(log.prune_before(3).unwrap(), 2);
+    assert_eq!(
+        log.insert(fr(4), fr(100), fr(1), fr(1)).unwrap(),
+        LogStatus::New
+    );
+    let log = NullifierLog::<Bn256>::with_store(Box::new(FileNullifierStore::new(&path))).unwrap();
+    assert_eq!(log.len(), 2);
+    assert_eq!(log.check(fr(1), fr(100), fr(2), fr(0)), LogStatus::New);
+    assert_eq!(
+        log.check(fr(3), fr(100), fr(1), fr(10)),
+        LogStatus::Duplicate
+    );
+    fs::remove_file(&path).unwrap();
+}
@@ -1,20 +1,76 @@
+mod bn256;
+pub mod circom;
+
 use blake2::{Blake2s, Digest};
 
 use sapling_crypto::bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr};
 use sapling_crypto::bellman::pairing::Engine;
+use std::sync::Arc;
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
 pub struct PoseidonParams<E: Engine> {
     rf: usize,
     rp: usize,
     t: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr_vec"))]
     round_constants: Vec<E::Fr>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr_vec"))]
     mds_matrix: Vec<E::Fr>,
 }
 
+/// cloning shares the parameters
 #[derive(Clone)]
 pub struct Poseidon<E: Engine> {
-    params: PoseidonParams<E>,
+    params: Arc<PoseidonParams<E>>,
+    rounds: Arc<OptimizedRounds<E>>,
+    digest: [u8; 32],
+}
+
+/// partial rounds rewritten with a single round constant and a sparse matrix each,
+/// as in appendix B of the poseidon paper
+/// * constants of the elements the partial sbox skips are carried through the matrix
+/// into the next round, what is left after the partial rounds is `post_partial_constants`
+/// * the mds matrix is factored as `m'' * m'` where `m' = diag(1, m_hat)` commutes with
+/// the partial round, `m'` is pushed into the previous round up to `pre_sparse_matrix`
+#[derive(Clone)]
+pub struct OptimizedRounds<E: Engine> {
+    /// replaces the mds matrix of the last full round before the partial rounds
+    pub pre_sparse_matrix: Vec<E::Fr>,
+    /// added to the first element before the sbox of each partial round
+    pub partial_constants: Vec<E::Fr>,
+    pub sparse_matrices: Vec<SparseMatrix<E>>,
+    /// added to the state with the constants of the first full round after the partial rounds
+    pub post_partial_constants: Vec<E::Fr>,
+}
+
+/// `[[m_00, row], [column, I]]`
+#[derive(Clone)]
+pub struct SparseMatrix<E: Engine> {
+    pub m_00: E::Fr,
+    pub row: Vec<E::Fr>,
+    pub column: Vec<E::Fr>,
+}
+
+impl<E: Engine> SparseMatrix<E> {
+    pub fn apply(&self, state: &mut [E::Fr]) {
+        let x_0 = state[0];
+        let mut y_0 = x_0;
+        y_0.mul_assign(&self.m_00);
+        for (s, (r, c)) in state[1..]
+            .iter_mut()
+            .zip(self.row.iter().zip(self.column.iter()))
+        {
+            let mut tmp = *s;
+            tmp.mul_assign(r);
+            y_0.add_assign(&tmp);
+            let mut tmp = x_0;
+            tmp.mul_assign(c);
+            s.add_assign(&tmp);
+        }
+        state[0] = y_0;
+    }
 }
 
 impl<E: Engine> PoseidonParams<E> {
@@ -31,15 +87,26 @@ impl<E: Engine> PoseidonParams<E> {
             None => b"".to_vec(),
         };
 
+        // constants of the empty seed are precomputed for bn256
+        let tabulated = seed.is_empty();
+
         let _round_constants = match round_constants {
             Some(round_constants) => round_constants,
-            None => PoseidonParams::<E>::generate_constants(b"drlnhdsc", seed.clone(), rf + rp),
+            None => bn256::round_constants::<E>(rf + rp)
+                .filter(|_| tabulated)
+                .unwrap_or_else(|| {
+                    PoseidonParams::<E>::generate_constants(b"drlnhdsc", seed.clone(), rf + rp)
+                }),
         };
         assert_eq!(rf + rp, _round_constants.len());
 
         let _mds_matrix = match mds_matrix {
             Some(mds_matrix) => mds_matrix,
-            None => PoseidonParams::<E>::generate_mds_matrix(b"drlnhdsm", seed.clone(), t),
+            None => bn256::mds_matrix::<E>(t)
+                .filter(|_| tabulated)
+                .unwrap_or_else(|| {
+                    PoseidonParams::<E>::generate_mds_matrix(b"drlnhdsm", seed.clone(), t)
+                }),
         };
         PoseidonParams {
             rf,
@@ -54,6 +121,23 @@ impl<E: Engine> PoseidonParams<E> {
         return self.t;
     }
 
+    /// blake2s digest of the round numbers, round constants and mds matrix,
+    /// equal parameters have the same digest
+    pub fn digest(&self) -> [u8; 32] {
+        let mut hasher = Blake2s::new();
+        for n in [self.rf, self.rp, self.t].iter() {
+            hasher.input(&(*n as u64).to_le_bytes());
+        }
+        for c in self.round_constants.iter().chain(self.mds_matrix.iter()) {
+            let mut bytes = Vec::with_capacity(32);
+            c.into_repr().write_le(&mut bytes).unwrap();
+            hasher.input(&bytes);
+        }
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&hasher.result());
+        digest
+    }
+
     pub fn partial_round_len(&self) -> usize {
         return self.rp;
     }
@@ -79,6 +163,71 @@ impl<E: Engine> PoseidonParams<E> {
         self.mds_matrix.clone()
     }
 
+    pub fn mds_matrix_ref(&self) -> &[E::Fr] {
+        &self.mds_matrix
+    }
+
+    /// precomputes the sparse representation of the partial rounds
+    /// * needs at least one full round before the partial rounds
+    pub fn optimized_rounds(&self) -> OptimizedRounds<E> {
+        let t = self.t;
+        let a1 = self.full_round_half_len();
+        assert!(a1 > 0, "no full round before the partial rounds");
+
+        let mut carry = vec![E::Fr::zero(); t];
+        let mut partial_constants = Vec::with_capacity(self.rp);
+        for round in a1..a1 + self.rp {
+            let mut k = carry;
+            for k_i in k.iter_mut() {
+                k_i.add_assign(&self.round_constants[round]);
+            }
+            partial_constants.push(k[0]);
+            k[0] = E::Fr::zero();
+            carry = mul_matrix::<E>(&self.mds_matrix, &k);
+        }
+
+        // going backwards, n = m' * m of the following round is factored again
+        let mut n = self.mds_matrix.clone();
+        let mut sparse_matrices = Vec::with_capacity(self.rp);
+        for _ in 0..self.rp {
+            let n_hat: Vec<E::Fr> = (1..t)
+                .flat_map(|i| n[i * t + 1..(i + 1) * t].to_vec())
+                .collect();
+            let n_hat_inv = invert_matrix::<E>(&n_hat, t - 1).expect("mds matrix is not mds");
+            let row = (0..t - 1)
+                .map(|j| {
+                    let mut acc = E::Fr::zero();
+                    for k in 0..t - 1 {
+                        let mut tmp = n[1 + k];
+                        tmp.mul_assign(&n_hat_inv[k * (t - 1) + j]);
+                        acc.add_assign(&tmp);
+                    }
+                    acc
+                })
+                .collect();
+            let column = (1..t).map(|i| n[i * t]).collect();
+            sparse_matrices.push(SparseMatrix {
+                m_00: n[0],
+                row,
+                column,
+            });
+            let mut m_prime = vec![E::Fr::zero(); t * t];
+            m_prime[0] = E::Fr::one();
+            for i in 1..t {
+                m_prime[i * t + 1..(i + 1) * t].copy_from_slice(&n[i * t + 1..(i + 1) * t]);
+            }
+            n = mul_matrices::<E>(&m_prime, &self.mds_matrix, t);
+        }
+        sparse_matrices.reverse();
+
+        OptimizedRounds {
+            pre_sparse_matrix: n,
+            partial_constants,
+            sparse_matrices,
+            post_partial_constants: carry,
+        }
+    }
+
     pub fn generate_mds_matrix(persona: &[u8; 8], seed: Vec<u8>, t: usize) -> Vec<E::Fr> {
         let v: Vec<E::Fr> = PoseidonParams::<E>::generate_constants(persona, seed, t * 2);
         let mut matrix: Vec<E::Fr> = Vec::with_capacity(t * t);
@@ -116,11 +265,65 @@ impl<E: Engine> PoseidonParams<E> {
 
 impl<E: Engine> Poseidon<E> {
     pub fn new(params: PoseidonParams<E>) -> Poseidon<E> {
-        Poseidon { params }
+        let rounds = params.optimized_rounds();
+        let digest = params.digest();
+        Poseidon {
+            params: Arc::new(params),
+            rounds: Arc::new(rounds),
+            digest,
+        }
     }
 
+    /// hashes inputs with a fresh per call state, so a hasher can be shared across threads
     pub fn hash(&self, inputs: Vec<E::Fr>) -> E::Fr {
-        let mut state = inputs.clone();
+        let mut state = inputs;
+        state.resize(self.t(), E::Fr::zero());
+        let a1 = self.params.full_round_half_len();
+        let a2 = a1 + self.params.partial_round_len();
+        let last_round = self.params.total_rounds() - 1;
+        for round in 0..a1 {
+            self.add_round_constants(&mut state, round);
+            self.apply_quintic_sbox(&mut state, true);
+            if round == a1 - 1 {
+                state = mul_matrix::<E>(&self.rounds.pre_sparse_matrix, &state);
+            } else {
+                self.mul_mds_matrix(&mut state);
+            }
+        }
+        for (constant, matrix) in self
+            .rounds
+            .partial_constants
+            .iter()
+            .zip(self.rounds.sparse_matrices.iter())
+        {
+            state[0].add_assign(constant);
+            self.apply_quintic_sbox(&mut state, false);
+            matrix.apply(&mut state);
+        }
+        for (s, c) in state
+            .iter_mut()
+            .zip(self.rounds.post_partial_constants.iter())
+        {
+            s.add_assign(c);
+        }
+        for round in a2..last_round {
+            self.full_round(&mut state, round);
+        }
+        self.full_round_last(&mut state);
+        state[0]
+    }
+
+    pub fn optimized_rounds(&self) -> &OptimizedRounds<E> {
+        &self.rounds
+    }
+
+    pub(crate) fn shared_params(&self) -> (Arc<PoseidonParams<E>>, Arc<OptimizedRounds<E>>) {
+        (self.params.clone(), self.rounds.clone())
+    }
+
+    // round by round permutation the optimized one is checked against
+    fn hash_reference(&self, inputs: Vec<E::Fr>) -> E::Fr {
+        let mut state = inputs;
         state.resize(self.t(), E::Fr::zero());
         let mut round_counter: usize = 0;
         loop {
@@ -133,11 +336,44 @@ impl<E: Engine> Poseidon<E> {
         state[0]
     }
 
+    /// hashes each input independently,
+    /// inputs are spread over the rayon pool with the `multicore` feature
+    pub fn hash_batch(&self, inputs: &[Vec<E::Fr>]) -> Vec<E::Fr> {
+        #[cfg(feature = "multicore")]
+        {
+            use rayon::prelude::*;
+            inputs
+                .par_iter()
+                .map(|input| self.hash(input.clone()))
+                .collect()
+        }
+        #[cfg(not(feature = "multicore"))]
+        {
+            inputs
+                .iter()
+                .map(|input| self.hash(input.clone()))
+                .collect()
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.params.width()
+    }
+
+    pub fn params(&self) -> &PoseidonParams<E> {
+        &self.params
+    }
+
+    /// see `PoseidonParams::digest`
+    pub fn digest(&self) -> [u8; 32] {
+        self.digest
+    }
+
     fn t(&self) -> usize {
         self.params.t
     }
 
-    fn round(&self, state: &mut Vec<E::Fr>, round: usize) {
+    fn round(&self, state: &mut [E::Fr], round: usize) {
         let a1 = self.params.full_round_half_len();
         let a2 = a1 + self.params.partial_round_len();
         let a3 = self.params.total_rounds();
@@ -156,32 +392,32 @@ impl<E: Engine> Poseidon<E> {
         }
     }
 
-    fn full_round(&self, state: &mut Vec<E::Fr>, round: usize) {
+    fn full_round(&self, state: &mut [E::Fr], round: usize) {
         self.add_round_constants(state, round);
         self.apply_quintic_sbox(state, true);
         self.mul_mds_matrix(state);
     }
 
-    fn full_round_last(&self, state: &mut Vec<E::Fr>) {
+    fn full_round_last(&self, state: &mut [E::Fr]) {
         let last_round = self.params.total_rounds() - 1;
         self.add_round_constants(state, last_round);
         self.apply_quintic_sbox(state, true);
     }
 
-    fn partial_round(&self, state: &mut Vec<E::Fr>, round: usize) {
+    fn partial_round(&self, state: &mut [E::Fr], round: usize) {
         self.add_round_constants(state, round);
         self.apply_quintic_sbox(state, false);
         self.mul_mds_matrix(state);
     }
 
-    fn add_round_constants(&self, state: &mut Vec<E::Fr>, round: usize) {
+    fn add_round_constants(&self, state: &mut [E::Fr], round: usize) {
         for (_, b) in state.iter_mut().enumerate() {
             let c = self.params.round_constants[round];
             b.add_assign(&c);
         }
     }
 
-    fn apply_quintic_sbox(&self, state: &mut Vec<E::Fr>, full: bool) {
+    fn apply_quintic_sbox(&self, state: &mut [E::Fr], full: bool) {
         for s in state.iter_mut() {
             let mut b = s.clone();
             b.square();
@@ -193,20 +429,77 @@ impl<E: Engine> Poseidon<E> {
         }
     }
 
-    fn mul_mds_matrix(&self, state: &mut Vec<E::Fr>) {
-        let w = self.params.t;
-        let mut new_state = vec![E::Fr::zero(); w];
-        for (i, ns) in new_state.iter_mut().enumerate() {
+    fn mul_mds_matrix(&self, state: &mut [E::Fr]) {
+        let new_state = mul_matrix::<E>(&self.params.mds_matrix, state);
+        state.copy_from_slice(&new_state[..]);
+    }
+}
+
+// matrices are square and row major
+
+fn mul_matrix<E: Engine>(matrix: &[E::Fr], state: &[E::Fr]) -> Vec<E::Fr> {
+    let w = state.len();
+    (0..w)
+        .map(|i| {
+            let mut acc = E::Fr::zero();
             for (j, s) in state.iter().enumerate() {
-                let mut tmp = s.clone();
-                tmp.mul_assign(&self.params.mds_matrix[i * w + j]);
-                ns.add_assign(&tmp);
+                let mut tmp = *s;
+                tmp.mul_assign(&matrix[i * w + j]);
+                acc.add_assign(&tmp);
+            }
+            acc
+        })
+        .collect()
+}
+
+fn mul_matrices<E: Engine>(a: &[E::Fr], b: &[E::Fr], n: usize) -> Vec<E::Fr> {
+    let mut c = vec![E::Fr::zero(); n * n];
+    for i in 0..n {
+        for j in 0..n {
+            for k in 0..n {
+                let mut tmp = a[i * n + k];
+                tmp.mul_assign(&b[k * n + j]);
+                c[i * n + j].add_assign(&tmp);
             }
         }
-        for (i, ns) in new_state.iter_mut().enumerate() {
-            state[i].clone_from(ns);
+    }
+    c
+}
+
+// gauss jordan elimination, none if the matrix is singular
+fn invert_matrix<E: Engine>(a: &[E::Fr], n: usize) -> Option<Vec<E::Fr>> {
+    let mut a = a.to_vec();
+    let mut inv = vec![E::Fr::zero(); n * n];
+    for i in 0..n {
+        inv[i * n + i] = E::Fr::one();
+    }
+    for col in 0..n {
+        let pivot = (col..n).find(|&r| !a[r * n + col].is_zero())?;
+        for j in 0..n {
+            a.swap(col * n + j, pivot * n + j);
+            inv.swap(col * n + j, pivot * n + j);
+        }
+        let pivot_inv = a[col * n + col].inverse()?;
+        for j in 0..n {
+            a[col * n + j].mul_assign(&pivot_inv);
+            inv[col * n + j].mul_assign(&pivot_inv);
+        }
+        for r in 0..n {
+            let factor = a[r * n + col];
+            if r == col || factor.is_zero() {
+                continue;
+            }
+            for j in 0..n {
+                let mut tmp = a[col * n + j];
+                tmp.mul_assign(&factor);
+                a[r * n + j].sub_assign(&tmp);
+                let mut tmp = inv[col * n + j];
+                tmp.mul_assign(&factor);
+                inv[r * n + j].sub_assign(&tmp);
+            }
         }
     }
+    Some(inv)
 }
 
 #[test]
@@ -225,3 +518,58 @@ fn test_poseidon_hash() {
     // println!("{:?}", r1);
     assert_eq!(r1, r2, "just to see if internal state resets");
 }
+
+#[test]
+fn test_poseidon_hash_batch() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Poseidon::<Bn256>::new(params);
+    let inputs: Vec<Vec<Fr>> = (0..9)
+        .map(|i| vec![Fr::from_str(&format!("{}", i)).unwrap(), Fr::one()])
+        .collect();
+    let hashes = hasher.hash_batch(&inputs);
+    assert_eq!(hashes.len(), inputs.len());
+    for (input, hash) in inputs.into_iter().zip(hashes.into_iter()) {
+        assert_eq!(hasher.hash(input), hash);
+    }
+}
+
+#[test]
+fn test_poseidon_shared_hasher() {
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    use std::sync::Arc;
+    use std::thread;
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Poseidon<Bn256>>();
+
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Arc::new(Poseidon::<Bn256>::new(params));
+    let input: Vec<Fr> = vec![Fr::from_str("1").unwrap(), Fr::from_str("2").unwrap()];
+    let expected = hasher.hash(input.clone());
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let hasher = hasher.clone();
+            let input = input.clone();
+            thread::spawn(move || hasher.hash(input))
+        })
+        .collect();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_poseidon_optimized_rounds() {
+    use rand::{thread_rng, Rand};
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    let mut rng = thread_rng();
+    for (t, rp) in [(3, 55), (4, 57), (5, 60)].iter() {
+        let params = PoseidonParams::<Bn256>::new(8, *rp, *t, None, None, None);
+        let hasher = Poseidon::<Bn256>::new(params);
+        assert_eq!(hasher.optimized_rounds().sparse_matrices.len(), *rp);
+        for n in 1..*t {
+            let inputs: Vec<Fr> = (0..n).map(|_| Fr::rand(&mut rng)).collect();
+            assert_eq!(hasher.hash(inputs.clone()), hasher.hash_reference(inputs));
+        }
+    }
+}
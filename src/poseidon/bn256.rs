@@ -0,0 +1,183 @@
+//! Poseidon constants of the default seed over bn256.
+//!
+//! Tables hold the canonical little endian limbs `PoseidonParams::generate_constants`
+//! derives with an empty seed, so default parameters skip the Blake2s derivation.
+//! Round constants are a prefix of one sequence and shared by every width.
+
+use lazy_static::lazy_static;
+use sapling_crypto::bellman::pairing::bn256::{Fr, FrRepr};
+use sapling_crypto::bellman::pairing::ff::PrimeField;
+use sapling_crypto::bellman::pairing::Engine;
+use std::any::Any;
+
+lazy_static! {
+    static ref ROUND_CONSTANTS_FR: Vec<Fr> = to_fr(&ROUND_CONSTANTS);
+    static ref MDS_MATRIX_T3_FR: Vec<Fr> = to_fr(&MDS_MATRIX_T3);
+    static ref MDS_MATRIX_T5_FR: Vec<Fr> = to_fr(&MDS_MATRIX_T5);
+}
+
+/// first `len` round constants if `E` is bn256 and the table is long enough
+pub fn round_constants<E: Engine>(len: usize) -> Option<Vec<E::Fr>> {
+    if len > ROUND_CONSTANTS.len() {
+        return None;
+    }
+    cast::<E>(&ROUND_CONSTANTS_FR[..len])
+}
+
+/// mds matrix of width `t` if `E` is bn256 and the width is tabulated
+pub fn mds_matrix<E: Engine>(t: usize) -> Option<Vec<E::Fr>> {
+    let matrix: &[Fr] = match t {
+        3 => &MDS_MATRIX_T3_FR,
+        5 => &MDS_MATRIX_T5_FR,
+        _ => return None,
+    };
+    cast::<E>(matrix)
+}
+
+// none unless `E::Fr` is the bn256 scalar field
+fn cast<E: Engine>(constants: &[Fr]) -> Option<Vec<E::Fr>> {
+    constants
+        .iter()
+        .map(|c| (c as &dyn Any).downcast_ref::<E::Fr>().cloned())
+        .collect()
+}
+
+fn to_fr(limbs: &[[u64; 4]]) -> Vec<Fr> {
+    limbs
+        .iter()
+        .map(|limbs| Fr::from_repr(FrRepr(*limbs)).unwrap())
+        .collect()
+}
+
+#[rustfmt::skip]
+const ROUND_CONSTANTS: [[u64; 4]; 68] = [
+    [0x6d20469e9fa36e8b, 0xeda2f749cdbe9a6a, 0x4c2f09d63d371cc5, 0x061f2e832c23bee8],
+    [0xeb778179d921a59b, 0x4d4f6118d45d9aef, 0x1958289f43ed5bbc, 0x0e4d154ca9b7f511],
+    [0xbfea62518a337e07, 0x584b6d816a653ee4, 0x2fe4371cf6cd37bb, 0x298d683000ab71c7],
+    [0xfe40d9305bd1e167, 0x290b9b5abb43a537, 0xe74905913ddcae47, 0x2f860295bc93d694],
+    [0x6573c8513f1f3dfa, 0xbfee5e1023c5d45e, 0x96be7f3f455e595c, 0x1dd8b95942d95c78],
+    [0xcb02b22fe64c5161, 0x363546619bb9c54b, 0x7dd3c74e2c8b9cba, 0x189aa3023aeaa726],
+    [0xab1936616f08e523, 0xac18184e237c1b20, 0xa523131d91e2a7f6, 0x0da6b697fd05fe54],
+    [0x196e59f16e63ac5a, 0x38b9d130fb44f276, 0xe34cf092f33ce93b, 0x019df963bfafa7f0],
+    [0x2af4fa1087ae658f, 0x7bd5034fb02472ff, 0xc5eb671528c9896b, 0x295332d5ab168bc3],
+    [0xd91b791df2f980fa, 0x04c75010411b87ad, 0x6c314228be9361a6, 0x0f423b8479245887],
+    [0x46eb737c84abec5f, 0x878f3730a9fc71f5, 0xad9cde4f1f5c4eab, 0x1171744890a155b5],
+    [0xc035b0c67173abac, 0x1d7959c3975c5c9f, 0xff5dc049bc603cff, 0x2a557a13928c7eac],
+    [0x3f5a9f7222c021af, 0xb07c2655dd08dcda, 0x22d7ea2174cd9a35, 0x00c9c858d5d1ab16],
+    [0x2f9fec01b500b978, 0x1b54ff289479cc53, 0xb6d3ba6e80c34588, 0x0b358e01fc7cf925],
+    [0x8cf7a480ee2b7f84, 0x8134f8d61ff0eaa2, 0xec05a61d5f36df4f, 0x26c26f6e5a92ff96],
+    [0x7b8cfa754ae41e7f, 0xf202d3b3f959650d, 0x295104a58533a601, 0x020930074e8c6c15],
+    [0x6841e72eb4c33027, 0x271ea16309f5fa90, 0x5a5cd8ba4ad94828, 0x10f5f06286fdde34],
+    [0xf21e3792d33fc969, 0xd374a16b2107c5d6, 0xfec7d369ab5c0d11, 0x116ce0fb46b45c99],
+    [0xd9bbc9c23557255b, 0x589b1baf87ddd790, 0xc3dbdfca6084e2ec, 0x2a59da7487bf7a05],
+    [0x723ff4fa77030ea8, 0x525dbdf2b6830632, 0x948f42a7f8fecfb1, 0x1559c4b2c04c419e],
+    [0xd25906897c3ca92a, 0xaebefcf468003d2d, 0xd8df6885a85651f9, 0x030b1745767217e6],
+    [0x27becfeabe76d044, 0x95585f0db79cbd17, 0x4cd6ddf2cbcaa8a6, 0x2668ff336585f1e6],
+    [0x09dfd474c4e47f99, 0x7c4fccb1aeed560a, 0xe0c3a7e6650b1f29, 0x158c1ceb605d6cb3],
+    [0xfbc2a22094d172d4, 0xcef54f5001f1c32e, 0x7f42921afbefd206, 0x27506f4872c8b8bb],
+    [0x57575ed93ab92c73, 0x985c691fb2a54c86, 0x01fd861b22ee463c, 0x0e915e793df4e8ee],
+    [0xed94844dda28d8a9, 0xeb14f5b6ac18b898, 0x03e5ef729bcaf466, 0x264bd2bc04862128],
+    [0x244f6aad8f2ebc8a, 0x40654cdd08677543, 0x27fee9ff2c51f30a, 0x2f23c5d822cd0d5b],
+    [0xbfe060ce6eb6c412, 0xb60b81269cabca38, 0xe680ca5acda3c7d4, 0x2776a03893ed5c5c],
+    [0xc37951eb77120d3a, 0x9f3cfea3b1f3eb70, 0x9f23313249f6a180, 0x07aba324bd6d746b],
+    [0x7821d93d453d0fa3, 0xddf88dbb146f2d88, 0x7987d7bb476b8ed8, 0x09c0a6b8cf7f1c45],
+    [0x41c4e4ebf44eb146, 0x0f2aa2a62edb2360, 0xbc7c9188b9db100f, 0x292e4542f0e5b148],
+    [0xb8274d7c0e52c9b6, 0x48dfb2da2d540503, 0x80f49265283832fc, 0x01b08e9289e3a730],
+    [0xb2a96d61581af2e3, 0xf87226cc416b703e, 0xc0247e718eb0b227, 0x0d897c5adfe393f4],
+    [0xe837234977e3477c, 0xd22de5d1bf79ee1b, 0x39c69ed08086d969, 0x057582e2ebdd031c],
+    [0xa5343e3f3fe77edf, 0x5f9e6dc5c6e647a3, 0x681fdc9ce4e27439, 0x0be6726fc2d3d2b9],
+    [0x2bcb2176f45fd942, 0x4b7dd7d8637feb11, 0xae88f1155eac9e3c, 0x0e919ea6e332df81],
+    [0x97450320c889c40d, 0xd8c09748d08edc0e, 0x4c1c03aa2d38aac0, 0x2dadfa8057c41397],
+    [0x4e93faa79e40f778, 0xb5266d436914d50d, 0x887becde3fc6c47d, 0x2954c27dbde3ba4f],
+    [0xe57c2f2a4b43f98a, 0x95adf6bcee924520, 0x6e0d02084ce2bbf0, 0x1e8f4f1b4fa7cc67],
+    [0xe28342ad41e63625, 0x7c3b2461e08ff82e, 0xe3763f5d67ea8615, 0x01892c0a3e4c3ffc],
+    [0x81bfa45ce9ce80bf, 0x565007b2defd87d9, 0xff4837049ea3d95f, 0x297b80afd662c439],
+    [0x0b2834c6ad5c17b8, 0xdfd0dcdf96d5e9ce, 0x826dd02d19d3383d, 0x28def6e44cc1e6b1],
+    [0x0217ea155e850d3e, 0x4982ae2a126ff3be, 0x6927ee306c83f4b2, 0x0e7efce265188824],
+    [0xdd8383835be6b380, 0x69bf0b4ddf75c4b4, 0x3ca673d814ad8ea2, 0x1b61bcbf0030070f],
+    [0x9427efbfe7ef2040, 0x9688dcb9da74df68, 0xd36dcba28d869c20, 0x2bfc5e31157cdf08],
+    [0x3ccfd551b25280ae, 0xb660b1992da99d3e, 0x2edc2f95d8fc9aa4, 0x2535e86274b29f68],
+    [0x2a1886df2eba4f2c, 0xf1d75532494e0738, 0x0866535943960e6b, 0x2e26f31955ddb83a],
+    [0x4ee3bdefb1418575, 0x596f8f859f7c4f0e, 0x04f8597f09a6e75e, 0x1367f3092e878d42],
+    [0xd7f0cd30ee34a593, 0xb551993358839a2a, 0xec5a24b4e77471af, 0x0786e0a89d59f9f1],
+    [0xe79ce87e4acf4443, 0x6bd599b73c618801, 0x426798eb1aeb61d1, 0x05a623c15705f67c],
+    [0xd955ec729f75c774, 0x7a241fd04f74ee76, 0x0f298c37a605d63a, 0x216a2cd9a5dec0ce],
+    [0xd30957e8b24feb89, 0xe622d88a899267cc, 0x903e1e0b04c13b6b, 0x1ac59c277950c8cf],
+    [0x1edcbce69d1a7302, 0x72bc69f4b69d99fb, 0x00806eb2d2b94b49, 0x21181ae96428a486],
+    [0xa18d6b794fc48290, 0xbf1f1a2e183b0e14, 0xed9408b11d96e377, 0x2a24403e8cf5fb93],
+    [0xe7ccfef724a8e4f5, 0x39bec4696dc84721, 0x877c22de405a88bd, 0x13324a9a9db19a8f],
+    [0x7183284b57a3910a, 0xaee4ff6891f211ae, 0xc58822ad53ad5785, 0x17f556db73809bef],
+    [0xa831a7619b129fe5, 0x05b9406a45fb4e10, 0x254ca73dc68572e8, 0x22e4f8f9c9ae56f5],
+    [0xcb754d74e7ce8df9, 0xd345f75fcb17934e, 0x2aa0c05909831d1d, 0x066e36c90fda2dd5],
+    [0x01416de7d6c755e1, 0x3f0f9afbf14adce4, 0x83891cbe244a8b06, 0x190c65fb8440d503],
+    [0x811a766ad089ab1b, 0xeec8b98b96793d29, 0xa9b932ae35a1269e, 0x0b4fec4e028a67f9],
+    [0xbfe8b27108ad4de7, 0x637723ff14615542, 0x372283c7d36bd153, 0x1def7475769de3cd],
+    [0x87033299d2d3c874, 0x76178e5939646f6c, 0x525a69e23a08f6a9, 0x119217aded5f1ebf],
+    [0x1938f8dbe4a7c28c, 0xf2d23f57b1e4286d, 0x3c5b354f03c664f9, 0x036bd8311510a8d0],
+    [0x1a4ad9952f361f72, 0xa324a927eadb9eba, 0xaea3e94a64e3900c, 0x2bd7a4f0dec4d0ba],
+    [0x33270b1bde5dc8e2, 0x4be48765833a7595, 0x503ba4b6367736ee, 0x16d26cbc837cd7fe],
+    [0xe09cc06747080024, 0x23c6060634d08bef, 0x5264520d43c06641, 0x0cee6d83dafc1bfa],
+    [0x9892c351a4b9945f, 0xa8a413ee2533042f, 0xdd0b1d6b1386a814, 0x277e5575bfd050df],
+    [0x2d2fe8b8c7933e07, 0x6a349fc81a4ae694, 0x47c6e74997b1de1d, 0x2a7f1b45c34ceece],
+];
+
+#[rustfmt::skip]
+const MDS_MATRIX_T3: [[u64; 4]; 9] = [
+    [0xdc82601244551ae2, 0x184bf67db11ce904, 0xa33800b04cc6617b, 0x165d45ae851912f9],
+    [0xbf142117f42f8204, 0xd80fbaac16d30518, 0x5f4fc650fe7c53b1, 0x10fc284d0af58816],
+    [0x65a2818b5c74814f, 0xae82c434bf7d5fb0, 0xa03545d0835bca75, 0x06b687bd3c688aa9],
+    [0x90c3ba4ae13f5ecd, 0x72e0605e6de827e9, 0x12f4ea819251708d, 0x01057eb8e4bba26f],
+    [0x2230a275b83017c3, 0x33a28ce6d10ad251, 0xa9beaf4dc0a2ab52, 0x0023779a38eb9ef4],
+    [0x2a27cd61d7fba2fb, 0x2987c8bbae32ad20, 0x753b70c897773f5d, 0x012e5dfdd4f34081],
+    [0x782d2ae672c0b11f, 0x2dc0acf7b43fea28, 0x9304a1522087b869, 0x0d1807f022a8d80d],
+    [0x1fe3b4bd522aa53c, 0xa250e5e301f27dc9, 0x01481931453ed1e4, 0x17d468d0e6541de5],
+    [0xd1d4d2d2b9862b00, 0xa668938fdd2f16fa, 0xfd75e775d85e568f, 0x1ea09a4bd33f14ea],
+];
+
+#[rustfmt::skip]
+const MDS_MATRIX_T5: [[u64; 4]; 25] = [
+    [0x65a2818b5c74814f, 0xae82c434bf7d5fb0, 0xa03545d0835bca75, 0x06b687bd3c688aa9],
+    [0x7d7f7da64c16863c, 0x11fe790bdff8fa65, 0xf390273cdc0917d8, 0x20eb20aa03bad7bc],
+    [0x43b8c4dbb941d0b8, 0x5f99c14cd74a469e, 0x2748ab7c4841b8e1, 0x0f3727c477b09ec8],
+    [0xa7a861a93fbc62fa, 0x2afc075559c4465e, 0x2d609195cf8d5eca, 0x13a2af5c279e7977],
+    [0x9dbbbe7da59786a5, 0xfb57df1c309f6bbc, 0x9a60424bc0176be5, 0x2cb00c86489a930d],
+    [0x2a27cd61d7fba2fb, 0x2987c8bbae32ad20, 0x753b70c897773f5d, 0x012e5dfdd4f34081],
+    [0xc72cd7e4357c6958, 0x7fdbf1444a9a7495, 0xe255081ae125d6df, 0x2c8120f7d5a5ef74],
+    [0x13a66fe51910ff5e, 0x2af3154ff0ac4fae, 0x32f09b9719bc1baa, 0x21c678a71da47d6f],
+    [0xe79b7bbdd7e69ace, 0x13d5a3974d4a0729, 0x17213e2f545f8af1, 0x284bc5549f9d52f0],
+    [0x3d686fea1ed6a23c, 0x5e5e02ed1c7e278f, 0xddaba166bc277e58, 0x252ce7c95910c5c1],
+    [0xd1d4d2d2b9862b00, 0xa668938fdd2f16fa, 0xfd75e775d85e568f, 0x1ea09a4bd33f14ea],
+    [0x46dc14d8f887e6d0, 0xb8b832748d173d9b, 0x40345dca5b4be28b, 0x08ff8e5e49cb85e6],
+    [0xae5c6a4a16b88366, 0x3064fa0abefc162c, 0xa4819305e47b2086, 0x2f93dfa232b4ba52],
+    [0xb308f9a4e25f7c82, 0xc6d8cf25b841ec4c, 0x8cecf94fea204a69, 0x28f402fb61eeea15],
+    [0xbc4b4d42af9babfc, 0xbd8590cdf34ea539, 0x011f73f5efe2b7ac, 0x0c582699d5861a68],
+    [0xd3068d50280b2f7b, 0xdaa259daea3d9da3, 0xd8e5289625d9cb93, 0x07558e8aedec3570],
+    [0xde388b07b4d73124, 0xf62735e0629c6d58, 0x5e3d9cf08be29f05, 0x2b7c283d1b9fed71],
+    [0x31857c5a23a4d7ef, 0x7d6bc297b2ae4366, 0x6ed642979502ad97, 0x1a2148dcbf719002],
+    [0x27000f9e141c1f7a, 0xc60862ac491a081b, 0xd56742bee19241d1, 0x2e7ea309332b0e4a],
+    [0x3cc794f408315b1a, 0xa785f70b28e6ff6d, 0x9cd43fd796289b9c, 0x1214c15a938b4963],
+    [0x0a84ca809c80699d, 0xb694ae0806e4f259, 0x3f35e7e23bc042e0, 0x0b8859af66ad2246],
+    [0xaa28ea64250deef7, 0x4ab52d734d77228c, 0x91afa23a3a1a56fd, 0x147be6abfcb8faab],
+    [0x21915816f5eac673, 0x506cb533a7034da0, 0x6cd57dfeca6086d8, 0x1a14817c5075f021],
+    [0x23d04cdae8e90199, 0xc714e803205e8790, 0xd5ac7ed800c3669d, 0x02410ce05b1428fe],
+    [0x7ad08d68dbc5ec34, 0x0041055e0f1c1313, 0xa6d32234c8744c03, 0x0f40efc7a3775842],
+];
+
+#[test]
+fn test_tables_match_derivation() {
+    use crate::poseidon::PoseidonParams;
+    use sapling_crypto::bellman::pairing::bn256::Bn256;
+
+    let derived = PoseidonParams::<Bn256>::generate_constants(b"drlnhdsc", vec![], 68);
+    assert_eq!(round_constants::<Bn256>(68).unwrap(), derived);
+    assert_eq!(
+        round_constants::<Bn256>(63).unwrap(),
+        derived[..63].to_vec()
+    );
+    assert!(round_constants::<Bn256>(69).is_none());
+    for t in [3, 5].iter() {
+        let derived = PoseidonParams::<Bn256>::generate_mds_matrix(b"drlnhdsm", vec![], *t);
+        assert_eq!(mds_matrix::<Bn256>(*t).unwrap(), derived);
+    }
+    assert!(mds_matrix::<Bn256>(4).is_none());
+}
@@ -1,282 +1,3203 @@
-use crate::circuit::rln::{RLNCircuit, RLNInputs};
-use crate::hash_to_field::hash_to_field;
-use crate::merkle::MerkleTree;
+use crate::bundle::{BundleHeader, RLNProofBundle};
+use crate::cache::{CacheStats, VerificationCache};
+#[cfg(feature = "prover")]
+use crate::circuit::progress::{ProgressCircuit, ProofPhase, SetupPhase};
+#[cfg(feature = "debug")]
+use crate::circuit::rln::CircuitDiagnostics;
+use crate::circuit::rln::{
+    derive_identity, external_nullifier, identity_commitment, identity_secret, key_gen,
+    semaphore_tree_hasher, staked_leaf, CircuitShape, IdentityScheme, RLNBatchCircuit, RLNCircuit,
+    RLNInputs,
+};
+use crate::circuit::withdrawal::{WithdrawalCircuit, WithdrawalInputs};
+use crate::hash_to_field::{hash_to_field, SignalHasher};
+use crate::hasher::TreeHasher;
+use crate::merkle::{MemberEvent, MerkleTree, TreeArity};
+use crate::metrics::{Metrics, NoopMetrics};
+use crate::nullifier::{recover_secret, LogStatus, NullifierLog, NullifierStore};
 use crate::poseidon::{Poseidon as PoseidonHasher, PoseidonParams};
-use crate::utils::{read_fr, read_signal_hash, read_uncompressed_proof, write_uncompressed_proof};
+use crate::telemetry;
+#[cfg(all(feature = "prover", any(test, feature = "test_utils")))]
+use crate::utils::seeded_rng;
+use crate::utils::{
+    epoch_number, parameters_fingerprint, read_fr, read_proof, read_signal_hash_with_limit,
+    read_witness, rerandomize_proof, write_proof, ProofFormat,
+};
+use crate::verifier_core::{self, PublicInputLayout};
 use crate::{circuit::poseidon::PoseidonCircuit, merkle::IncrementalMerkleTree};
 use bellman::groth16::generate_random_parameters;
 use bellman::groth16::{create_proof, prepare_verifying_key, verify_proof};
-use bellman::groth16::{create_random_proof, Parameters, Proof};
+use bellman::groth16::{
+    create_random_proof, Parameters, PreparedVerifyingKey, Proof, VerifyingKey,
+};
 use bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr, ScalarEngine};
 use bellman::pairing::{CurveAffine, EncodedPoint, Engine};
 use bellman::{Circuit, ConstraintSystem, SynthesisError};
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use rand::{thread_rng, Rand, Rng};
 use std::{
+    fs::File,
     io::{self, Error, ErrorKind, Read, Write},
+    path::Path,
     ptr::null,
+    sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::Instant,
 };
 
 // Rate Limit Nullifier
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
 pub struct RLNSignal<E>
 where
     E: Engine,
 {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr"))]
     pub epoch: E::Fr,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr"))]
     pub hash: E::Fr,
+    /// signal bytes `hash` is computed from
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub payload: Vec<u8>,
 }
 
 impl<E> RLNSignal<E>
 where
     E: Engine,
 {
-    pub fn read<R: Read>(mut reader: R) -> io::Result<RLNSignal<E>> {
+    /// * expect `reader` is serialized as [ epoch<32> | signal_len<8> | signal<var> ]
+    pub fn read<R: Read>(reader: R, signal_hasher: SignalHasher) -> io::Result<RLNSignal<E>> {
+        Self::read_with_limit(reader, signal_hasher, None)
+    }
+
+    /// reads like `read`, signals longer than `max_signal_size` are rejected before any of it is read
+    pub fn read_with_limit<R: Read>(
+        mut reader: R,
+        signal_hasher: SignalHasher,
+        max_signal_size: Option<u64>,
+    ) -> io::Result<RLNSignal<E>> {
         let mut buf = <E::Fr as PrimeField>::Repr::default();
 
         buf.read_le(&mut reader)?;
         let epoch =
             E::Fr::from_repr(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
-        let hash = read_signal_hash::<R, E>(reader)?;
+        let n = reader.read_u64::<LittleEndian>()?;
+        if let Some(max_signal_size) = max_signal_size {
+            if n > max_signal_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "signal exceeds maximum size",
+                ));
+            }
+        }
+        let mut payload: Vec<u8> = Vec::new();
+        reader.take(n).read_to_end(&mut payload)?;
+        if payload.len() as u64 != n {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "signal is shorter than its length",
+            ));
+        }
+
+        Self::from_parts(epoch, payload, signal_hasher)
+    }
+
+    /// hashes `payload` with `signal_hasher`
+    pub fn from_parts(
+        epoch: E::Fr,
+        payload: Vec<u8>,
+        signal_hasher: SignalHasher,
+    ) -> io::Result<RLNSignal<E>> {
+        let hash = signal_hasher.hash::<E>(&payload)?;
+        Ok(RLNSignal {
+            epoch,
+            hash,
+            payload,
+        })
+    }
 
-        Ok(RLNSignal { epoch, hash })
+    /// * `writer` is serialized as [ epoch<32> | signal_len<8> | signal<var> ], as `read` expects
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        self.epoch.into_repr().write_le(&mut writer)?;
+        writer.write_u64::<LittleEndian>(self.payload.len() as u64)?;
+        writer.write_all(&self.payload)
     }
 }
 
-pub struct RLN<E: Engine> {
-    circuit_parameters: Parameters<E>,
-    poseidon_params: PoseidonParams<E>,
-    tree: IncrementalMerkleTree<E>,
+/// signal size `RLN` and `RLNVerifier` accept unless changed with `set_max_signal_size`
+pub const DEFAULT_MAX_SIGNAL_SIZE: u64 = 4 << 20;
+
+/// verifies proofs with only the verifying key of the circuit
+pub struct RLNVerifier<E: Engine> {
+    raw_verifying_key: VerifyingKey<E>,
+    verifying_key: Arc<PreparedVerifyingKey<E>>,
+    proof_format: ProofFormat,
+    signal_hasher: SignalHasher,
+    rln_identifier: E::Fr,
+    max_signal_size: Option<u64>,
+    public_input_layout: Option<PublicInputLayout>,
 }
 
-impl<E: Engine> RLN<E> {
-    fn default_poseidon_params() -> PoseidonParams<E> {
-        PoseidonParams::<E>::new(8, 55, 3, None, None, None)
+impl<E: Engine> RLNVerifier<E> {
+    pub fn new(verifying_key: &VerifyingKey<E>) -> RLNVerifier<E> {
+        Self::new_with_prepared_vk(
+            verifying_key,
+            Arc::new(prepare_verifying_key(verifying_key)),
+        )
     }
 
-    fn new_circuit(merkle_depth: usize, poseidon_params: PoseidonParams<E>) -> Parameters<E> {
-        let mut rng = thread_rng();
-        let inputs = RLNInputs::<E>::empty(merkle_depth);
-        let circuit = RLNCircuit::<E> {
-            inputs,
-            hasher: PoseidonCircuit::new(poseidon_params.clone()),
-        };
-        generate_random_parameters(circuit, &mut rng).unwrap()
+    /// shares a key already prepared by another verifier instead of preparing it again
+    /// * `prepared_verifying_key` must be prepared from `verifying_key`
+    pub fn new_with_prepared_vk(
+        verifying_key: &VerifyingKey<E>,
+        prepared_verifying_key: Arc<PreparedVerifyingKey<E>>,
+    ) -> RLNVerifier<E> {
+        RLNVerifier {
+            raw_verifying_key: verifying_key.clone(),
+            verifying_key: prepared_verifying_key,
+            proof_format: ProofFormat::default(),
+            signal_hasher: SignalHasher::default(),
+            rln_identifier: E::Fr::zero(),
+            max_signal_size: Some(DEFAULT_MAX_SIGNAL_SIZE),
+            public_input_layout: None,
+        }
     }
 
-    fn new_with_params(
-        merkle_depth: usize,
-        circuit_parameters: Parameters<E>,
-        poseidon_params: PoseidonParams<E>,
-    ) -> RLN<E> {
-        let hasher = PoseidonHasher::new(poseidon_params.clone());
-        let tree = IncrementalMerkleTree::empty(hasher, merkle_depth);
-        RLN {
-            circuit_parameters,
-            poseidon_params,
-            tree,
-        }
+    pub fn new_with_raw_vk<R: Read>(raw_verifying_key: R) -> io::Result<RLNVerifier<E>> {
+        let verifying_key = VerifyingKey::<E>::read(raw_verifying_key)?;
+        Ok(Self::new(&verifying_key))
     }
 
-    pub fn new(merkle_depth: usize, poseidon_params: Option<PoseidonParams<E>>) -> RLN<E> {
-        let poseidon_params = match poseidon_params {
-            Some(params) => params,
-            None => Self::default_poseidon_params(),
-        };
-        let circuit_parameters = Self::new_circuit(merkle_depth, poseidon_params.clone());
-        Self::new_with_params(merkle_depth, circuit_parameters, poseidon_params)
+    pub fn prepared_verifying_key(&self) -> Arc<PreparedVerifyingKey<E>> {
+        self.verifying_key.clone()
     }
 
-    pub fn new_with_raw_params<R: Read>(
-        merkle_depth: usize,
-        raw_circuit_parameters: R,
-        poseidon_params: Option<PoseidonParams<E>>,
-    ) -> io::Result<RLN<E>> {
-        let circuit_parameters = Parameters::<E>::read(raw_circuit_parameters, true)?;
-        let poseidon_params = match poseidon_params {
-            Some(params) => params,
-            None => Self::default_poseidon_params(),
-        };
-        Ok(Self::new_with_params(
-            merkle_depth,
-            circuit_parameters,
-            poseidon_params,
-        ))
+    // verifier of another circuit with the settings of this one
+    fn with_verifying_key(
+        &self,
+        verifying_key: &VerifyingKey<E>,
+        prepared_verifying_key: Arc<PreparedVerifyingKey<E>>,
+    ) -> RLNVerifier<E> {
+        RLNVerifier {
+            raw_verifying_key: verifying_key.clone(),
+            verifying_key: prepared_verifying_key,
+            proof_format: self.proof_format,
+            signal_hasher: self.signal_hasher,
+            rln_identifier: self.rln_identifier,
+            max_signal_size: self.max_signal_size,
+            public_input_layout: self.public_input_layout.clone(),
+        }
     }
 
-    /// returns current membership root
-    /// * `root` is a scalar field element in 32 bytes
-    pub fn get_root<W: Write>(&self, mut result_data: W) -> io::Result<()> {
-        let root = self.tree.get_root();
-        root.into_repr().write_le(&mut result_data)?;
-        Ok(())
+    pub fn set_proof_format(&mut self, proof_format: ProofFormat) {
+        self.proof_format = proof_format;
     }
 
-    /// inserts new member with given public key
-    /// * `input_data` is a 32 scalar field element in 32 bytes
-    pub fn update_next_member<R: Read>(&mut self, input_data: R) -> io::Result<()> {
-        let mut buf = <E::Fr as PrimeField>::Repr::default();
-        buf.read_le(input_data)?;
-        let leaf =
-            E::Fr::from_repr(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        self.tree.update_next(leaf)?;
-        Ok(())
+    pub fn proof_format(&self) -> ProofFormat {
+        self.proof_format
     }
 
-    //// deletes member with given index
-    pub fn delete_member(&mut self, index: usize) -> io::Result<()> {
-        self.tree.delete(index)?;
-        Ok(())
+    pub fn set_signal_hasher(&mut self, signal_hasher: SignalHasher) {
+        self.signal_hasher = signal_hasher;
     }
 
-    /// hashes plain text to a field element
-    pub fn signal_to_field<R: Read, W: Write>(
-        &self,
-        input_data: R,
-        mut result_data: W,
-    ) -> io::Result<()> {
-        let result = read_signal_hash::<R, E>(input_data)?;
-        result.into_repr().write_le(&mut result_data)?;
-        Ok(())
+    pub fn signal_hasher(&self) -> SignalHasher {
+        self.signal_hasher
     }
 
-    /// given public inputs and autharization data generates public inputs and proof
-    /// * expect `input_data`  serialized as  [ id_key<32> | id_index<8> | epoch<32> | signal_len<8> | signal<var> ]
-    /// * `result_data` is proof data serialized as [ proof<256>| root<32>| epoch<32>| share_x<32>| share_y<32>| nullifier<32> ]
-    pub fn generate_proof<R: Read, W: Write>(
-        &self,
-        mut input_data: R,
-        mut result_data: W,
-    ) -> io::Result<()> {
-        let id_key: E::Fr = read_fr::<_, E>(&mut input_data, 1)?[0];
-        let id_index = input_data.read_u64::<LittleEndian>()? as usize;
+    /// sets the identifier of the deployment proofs are checked against, zero by default
+    pub fn set_rln_identifier(&mut self, rln_identifier: E::Fr) {
+        self.rln_identifier = rln_identifier;
+    }
 
-        let signal = RLNSignal::<E>::read(input_data)?;
+    pub fn rln_identifier(&self) -> E::Fr {
+        self.rln_identifier
+    }
 
-        use hex;
+    /// sets the longest signal accepted, `None` accepts signals of any length
+    pub fn set_max_signal_size(&mut self, max_signal_size: Option<u64>) {
+        self.max_signal_size = max_signal_size;
+    }
 
-        let hasher = self.hasher();
-        let share_x = signal.hash.clone();
+    pub fn max_signal_size(&self) -> Option<u64> {
+        self.max_signal_size
+    }
 
-        // line equation
-        let a_0 = id_key.clone();
-        let a_1: E::Fr = hasher.hash(vec![a_0, signal.epoch]);
-        // evaluate line equation
-        let mut share_y = a_1.clone();
-        share_y.mul_assign(&share_x);
-        share_y.add_assign(&a_0);
-        let nullifier = hasher.hash(vec![a_1]);
+    /// sets the order the verifying key takes public inputs in, e.g. the one of
+    /// a circom deployment, `None` keeps the order of `RLNCircuit`
+    /// * proofs are read in the layout of `verify` either way
+    pub fn set_public_input_layout(&mut self, public_input_layout: Option<PublicInputLayout>) {
+        self.public_input_layout = public_input_layout;
+    }
 
-        let root = self.tree.get_root();
-        // TODO: check id key here
-        let auth_path = self.tree.get_witness(id_index)?;
+    pub fn public_input_layout(&self) -> Option<&PublicInputLayout> {
+        self.public_input_layout.as_ref()
+    }
 
-        let inputs = RLNInputs::<E> {
-            share_x: Some(share_x),
-            share_y: Some(share_y),
-            epoch: Some(signal.epoch),
-            nullifier: Some(nullifier),
-            root: Some(root),
-            id_key: Some(id_key),
-            auth_path: auth_path.into_iter().map(|w| Some(w)).collect(),
-        };
+    /// given proof and public data verifies the signal
+    /// * expect `input_data` is serialized as:
+    /// [ proof<256>| root<32>| epoch<32>| share_x<32>| share_y<32>| nullifier<32> | signal_len<8> | signal<var> ]
+    pub fn verify<R: Read>(&self, input_data: R) -> io::Result<bool> {
+        self.verify_signal(input_data, None)
+    }
 
-        let circuit = RLNCircuit {
-            inputs: inputs.clone(),
-            hasher: PoseidonCircuit::new(self.poseidon_params.clone()),
-        };
+    /// same as `verify` for circuits of the `PublicStake` identity scheme,
+    /// the proof holds only if the member has staked `stake`
+    /// * the stake is not part of `input_data`, senders announce it next to the proof
+    pub fn verify_with_stake<R: Read>(&self, input_data: R, stake: E::Fr) -> io::Result<bool> {
+        self.verify_signal(input_data, Some(stake))
+    }
 
-        let mut rng = thread_rng();
-        let proof = create_random_proof(circuit, &self.circuit_parameters, &mut rng).unwrap();
-        write_uncompressed_proof(proof.clone(), &mut result_data)?;
-        root.into_repr().write_le(&mut result_data)?;
-        signal.epoch.into_repr().write_le(&mut result_data)?;
-        share_x.into_repr().write_le(&mut result_data)?;
-        share_y.into_repr().write_le(&mut result_data)?;
-        nullifier.into_repr().write_le(&mut result_data)?;
+    fn verify_signal<R: Read>(&self, mut input_data: R, stake: Option<E::Fr>) -> io::Result<bool> {
+        let proof = read_proof(&mut input_data, self.proof_format)?;
+        let public_inputs = RLNInputs::<E>::read_public_inputs(&mut input_data)?;
+        let signal_hash = read_signal_hash_with_limit::<R, E>(
+            input_data,
+            self.signal_hasher,
+            self.max_signal_size,
+        )?;
 
-        Ok(())
+        if signal_hash != public_inputs[2] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "signal hash mismatch",
+            ));
+        }
+
+        self.verify_public_inputs(proof, public_inputs, stake)
     }
 
-    /// given proof and public data verifies the signal
+    /// verifies proof against public inputs without checking the signal hash
     /// * expect `input_data` is serialized as:
-    /// [ proof<256>| root<32>| epoch<32>| share_x<32>| share_y<32>| nullifier<32> | signal_len<8> | signal<var> ]
-    pub fn verify<R: Read>(&self, mut input_data: R) -> io::Result<bool> {
-        let proof = read_uncompressed_proof(&mut input_data)?;
+    /// [ proof<256>| root<32>| epoch<32>| share_x<32>| share_y<32>| nullifier<32> ]
+    pub fn verify_proof<R: Read>(&self, mut input_data: R) -> io::Result<bool> {
+        let proof = read_proof(&mut input_data, self.proof_format)?;
         let public_inputs = RLNInputs::<E>::read_public_inputs(&mut input_data)?;
-        let signal_hash = read_signal_hash::<R, E>(input_data)?;
+        self.verify_public_inputs(proof, public_inputs, None)
+    }
 
+    /// verifies a proof of `generate_proof_prehashed` against the hash of its signal
+    /// * expect `input_data` is serialized as:
+    /// [ proof<256>| root<32>| epoch<32>| share_x<32>| share_y<32>| nullifier<32> | signal_hash<32> ]
+    pub fn verify_prehashed<R: Read>(&self, mut input_data: R) -> io::Result<bool> {
+        let proof = read_proof(&mut input_data, self.proof_format)?;
+        let public_inputs = RLNInputs::<E>::read_public_inputs(&mut input_data)?;
+        let signal_hash = read_fr::<_, E>(input_data, 1)?[0];
         if signal_hash != public_inputs[2] {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
                 "signal hash mismatch",
             ));
         }
+        self.verify_public_inputs(proof, public_inputs, None)
+    }
 
-        let verifing_key = prepare_verifying_key(&self.circuit_parameters.vk);
-        let success = verify_proof(&verifing_key, &proof, &public_inputs).unwrap();
-        Ok(success)
+    /// skips the proof and reads the public inputs following it, without verifying anything
+    /// * returns [ root, epoch, share_x, share_y, nullifier ]
+    pub fn read_proof_public_inputs<R: Read>(&self, mut proof_data: R) -> io::Result<Vec<E::Fr>> {
+        read_proof::<_, E>(&mut proof_data, self.proof_format)?;
+        RLNInputs::<E>::read_public_inputs(proof_data)
     }
 
-    /// generates public private key pair
-    /// * `key_pair_data` is seralized as [ secret<32> | public<32> ]
-    pub fn key_gen<W: Write>(&self, mut input_data: W) -> io::Result<()> {
-        let mut rng = thread_rng();
-        let hasher = self.hasher();
-        let secret = E::Fr::rand(&mut rng);
-        let public: E::Fr = hasher.hash(vec![secret.clone()]);
-        secret.into_repr().write_le(&mut input_data)?;
-        public.into_repr().write_le(&mut input_data)?;
+    /// recovers the secret of a member from two proofs sharing epoch and nullifier,
+    /// see `RLN::recover_secret`
+    pub fn recover_secret<R: Read, W: Write>(
+        &self,
+        share1_data: R,
+        share2_data: R,
+        mut result_data: W,
+    ) -> io::Result<()> {
+        let share1 = self.read_proof_public_inputs(share1_data)?;
+        let share2 = self.read_proof_public_inputs(share2_data)?;
+        if share1[1] != share2[1] || share1[4] != share2[4] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "shares are from different epochs or nullifiers",
+            ));
+        }
+        let secret = recover_secret::<E>((share1[2], share1[3]), (share2[2], share2[3]))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "shares are identical"))?;
+        secret.into_repr().write_le(&mut result_data)?;
         Ok(())
     }
 
-    pub fn export_verifier_key<W: Write>(&self, w: W) -> io::Result<()> {
-        self.circuit_parameters.vk.write(w)
+    fn verify_public_inputs(
+        &self,
+        proof: Proof<E>,
+        mut public_inputs: Vec<E::Fr>,
+        stake: Option<E::Fr>,
+    ) -> io::Result<bool> {
+        public_inputs.push(self.rln_identifier);
+        public_inputs.extend(stake);
+        if let Some(layout) = self.public_input_layout.as_ref() {
+            public_inputs = layout.arrange(&public_inputs)?;
+        }
+        let _step = telemetry::step(telemetry::PAIRING_CHECK);
+        Ok(verifier_core::verify_public_inputs(
+            &self.verifying_key,
+            &proof,
+            &public_inputs,
+        )?)
     }
+}
 
-    pub fn export_circuit_parameters<W: Write>(&self, w: W) -> io::Result<()> {
-        self.circuit_parameters.write(w)
+/// circuit parameters do not match the circuit of the requested merkle depth
+/// * returned wrapped in an `io::Error` of kind `InvalidData`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParametersShapeError {
+    pub merkle_depth: usize,
+    pub expected_inputs: usize,
+    pub found_inputs: usize,
+    pub expected_aux: usize,
+    pub found_aux: usize,
+    pub expected_domain_size: usize,
+    pub found_domain_size: usize,
+}
+
+impl std::fmt::Display for ParametersShapeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "circuit parameters do not match merkle depth {}: inputs {} (expected {}), aux {} (expected {}), domain {} (expected {})",
+            self.merkle_depth,
+            self.found_inputs,
+            self.expected_inputs,
+            self.found_aux,
+            self.expected_aux,
+            self.found_domain_size,
+            self.expected_domain_size,
+        )
     }
+}
 
-    pub fn hasher(&self) -> PoseidonHasher<E> {
-        PoseidonHasher::new(self.poseidon_params.clone())
+impl std::error::Error for ParametersShapeError {}
+
+/// signal epochs `validate_message` accepts around the current one unless changed
+pub const DEFAULT_MAX_EPOCH_GAP: u64 = 1;
+
+/// outcome of `RLN::validate_message`
+#[derive(Clone)]
+pub enum Verdict<E: Engine> {
+    Valid,
+    /// the proof does not verify or does not commit to the signal
+    InvalidProof,
+    /// the proof is for a root that is not one of the latest roots of the tree
+    InvalidRoot,
+    /// the epoch is too far from the current one
+    StaleEpoch,
+    /// the same share was seen before
+    Duplicate,
+    /// a second message of the member in the epoch, its secret is recovered
+    Spam {
+        recovered_secret: E::Fr,
+    },
+}
+
+impl<E: Engine> PartialEq for Verdict<E> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Verdict::Spam {
+                    recovered_secret: a,
+                },
+                Verdict::Spam {
+                    recovered_secret: b,
+                },
+            ) => a == b,
+            (Verdict::Spam { .. }, _) | (_, Verdict::Spam { .. }) => false,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
     }
+}
 
-    pub fn poseidon_params(&self) -> PoseidonParams<E> {
-        self.poseidon_params.clone()
+impl<E: Engine> std::fmt::Debug for Verdict<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Verdict::Valid => write!(f, "Valid"),
+            Verdict::InvalidProof => write!(f, "InvalidProof"),
+            Verdict::InvalidRoot => write!(f, "InvalidRoot"),
+            Verdict::StaleEpoch => write!(f, "StaleEpoch"),
+            Verdict::Duplicate => write!(f, "Duplicate"),
+            Verdict::Spam { recovered_secret } => {
+                write!(f, "Spam {{ recovered_secret: {:?} }}", recovered_secret)
+            }
+        }
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use crate::{circuit::bench, public::RLNSignal};
-//     use crate::{poseidon::PoseidonParams, public};
-//     use bellman::pairing::bn256::{Bn256, Fr};
-//     use rand::{Rand, SeedableRng, XorShiftRng};
+/// leaf of a member with the secret `a_0`, `poseidon(a_0)`,
+/// with the poseidon parameters `RLN` uses for binary trees by default
+/// * builds the parameters on every call, `commitment_with_params` takes them once
+/// * semaphore identities commit with `identity_commitment` instead,
+/// staked ones `staked_leaf` the result with the stake
+pub fn commitment<E: Engine>(secret: E::Fr) -> E::Fr {
+    commitment_with_params(
+        &RLN::<E>::default_poseidon_params(TreeArity::Binary),
+        secret,
+    )
+}
 
-//     fn merkle_depth() -> usize {
-//         4usize
-//     }
+/// same as `commitment` with the poseidon parameters of the deployment
+pub fn commitment_with_params<E: Engine>(
+    poseidon_params: &PoseidonParams<E>,
+    secret: E::Fr,
+) -> E::Fr {
+    PoseidonHasher::new(poseidon_params.clone()).hash(vec![secret])
+}
 
-//     fn rln_test() -> bench::RLNTest<Bn256> {
-//         let merkle_depth = merkle_depth();
-//         let poseidon_params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
-//         let rln_test = bench::RLNTest::<Bn256>::new(merkle_depth, Some(poseidon_params));
-//         rln_test
-//     }
+/// a `generate_proof` input with its circuit assigned, see `RLN::prepare_proof`
+/// * root and witness are the ones of the tree when it was prepared
+/// * it shares the circuit parameters of the instance and borrows nothing from it,
+/// so it can be proved later, e.g. from a future
+pub struct PreparedProof<E: Engine> {
+    circuit: RLNCircuit<E>,
+    circuit_parameters: Arc<Parameters<E>>,
+    proof_format: ProofFormat,
+    metrics: Arc<dyn Metrics>,
+    start: Instant,
+}
 
-//     #[test]
-//     fn test_xxx() {
-//         let rln = rln_test();
-//         let rln = rln.rln;
+impl<E: Engine> PreparedProof<E> {
+    /// generates the proof and writes the output of `generate_proof`
+    pub fn prove<W: Write, G: Rng>(self, result_data: W, rng: &mut G) -> io::Result<()> {
+        self.prove_circuit(self.circuit.clone(), result_data, rng)
+    }
 
-//         let input_data: Vec<u8> = vec![1, 2, 3, 4];
-//         let n = 4;
-//         let mut result_data: Vec<u8> = Vec::new();
+    /// same as `prove` reporting its progress to `progress` as a phase and a percentage
+    /// * `Synthesis` goes from 0 to 100 while the witness is assigned,
+    /// `Proving` is reported at 0 when the ffts and multiexps start
+    /// and at 100 once the proof is written
+    #[cfg(feature = "prover")]
+    pub fn prove_with_progress<W: Write, G: Rng, F: FnMut(ProofPhase, u8)>(
+        self,
+        result_data: W,
+        rng: &mut G,
+        mut progress: F,
+    ) -> io::Result<()> {
+        let num_constraints = self
+            .circuit
+            .clone()
+            .shape()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .num_constraints;
+        let mut report = |phase: SetupPhase, percent: u8| progress(phase.into(), percent);
+        let circuit = ProgressCircuit {
+            circuit: self.circuit.clone(),
+            num_constraints,
+            progress: &mut report,
+        };
+        self.prove_circuit(circuit, result_data, rng)?;
+        progress(ProofPhase::Proving, 100);
+        Ok(())
+    }
 
-//         rln.signal_to_field(&input_data[..], n, &mut result_data)
-//             .unwrap();
-//     }
-// }
+    fn prove_circuit<C: Circuit<E>, W: Write, G: Rng>(
+        &self,
+        circuit: C,
+        mut result_data: W,
+        rng: &mut G,
+    ) -> io::Result<()> {
+        let proof = {
+            let _step = telemetry::step(telemetry::SYNTHESIS_AND_MULTIEXP);
+            create_random_proof(circuit, &*self.circuit_parameters, rng)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+        };
+        self.metrics.proof_generated(self.start.elapsed());
+        write_proof(proof, self.proof_format, &mut result_data)?;
+        for fr in self.circuit.inputs.public_inputs().iter().take(5) {
+            fr.into_repr().write_le(&mut result_data)?;
+        }
+        Ok(())
+    }
+}
+
+/// `RLN` is `Send + Sync`, proof generation, verification and membership updates
+/// can be called concurrently through a shared reference.
+/// Circuit parameters are immutable and the membership tree is guarded by a lock,
+/// a proof is generated against a consistent snapshot of root and witness.
+pub struct RLN<E: Engine> {
+    circuit_parameters: Arc<Parameters<E>>,
+    poseidon_params: PoseidonParams<E>,
+    hasher: PoseidonHasher<E>,
+    tree: RwLock<IncrementalMerkleTree<E, TreeHasher<E>>>,
+    verifier: RLNVerifier<E>,
+    prover_threads: Option<usize>,
+    // pool of `prover_threads` built once in `set_prover_threads`
+    #[cfg(feature = "multicore")]
+    prover_pool: Option<Arc<rayon::ThreadPool>>,
+    nullifier_log: RwLock<NullifierLog<E>>,
+    identity_scheme: IdentityScheme,
+    // batch size, parameters and verifier of the batch circuit
+    batch: Option<(usize, Arc<Parameters<E>>, PreparedVerifyingKey<E>)>,
+    // parameters and verifier of the withdrawal circuit
+    withdrawal: Option<(Arc<Parameters<E>>, PreparedVerifyingKey<E>)>,
+    // arity, depth and verifying keys of other circuits `verify_bundle` accepts
+    other_circuits: Vec<(
+        TreeArity,
+        usize,
+        VerifyingKey<E>,
+        Arc<PreparedVerifyingKey<E>>,
+    )>,
+    verify_cache: Option<Mutex<VerificationCache>>,
+    max_epoch_gap: Option<u64>,
+    metrics: Arc<dyn Metrics>,
+}
+
+impl<E: Engine> RLN<E> {
+    fn default_poseidon_params(arity: TreeArity) -> PoseidonParams<E> {
+        match arity {
+            TreeArity::Binary => PoseidonParams::<E>::new(8, 55, 3, None, None, None),
+            TreeArity::Quad => PoseidonParams::<E>::new(8, 60, 5, None, None, None),
+        }
+    }
+
+    #[cfg(feature = "prover")]
+    fn new_circuit<R: Rng>(
+        merkle_depth: usize,
+        arity: TreeArity,
+        poseidon_params: PoseidonParams<E>,
+        identity_scheme: IdentityScheme,
+        progress: &mut dyn FnMut(SetupPhase, u8),
+        rng: &mut R,
+    ) -> Parameters<E> {
+        let inputs = RLNInputs::<E>::empty_with_identity(merkle_depth, arity, identity_scheme);
+        let circuit = RLNCircuit::<E> {
+            inputs,
+            hasher: PoseidonCircuit::new(poseidon_params.clone()),
+        };
+        let num_constraints = circuit.clone().shape().unwrap().num_constraints;
+        let circuit = ProgressCircuit {
+            circuit,
+            num_constraints,
+            progress: &mut *progress,
+        };
+        let parameters = generate_random_parameters(circuit, rng).unwrap();
+        progress(SetupPhase::Parameters, 100);
+        parameters
+    }
+
+    fn new_with_params(
+        merkle_depth: usize,
+        arity: TreeArity,
+        circuit_parameters: Arc<Parameters<E>>,
+        verifying_key: Arc<PreparedVerifyingKey<E>>,
+        poseidon_params: PoseidonParams<E>,
+        identity_scheme: IdentityScheme,
+    ) -> io::Result<RLN<E>> {
+        let hasher = PoseidonHasher::new(poseidon_params.clone());
+        let tree_hasher = Self::tree_hasher(&hasher, arity, identity_scheme)?;
+        let tree = IncrementalMerkleTree::empty_with_arity(tree_hasher, merkle_depth, arity);
+        let verifier = RLNVerifier::new_with_prepared_vk(&circuit_parameters.vk, verifying_key);
+        Ok(RLN {
+            circuit_parameters,
+            poseidon_params,
+            hasher,
+            tree: RwLock::new(tree),
+            verifier,
+            prover_threads: None,
+            #[cfg(feature = "multicore")]
+            prover_pool: None,
+            nullifier_log: RwLock::new(NullifierLog::new()),
+            identity_scheme,
+            batch: None,
+            withdrawal: None,
+            other_circuits: Vec::new(),
+            verify_cache: None,
+            max_epoch_gap: Some(DEFAULT_MAX_EPOCH_GAP),
+            metrics: Arc::new(NoopMetrics),
+        })
+    }
+
+    // semaphore trees are binary ones hashed with the circomlib poseidon,
+    // others are hashed with the poseidon of the instance
+    fn tree_hasher(
+        hasher: &PoseidonHasher<E>,
+        arity: TreeArity,
+        identity_scheme: IdentityScheme,
+    ) -> io::Result<TreeHasher<E>> {
+        match (identity_scheme, arity) {
+            (IdentityScheme::Semaphore, TreeArity::Binary) => {
+                Ok(TreeHasher::Circom(semaphore_tree_hasher()?))
+            }
+            (IdentityScheme::Semaphore, TreeArity::Quad) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "semaphore trees are binary",
+            )),
+            _ => Ok(TreeHasher::Poseidon(hasher.clone())),
+        }
+    }
+
+    /// creates a new instance with freshly generated circuit parameters
+    /// * `arity` selects a binary or a quad membership tree,
+    /// quad trees need a poseidon of width 5
+    /// * `rln_identifier` separates nullifiers of this deployment from others, zero if `None`
+    #[cfg(feature = "prover")]
+    pub fn new(
+        merkle_depth: usize,
+        arity: TreeArity,
+        poseidon_params: Option<PoseidonParams<E>>,
+        rln_identifier: Option<E::Fr>,
+    ) -> RLN<E> {
+        Self::new_with_identity_scheme(
+            merkle_depth,
+            arity,
+            poseidon_params,
+            rln_identifier,
+            IdentityScheme::Single,
+        )
+    }
+
+    /// same as `new` with members identified as in `identity_scheme`,
+    /// see `key_gen` and `generate_proof` for the layouts of each scheme
+    #[cfg(feature = "prover")]
+    pub fn new_with_identity_scheme(
+        merkle_depth: usize,
+        arity: TreeArity,
+        poseidon_params: Option<PoseidonParams<E>>,
+        rln_identifier: Option<E::Fr>,
+        identity_scheme: IdentityScheme,
+    ) -> RLN<E> {
+        Self::generate(
+            merkle_depth,
+            arity,
+            poseidon_params,
+            rln_identifier,
+            identity_scheme,
+            &mut |_, _| {},
+            &mut thread_rng(),
+        )
+    }
+
+    /// same as `new` reporting the progress of parameter generation to `progress`,
+    /// which can take many minutes for deep trees
+    /// * `progress` is called with a phase and a percentage, see `ProgressCircuit`
+    #[cfg(feature = "prover")]
+    pub fn new_with_progress<F: FnMut(SetupPhase, u8)>(
+        merkle_depth: usize,
+        arity: TreeArity,
+        poseidon_params: Option<PoseidonParams<E>>,
+        rln_identifier: Option<E::Fr>,
+        mut progress: F,
+    ) -> RLN<E> {
+        Self::generate(
+            merkle_depth,
+            arity,
+            poseidon_params,
+            rln_identifier,
+            IdentityScheme::Single,
+            &mut progress,
+            &mut thread_rng(),
+        )
+    }
+
+    /// binary tree instance with circuit parameters derived from `seed`,
+    /// so test suites can share parameters and golden proofs across runs
+    /// * for tests only, anyone knowing the seed can forge proofs
+    #[cfg(all(feature = "prover", any(test, feature = "test_utils")))]
+    pub fn new_deterministic(merkle_depth: usize, seed: &[u8]) -> RLN<E> {
+        Self::generate(
+            merkle_depth,
+            TreeArity::Binary,
+            None,
+            None,
+            IdentityScheme::Single,
+            &mut |_, _| {},
+            &mut seeded_rng(seed),
+        )
+    }
+
+    #[cfg(feature = "prover")]
+    fn generate<R: Rng>(
+        merkle_depth: usize,
+        arity: TreeArity,
+        poseidon_params: Option<PoseidonParams<E>>,
+        rln_identifier: Option<E::Fr>,
+        identity_scheme: IdentityScheme,
+        progress: &mut dyn FnMut(SetupPhase, u8),
+        rng: &mut R,
+    ) -> RLN<E> {
+        let poseidon_params = match poseidon_params {
+            Some(params) => params,
+            None => Self::default_poseidon_params(arity),
+        };
+        let circuit_parameters = Self::new_circuit(
+            merkle_depth,
+            arity,
+            poseidon_params.clone(),
+            identity_scheme,
+            progress,
+            rng,
+        );
+        let verifying_key = Arc::new(prepare_verifying_key(&circuit_parameters.vk));
+        let mut rln = Self::new_with_params(
+            merkle_depth,
+            arity,
+            Arc::new(circuit_parameters),
+            verifying_key,
+            poseidon_params,
+            identity_scheme,
+        )
+        .unwrap();
+        if let Some(rln_identifier) = rln_identifier {
+            rln.set_rln_identifier(rln_identifier);
+        }
+        rln
+    }
+
+    /// loads serialized circuit parameters
+    /// * `expected_fingerprint` if given is checked against `parameters_fingerprint`
+    /// of the loaded parameters
+    /// * the identity scheme is the one the parameters are shaped for
+    pub fn new_with_raw_params<R: Read>(
+        merkle_depth: usize,
+        arity: TreeArity,
+        raw_circuit_parameters: R,
+        poseidon_params: Option<PoseidonParams<E>>,
+        expected_fingerprint: Option<&[u8]>,
+    ) -> io::Result<RLN<E>> {
+        let circuit_parameters = {
+            let _step = telemetry::step(telemetry::PARAMETER_LOAD);
+            Parameters::<E>::read(raw_circuit_parameters, true)?
+        };
+        if let Some(expected_fingerprint) = expected_fingerprint {
+            if parameters_fingerprint(&circuit_parameters)?.as_slice() != expected_fingerprint {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "circuit parameters fingerprint mismatch",
+                ));
+            }
+        }
+        Self::new_with_shared_params(
+            merkle_depth,
+            arity,
+            Arc::new(circuit_parameters),
+            poseidon_params,
+        )
+    }
+
+    /// same as `new_with_raw_params` over already loaded parameters,
+    /// instances with the same circuit share them instead of holding a copy each
+    pub fn new_with_shared_params(
+        merkle_depth: usize,
+        arity: TreeArity,
+        circuit_parameters: Arc<Parameters<E>>,
+        poseidon_params: Option<PoseidonParams<E>>,
+    ) -> io::Result<RLN<E>> {
+        let verifying_key = Arc::new(prepare_verifying_key(&circuit_parameters.vk));
+        Self::new_with_shared_keys(
+            merkle_depth,
+            arity,
+            circuit_parameters,
+            verifying_key,
+            poseidon_params,
+        )
+    }
+
+    /// same as `new_with_shared_params` sharing the prepared verifying key as well,
+    /// e.g. `circuit_parameters()` and `prepared_verifying_key()` of another instance
+    /// * `verifying_key` must be prepared from the verifying key of `circuit_parameters`
+    pub fn new_with_shared_keys(
+        merkle_depth: usize,
+        arity: TreeArity,
+        circuit_parameters: Arc<Parameters<E>>,
+        verifying_key: Arc<PreparedVerifyingKey<E>>,
+        poseidon_params: Option<PoseidonParams<E>>,
+    ) -> io::Result<RLN<E>> {
+        let poseidon_params = match poseidon_params {
+            Some(params) => params,
+            None => Self::default_poseidon_params(arity),
+        };
+        let mut identity_scheme = IdentityScheme::Single;
+        if let Err(e) = Self::check_parameters_shape(
+            merkle_depth,
+            arity,
+            &circuit_parameters,
+            &poseidon_params,
+            IdentityScheme::Single,
+        ) {
+            identity_scheme = [
+                IdentityScheme::Semaphore,
+                IdentityScheme::Staked,
+                IdentityScheme::PublicStake,
+            ]
+            .iter()
+            .cloned()
+            .find(|identity_scheme| {
+                Self::check_parameters_shape(
+                    merkle_depth,
+                    arity,
+                    &circuit_parameters,
+                    &poseidon_params,
+                    *identity_scheme,
+                )
+                .is_ok()
+            })
+            .ok_or(e)?;
+        }
+        Self::new_with_params(
+            merkle_depth,
+            arity,
+            circuit_parameters,
+            verifying_key,
+            poseidon_params,
+            identity_scheme,
+        )
+    }
+
+    // parameters must be generated for the circuit of this depth, arity, hasher and identity
+    fn check_parameters_shape(
+        merkle_depth: usize,
+        arity: TreeArity,
+        circuit_parameters: &Parameters<E>,
+        poseidon_params: &PoseidonParams<E>,
+        identity_scheme: IdentityScheme,
+    ) -> io::Result<()> {
+        let circuit = RLNCircuit::<E> {
+            inputs: RLNInputs::<E>::empty_with_identity(merkle_depth, arity, identity_scheme),
+            hasher: PoseidonCircuit::new(poseidon_params.clone()),
+        };
+        let shape: CircuitShape = circuit
+            .shape()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let err = ParametersShapeError {
+            merkle_depth,
+            expected_inputs: shape.num_inputs,
+            found_inputs: circuit_parameters.vk.ic.len(),
+            expected_aux: shape.num_aux,
+            found_aux: circuit_parameters.l.len(),
+            expected_domain_size: shape.domain_size(),
+            found_domain_size: circuit_parameters.h.len() + 1,
+        };
+        if err.expected_inputs != err.found_inputs
+            || err.expected_aux != err.found_aux
+            || err.expected_domain_size != err.found_domain_size
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+        Ok(())
+    }
+
+    /// loads circuit parameters from a file
+    /// * with the `mmap` feature the file is memory mapped instead of read through a buffer,
+    /// the parameters are still decoded in full on load, sections are not read lazily
+    pub fn new_with_params_file<P: AsRef<Path>>(
+        merkle_depth: usize,
+        arity: TreeArity,
+        path: P,
+        poseidon_params: Option<PoseidonParams<E>>,
+    ) -> io::Result<RLN<E>> {
+        let file = File::open(path)?;
+        #[cfg(feature = "mmap")]
+        {
+            let raw_circuit_parameters = unsafe { memmap2::Mmap::map(&file)? };
+            Self::new_with_raw_params(
+                merkle_depth,
+                arity,
+                &raw_circuit_parameters[..],
+                poseidon_params,
+                None,
+            )
+        }
+        #[cfg(not(feature = "mmap"))]
+        {
+            Self::new_with_raw_params(
+                merkle_depth,
+                arity,
+                io::BufReader::new(file),
+                poseidon_params,
+                None,
+            )
+        }
+    }
+
+    /// sets the proof encoding used by `generate_proof` and `verify`
+    pub fn set_proof_format(&mut self, proof_format: ProofFormat) {
+        self.verifier.set_proof_format(proof_format);
+        self.clear_verify_cache();
+    }
+
+    pub fn proof_format(&self) -> ProofFormat {
+        self.verifier.proof_format()
+    }
+
+    /// sets the hash applied to signals by `generate_proof`, `verify` and `signal_to_field`
+    /// * provers and verifiers of the same deployment must agree on it
+    pub fn set_signal_hasher(&mut self, signal_hasher: SignalHasher) {
+        self.verifier.set_signal_hasher(signal_hasher);
+        self.clear_verify_cache();
+    }
+
+    pub fn signal_hasher(&self) -> SignalHasher {
+        self.verifier.signal_hasher()
+    }
+
+    /// sets the identifier `generate_proof` and `verify` bind proofs to,
+    /// see `RLN::new`
+    pub fn set_rln_identifier(&mut self, rln_identifier: E::Fr) {
+        self.verifier.set_rln_identifier(rln_identifier);
+        self.clear_verify_cache();
+    }
+
+    pub fn rln_identifier(&self) -> E::Fr {
+        self.verifier.rln_identifier()
+    }
+
+    /// sets the longest signal `generate_proof`, `verify` and `signal_to_field` accept,
+    /// `DEFAULT_MAX_SIGNAL_SIZE` by default
+    /// * `None` accepts signals of any length
+    pub fn set_max_signal_size(&mut self, max_signal_size: Option<u64>) {
+        self.verifier.set_max_signal_size(max_signal_size);
+        self.clear_verify_cache();
+    }
+
+    pub fn max_signal_size(&self) -> Option<u64> {
+        self.verifier.max_signal_size()
+    }
+
+    /// sets how many epochs a message may be away from the current one
+    /// for `validate_message`, `DEFAULT_MAX_EPOCH_GAP` by default
+    /// * `None` accepts any epoch
+    pub fn set_max_epoch_gap(&mut self, max_epoch_gap: Option<u64>) {
+        self.max_epoch_gap = max_epoch_gap;
+    }
+
+    pub fn max_epoch_gap(&self) -> Option<u64> {
+        self.max_epoch_gap
+    }
+
+    /// sets how many of the latest roots `validate_message` accepts proofs for,
+    /// see `DEFAULT_ROOT_HISTORY_SIZE`
+    pub fn set_root_history_size(&mut self, root_history_size: usize) {
+        self.tree_mut().set_root_history_size(root_history_size);
+    }
+
+    /// limits how many proofs of `generate_proofs_batch` are generated at once,
+    /// the thread pool is built here and reused by every batch
+    /// * `None` uses the global rayon pool, one thread per cpu
+    /// * this does not bound the threads of a single proof, bellman runs the
+    /// multiexps and ffts of each proof on its own worker sized to the cpu count
+    pub fn set_prover_threads(&mut self, prover_threads: Option<usize>) -> io::Result<()> {
+        #[cfg(feature = "multicore")]
+        {
+            self.prover_pool = match prover_threads {
+                Some(n) => Some(Arc::new(
+                    rayon::ThreadPoolBuilder::new()
+                        .num_threads(n)
+                        .build()
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?,
+                )),
+                None => None,
+            };
+        }
+        self.prover_threads = prover_threads;
+        Ok(())
+    }
+
+    pub fn prover_threads(&self) -> Option<usize> {
+        self.prover_threads
+    }
+
+    /// sets the value empty slots of the membership tree hold, zero by default
+    /// * only allowed before the first member is inserted
+    pub fn set_default_leaf(&mut self, default_leaf: E::Fr) -> io::Result<()> {
+        let (depth, arity) = (self.merkle_depth(), self.arity());
+        let mut tree = self.tree_mut();
+        if tree.current_index != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "membership tree is not empty",
+            ));
+        }
+        let hasher = tree.hasher().clone();
+        let root_history_size = tree.root_history_size();
+        *tree = IncrementalMerkleTree::empty_with_default_leaf(hasher, depth, arity, default_leaf);
+        tree.set_root_history_size(root_history_size);
+        Ok(())
+    }
+
+    /// returns current membership root
+    /// * `root` is a scalar field element in 32 bytes
+    pub fn get_root<W: Write>(&self, mut result_data: W) -> io::Result<()> {
+        let root = self.tree().get_root();
+        root.into_repr().write_le(&mut result_data)?;
+        Ok(())
+    }
+
+    /// writes the membership proof of the member at the index
+    /// * `result_data` is [ root<32> | path ] with root and path taken from the same tree state
+    /// * binary paths are [ direction<1> | sibling<32> ] for each level, leaf level first,
+    /// with direction 1 when the sibling is on the right
+    /// * quad paths are [ position<1> | sibling<32> * 3 ] for each level, leaf level first
+    pub fn get_witness<W: Write>(&self, index: u64, mut result_data: W) -> io::Result<()> {
+        let tree = self.tree();
+        let root = tree.get_root();
+        match tree.arity() {
+            TreeArity::Binary => {
+                let proof = tree.merkle_proof(index)?;
+                drop(tree);
+                root.into_repr().write_le(&mut result_data)?;
+                proof.write(&mut result_data)?;
+            }
+            TreeArity::Quad => {
+                let path = tree.get_path(index)?;
+                drop(tree);
+                root.into_repr().write_le(&mut result_data)?;
+                for (siblings, position) in path.iter() {
+                    result_data.write_all(&[*position as u8])?;
+                    for sibling in siblings.iter() {
+                        sibling.into_repr().write_le(&mut result_data)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// inserts new member with given public key and returns its index
+    /// * `input_data` is a 32 scalar field element in 32 bytes
+    pub fn update_next_member<R: Read>(&self, input_data: R) -> io::Result<u64> {
+        let mut buf = <E::Fr as PrimeField>::Repr::default();
+        buf.read_le(input_data)?;
+        let leaf =
+            E::Fr::from_repr(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut tree = self.tree_mut();
+        let index = tree.update_next(leaf)?;
+        self.metrics.tree_size(tree.member_count());
+        Ok(index)
+    }
+
+    /// returns the index of the member with given public key if it is registered
+    /// * `input_data` is a scalar field element in 32 bytes
+    pub fn member_index<R: Read>(&self, input_data: R) -> io::Result<Option<u64>> {
+        let leaf = read_fr::<_, E>(input_data, 1)?[0];
+        Ok(self.tree().index_of(leaf))
+    }
+
+    /// returns the number of registered members that are not deleted
+    pub fn member_count(&self) -> usize {
+        self.tree().member_count()
+    }
+
+    /// drops tree nodes left empty by deletions, returns the number of nodes dropped
+    pub fn compact_tree(&self) -> usize {
+        self.tree_mut().compact()
+    }
+
+    /// approximate heap bytes held by the membership tree
+    pub fn tree_memory_usage(&self) -> usize {
+        self.tree().memory_usage()
+    }
+
+    //// deletes member with given index
+    pub fn delete_member(&self, index: u64) -> io::Result<()> {
+        let mut tree = self.tree_mut();
+        tree.delete(index)?;
+        self.metrics.tree_size(tree.member_count());
+        Ok(())
+    }
+
+    /// inserts a member holding a stake and returns its index
+    /// * `input_data` is [ public_key<32> | stake<32> ], the leaf is `staked_leaf(public_key, stake)`
+    pub fn register_stake<R: Read>(&self, input_data: R) -> io::Result<u64> {
+        let values = read_fr::<_, E>(input_data, 2)?;
+        self.check_staked()?;
+        let leaf = staked_leaf(&self.hasher, values[0], values[1]);
+        let mut tree = self.tree_mut();
+        let index = tree.update_next(leaf)?;
+        self.metrics.tree_size(tree.member_count());
+        Ok(index)
+    }
+
+    /// deletes the member a recovered secret belongs to and returns its index
+    /// * `input_data` is [ secret<32> | stake<32> ] with the stake the member registered with
+    /// * the secret is the output of `recover_secret`
+    pub fn slash_stake<R: Read>(&self, input_data: R) -> io::Result<u64> {
+        let values = read_fr::<_, E>(input_data, 2)?;
+        self.check_staked()?;
+        let public_key = self.hasher.hash(vec![values[0]]);
+        let leaf = staked_leaf(&self.hasher, public_key, values[1]);
+        let mut tree = self.tree_mut();
+        let index = tree
+            .index_of(leaf)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "staked member not found"))?;
+        tree.delete(index)?;
+        self.metrics.tree_size(tree.member_count());
+        Ok(index)
+    }
+
+    fn check_staked(&self) -> io::Result<()> {
+        if !self.identity_scheme.is_staked() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "identity scheme has no stake",
+            ));
+        }
+        Ok(())
+    }
+
+    /// applies registry events in order and returns the new root,
+    /// see `IncrementalMerkleTree::apply_events`
+    pub fn apply_membership_events(
+        &self,
+        events: impl Iterator<Item = MemberEvent<E>>,
+    ) -> io::Result<E::Fr> {
+        let mut tree = self.tree_mut();
+        let root = tree.apply_events(events)?;
+        self.metrics.tree_size(tree.member_count());
+        Ok(root)
+    }
+
+    /// starts recording changed leaves for `take_tree_changes`
+    pub fn track_tree_changes(&self) {
+        self.tree_mut().track_changes();
+    }
+
+    /// writes the leaves changed since the last call, for incremental persistence
+    /// * `result_data` is [ current_index<8> | n<8> | (index<8> | occupied<1> | leaf<32>) * n ]
+    /// where the leaf of an emptied slot is zero
+    pub fn take_tree_changes<W: Write>(&self, mut result_data: W) -> io::Result<()> {
+        let mut tree = self.tree_mut();
+        let changes = tree.take_changes();
+        result_data.write_u64::<LittleEndian>(tree.current_index)?;
+        result_data.write_u64::<LittleEndian>(changes.len() as u64)?;
+        for (index, leaf) in changes.into_iter() {
+            result_data.write_u64::<LittleEndian>(index)?;
+            result_data.write_all(&[leaf.is_some() as u8])?;
+            leaf.unwrap_or_else(E::Fr::zero)
+                .into_repr()
+                .write_le(&mut result_data)?;
+        }
+        Ok(())
+    }
+
+    /// marks leaves as changed again for `take_tree_changes`, e.g. after their writes failed
+    pub fn requeue_tree_changes(&self, indexes: &[u64]) {
+        self.tree_mut().requeue_changes(indexes);
+    }
+
+    /// writes a proof that the tree extends its state at incremental index `old_size`
+    /// by appends, see `merkle::verify_consistency`
+    /// * `result_data` is a serialized `ConsistencyProof`
+    pub fn consistency_proof<W: Write>(&self, old_size: u64, result_data: W) -> io::Result<()> {
+        self.tree().consistency_proof(old_size)?.write(result_data)
+    }
+
+    /// writes the occupied leaves of the tree, for peers to sync with `restore_tree`
+    /// * `result_data` is [ current_index<8> | n<8> | (index<8> | leaf<32>) * n ]
+    /// in index order
+    pub fn export_tree<W: Write>(&self, result_data: W) -> io::Result<()> {
+        self.tree().export_leaves(result_data)
+    }
+
+    /// replaces the tree with persisted leaves
+    /// * `input_data` is [ current_index<8> | n<8> | (index<8> | leaf<32>) * n ]
+    /// with occupied leaves only
+    pub fn restore_tree<R: Read>(&self, mut input_data: R) -> io::Result<()> {
+        let current_index = input_data.read_u64::<LittleEndian>()?;
+        let n = input_data.read_u64::<LittleEndian>()?;
+        let mut leaves: Vec<(u64, E::Fr)> = Vec::new();
+        for _ in 0..n {
+            let index = input_data.read_u64::<LittleEndian>()?;
+            leaves.push((index, read_fr::<_, E>(&mut input_data, 1)?[0]));
+        }
+        let mut tree = self.tree_mut();
+        tree.restore(current_index, &leaves)?;
+        self.metrics.tree_size(tree.member_count());
+        Ok(())
+    }
+
+    /// hashes plain text to a field element
+    pub fn signal_to_field<R: Read, W: Write>(
+        &self,
+        input_data: R,
+        mut result_data: W,
+    ) -> io::Result<()> {
+        let result = read_signal_hash_with_limit::<R, E>(
+            input_data,
+            self.signal_hasher(),
+            self.max_signal_size(),
+        )?;
+        result.into_repr().write_le(&mut result_data)?;
+        Ok(())
+    }
+
+    /// given public inputs and autharization data generates public inputs and proof
+    /// * expect `input_data`  serialized as  [ id_key<32> | id_index<8> | epoch<32> | signal_len<8> | signal<var> ]
+    /// * `result_data` is proof data serialized as [ proof<256>| root<32>| epoch<32>| share_x<32>| share_y<32>| nullifier<32> ]
+    /// * `proof` is 128 bytes instead if the compressed proof format is set
+    /// * with semaphore identities `id_key<32>` is replaced by [ trapdoor<32> | nullifier<32> ]
+    /// * with staked identities `id_key<32>` is followed by `stake<32>`
+    pub fn generate_proof<R: Read, W: Write>(
+        &self,
+        input_data: R,
+        result_data: W,
+    ) -> io::Result<()> {
+        self.generate_proof_with_rng(input_data, result_data, &mut thread_rng())
+    }
+
+    /// length of `generate_proof` output, the proof and five public inputs
+    pub fn proof_output_len(&self) -> usize {
+        self.proof_format().proof_size::<E>() + 5 * 32
+    }
+
+    /// same as `generate_proof` with proof randomness drawn from `rng`,
+    /// a seeded rng gives reproducible proofs
+    pub fn generate_proof_with_rng<R: Read, W: Write, G: Rng>(
+        &self,
+        input_data: R,
+        mut result_data: W,
+        rng: &mut G,
+    ) -> io::Result<()> {
+        let start = Instant::now();
+        let inputs = self.prepare_inputs(input_data)?;
+        self.prove(inputs, start, result_data, rng)
+    }
+
+    /// same as `generate_proof` for signals hashed upstream
+    /// * expect `input_data` serialized as
+    /// [ id_key<32> | id_index<8> | epoch<32> | prehashed<1> | signal_hash<32> ] if `prehashed` is 1
+    /// and as the input of `generate_proof` if it is 0
+    /// * `signal_hash` becomes `share_x` as is, it is up to the protocol to hash into the field
+    pub fn generate_proof_prehashed<R: Read, W: Write>(
+        &self,
+        input_data: R,
+        result_data: W,
+    ) -> io::Result<()> {
+        let start = Instant::now();
+        let inputs = self.prepare_inputs_with_flag(input_data, true)?;
+        self.prove(inputs, start, result_data, &mut thread_rng())
+    }
+
+    /// same as `generate_proof` reporting its progress to `progress`,
+    /// see `PreparedProof::prove_with_progress`
+    #[cfg(feature = "prover")]
+    pub fn generate_proof_with_progress<R: Read, W: Write, F: FnMut(ProofPhase, u8)>(
+        &self,
+        input_data: R,
+        result_data: W,
+        progress: F,
+    ) -> io::Result<()> {
+        self.prepare_proof(input_data)?.prove_with_progress(
+            result_data,
+            &mut thread_rng(),
+            progress,
+        )
+    }
+
+    /// reads a `generate_proof` input and assigns the circuit for it,
+    /// the proof is generated by the returned `PreparedProof`
+    pub fn prepare_proof<R: Read>(&self, input_data: R) -> io::Result<PreparedProof<E>> {
+        let start = Instant::now();
+        let inputs = self.prepare_inputs(input_data)?;
+        Ok(self.prepared_proof(inputs, start))
+    }
+
+    fn prepared_proof(&self, inputs: RLNInputs<E>, start: Instant) -> PreparedProof<E> {
+        PreparedProof {
+            circuit: RLNCircuit {
+                inputs,
+                hasher: PoseidonCircuit::from_hasher(&self.hasher),
+            },
+            circuit_parameters: self.circuit_parameters.clone(),
+            proof_format: self.proof_format(),
+            metrics: self.metrics.clone(),
+            start,
+        }
+    }
+
+    fn prove<W: Write, G: Rng>(
+        &self,
+        inputs: RLNInputs<E>,
+        start: Instant,
+        result_data: W,
+        rng: &mut G,
+    ) -> io::Result<()> {
+        self.prepared_proof(inputs, start).prove(result_data, rng)
+    }
+
+    // reads one `generate_proof` input and assigns the circuit for it
+    fn prepare_inputs<R: Read>(&self, input_data: R) -> io::Result<RLNInputs<E>> {
+        self.prepare_inputs_with_flag(input_data, false)
+    }
+
+    // same as `prepare_inputs`, with `flagged` the epoch is followed by the flag
+    // of `generate_proof_prehashed`
+    fn prepare_inputs_with_flag<R: Read>(
+        &self,
+        mut input_data: R,
+        flagged: bool,
+    ) -> io::Result<RLNInputs<E>> {
+        let _step = telemetry::step(telemetry::WITNESS_BUILD);
+        let (id_key, id_nullifier, stake) = self.read_identity(&mut input_data)?;
+        let id_index = input_data.read_u64::<LittleEndian>()?;
+
+        let (epoch, share_x) = if flagged {
+            let epoch = read_fr::<_, E>(&mut input_data, 1)?[0];
+            let share_x = match input_data.read_u8()? {
+                0 => read_signal_hash_with_limit::<_, E>(
+                    input_data,
+                    self.signal_hasher(),
+                    self.max_signal_size(),
+                )?,
+                1 => read_fr::<_, E>(input_data, 1)?[0],
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unknown signal flag",
+                    ))
+                }
+            };
+            (epoch, share_x)
+        } else {
+            let signal = RLNSignal::<E>::read_with_limit(
+                input_data,
+                self.signal_hasher(),
+                self.max_signal_size(),
+            )?;
+            (signal.epoch, signal.hash)
+        };
+
+        use hex;
+
+        let hasher = self.hasher();
+
+        // line equation
+        let a_0 = match id_nullifier {
+            Some(id_nullifier) => identity_secret::<E>(id_key, id_nullifier)?,
+            None => id_key.clone(),
+        };
+        let rln_identifier = self.rln_identifier();
+        let external_nullifier = external_nullifier(&hasher, epoch, rln_identifier);
+        let a_1: E::Fr = hasher.hash(vec![a_0, external_nullifier]);
+        // evaluate line equation
+        let mut share_y = a_1.clone();
+        share_y.mul_assign(&share_x);
+        share_y.add_assign(&a_0);
+        let nullifier = hasher.hash(vec![a_1]);
+
+        // root and witness are taken under the same lock
+        let tree = self.tree();
+        let root = tree.get_root();
+        // TODO: check id key here
+        let (auth_path, quad_auth_path) = match tree.arity() {
+            TreeArity::Binary => (tree.get_witness(id_index)?, vec![]),
+            TreeArity::Quad => (vec![], tree.get_path(id_index)?),
+        };
+        drop(tree);
+
+        let inputs = RLNInputs::<E> {
+            share_x: Some(share_x),
+            share_y: Some(share_y),
+            epoch: Some(epoch),
+            nullifier: Some(nullifier),
+            root: Some(root),
+            rln_identifier: Some(rln_identifier),
+            id_key: Some(id_key),
+            id_nullifier,
+            identity_scheme: self.identity_scheme,
+            stake,
+            auth_path: auth_path.into_iter().map(|w| Some(w)).collect(),
+            quad_auth_path: quad_auth_path.into_iter().map(|w| Some(w)).collect(),
+        };
+        Ok(inputs)
+    }
+
+    /// synthesizes a `generate_proof` input without proving,
+    /// to find out why a proof of it does not verify
+    #[cfg(feature = "debug")]
+    pub fn diagnose<R: Read>(&self, input_data: R) -> io::Result<CircuitDiagnostics> {
+        let circuit = RLNCircuit {
+            inputs: self.prepare_inputs(input_data)?,
+            hasher: PoseidonCircuit::from_hasher(&self.hasher),
+        };
+        circuit
+            .diagnose()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    /// generates parameters of the batch circuit proving `batch_size` messages at once
+    /// and keeps them for `generate_batch_proof` and `verify_batch_proof`
+    #[cfg(feature = "prover")]
+    pub fn setup_batch(&mut self, batch_size: usize) -> io::Result<()> {
+        if batch_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "batch size must be positive",
+            ));
+        }
+        let mut rng = thread_rng();
+        let inputs = RLNInputs::<E>::empty_with_identity(
+            self.merkle_depth(),
+            self.arity(),
+            self.identity_scheme,
+        );
+        let circuit = RLNBatchCircuit::<E> {
+            inputs: vec![inputs; batch_size],
+            hasher: PoseidonCircuit::from_hasher(&self.hasher),
+        };
+        let batch_parameters = generate_random_parameters(circuit, &mut rng).unwrap();
+        self.set_batch_parameters(batch_size, batch_parameters)
+    }
+
+    /// sets previously generated batch circuit parameters
+    /// * fails if the parameters are not for `batch_size` messages of the identity scheme
+    pub fn set_batch_parameters(
+        &mut self,
+        batch_size: usize,
+        batch_parameters: Parameters<E>,
+    ) -> io::Result<()> {
+        let num_inputs = self.identity_scheme.num_public_inputs();
+        if batch_size == 0 || batch_parameters.vk.ic.len() != num_inputs * batch_size + 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "parameters do not match the batch size",
+            ));
+        }
+        let verifying_key = prepare_verifying_key(&batch_parameters.vk);
+        self.batch = Some((batch_size, Arc::new(batch_parameters), verifying_key));
+        Ok(())
+    }
+
+    /// writes the batch circuit parameters, see `set_batch_parameters`
+    pub fn export_batch_parameters<W: Write>(&self, w: W) -> io::Result<()> {
+        self.batch()?.1.write(w)
+    }
+
+    /// proves several messages in a single proof, see `generate_proof` for the message layout
+    /// * `input_data` is [ batch_size<8> | message_input<var> * batch_size ]
+    /// * `result_data` is [ proof<256> | (root<32> | epoch<32> | share_x<32> | share_y<32> |
+    /// nullifier<32>) * batch_size ]
+    pub fn generate_batch_proof<R: Read, W: Write>(
+        &self,
+        mut input_data: R,
+        mut result_data: W,
+    ) -> io::Result<()> {
+        let (batch_size, batch_parameters, _) = self.batch()?;
+        if input_data.read_u64::<LittleEndian>()? != *batch_size as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "batch size mismatch",
+            ));
+        }
+        let inputs = (0..*batch_size)
+            .map(|_| self.prepare_inputs(&mut input_data))
+            .collect::<io::Result<Vec<RLNInputs<E>>>>()?;
+        let circuit = RLNBatchCircuit {
+            inputs: inputs.clone(),
+            hasher: PoseidonCircuit::from_hasher(&self.hasher),
+        };
+        let proof = create_random_proof(circuit, &**batch_parameters, &mut thread_rng())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        write_proof(proof, self.proof_format(), &mut result_data)?;
+        for inputs in inputs.iter() {
+            for fr in inputs.public_inputs().iter().take(5) {
+                fr.into_repr().write_le(&mut result_data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// verifies `generate_batch_proof` output, signals are not checked
+    pub fn verify_batch_proof<R: Read>(&self, input_data: R) -> io::Result<bool> {
+        self.verify_batch_inputs(input_data, None)
+    }
+
+    /// same as `verify_batch_proof` for circuits of the `PublicStake` identity scheme,
+    /// `stakes` are the announced stakes of the messages in batch order
+    pub fn verify_batch_proof_with_stakes<R: Read>(
+        &self,
+        input_data: R,
+        stakes: &[E::Fr],
+    ) -> io::Result<bool> {
+        self.verify_batch_inputs(input_data, Some(stakes))
+    }
+
+    fn verify_batch_inputs<R: Read>(
+        &self,
+        mut input_data: R,
+        stakes: Option<&[E::Fr]>,
+    ) -> io::Result<bool> {
+        let (batch_size, _, verifying_key) = self.batch()?;
+        let staked = self.identity_scheme == IdentityScheme::PublicStake;
+        if staked != stakes.is_some() || stakes.map_or(false, |s| s.len() != *batch_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "stakes do not match the batch",
+            ));
+        }
+        let proof = read_proof(&mut input_data, self.proof_format())?;
+        let mut public_inputs: Vec<E::Fr> = Vec::new();
+        for i in 0..*batch_size {
+            public_inputs.extend(RLNInputs::<E>::read_public_inputs(&mut input_data)?);
+            public_inputs.push(self.rln_identifier());
+            public_inputs.extend(stakes.map(|stakes| stakes[i]));
+        }
+        verify_proof(verifying_key, &proof, &public_inputs)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    fn batch(&self) -> io::Result<&(usize, Arc<Parameters<E>>, PreparedVerifyingKey<E>)> {
+        self.batch.as_ref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "batch circuit is not set up")
+        })
+    }
+
+    // reads the secrets of a member as laid out in `generate_proof` input,
+    // returns id_key with the identity nullifier and stake of the schemes using them
+    fn read_identity<R: Read>(
+        &self,
+        mut input_data: R,
+    ) -> io::Result<(E::Fr, Option<E::Fr>, Option<E::Fr>)> {
+        let id_key: E::Fr = read_fr::<_, E>(&mut input_data, 1)?[0];
+        let id_nullifier = match self.identity_scheme {
+            IdentityScheme::Semaphore => Some(read_fr::<_, E>(&mut input_data, 1)?[0]),
+            _ => None,
+        };
+        let stake = if self.identity_scheme.is_staked() {
+            Some(read_fr::<_, E>(&mut input_data, 1)?[0])
+        } else {
+            None
+        };
+        Ok((id_key, id_nullifier, stake))
+    }
+
+    /// generates parameters of the withdrawal circuit
+    /// and keeps them for `generate_withdrawal_proof` and `verify_withdrawal_proof`
+    #[cfg(feature = "prover")]
+    pub fn setup_withdrawal(&mut self) -> io::Result<()> {
+        let circuit = WithdrawalCircuit::<E> {
+            inputs: WithdrawalInputs::empty(),
+            hasher: PoseidonCircuit::from_hasher(&self.hasher),
+        };
+        let withdrawal_parameters = generate_random_parameters(circuit, &mut thread_rng())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.set_withdrawal_parameters(withdrawal_parameters)
+    }
+
+    /// sets previously generated withdrawal circuit parameters
+    pub fn set_withdrawal_parameters(
+        &mut self,
+        withdrawal_parameters: Parameters<E>,
+    ) -> io::Result<()> {
+        if withdrawal_parameters.vk.ic.len() != 3 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "parameters are not for the withdrawal circuit",
+            ));
+        }
+        let verifying_key = prepare_verifying_key(&withdrawal_parameters.vk);
+        self.withdrawal = Some((Arc::new(withdrawal_parameters), verifying_key));
+        Ok(())
+    }
+
+    /// writes the withdrawal circuit parameters, see `set_withdrawal_parameters`
+    pub fn export_withdrawal_parameters<W: Write>(&self, w: W) -> io::Result<()> {
+        self.withdrawal()?.0.write(w)
+    }
+
+    /// writes the verifying key of the withdrawal circuit, e.g. for a membership contract
+    pub fn export_withdrawal_verifier_key<W: Write>(&self, w: W) -> io::Result<()> {
+        self.withdrawal()?.0.vk.write(w)
+    }
+
+    /// proves the member at `id_index` holds its secret, so it can exit and reclaim its deposit
+    /// * expect `input_data` serialized as [ id_key<32> | id_index<8> | receiver<32> ]
+    /// with identities laid out as in `generate_proof`
+    /// * `result_data` is [ proof<256> | public_key<32> | receiver<32> ]
+    /// where the public key is `hash(a_0)`, the leaf itself unless the scheme is staked
+    /// * fails if the secret does not belong to the member at `id_index`,
+    /// and for semaphore identities whose leaves are not `hash(a_0)`
+    pub fn generate_withdrawal_proof<R: Read, W: Write>(
+        &self,
+        mut input_data: R,
+        mut result_data: W,
+    ) -> io::Result<()> {
+        let (withdrawal_parameters, _) = self.withdrawal()?;
+        // semaphore commitments are not `hash(a_0)` of the tree hasher
+        if self.identity_scheme == IdentityScheme::Semaphore {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "semaphore identities can not withdraw",
+            ));
+        }
+        let (a_0, _, stake) = self.read_identity(&mut input_data)?;
+        let id_index = input_data.read_u64::<LittleEndian>()?;
+        let receiver = read_fr::<_, E>(&mut input_data, 1)?[0];
+
+        let hasher = self.hasher();
+        let public_key = hasher.hash(vec![a_0]);
+        let leaf = match stake {
+            Some(stake) => staked_leaf(&hasher, public_key, stake),
+            None => public_key,
+        };
+        if self.tree().get_leaf(id_index)? != leaf {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "secret does not belong to the member",
+            ));
+        }
+
+        let inputs = WithdrawalInputs {
+            public_key: Some(public_key),
+            receiver: Some(receiver),
+            secret: Some(a_0),
+        };
+        let circuit = WithdrawalCircuit {
+            inputs: inputs.clone(),
+            hasher: PoseidonCircuit::from_hasher(&self.hasher),
+        };
+        let proof = create_random_proof(circuit, &**withdrawal_parameters, &mut thread_rng())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        write_proof(proof, self.proof_format(), &mut result_data)?;
+        for fr in inputs.public_inputs().iter() {
+            fr.into_repr().write_le(&mut result_data)?;
+        }
+        Ok(())
+    }
+
+    /// verifies `generate_withdrawal_proof` output
+    pub fn verify_withdrawal_proof<R: Read>(&self, mut input_data: R) -> io::Result<bool> {
+        let (_, verifying_key) = self.withdrawal()?;
+        let proof = read_proof(&mut input_data, self.proof_format())?;
+        let public_inputs = read_fr::<_, E>(&mut input_data, 2)?;
+        verify_proof(verifying_key, &proof, &public_inputs)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    fn withdrawal(&self) -> io::Result<&(Arc<Parameters<E>>, PreparedVerifyingKey<E>)> {
+        self.withdrawal.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "withdrawal circuit is not set up",
+            )
+        })
+    }
+
+    /// generates a proof for each input, see `generate_proof` for the layouts
+    /// * proofs are spread over the pool of `set_prover_threads` with the `multicore` feature
+    #[cfg(feature = "multicore")]
+    pub fn generate_proofs_batch(&self, inputs: &[Vec<u8>]) -> io::Result<Vec<Vec<u8>>>
+    where
+        E: Sync,
+    {
+        use rayon::prelude::*;
+        let prove = || -> io::Result<Vec<Vec<u8>>> {
+            inputs
+                .par_iter()
+                .map(|input| {
+                    let mut proof: Vec<u8> = Vec::new();
+                    self.generate_proof(input.as_slice(), &mut proof)?;
+                    Ok(proof)
+                })
+                .collect()
+        };
+        match &self.prover_pool {
+            Some(pool) => pool.install(prove),
+            None => prove(),
+        }
+    }
+
+    /// generates a proof for each input, see `generate_proof` for the layouts
+    #[cfg(not(feature = "multicore"))]
+    pub fn generate_proofs_batch(&self, inputs: &[Vec<u8>]) -> io::Result<Vec<Vec<u8>>> {
+        inputs
+            .iter()
+            .map(|input| {
+                let mut proof: Vec<u8> = Vec::new();
+                self.generate_proof(input.as_slice(), &mut proof)?;
+                Ok(proof)
+            })
+            .collect()
+    }
+
+    /// same as `generate_proof` with the output wrapped in a versioned bundle
+    /// * `result_data` is serialized as [ header<9> | generate_proof output ], see `crate::bundle`
+    pub fn generate_proof_bundle<R: Read, W: Write>(
+        &self,
+        input_data: R,
+        mut result_data: W,
+    ) -> io::Result<()> {
+        self.bundle_header()?.write(&mut result_data)?;
+        self.generate_proof(input_data, result_data)
+    }
+
+    /// verifies a bundle written by `generate_proof_bundle` followed by its signal
+    /// * expect `input_data` is serialized as [ bundle<var> | signal_len<8> | signal<var> ]
+    /// * bundles of another version, curve or circuit are rejected with an error,
+    /// circuits added with `add_circuit_verifier` are verified with their own key
+    /// and their results are not cached
+    pub fn verify_bundle<R: Read>(&self, mut input_data: R) -> io::Result<bool> {
+        let header = BundleHeader::read(&mut input_data)?;
+        let expected = self.bundle_header()?;
+        let own_circuit =
+            header.arity == expected.arity && header.merkle_depth == expected.merkle_depth;
+        let other_circuit = self.other_circuits.iter().find(|(arity, depth, _, _)| {
+            *arity == header.arity && *depth == header.merkle_depth as usize
+        });
+        if header.curve_id != expected.curve_id || (!own_circuit && other_circuit.is_none()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "proof bundle is for another circuit",
+            ));
+        }
+        if header.proof_format != expected.proof_format {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "proof bundle uses another proof format",
+            ));
+        }
+        match other_circuit {
+            Some((_, _, verifying_key, prepared_verifying_key)) if !own_circuit => {
+                let verifier = self
+                    .verifier
+                    .with_verifying_key(verifying_key, prepared_verifying_key.clone());
+                self.measure_verification(|| verifier.verify(input_data))
+            }
+            _ => self.verify(input_data),
+        }
+    }
+
+    /// makes `verify_bundle` accept bundles of another circuit, e.g. of the previous
+    /// depth while members move to a deeper tree
+    /// * `raw_verifying_key` is `export_verifier_key` output of the other circuit,
+    /// it replaces a key added before for the same arity and depth
+    /// * the key must take the public inputs of the identity scheme of this instance
+    pub fn add_circuit_verifier<R: Read>(
+        &mut self,
+        arity: TreeArity,
+        merkle_depth: usize,
+        raw_verifying_key: R,
+    ) -> io::Result<()> {
+        if arity == self.arity() && merkle_depth == self.merkle_depth() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "circuit is the one of the instance",
+            ));
+        }
+        BundleHeader::new::<E>(arity, merkle_depth, self.proof_format())?;
+        let verifying_key = VerifyingKey::<E>::read(raw_verifying_key)?;
+        if verifying_key.ic.len() != self.circuit_parameters.vk.ic.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "verifying key takes other public inputs",
+            ));
+        }
+        let prepared_verifying_key = Arc::new(prepare_verifying_key(&verifying_key));
+        self.remove_circuit_verifier(arity, merkle_depth);
+        self.other_circuits
+            .push((arity, merkle_depth, verifying_key, prepared_verifying_key));
+        Ok(())
+    }
+
+    /// stops accepting bundles of a circuit added with `add_circuit_verifier`,
+    /// false if it was not added
+    pub fn remove_circuit_verifier(&mut self, arity: TreeArity, merkle_depth: usize) -> bool {
+        let len = self.other_circuits.len();
+        self.other_circuits
+            .retain(|(a, depth, _, _)| !(*a == arity && *depth == merkle_depth));
+        self.other_circuits.len() != len
+    }
+
+    /// arity and depth of every circuit `verify_bundle` accepts, the one of the instance first
+    pub fn verified_circuits(&self) -> Vec<(TreeArity, usize)> {
+        let mut circuits = vec![(self.arity(), self.merkle_depth())];
+        circuits.extend(
+            self.other_circuits
+                .iter()
+                .map(|(arity, depth, _, _)| (*arity, *depth)),
+        );
+        circuits
+    }
+
+    /// rerandomizes a proof generated by `generate_proof`, public inputs are copied unchanged
+    /// * `input_data` and `result_data` have the layout of `generate_proof` output
+    pub fn rerandomize_proof<R: Read, W: Write, G: Rng>(
+        &self,
+        mut input_data: R,
+        mut result_data: W,
+        rng: &mut G,
+    ) -> io::Result<()> {
+        let proof = read_proof(&mut input_data, self.proof_format())?;
+        let proof = rerandomize_proof(&self.circuit_parameters.vk, &proof, rng);
+        write_proof(proof, self.proof_format(), &mut result_data)?;
+        io::copy(&mut input_data, &mut result_data)?;
+        Ok(())
+    }
+
+    /// given proof and public data verifies the signal
+    /// * expect `input_data` is serialized as:
+    /// [ proof<256>| root<32>| epoch<32>| share_x<32>| share_y<32>| nullifier<32> | signal_len<8> | signal<var> ]
+    /// * with `set_verify_cache` the input is buffered and results of inputs seen before
+    /// are returned without checking the proof again
+    pub fn verify<R: Read>(&self, mut input_data: R) -> io::Result<bool> {
+        let cache = match self.verify_cache.as_ref() {
+            Some(cache) => cache,
+            None => return self.measure_verification(|| self.verifier.verify(input_data)),
+        };
+        let mut data: Vec<u8> = Vec::new();
+        match self.max_signal_size() {
+            // proof, public inputs and signal_len come first
+            Some(max_signal_size) => {
+                let limit = (self.proof_format().proof_size::<E>() + 5 * 32 + 8) as u64;
+                input_data
+                    .take(limit.saturating_add(max_signal_size))
+                    .read_to_end(&mut data)?
+            }
+            None => input_data.read_to_end(&mut data)?,
+        };
+        let digest = VerificationCache::digest(&data);
+        if let Some(verified) = cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&digest)
+        {
+            return Ok(verified);
+        }
+        let verified = self.measure_verification(|| self.verifier.verify(data.as_slice()))?;
+        cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(digest, verified);
+        Ok(verified)
+    }
+
+    /// keeps the results of the last `capacity` distinct inputs of `verify`,
+    /// `None` disables the cache
+    /// * the cache is cleared when the proof format, signal hasher, rln identifier
+    /// or maximum signal size change
+    pub fn set_verify_cache(&mut self, capacity: Option<usize>) {
+        self.verify_cache = capacity.map(|capacity| Mutex::new(VerificationCache::new(capacity)));
+    }
+
+    /// hit and miss counters of the verify cache, `None` if it is disabled
+    pub fn verify_cache_stats(&self) -> Option<CacheStats> {
+        self.verify_cache
+            .as_ref()
+            .map(|cache| cache.lock().unwrap_or_else(PoisonError::into_inner).stats())
+    }
+
+    fn clear_verify_cache(&mut self) {
+        if let Some(cache) = self.verify_cache.as_mut() {
+            cache
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear();
+        }
+    }
+
+    /// verifies proof against public inputs without checking the signal hash
+    /// * expect `input_data` is serialized as:
+    /// [ proof<256>| root<32>| epoch<32>| share_x<32>| share_y<32>| nullifier<32> ]
+    pub fn verify_proof<R: Read>(&self, input_data: R) -> io::Result<bool> {
+        self.measure_verification(|| self.verifier.verify_proof(input_data))
+    }
+
+    /// see `RLNVerifier::verify_prehashed`, results are not cached
+    pub fn verify_prehashed<R: Read>(&self, input_data: R) -> io::Result<bool> {
+        self.measure_verification(|| self.verifier.verify_prehashed(input_data))
+    }
+
+    /// see `RLNVerifier::verify_with_stake`, results are not cached
+    pub fn verify_with_stake<R: Read>(&self, input_data: R, stake: E::Fr) -> io::Result<bool> {
+        self.measure_verification(|| self.verifier.verify_with_stake(input_data, stake))
+    }
+
+    // reports a verification to the metrics, errors count as failures
+    fn measure_verification<F: FnOnce() -> io::Result<bool>>(&self, verify: F) -> io::Result<bool> {
+        let start = Instant::now();
+        let result = verify();
+        self.metrics
+            .proof_verified(*result.as_ref().unwrap_or(&false), start.elapsed());
+        result
+    }
+
+    /// reports proofs, verifications, slash events and tree size to `metrics`,
+    /// see `Metrics`
+    pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) {
+        self.metrics = metrics;
+    }
+
+    pub fn verifier(&self) -> &RLNVerifier<E> {
+        &self.verifier
+    }
+
+    /// recovers the secret of a member from two proofs sharing epoch and nullifier
+    /// * `share1_data` and `share2_data` have the layout of `generate_proof` output
+    /// * `result_data` is the secret key in 32 bytes,
+    /// for semaphore identities it is `hash(trapdoor, nullifier)` which hashes to the commitment
+    pub fn recover_secret<R: Read, W: Write>(
+        &self,
+        share1_data: R,
+        share2_data: R,
+        result_data: W,
+    ) -> io::Result<()> {
+        self.verifier
+            .recover_secret(share1_data, share2_data, result_data)
+    }
+
+    /// checks a message the way a relay does before forwarding it
+    /// * `bundle` is `generate_proof` or `generate_proof_bundle` output without the signal
+    /// * epochs are compared as integers, `current_epoch` as passed to `generate_proof`
+    /// * the epoch, root and nullifier log are checked before the proof,
+    /// valid shares are logged so a second message of the member in the epoch is `Spam`
+    /// * errors only if `bundle` can not be decoded
+    pub fn validate_message(
+        &self,
+        bundle: &[u8],
+        signal: &[u8],
+        current_epoch: E::Fr,
+    ) -> io::Result<Verdict<E>> {
+        let parsed = RLNProofBundle::<E>::parse_with_format(bundle, self.proof_format())?;
+        // the fields naming the circuit and proof encoding must match,
+        // whatever version `BundleHeader::read` accepted
+        if let Some(header) = parsed.header {
+            let expected = self.bundle_header()?;
+            if header.curve_id != expected.curve_id
+                || header.arity != expected.arity
+                || header.merkle_depth != expected.merkle_depth
+                || header.proof_format != expected.proof_format
+            {
+                return Ok(Verdict::InvalidProof);
+            }
+        }
+        if let Some(max_epoch_gap) = self.max_epoch_gap {
+            match (epoch_number(parsed.epoch()), epoch_number(current_epoch)) {
+                (Some(epoch), Some(current)) => {
+                    let gap = if epoch > current {
+                        epoch - current
+                    } else {
+                        current - epoch
+                    };
+                    if gap > max_epoch_gap {
+                        return Ok(Verdict::StaleEpoch);
+                    }
+                }
+                _ if parsed.epoch() == current_epoch => {}
+                _ => return Ok(Verdict::StaleEpoch),
+            }
+        }
+        if !self.tree().is_recent_root(parsed.root()) {
+            return Ok(Verdict::InvalidRoot);
+        }
+        let (epoch, nullifier, share_x, share_y) = (
+            parsed.epoch(),
+            parsed.nullifier(),
+            parsed.share_x(),
+            parsed.share_y(),
+        );
+        let log_status = self
+            .nullifier_log
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .check(epoch, nullifier, share_x, share_y);
+        if log_status == LogStatus::Duplicate {
+            return Ok(Verdict::Duplicate);
+        }
+
+        let mut input_data = parsed.proof.clone();
+        for fr in parsed.public_inputs().iter() {
+            fr.into_repr().write_le(&mut input_data)?;
+        }
+        input_data.write_u64::<LittleEndian>(signal.len() as u64)?;
+        input_data.write_all(signal)?;
+        match self.verify(input_data.as_slice()) {
+            Ok(true) => {}
+            Ok(false) | Err(_) => return Ok(Verdict::InvalidProof),
+        }
+
+        let log_status = self
+            .nullifier_log
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(epoch, nullifier, share_x, share_y)?;
+        Ok(match log_status {
+            LogStatus::New => Verdict::Valid,
+            LogStatus::Duplicate => Verdict::Duplicate,
+            LogStatus::Breach(recovered_secret) => {
+                self.metrics.slash();
+                Verdict::Spam { recovered_secret }
+            }
+        })
+    }
+
+    /// logs the share of a proof in the nullifier log of this instance
+    /// * `proof_data` has the layout of `generate_proof` output
+    /// * the proof itself is not verified
+    pub fn nullifier_log_insert<R: Read>(&self, proof_data: R) -> io::Result<LogStatus<E>> {
+        let share = self.read_share(proof_data)?;
+        let mut log = self
+            .nullifier_log
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let status = log.insert(share[1], share[4], share[2], share[3])?;
+        if let LogStatus::Breach(_) = status {
+            self.metrics.slash();
+        }
+        Ok(status)
+    }
+
+    /// keeps the nullifier log in `store`, shares stored before are loaded into the log
+    /// * shares logged so far in memory are replaced
+    pub fn set_nullifier_store(&self, store: Box<dyn NullifierStore<E>>) -> io::Result<()> {
+        let log = NullifierLog::with_store(store)?;
+        *self
+            .nullifier_log
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = log;
+        Ok(())
+    }
+
+    /// drops logged shares of epochs before `epoch`, see `NullifierLog::prune_before`
+    pub fn nullifier_log_prune_before(&self, epoch: u64) -> io::Result<usize> {
+        self.nullifier_log
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .prune_before(epoch)
+    }
+
+    /// same as `nullifier_log_insert` without logging the share
+    pub fn nullifier_log_check<R: Read>(&self, proof_data: R) -> io::Result<LogStatus<E>> {
+        let share = self.read_share(proof_data)?;
+        let log = self
+            .nullifier_log
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        Ok(log.check(share[1], share[4], share[2], share[3]))
+    }
+
+    fn read_share<R: Read>(&self, proof_data: R) -> io::Result<Vec<E::Fr>> {
+        self.verifier.read_proof_public_inputs(proof_data)
+    }
+
+    /// generates public private key pair
+    /// * `key_pair_data` is seralized as [ secret<32> | public<32> ]
+    /// * with semaphore identities as [ trapdoor<32> | nullifier<32> | commitment<32> ]
+    /// * with staked identities `public<32>` is registered along with a stake, see `register_stake`
+    pub fn key_gen<W: Write>(&self, mut input_data: W) -> io::Result<()> {
+        let (secrets, public) = key_gen(&self.hasher(), self.identity_scheme, &mut thread_rng())?;
+        for secret in secrets.iter().chain(Some(&public)) {
+            secret.into_repr().write_le(&mut input_data)?;
+        }
+        Ok(())
+    }
+
+    /// leaf of a member with the secret `a_0` with the hasher of this instance,
+    /// see `commitment`
+    /// * semaphore identities commit with `identity_commitment` instead of the hasher
+    pub fn commitment(&self, secret: E::Fr) -> io::Result<E::Fr> {
+        match self.identity_scheme {
+            IdentityScheme::Semaphore => identity_commitment::<E>(secret),
+            _ => Ok(self.hasher.hash(vec![secret])),
+        }
+    }
+
+    /// derives the key pair at `path` below `master_seed`, see `circuit::rln::derive_identity`
+    /// * `result_data` has the layout of `key_gen` output
+    pub fn derive_identity<W: Write>(
+        &self,
+        master_seed: &[u8],
+        path: &[u32],
+        mut result_data: W,
+    ) -> io::Result<()> {
+        let (secrets, public) =
+            derive_identity(&self.hasher, self.identity_scheme, master_seed, path)?;
+        for fr in secrets.iter().chain(Some(&public)) {
+            fr.into_repr().write_le(&mut result_data)?;
+        }
+        Ok(())
+    }
+
+    /// generates `n` key pairs as `key_gen` does, returned as secrets and leaf
+    /// * pairs are generated in parallel with the `multicore` feature
+    #[cfg(feature = "multicore")]
+    pub fn key_gen_batch(&self, n: usize) -> io::Result<Vec<(Vec<E::Fr>, E::Fr)>>
+    where
+        E: Sync,
+    {
+        use rayon::prelude::*;
+        (0..n)
+            .into_par_iter()
+            .map(|_| key_gen(&self.hasher, self.identity_scheme, &mut thread_rng()))
+            .collect()
+    }
+
+    /// generates `n` key pairs as `key_gen` does, returned as secrets and leaf
+    #[cfg(not(feature = "multicore"))]
+    pub fn key_gen_batch(&self, n: usize) -> io::Result<Vec<(Vec<E::Fr>, E::Fr)>> {
+        let mut rng = thread_rng();
+        (0..n)
+            .map(|_| key_gen(&self.hasher, self.identity_scheme, &mut rng))
+            .collect()
+    }
+
+    /// same as `key_gen_batch` with the pairs serialized
+    /// * `result_data` is [ n<8> | key_pair * n ] with key pairs in the layout of `key_gen`
+    pub fn write_key_gen_batch<W: Write>(&self, n: usize, mut result_data: W) -> io::Result<()>
+    where
+        E: Sync,
+    {
+        result_data.write_u64::<LittleEndian>(n as u64)?;
+        for (secrets, public) in self.key_gen_batch(n)?.iter() {
+            for fr in secrets.iter().chain(Some(public)) {
+                fr.into_repr().write_le(&mut result_data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// restores a key pair from its secrets, e.g. a wallet backup,
+    /// and returns the secret `a_0` and the leaf the member is registered with
+    /// * `input_data` is the secret part of `key_gen` output, with staked identities
+    /// followed by `stake<32>`
+    /// * secrets must be canonical field elements with no bytes after them
+    pub fn import_key<R: Read>(&self, mut input_data: R) -> io::Result<(E::Fr, E::Fr)> {
+        let (id_key, id_nullifier, stake) = self.read_identity(&mut input_data)?;
+        if input_data.read(&mut [0u8; 1])? != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "trailing bytes after the key",
+            ));
+        }
+        let (secret, public_key) = match id_nullifier {
+            Some(id_nullifier) => {
+                let secret = identity_secret::<E>(id_key, id_nullifier)?;
+                (secret, identity_commitment::<E>(secret)?)
+            }
+            None => (id_key, self.hasher.hash(vec![id_key])),
+        };
+        let leaf = match stake {
+            Some(stake) => staked_leaf(&self.hasher, public_key, stake),
+            None => public_key,
+        };
+        Ok((secret, leaf))
+    }
+
+    /// same as `import_key` also returning the index of the member in the tree
+    /// * fails with `NotFound` if the leaf is not registered
+    pub fn import_member_key<R: Read>(&self, input_data: R) -> io::Result<(E::Fr, E::Fr, u64)> {
+        let (secret, leaf) = self.import_key(input_data)?;
+        let index = self
+            .tree()
+            .index_of(leaf)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "key is not registered"))?;
+        Ok((secret, leaf, index))
+    }
+
+    pub fn identity_scheme(&self) -> IdentityScheme {
+        self.identity_scheme
+    }
+
+    /// sets the identity scheme loaded parameters are used with,
+    /// loading takes the first scheme the parameters are shaped for
+    /// * fails if the parameters are not shaped for `identity_scheme`
+    pub fn set_identity_scheme(&mut self, identity_scheme: IdentityScheme) -> io::Result<()> {
+        Self::check_parameters_shape(
+            self.merkle_depth(),
+            self.arity(),
+            &self.circuit_parameters,
+            &self.poseidon_params,
+            identity_scheme,
+        )?;
+        self.identity_scheme = identity_scheme;
+        Ok(())
+    }
+
+    pub fn export_verifier_key<W: Write>(&self, w: W) -> io::Result<()> {
+        self.circuit_parameters.vk.write(w)
+    }
+
+    /// verifier of the circuit built from `export_verifier_key` output alone,
+    /// for deployments that check proofs without holding the proving key
+    pub fn new_verifier_from_vk<R: Read>(raw_verifying_key: R) -> io::Result<RLNVerifier<E>> {
+        RLNVerifier::new_with_raw_vk(raw_verifying_key)
+    }
+
+    pub fn export_circuit_parameters<W: Write>(&self, w: W) -> io::Result<()> {
+        self.circuit_parameters.write(w)
+    }
+
+    /// circuit parameters of the instance, see `new_with_shared_keys`
+    pub fn circuit_parameters(&self) -> Arc<Parameters<E>> {
+        self.circuit_parameters.clone()
+    }
+
+    /// prepared verifying key of the instance, see `new_with_shared_keys`
+    pub fn prepared_verifying_key(&self) -> Arc<PreparedVerifyingKey<E>> {
+        self.verifier.prepared_verifying_key()
+    }
+
+    /// blake2b digest of the serialized circuit parameters, 64 bytes
+    pub fn parameters_fingerprint(&self) -> io::Result<Vec<u8>> {
+        parameters_fingerprint(&self.circuit_parameters)
+    }
+
+    /// hasher of the instance, clones share its parameters
+    pub fn hasher(&self) -> PoseidonHasher<E> {
+        self.hasher.clone()
+    }
+
+    /// hasher of the membership tree, the circomlib poseidon for semaphore identities
+    /// and `hasher` otherwise
+    pub fn tree_hasher(&self) -> TreeHasher<E> {
+        self.tree().hasher().clone()
+    }
+
+    pub fn arity(&self) -> TreeArity {
+        self.tree().arity()
+    }
+
+    pub fn merkle_depth(&self) -> usize {
+        self.tree().depth()
+    }
+
+    /// bundle header describing proofs of this instance
+    pub fn bundle_header(&self) -> io::Result<BundleHeader> {
+        BundleHeader::new::<E>(self.arity(), self.merkle_depth(), self.proof_format())
+    }
+
+    pub fn poseidon_params(&self) -> PoseidonParams<E> {
+        self.poseidon_params.clone()
+    }
+
+    // tree operations validate their input before mutating nodes,
+    // so a lock poisoned by a panicking reader or writer still guards a usable tree
+    fn tree(&self) -> RwLockReadGuard<IncrementalMerkleTree<E, TreeHasher<E>>> {
+        self.tree.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn tree_mut(&self) -> RwLockWriteGuard<IncrementalMerkleTree<E, TreeHasher<E>>> {
+        self.tree.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[test]
+fn test_rln_is_send_sync() {
+    use bellman::pairing::bn256::Bn256;
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RLN<Bn256>>();
+}
+
+#[test]
+fn test_signal_roundtrip() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    let signal = RLNSignal::<Bn256>::from_parts(
+        Fr::from_str("3").unwrap(),
+        b"rln signal".to_vec(),
+        SignalHasher::default(),
+    )
+    .unwrap();
+    let mut raw: Vec<u8> = Vec::new();
+    signal.write(&mut raw).unwrap();
+    assert_eq!(raw.len(), 32 + 8 + 10);
+    let decoded = RLNSignal::<Bn256>::read(raw.as_slice(), SignalHasher::default()).unwrap();
+    assert_eq!(decoded.epoch, signal.epoch);
+    assert_eq!(decoded.hash, signal.hash);
+    assert_eq!(decoded.payload, signal.payload);
+    assert!(RLNSignal::<Bn256>::read(&raw[..raw.len() - 1], SignalHasher::default()).is_err());
+}
+
+#[test]
+fn test_new_deterministic() {
+    use bellman::pairing::bn256::Bn256;
+    let rln_0 = RLN::<Bn256>::new_deterministic(3, b"rln ci");
+    let rln_1 = RLN::<Bn256>::new_deterministic(3, b"rln ci");
+    let rln_2 = RLN::<Bn256>::new_deterministic(3, b"other");
+    let fingerprint = rln_0.parameters_fingerprint().unwrap();
+    assert_eq!(fingerprint, rln_1.parameters_fingerprint().unwrap());
+    assert_ne!(fingerprint, rln_2.parameters_fingerprint().unwrap());
+}
+
+#[test]
+fn test_shared_keys() {
+    use bellman::pairing::bn256::Bn256;
+    let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let other = RLN::<Bn256>::new_with_shared_keys(
+        3,
+        TreeArity::Binary,
+        rln.circuit_parameters(),
+        rln.prepared_verifying_key(),
+        None,
+    )
+    .unwrap();
+    assert!(Arc::ptr_eq(
+        &rln.circuit_parameters(),
+        &other.circuit_parameters()
+    ));
+    assert!(Arc::ptr_eq(
+        &rln.prepared_verifying_key(),
+        &other.prepared_verifying_key()
+    ));
+    assert_eq!(
+        rln.parameters_fingerprint().unwrap(),
+        other.parameters_fingerprint().unwrap()
+    );
+}
+
+#[test]
+fn test_validate_message() {
+    use crate::test_utils::{proof_input, register_member};
+    use bellman::pairing::bn256::{Bn256, Fr};
+    let mut rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let fr = |n: u64| Fr::from_str(&n.to_string()).unwrap();
+    let id_key = fr(1001);
+    let bundle = |rln: &RLN<Bn256>, input_data: Vec<u8>| {
+        let mut bundle: Vec<u8> = Vec::new();
+        rln.generate_proof_bundle(input_data.as_slice(), &mut bundle)
+            .unwrap();
+        bundle
+    };
+    let first = bundle(&rln, register_member(&rln, id_key, fr(10), b"first"));
+    // the member is the first one of the tree
+    let prove = |rln: &RLN<Bn256>, epoch: u64, signal: &[u8]| {
+        bundle(rln, proof_input::<Bn256>(id_key, 0, fr(epoch), signal))
+    };
+    let add_member = |rln: &RLN<Bn256>, n: u64| {
+        register_member(rln, fr(n), fr(0), b"");
+    };
+
+    // headers of another circuit are rejected before the proof is checked
+    let v1 = prove(&rln, 9, b"v1");
+    assert_eq!(v1[4], 1);
+    let mut other_depth = v1.clone();
+    other_depth[7] = 4;
+    assert_eq!(
+        rln.validate_message(&other_depth, b"v1", fr(10)).unwrap(),
+        Verdict::InvalidProof
+    );
+    assert_eq!(
+        rln.validate_message(&v1, b"v1", fr(10)).unwrap(),
+        Verdict::Valid
+    );
+
+    let second = prove(&rln, 10, b"second");
+    assert_eq!(
+        rln.validate_message(&first, b"first", fr(10)).unwrap(),
+        Verdict::Valid
+    );
+    assert_eq!(
+        rln.validate_message(&first, b"first", fr(11)).unwrap(),
+        Verdict::Duplicate
+    );
+    assert_eq!(
+        rln.validate_message(&first, b"first", fr(12)).unwrap(),
+        Verdict::StaleEpoch
+    );
+    assert_eq!(
+        rln.validate_message(&second, b"forged", fr(10)).unwrap(),
+        Verdict::InvalidProof
+    );
+    assert_eq!(
+        rln.validate_message(&second, b"second", fr(10)).unwrap(),
+        Verdict::Spam {
+            recovered_secret: id_key
+        }
+    );
+
+    // the root of a proof stays valid for a few membership changes
+    let third = prove(&rln, 20, b"third");
+    rln.set_root_history_size(2);
+    add_member(&rln, 2001);
+    rln.set_max_epoch_gap(None);
+    assert_eq!(
+        rln.validate_message(&third, b"third", fr(0)).unwrap(),
+        Verdict::Valid
+    );
+    add_member(&rln, 2002);
+    let fourth = prove(&rln, 21, b"fourth");
+    add_member(&rln, 2003);
+    add_member(&rln, 2004);
+    assert_eq!(
+        rln.validate_message(&fourth, b"fourth", fr(21)).unwrap(),
+        Verdict::InvalidRoot
+    );
+    assert!(rln
+        .validate_message(&fourth[..20], b"fourth", fr(21))
+        .is_err());
+}
+
+#[test]
+fn test_verify_cache() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    let mut rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    assert!(rln.verify_cache_stats().is_none());
+    rln.set_verify_cache(Some(16));
+
+    let id_key = Fr::from_str("1001").unwrap();
+    let signal = b"rln";
+    let input_data = crate::test_utils::register_member(&rln, id_key, Fr::one(), signal);
+    let mut verify_data: Vec<u8> = Vec::new();
+    rln.generate_proof(input_data.as_slice(), &mut verify_data)
+        .unwrap();
+    verify_data
+        .write_u64::<LittleEndian>(signal.len() as u64)
+        .unwrap();
+    verify_data.write_all(signal).unwrap();
+
+    assert!(rln.verify(verify_data.as_slice()).unwrap());
+    assert!(rln.verify(verify_data.as_slice()).unwrap());
+    let stats = rln.verify_cache_stats().unwrap();
+    assert_eq!((stats.hits, stats.misses, stats.len), (1, 1, 1));
+
+    // malformed input is not cached
+    assert!(rln.verify(&verify_data[..100]).is_err());
+    assert_eq!(rln.verify_cache_stats().unwrap().len, 1);
+
+    rln.set_rln_identifier(Fr::from_str("7").unwrap());
+    assert_eq!(rln.verify_cache_stats().unwrap().len, 0);
+    assert!(!rln.verify(verify_data.as_slice()).unwrap());
+}
+
+#[test]
+fn test_max_signal_size() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    let mut rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    assert_eq!(rln.max_signal_size(), Some(DEFAULT_MAX_SIGNAL_SIZE));
+
+    let mut oversized: Vec<u8> = Vec::new();
+    oversized.write_u64::<LittleEndian>(u64::MAX).unwrap();
+    let err = rln
+        .signal_to_field(oversized.as_slice(), Vec::new())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let mut input_data = crate::test_utils::proof_input::<Bn256>(Fr::one(), 0, Fr::one(), b"");
+    input_data.truncate(32 + 8 + 32);
+    input_data.write_u64::<LittleEndian>(u64::MAX).unwrap();
+    let err = rln
+        .generate_proof(input_data.as_slice(), Vec::new())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    rln.set_max_signal_size(Some(2));
+    let mut signal: Vec<u8> = Vec::new();
+    signal.write_u64::<LittleEndian>(3).unwrap();
+    signal.write_all(b"rln").unwrap();
+    assert!(rln.signal_to_field(signal.as_slice(), Vec::new()).is_err());
+    rln.set_max_signal_size(None);
+    assert!(rln.signal_to_field(signal.as_slice(), Vec::new()).is_ok());
+}
+
+#[test]
+fn test_parameters_fingerprint() {
+    use bellman::pairing::bn256::Bn256;
+    let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let fingerprint = rln.parameters_fingerprint().unwrap();
+    assert_eq!(fingerprint.len(), 64);
+    let mut raw_params: Vec<u8> = Vec::new();
+    rln.export_circuit_parameters(&mut raw_params).unwrap();
+
+    let loaded = RLN::<Bn256>::new_with_raw_params(
+        3,
+        TreeArity::Binary,
+        raw_params.as_slice(),
+        None,
+        Some(&fingerprint),
+    )
+    .unwrap();
+    assert_eq!(loaded.parameters_fingerprint().unwrap(), fingerprint);
+
+    let mut wrong_fingerprint = fingerprint.clone();
+    wrong_fingerprint[0] ^= 1;
+    assert!(RLN::<Bn256>::new_with_raw_params(
+        3,
+        TreeArity::Binary,
+        raw_params.as_slice(),
+        None,
+        Some(&wrong_fingerprint),
+    )
+    .is_err());
+}
+
+#[test]
+fn test_generate_proof_with_progress() {
+    use crate::test_utils::register_member;
+    use bellman::pairing::bn256::{Bn256, Fr};
+    let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let input_data = register_member(&rln, Fr::from_str("1001").unwrap(), Fr::one(), b"signal");
+
+    let mut phases: Vec<(ProofPhase, u8)> = Vec::new();
+    let mut proof: Vec<u8> = Vec::new();
+    rln.generate_proof_with_progress(input_data.as_slice(), &mut proof, |phase, percent| {
+        phases.push((phase, percent))
+    })
+    .unwrap();
+    assert!(rln.verify_proof(proof.as_slice()).unwrap());
+    assert_eq!(phases[0], (ProofPhase::Synthesis, 0));
+    assert_eq!(
+        phases[phases.len() - 3..],
+        [
+            (ProofPhase::Synthesis, 100),
+            (ProofPhase::Proving, 0),
+            (ProofPhase::Proving, 100)
+        ]
+    );
+    assert!(phases
+        .windows(2)
+        .all(|w| w[0].0 != w[1].0 || w[0].1 < w[1].1));
+
+    // a prepared proof keeps the witness it was prepared with
+    let prepared = rln.prepare_proof(input_data.as_slice()).unwrap();
+    register_member(&rln, Fr::from_str("1002").unwrap(), Fr::one(), b"signal");
+    let mut proof: Vec<u8> = Vec::new();
+    prepared.prove(&mut proof, &mut thread_rng()).unwrap();
+    assert!(rln.verify_proof(proof.as_slice()).unwrap());
+}
+
+#[test]
+fn test_generate_proof_with_rng() {
+    use crate::utils::seeded_rng;
+    use bellman::pairing::bn256::{Bn256, Fr};
+    use byteorder::WriteBytesExt;
+    let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let id_key = Fr::rand(&mut thread_rng());
+    let public_key = rln.hasher().hash(vec![id_key]);
+    let mut member: Vec<u8> = Vec::new();
+    public_key.into_repr().write_le(&mut member).unwrap();
+    let index = rln.update_next_member(member.as_slice()).unwrap();
+
+    let signal = b"rln signal";
+    let mut input_data: Vec<u8> = Vec::new();
+    id_key.into_repr().write_le(&mut input_data).unwrap();
+    input_data.write_u64::<LittleEndian>(index).unwrap();
+    Fr::one().into_repr().write_le(&mut input_data).unwrap();
+    input_data
+        .write_u64::<LittleEndian>(signal.len() as u64)
+        .unwrap();
+    input_data.write_all(&signal[..]).unwrap();
+
+    let mut proof_0: Vec<u8> = Vec::new();
+    rln.generate_proof_with_rng(
+        input_data.as_slice(),
+        &mut proof_0,
+        &mut seeded_rng(b"seed"),
+    )
+    .unwrap();
+    let mut proof_1: Vec<u8> = Vec::new();
+    rln.generate_proof_with_rng(
+        input_data.as_slice(),
+        &mut proof_1,
+        &mut seeded_rng(b"seed"),
+    )
+    .unwrap();
+    assert_eq!(proof_0, proof_1);
+    assert!(rln.verify_proof(proof_0.as_slice()).unwrap());
+
+    let mut rerandomized: Vec<u8> = Vec::new();
+    rln.rerandomize_proof(proof_0.as_slice(), &mut rerandomized, &mut thread_rng())
+        .unwrap();
+    let proof_size = rln.proof_format().proof_size::<Bn256>();
+    assert!(rerandomized[..proof_size] != proof_0[..proof_size]);
+    assert_eq!(rerandomized[proof_size..], proof_0[proof_size..]);
+    assert!(rln.verify_proof(rerandomized.as_slice()).unwrap());
+}
+
+#[test]
+fn test_rln_identifier() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    use byteorder::WriteBytesExt;
+    let rln_identifier = Fr::from_str("42").unwrap();
+    let mut rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, Some(rln_identifier));
+    assert_eq!(rln.rln_identifier(), rln_identifier);
+    let id_key = Fr::rand(&mut thread_rng());
+    let public_key = rln.hasher().hash(vec![id_key]);
+    let mut member: Vec<u8> = Vec::new();
+    public_key.into_repr().write_le(&mut member).unwrap();
+    let index = rln.update_next_member(member.as_slice()).unwrap();
+
+    let mut input_data: Vec<u8> = Vec::new();
+    id_key.into_repr().write_le(&mut input_data).unwrap();
+    input_data.write_u64::<LittleEndian>(index).unwrap();
+    Fr::one().into_repr().write_le(&mut input_data).unwrap();
+    input_data.write_u64::<LittleEndian>(0).unwrap();
+    let mut proof: Vec<u8> = Vec::new();
+    rln.generate_proof(input_data.as_slice(), &mut proof)
+        .unwrap();
+    assert!(rln.verify_proof(proof.as_slice()).unwrap());
+
+    // the same key and epoch in another deployment give another nullifier
+    let nullifier_0 = rln.read_proof_public_inputs(proof.as_slice()).unwrap()[4];
+    rln.set_rln_identifier(Fr::one());
+    assert!(!rln.verify_proof(proof.as_slice()).unwrap());
+    let mut proof: Vec<u8> = Vec::new();
+    rln.generate_proof(input_data.as_slice(), &mut proof)
+        .unwrap();
+    let nullifier_1 = rln.read_proof_public_inputs(proof.as_slice()).unwrap()[4];
+    assert!(nullifier_0 != nullifier_1);
+}
+
+#[test]
+fn test_semaphore_identity() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    use byteorder::WriteBytesExt;
+    let rln = RLN::<Bn256>::new_with_identity_scheme(
+        3,
+        TreeArity::Binary,
+        None,
+        None,
+        IdentityScheme::Semaphore,
+    );
+    let mut keypair: Vec<u8> = Vec::new();
+    rln.key_gen(&mut keypair).unwrap();
+    assert_eq!(keypair.len(), 96);
+    let identity = read_fr::<_, Bn256>(keypair.as_slice(), 3).unwrap();
+    let secret = identity_secret::<Bn256>(identity[0], identity[1]).unwrap();
+    assert_eq!(identity_commitment::<Bn256>(secret).unwrap(), identity[2]);
+    assert_eq!(rln.commitment(secret).unwrap(), identity[2]);
+    assert_eq!(
+        rln.import_key(&keypair[..64]).unwrap(),
+        (secret, identity[2])
+    );
+
+    // trapdoor 2 and nullifier 1, the secret is `poseidon([1, 2])` of circomlibjs
+    let mut pinned: Vec<u8> = Vec::new();
+    Fr::from_str("2")
+        .unwrap()
+        .into_repr()
+        .write_le(&mut pinned)
+        .unwrap();
+    Fr::one().into_repr().write_le(&mut pinned).unwrap();
+    assert_eq!(
+        rln.import_key(pinned.as_slice()).unwrap(),
+        (
+            Fr::from_str(
+                "7853200120776062878684798364095072458815029376092732009249414926327459813530"
+            )
+            .unwrap(),
+            Fr::from_str(
+                "1726140942480881257963748121685659126946424978635264596106980875531445116889"
+            )
+            .unwrap()
+        )
+    );
+    let index = rln.update_next_member(&keypair[64..]).unwrap();
+
+    // the tree is the one of semaphore, hashed with the circomlib poseidon
+    let mut semaphore_tree =
+        MerkleTree::<Bn256, _>::empty(semaphore_tree_hasher::<Bn256>().unwrap(), 3);
+    semaphore_tree.update(index, identity[2]).unwrap();
+    let mut root: Vec<u8> = Vec::new();
+    rln.get_root(&mut root).unwrap();
+    assert_eq!(
+        read_fr::<_, Bn256>(root.as_slice(), 1).unwrap()[0],
+        semaphore_tree.get_root()
+    );
+
+    let proof = |signal: &[u8]| {
+        let mut input_data: Vec<u8> = keypair[..64].to_vec();
+        input_data.write_u64::<LittleEndian>(index).unwrap();
+        Fr::one().into_repr().write_le(&mut input_data).unwrap();
+        input_data
+            .write_u64::<LittleEndian>(signal.len() as u64)
+            .unwrap();
+        input_data.write_all(signal).unwrap();
+        let mut proof: Vec<u8> = Vec::new();
+        rln.generate_proof(input_data.as_slice(), &mut proof)
+            .unwrap();
+        proof
+    };
+    let proof_0 = proof(b"signal 0");
+    let proof_1 = proof(b"signal 1");
+    assert!(rln.verify_proof(proof_0.as_slice()).unwrap());
+
+    let mut recovered: Vec<u8> = Vec::new();
+    rln.recover_secret(proof_0.as_slice(), proof_1.as_slice(), &mut recovered)
+        .unwrap();
+    assert_eq!(
+        read_fr::<_, Bn256>(recovered.as_slice(), 1).unwrap()[0],
+        secret
+    );
+
+    let mut raw_params: Vec<u8> = Vec::new();
+    rln.export_circuit_parameters(&mut raw_params).unwrap();
+    let loaded =
+        RLN::<Bn256>::new_with_raw_params(3, TreeArity::Binary, raw_params.as_slice(), None, None)
+            .unwrap();
+    assert_eq!(loaded.identity_scheme(), IdentityScheme::Semaphore);
+
+    // circomlib constants are over bn256 only
+    use bellman::pairing::bls12_381::{Bls12, Fr as BlsFr};
+    assert!(identity_secret::<Bls12>(BlsFr::one(), BlsFr::one()).is_err());
+    assert!(identity_commitment::<Bls12>(BlsFr::one()).is_err());
+}
+
+#[test]
+fn test_public_stake() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    use byteorder::WriteBytesExt;
+    let rln = RLN::<Bn256>::new_with_identity_scheme(
+        3,
+        TreeArity::Binary,
+        None,
+        None,
+        IdentityScheme::PublicStake,
+    );
+    let mut keypair: Vec<u8> = Vec::new();
+    rln.key_gen(&mut keypair).unwrap();
+    let stake = Fr::from_str("1000").unwrap();
+    let mut member = keypair[32..].to_vec();
+    stake.into_repr().write_le(&mut member).unwrap();
+    let index = rln.register_stake(member.as_slice()).unwrap();
+
+    let signal = b"staked";
+    let mut input_data: Vec<u8> = keypair[..32].to_vec();
+    stake.into_repr().write_le(&mut input_data).unwrap();
+    input_data.write_u64::<LittleEndian>(index).unwrap();
+    Fr::one().into_repr().write_le(&mut input_data).unwrap();
+    input_data
+        .write_u64::<LittleEndian>(signal.len() as u64)
+        .unwrap();
+    input_data.write_all(signal).unwrap();
+    let mut proof: Vec<u8> = Vec::new();
+    rln.generate_proof(input_data.as_slice(), &mut proof)
+        .unwrap();
+    proof
+        .write_u64::<LittleEndian>(signal.len() as u64)
+        .unwrap();
+    proof.write_all(signal).unwrap();
+    assert!(rln.verify_with_stake(proof.as_slice(), stake).unwrap());
+    assert!(!rln.verify_with_stake(proof.as_slice(), Fr::one()).unwrap());
+    assert!(rln.verify(proof.as_slice()).is_err());
+
+    let mut raw_params: Vec<u8> = Vec::new();
+    rln.export_circuit_parameters(&mut raw_params).unwrap();
+    let loaded =
+        RLN::<Bn256>::new_with_raw_params(3, TreeArity::Binary, raw_params.as_slice(), None, None)
+            .unwrap();
+    assert_eq!(loaded.identity_scheme(), IdentityScheme::PublicStake);
+
+    let mut evidence = keypair[..32].to_vec();
+    stake.into_repr().write_le(&mut evidence).unwrap();
+    assert_eq!(rln.slash_stake(evidence.as_slice()).unwrap(), index);
+    assert_eq!(rln.member_count(), 0);
+    assert!(rln.slash_stake(evidence.as_slice()).is_err());
+}
+
+#[test]
+fn test_prehashed_signal() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+
+    let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let id_key = Fr::from_str("1001").unwrap();
+    let signal = b"hello";
+    let mut input_data = crate::test_utils::register_member(&rln, id_key, Fr::one(), signal);
+
+    let signal_hash = Fr::from_str("42").unwrap();
+    let mut prehashed_data = input_data[..32 + 8 + 32].to_vec();
+    prehashed_data.write_u8(1).unwrap();
+    signal_hash
+        .into_repr()
+        .write_le(&mut prehashed_data)
+        .unwrap();
+    let mut proof: Vec<u8> = Vec::new();
+    rln.generate_proof_prehashed(prehashed_data.as_slice(), &mut proof)
+        .unwrap();
+
+    let mut verify_data = proof.clone();
+    signal_hash.into_repr().write_le(&mut verify_data).unwrap();
+    assert!(rln.verify_prehashed(verify_data.as_slice()).unwrap());
+    let mut other_data = proof.clone();
+    Fr::one().into_repr().write_le(&mut other_data).unwrap();
+    assert!(rln.verify_prehashed(other_data.as_slice()).is_err());
+
+    // flag 0 takes the signal as `generate_proof` does
+    input_data.insert(32 + 8 + 32, 0);
+    let mut proof: Vec<u8> = Vec::new();
+    rln.generate_proof_prehashed(input_data.as_slice(), &mut proof)
+        .unwrap();
+    proof
+        .write_u64::<LittleEndian>(signal.len() as u64)
+        .unwrap();
+    proof.write_all(signal).unwrap();
+    assert!(rln.verify(proof.as_slice()).unwrap());
+
+    input_data[32 + 8 + 32] = 2;
+    assert!(rln
+        .generate_proof_prehashed(input_data.as_slice(), &mut Vec::new())
+        .is_err());
+}
+
+#[test]
+fn test_commitment() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+
+    let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let mut key_pair: Vec<u8> = Vec::new();
+    rln.key_gen(&mut key_pair).unwrap();
+    let key_pair = read_fr::<_, Bn256>(key_pair.as_slice(), 2).unwrap();
+    assert_eq!(commitment::<Bn256>(key_pair[0]), key_pair[1]);
+    assert_eq!(rln.commitment(key_pair[0]).unwrap(), key_pair[1]);
+
+    let quad = RLN::<Bn256>::new(2, TreeArity::Quad, None, None);
+    let secret = Fr::from_str("1001").unwrap();
+    assert_eq!(
+        commitment_with_params(&quad.poseidon_params(), secret),
+        quad.commitment(secret).unwrap()
+    );
+}
+
+#[test]
+fn test_key_gen_batch() {
+    use bellman::pairing::bn256::Bn256;
+    use std::collections::HashSet;
+
+    let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let keys = rln.key_gen_batch(16).unwrap();
+    assert_eq!(keys.len(), 16);
+    let leaves: HashSet<String> = keys
+        .iter()
+        .map(|(secrets, public)| {
+            assert_eq!(rln.hasher().hash(secrets.clone()), *public);
+            format!("{}", public)
+        })
+        .collect();
+    assert_eq!(leaves.len(), 16);
+
+    let mut raw: Vec<u8> = Vec::new();
+    rln.write_key_gen_batch(3, &mut raw).unwrap();
+    assert_eq!(raw.len(), 8 + 3 * 64);
+    assert_eq!(raw[..8], 3u64.to_le_bytes());
+}
+
+#[test]
+fn test_import_key() {
+    use bellman::pairing::bn256::Bn256;
+
+    let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let mut key_pair: Vec<u8> = Vec::new();
+    rln.key_gen(&mut key_pair).unwrap();
+    let (secret_data, public_data) = key_pair.split_at(32);
+    let (secret, leaf) = rln.import_key(secret_data).unwrap();
+    assert_eq!(read_fr::<_, Bn256>(secret_data, 1).unwrap()[0], secret);
+    assert_eq!(read_fr::<_, Bn256>(public_data, 1).unwrap()[0], leaf);
+    let err = rln.import_member_key(secret_data).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    let mut other: Vec<u8> = Vec::new();
+    rln.key_gen(&mut other).unwrap();
+    rln.update_next_member(&other[32..]).unwrap();
+    rln.update_next_member(public_data).unwrap();
+    assert_eq!(
+        rln.import_member_key(secret_data).unwrap(),
+        (secret, leaf, 1)
+    );
+
+    let err = rln.import_key(&key_pair[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = rln.import_key(&[0xffu8; 32][..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_get_witness() {
+    use crate::merkle::verify_inclusion;
+    use bellman::pairing::bn256::{Bn256, Fr};
+    let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let leaves: Vec<Fr> = (1..4)
+        .map(|i| {
+            rln.hasher()
+                .hash(vec![Fr::from_str(&format!("{}", i)).unwrap()])
+        })
+        .collect();
+    for leaf in leaves.iter() {
+        let mut member: Vec<u8> = Vec::new();
+        leaf.into_repr().write_le(&mut member).unwrap();
+        rln.update_next_member(member.as_slice()).unwrap();
+    }
+    let mut proof: Vec<u8> = Vec::new();
+    rln.get_witness(2, &mut proof).unwrap();
+    assert_eq!(proof.len(), 32 + 3 * 33);
+    let root = read_fr::<_, Bn256>(&proof[..32], 1).unwrap()[0];
+    let witness = read_witness::<_, Bn256>(&proof[32..], 3).unwrap();
+    assert!(verify_inclusion(
+        &rln.hasher(),
+        root,
+        2,
+        leaves[2],
+        &witness
+    ));
+    assert!(rln.get_witness(3, &mut Vec::new()).is_err());
+    assert!(rln.get_witness(1 << 3, &mut Vec::new()).is_err());
+    assert!(rln.get_witness(u64::MAX, &mut Vec::new()).is_err());
+    assert!(rln.delete_member(1 << 3).is_err());
+    assert!(rln.delete_member(u64::MAX).is_err());
+}
+
+#[test]
+fn test_proof_bundle() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    use byteorder::WriteBytesExt;
+    let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let id_key = Fr::rand(&mut thread_rng());
+    let public_key = rln.hasher().hash(vec![id_key]);
+    let mut member: Vec<u8> = Vec::new();
+    public_key.into_repr().write_le(&mut member).unwrap();
+    let index = rln.update_next_member(member.as_slice()).unwrap();
+
+    let signal = b"rln signal";
+    let mut input_data: Vec<u8> = Vec::new();
+    id_key.into_repr().write_le(&mut input_data).unwrap();
+    input_data.write_u64::<LittleEndian>(index).unwrap();
+    Fr::one().into_repr().write_le(&mut input_data).unwrap();
+    input_data
+        .write_u64::<LittleEndian>(signal.len() as u64)
+        .unwrap();
+    input_data.write_all(&signal[..]).unwrap();
+
+    let mut bundle: Vec<u8> = Vec::new();
+    rln.generate_proof_bundle(input_data.as_slice(), &mut bundle)
+        .unwrap();
+    bundle
+        .write_u64::<LittleEndian>(signal.len() as u64)
+        .unwrap();
+    bundle.write_all(&signal[..]).unwrap();
+    assert!(rln.verify_bundle(bundle.as_slice()).unwrap());
+
+    // depth 3 bundle is rejected by a depth 4 instance
+    let mut other_depth = bundle.clone();
+    other_depth[7] = 4;
+    assert!(rln.verify_bundle(other_depth.as_slice()).is_err());
+
+    // unless the instance holds the key of depth 3
+    let mut deeper = RLN::<Bn256>::new(4, TreeArity::Binary, None, None);
+    assert!(deeper.verify_bundle(bundle.as_slice()).is_err());
+    let mut raw_vk: Vec<u8> = Vec::new();
+    rln.export_verifier_key(&mut raw_vk).unwrap();
+    assert!(deeper
+        .add_circuit_verifier(TreeArity::Binary, 4, raw_vk.as_slice())
+        .is_err());
+    deeper
+        .add_circuit_verifier(TreeArity::Binary, 3, raw_vk.as_slice())
+        .unwrap();
+    assert_eq!(
+        deeper.verified_circuits(),
+        vec![(TreeArity::Binary, 4), (TreeArity::Binary, 3)]
+    );
+    assert!(deeper.verify_bundle(bundle.as_slice()).unwrap());
+    let mut tampered = bundle.clone();
+    tampered[crate::bundle::BUNDLE_HEADER_SIZE + 256 + 32] ^= 1;
+    assert!(!deeper.verify_bundle(tampered.as_slice()).unwrap_or(false));
+    assert!(deeper.remove_circuit_verifier(TreeArity::Binary, 3));
+    assert!(deeper.verify_bundle(bundle.as_slice()).is_err());
+}
+
+#[test]
+fn test_parameters_shape() {
+    use bellman::pairing::bn256::Bn256;
+    let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let mut raw_params: Vec<u8> = Vec::new();
+    rln.export_circuit_parameters(&mut raw_params).unwrap();
+
+    let err = match RLN::<Bn256>::new_with_raw_params(
+        4,
+        TreeArity::Binary,
+        raw_params.as_slice(),
+        None,
+        None,
+    ) {
+        Ok(_) => panic!("parameters of depth 3 accepted for depth 4"),
+        Err(err) => err,
+    };
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let shape_err = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<ParametersShapeError>())
+        .unwrap();
+    assert_eq!(shape_err.merkle_depth, 4);
+    assert!(shape_err.expected_aux > shape_err.found_aux);
+}
+
+#[cfg(feature = "debug")]
+#[test]
+fn test_diagnose() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    let id_key = Fr::rand(&mut thread_rng());
+    let public_key = rln.hasher().hash(vec![id_key]);
+    let mut member: Vec<u8> = Vec::new();
+    public_key.into_repr().write_le(&mut member).unwrap();
+    let index = rln.update_next_member(member.as_slice()).unwrap();
+
+    let input = |id_key: Fr| {
+        let mut input_data: Vec<u8> = Vec::new();
+        id_key.into_repr().write_le(&mut input_data).unwrap();
+        input_data.write_u64::<LittleEndian>(index).unwrap();
+        Fr::one().into_repr().write_le(&mut input_data).unwrap();
+        input_data.write_u64::<LittleEndian>(0).unwrap();
+        input_data
+    };
+    let diagnostics = rln.diagnose(input(id_key).as_slice()).unwrap();
+    assert!(diagnostics.is_ok());
+    assert!(diagnostics.num_constraints > 0);
+
+    // key of another member
+    let diagnostics = rln.diagnose(input(Fr::one()).as_slice()).unwrap();
+    assert!(diagnostics.unsatisfied.unwrap().contains("membership"));
+}
+
+#[test]
+fn test_batch_proof() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    let mut rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, Some(Fr::from_str("7").unwrap()));
+    let mut input_data: Vec<u8> = Vec::new();
+    assert!(rln
+        .generate_batch_proof(input_data.as_slice(), &mut Vec::new())
+        .is_err());
+    rln.setup_batch(2).unwrap();
+
+    // two members, one message each
+    input_data.write_u64::<LittleEndian>(2).unwrap();
+    for epoch in 0..2u64 {
+        let id_key = Fr::rand(&mut thread_rng());
+        let public_key = rln.hasher().hash(vec![id_key]);
+        let mut member: Vec<u8> = Vec::new();
+        public_key.into_repr().write_le(&mut member).unwrap();
+        let index = rln.update_next_member(member.as_slice()).unwrap();
+        id_key.into_repr().write_le(&mut input_data).unwrap();
+        input_data.write_u64::<LittleEndian>(index).unwrap();
+        Fr::from_str(&format!("{}", epoch))
+            .unwrap()
+            .into_repr()
+            .write_le(&mut input_data)
+            .unwrap();
+        input_data.write_u64::<LittleEndian>(1).unwrap();
+        input_data.write_all(&[epoch as u8]).unwrap();
+    }
+
+    let mut proof: Vec<u8> = Vec::new();
+    rln.generate_batch_proof(input_data.as_slice(), &mut proof)
+        .unwrap();
+    assert_eq!(proof.len(), 256 + 2 * 5 * 32);
+    assert!(rln.verify_batch_proof(proof.as_slice()).unwrap());
+
+    // share_y of the second message
+    let mut tampered = proof.clone();
+    tampered[256 + 5 * 32 + 3 * 32] ^= 1;
+    assert!(!rln.verify_batch_proof(tampered.as_slice()).unwrap());
+
+    input_data[0] = 3;
+    assert!(rln
+        .generate_batch_proof(input_data.as_slice(), &mut Vec::new())
+        .is_err());
+    assert!(rln
+        .verify_batch_proof_with_stakes(proof.as_slice(), &[Fr::one(), Fr::one()])
+        .is_err());
+}
+
+#[test]
+fn test_public_stake_batch_proof() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    let mut rln = RLN::<Bn256>::new_with_identity_scheme(
+        3,
+        TreeArity::Binary,
+        None,
+        None,
+        IdentityScheme::PublicStake,
+    );
+    rln.setup_batch(2).unwrap();
+
+    let stakes = [Fr::from_str("1000").unwrap(), Fr::from_str("2000").unwrap()];
+    let mut input_data: Vec<u8> = Vec::new();
+    input_data.write_u64::<LittleEndian>(2).unwrap();
+    for stake in stakes.iter() {
+        let mut keypair: Vec<u8> = Vec::new();
+        rln.key_gen(&mut keypair).unwrap();
+        let mut member = keypair[32..].to_vec();
+        stake.into_repr().write_le(&mut member).unwrap();
+        let index = rln.register_stake(member.as_slice()).unwrap();
+        input_data.extend_from_slice(&keypair[..32]);
+        stake.into_repr().write_le(&mut input_data).unwrap();
+        input_data.write_u64::<LittleEndian>(index).unwrap();
+        Fr::one().into_repr().write_le(&mut input_data).unwrap();
+        input_data.write_u64::<LittleEndian>(1).unwrap();
+        input_data.write_all(&[index as u8]).unwrap();
+    }
+
+    let mut proof: Vec<u8> = Vec::new();
+    rln.generate_batch_proof(input_data.as_slice(), &mut proof)
+        .unwrap();
+    assert!(rln
+        .verify_batch_proof_with_stakes(proof.as_slice(), &stakes)
+        .unwrap());
+    let swapped = [stakes[1], stakes[0]];
+    assert!(!rln
+        .verify_batch_proof_with_stakes(proof.as_slice(), &swapped)
+        .unwrap());
+    assert!(rln
+        .verify_batch_proof_with_stakes(proof.as_slice(), &stakes[..1])
+        .is_err());
+    assert!(rln.verify_batch_proof(proof.as_slice()).is_err());
+
+    // parameters of a batch of unstaked messages have fewer inputs
+    let mut raw_params: Vec<u8> = Vec::new();
+    rln.export_batch_parameters(&mut raw_params).unwrap();
+    let batch_parameters = Parameters::<Bn256>::read(raw_params.as_slice(), true).unwrap();
+    let mut unstaked = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    assert!(unstaked.set_batch_parameters(2, batch_parameters).is_err());
+}
+
+// #[cfg(test)]
+// mod tests {
+//     use crate::{circuit::bench, public::RLNSignal};
+//     use crate::{poseidon::PoseidonParams, public};
+//     use bellman::pairing::bn256::{Bn256, Fr};
+//     use rand::{Rand, SeedableRng, XorShiftRng};
+
+//     fn merkle_depth() -> usize {
+//         4usize
+//     }
+
+//     fn rln_test() -> bench::RLNTest<Bn256> {
+//         let merkle_depth = merkle_depth();
+//         let poseidon_params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+//         let rln_test = bench::RLNTest::<Bn256>::new(merkle_depth, Some(poseidon_params));
+//         rln_test
+//     }
+
+//     #[test]
+//     fn test_xxx() {
+//         let rln = rln_test();
+//         let rln = rln.rln;
+
+//         let input_data: Vec<u8> = vec![1, 2, 3, 4];
+//         let n = 4;
+//         let mut result_data: Vec<u8> = Vec::new();
+
+//         rln.signal_to_field(&input_data[..], n, &mut result_data)
+//             .unwrap();
+//     }
+// }
+
+#[test]
+fn test_withdrawal_proof() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    let mut rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, None);
+    assert!(rln.verify_withdrawal_proof(&[0u8; 320][..]).is_err());
+    rln.setup_withdrawal().unwrap();
+
+    let mut keypair: Vec<u8> = Vec::new();
+    rln.key_gen(&mut keypair).unwrap();
+    let index = rln.update_next_member(&keypair[32..]).unwrap();
+    let receiver = Fr::from_str("42").unwrap();
+    let withdrawal_input = |index: u64| {
+        let mut input_data: Vec<u8> = keypair[..32].to_vec();
+        input_data.write_u64::<LittleEndian>(index).unwrap();
+        receiver.into_repr().write_le(&mut input_data).unwrap();
+        input_data
+    };
+
+    let mut proof: Vec<u8> = Vec::new();
+    rln.generate_withdrawal_proof(withdrawal_input(index).as_slice(), &mut proof)
+        .unwrap();
+    assert_eq!(proof.len(), 256 + 2 * 32);
+    assert_eq!(&proof[256..288], &keypair[32..]);
+    assert!(rln.verify_withdrawal_proof(proof.as_slice()).unwrap());
+    proof[288] ^= 1;
+    assert!(!rln.verify_withdrawal_proof(proof.as_slice()).unwrap());
+
+    rln.delete_member(index).unwrap();
+    let err = rln
+        .generate_withdrawal_proof(withdrawal_input(index).as_slice(), &mut Vec::new())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,209 @@
+//! Message allowances over a sliding window of epochs.
+//!
+//! The circuit limits a member to one share per external nullifier, `RateLimiter`
+//! adds a policy on top such as 3 messages per 10 epochs. Nullifiers of different
+//! epochs are unlinkable, so messages are counted per `sender`, a key the deployment
+//! links members by, e.g. a peer id or the nullifier of a window wide external nullifier.
+//! Shares still go through a `NullifierLog`, a second share of an epoch is slashable
+//! whatever the allowance.
+
+use crate::bundle::RLNProofBundle;
+use crate::nullifier::{LogStatus, NullifierLog};
+use crate::utils::epoch_number;
+use bellman::pairing::Engine;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+
+#[derive(Clone)]
+pub enum RateDecision<E: Engine> {
+    /// within the allowance, the message is counted
+    Allow,
+    /// over the allowance of the window, the message is not counted
+    Throttle,
+    /// the same share was seen before
+    Duplicate,
+    /// a second share of the epoch, the secret of the sender is recovered
+    Slash(E::Fr),
+}
+
+impl<E: Engine> PartialEq for RateDecision<E> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (RateDecision::Allow, RateDecision::Allow) => true,
+            (RateDecision::Throttle, RateDecision::Throttle) => true,
+            (RateDecision::Duplicate, RateDecision::Duplicate) => true,
+            (RateDecision::Slash(a), RateDecision::Slash(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl<E: Engine> fmt::Debug for RateDecision<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateDecision::Allow => write!(f, "Allow"),
+            RateDecision::Throttle => write!(f, "Throttle"),
+            RateDecision::Duplicate => write!(f, "Duplicate"),
+            RateDecision::Slash(secret) => write!(f, "Slash({:?})", secret),
+        }
+    }
+}
+
+pub struct RateLimiter<E: Engine> {
+    allowance: usize,
+    window: u64,
+    log: NullifierLog<E>,
+    // epochs of counted messages of each sender, oldest first
+    messages: HashMap<Vec<u8>, VecDeque<u64>>,
+}
+
+impl<E: Engine> RateLimiter<E> {
+    /// allows `allowance` messages per sender in any `window` consecutive epochs
+    pub fn new(allowance: usize, window: u64) -> RateLimiter<E> {
+        RateLimiter {
+            allowance,
+            window: window.max(1),
+            log: NullifierLog::new(),
+            messages: HashMap::new(),
+        }
+    }
+
+    /// logs the share of `proof` and counts it against `sender`
+    /// * the proof is expected to be verified already
+    /// * epochs must be integers, see `epoch_number`
+    pub fn insert(
+        &mut self,
+        sender: &[u8],
+        proof: &RLNProofBundle<E>,
+    ) -> io::Result<RateDecision<E>> {
+        let allowance = self.allowance;
+        self.insert_with_allowance(sender, proof, allowance)
+    }
+
+    /// same as `insert` with the allowance of this sender,
+    /// e.g. one that grows with the stake a `PublicStake` proof is verified against
+    pub fn insert_with_allowance(
+        &mut self,
+        sender: &[u8],
+        proof: &RLNProofBundle<E>,
+        allowance: usize,
+    ) -> io::Result<RateDecision<E>> {
+        let epoch = epoch_number(proof.epoch()).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "epoch is not an integer")
+        })?;
+        match self.log.insert(
+            proof.epoch(),
+            proof.nullifier(),
+            proof.share_x(),
+            proof.share_y(),
+        )? {
+            LogStatus::New => {}
+            LogStatus::Duplicate => return Ok(RateDecision::Duplicate),
+            LogStatus::Breach(secret) => return Ok(RateDecision::Slash(secret)),
+        }
+
+        let window = self.window;
+        let epochs = self.messages.entry(sender.to_vec()).or_default();
+        // messages of epochs in ( epoch - window, epoch ] count, later ones stay
+        let in_window = epochs
+            .iter()
+            .filter(|e| **e <= epoch && epoch - **e < window)
+            .count();
+        if in_window >= allowance {
+            return Ok(RateDecision::Throttle);
+        }
+        epochs.push_back(epoch);
+        Ok(RateDecision::Allow)
+    }
+
+    /// forgets messages and shares that are out of the window of `current_epoch`
+    pub fn prune(&mut self, current_epoch: u64) -> io::Result<()> {
+        let window = self.window;
+        self.log
+            .prune_before((current_epoch + 1).saturating_sub(window))?;
+        for epochs in self.messages.values_mut() {
+            epochs.retain(|e| *e > current_epoch || current_epoch - *e < window);
+        }
+        self.messages.retain(|_, epochs| !epochs.is_empty());
+        Ok(())
+    }
+
+    /// number of senders with counted messages
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+}
+
+#[test]
+fn test_rate_limiter() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    use bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr};
+
+    let fr = |n: u64| Fr::from_str(&n.to_string()).unwrap();
+    let secret = fr(1001);
+    // shares on the line y = secret + a_1 x of the epoch, nullifier tied to the epoch
+    let message = |epoch: u64, x: u64| {
+        let a_1 = fr(epoch + 7);
+        let mut share_y = a_1;
+        share_y.mul_assign(&fr(x));
+        share_y.add_assign(&secret);
+        let mut bytes = vec![0u8; 256];
+        for value in [fr(0), fr(epoch), fr(x), share_y, fr(epoch + 100)].iter() {
+            value.into_repr().write_le(&mut bytes).unwrap();
+        }
+        RLNProofBundle::<Bn256>::parse(&bytes).unwrap()
+    };
+
+    let mut limiter = RateLimiter::<Bn256>::new(2, 3);
+    let sender = b"peer";
+    assert_eq!(
+        limiter.insert(sender, &message(1, 1)).unwrap(),
+        RateDecision::Allow
+    );
+    assert_eq!(
+        limiter.insert(sender, &message(1, 1)).unwrap(),
+        RateDecision::Duplicate
+    );
+    assert_eq!(
+        limiter.insert(sender, &message(2, 1)).unwrap(),
+        RateDecision::Allow
+    );
+    assert_eq!(
+        limiter.insert(sender, &message(3, 1)).unwrap(),
+        RateDecision::Throttle
+    );
+    assert_eq!(
+        limiter.insert(b"other", &message(5, 1)).unwrap(),
+        RateDecision::Allow
+    );
+    assert_eq!(
+        limiter
+            .insert_with_allowance(b"staked", &message(0, 1), 0)
+            .unwrap(),
+        RateDecision::Throttle
+    );
+    // epoch 1 left the window of epoch 4
+    assert_eq!(
+        limiter.insert(sender, &message(4, 1)).unwrap(),
+        RateDecision::Allow
+    );
+    assert_eq!(
+        limiter.insert(sender, &message(4, 2)).unwrap(),
+        RateDecision::Slash(secret)
+    );
+
+    limiter.prune(8).unwrap();
+    assert_eq!(limiter.len(), 0);
+    let mut wide = vec![0u8; 256];
+    for value in [Fr::one(), Fr::one(), Fr::one(), Fr::one(), Fr::one()].iter() {
+        value.into_repr().write_le(&mut wide).unwrap();
+    }
+    wide[256 + 32 + 31] = 1;
+    let wide = RLNProofBundle::<Bn256>::parse(&wide).unwrap();
+    assert!(limiter.insert(sender, &wide).is_err());
+}
@@ -0,0 +1,169 @@
+//! Membership tree synchronization from an RLN registry contract.
+//!
+//! The contract is expected to emit
+//! `MemberRegistered(uint256 commitment, uint256 index)` and
+//! `MemberRemoved(uint256 commitment, uint256 index)` with non indexed arguments,
+//! and to expose the current membership root with `root()`.
+//! Logs are applied in chain order through `RLN::apply_membership_events`.
+
+use crate::merkle::MemberEvent;
+use crate::public::RLN;
+use crate::utils::{read_fr, read_fr_be};
+use bellman::pairing::bn256::{Bn256, Fr};
+use ethers::providers::Middleware;
+use ethers::types::{
+    transaction::eip2718::TypedTransaction, Address, BlockId, BlockNumber, Filter, Log,
+    TransactionRequest, H256, U64,
+};
+use ethers::utils::{id, keccak256};
+use std::io;
+use std::sync::Arc;
+
+pub const MEMBER_REGISTERED: &str = "MemberRegistered(uint256,uint256)";
+pub const MEMBER_REMOVED: &str = "MemberRemoved(uint256,uint256)";
+pub const ROOT: &str = "root()";
+
+pub struct Registry<M: Middleware> {
+    provider: Arc<M>,
+    address: Address,
+    synced_block: Option<u64>,
+}
+
+impl<M: Middleware> Registry<M> {
+    /// watches the registry at the address, logs are read from `deployed_block`
+    pub fn new(provider: Arc<M>, address: Address, deployed_block: u64) -> Registry<M> {
+        Registry {
+            provider,
+            address,
+            synced_block: deployed_block.checked_sub(1),
+        }
+    }
+
+    /// last block whose logs are applied to the tree
+    pub fn synced_block(&self) -> Option<u64> {
+        self.synced_block
+    }
+
+    /// applies the logs of the blocks after the last synced one up to `block`
+    /// and returns the new root of the tree
+    pub async fn sync_to_block(&mut self, rln: &RLN<Bn256>, block: u64) -> io::Result<Fr> {
+        let from = self.synced_block.map(|b| b + 1).unwrap_or(0);
+        if block < from {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "block is already synced",
+            ));
+        }
+        let filter = Filter::new()
+            .address(self.address)
+            .topic0(vec![
+                H256::from(keccak256(MEMBER_REGISTERED)),
+                H256::from(keccak256(MEMBER_REMOVED)),
+            ])
+            .from_block(from)
+            .to_block(block);
+        let mut logs = self
+            .provider
+            .get_logs(&filter)
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        logs.retain(|log| log.removed != Some(true));
+        logs.sort_by_key(|log| (log.block_number, log.log_index));
+        let events = logs
+            .iter()
+            .map(decode_event)
+            .collect::<io::Result<Vec<MemberEvent<Bn256>>>>()?;
+        let root = rln.apply_membership_events(events.into_iter())?;
+        self.synced_block = Some(block);
+        Ok(root)
+    }
+
+    /// root reported by the contract at the block
+    pub async fn onchain_root(&self, block: u64) -> io::Result<Fr> {
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(self.address)
+            .data(id(ROOT).to_vec())
+            .into();
+        let block = BlockId::Number(BlockNumber::Number(U64::from(block)));
+        let result = self
+            .provider
+            .call(&tx, Some(block))
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        read_fr_be(result.as_ref())
+    }
+
+    /// checks the root of the tree against the contract at the last synced block
+    pub async fn check_root(&self, rln: &RLN<Bn256>) -> io::Result<bool> {
+        let block = self
+            .synced_block
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "registry is not synced"))?;
+        let onchain_root = self.onchain_root(block).await?;
+        let mut root: Vec<u8> = Vec::new();
+        rln.get_root(&mut root)?;
+        Ok(read_fr::<_, Bn256>(root.as_slice(), 1)?[0] == onchain_root)
+    }
+}
+
+fn decode_event(log: &Log) -> io::Result<MemberEvent<Bn256>> {
+    if log.data.as_ref().len() != 64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected registry log data",
+        ));
+    }
+    let commitment = read_fr_be(&log.data.as_ref()[..32])?;
+    let index = &log.data.as_ref()[32..];
+    if index[..24].iter().any(|b| *b != 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "member index overflows",
+        ));
+    }
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&index[24..]);
+    let index = u64::from_be_bytes(buf);
+
+    let topic = log.topics.first().cloned().unwrap_or_default();
+    if topic == H256::from(keccak256(MEMBER_REGISTERED)) {
+        Ok(MemberEvent::Insert { index, commitment })
+    } else if topic == H256::from(keccak256(MEMBER_REMOVED)) {
+        Ok(MemberEvent::Delete { index })
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unknown registry event",
+        ))
+    }
+}
+
+#[test]
+fn test_decode_event() {
+    use crate::utils::write_fr_be;
+    use bellman::pairing::ff::PrimeField;
+    let commitment = Fr::from_str("12345").unwrap();
+    let mut data: Vec<u8> = Vec::new();
+    write_fr_be(&commitment, &mut data).unwrap();
+    data.extend_from_slice(&[0u8; 31]);
+    data.push(7);
+    let mut log = Log::default();
+    log.topics = vec![H256::from(keccak256(MEMBER_REGISTERED))];
+    log.data = data.into();
+    match decode_event(&log).unwrap() {
+        MemberEvent::Insert {
+            index,
+            commitment: c,
+        } => {
+            assert_eq!(index, 7);
+            assert_eq!(c, commitment);
+        }
+        _ => panic!("expected an insert"),
+    }
+    log.topics = vec![H256::from(keccak256(MEMBER_REMOVED))];
+    match decode_event(&log).unwrap() {
+        MemberEvent::Delete { index } => assert_eq!(index, 7),
+        _ => panic!("expected a delete"),
+    }
+    log.topics = vec![];
+    assert!(decode_event(&log).is_err());
+}
@@ -0,0 +1,221 @@
+//! `#[serde(with = "...")]` helpers for field elements and merkle paths.
+//!
+//! Field elements are encoded as 0x prefixed big endian hex strings for human readable
+//! formats (see `utils::fr_to_hex`) and as the 32 little endian bytes `write_le`
+//! produces otherwise.
+
+use crate::utils::{fr_from_hex, fr_to_hex};
+use sapling_crypto::bellman::pairing::ff::{PrimeField, PrimeFieldRepr};
+use serde::de::Error as DeError;
+use serde::ser::Error as SerError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+struct Ser<'a, F: PrimeField>(&'a F);
+
+struct SerVec<'a, F: PrimeField>(&'a [F]);
+
+struct De<F: PrimeField>(F);
+
+impl<'a, F: PrimeField> Serialize for Ser<'a, F> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        fr::serialize(self.0, serializer)
+    }
+}
+
+impl<'a, F: PrimeField> Serialize for SerVec<'a, F> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().map(Ser))
+    }
+}
+
+impl<'de, F: PrimeField> Deserialize<'de> for De<F> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        fr::deserialize(deserializer).map(De)
+    }
+}
+
+pub mod fr {
+    use super::*;
+
+    pub fn serialize<S: Serializer, F: PrimeField>(
+        value: &F,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            return serializer.serialize_str(&fr_to_hex(value));
+        }
+        let mut buf: Vec<u8> = Vec::new();
+        value
+            .into_repr()
+            .write_le(&mut buf)
+            .map_err(S::Error::custom)?;
+        buf.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, F: PrimeField>(
+        deserializer: D,
+    ) -> Result<F, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            return fr_from_hex(&s).map_err(D::Error::custom);
+        }
+        let buf = Vec::<u8>::deserialize(deserializer)?;
+        let mut repr = F::Repr::default();
+        if buf.len() != repr.as_ref().len() * 8 {
+            return Err(D::Error::custom("invalid field element length"));
+        }
+        repr.read_le(&buf[..]).map_err(D::Error::custom)?;
+        F::from_repr(repr).map_err(D::Error::custom)
+    }
+}
+
+pub mod fr_option {
+    use super::*;
+
+    pub fn serialize<S: Serializer, F: PrimeField>(
+        value: &Option<F>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value.as_ref().map(Ser).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, F: PrimeField>(
+        deserializer: D,
+    ) -> Result<Option<F>, D::Error> {
+        Ok(Option::<De<F>>::deserialize(deserializer)?.map(|e| e.0))
+    }
+}
+
+pub mod fr_vec {
+    use super::*;
+
+    pub fn serialize<S: Serializer, F: PrimeField>(
+        value: &[F],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        SerVec(value).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, F: PrimeField>(
+        deserializer: D,
+    ) -> Result<Vec<F>, D::Error> {
+        Ok(Vec::<De<F>>::deserialize(deserializer)?
+            .into_iter()
+            .map(|e| e.0)
+            .collect())
+    }
+}
+
+/// binary merkle witness as returned by `MerkleTree::get_witness`
+pub mod witness {
+    use super::*;
+
+    pub fn serialize<S: Serializer, F: PrimeField>(
+        value: &[(F, bool)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(value.iter().map(|(node, right)| (Ser(node), *right)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, F: PrimeField>(
+        deserializer: D,
+    ) -> Result<Vec<(F, bool)>, D::Error> {
+        Ok(Vec::<(De<F>, bool)>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(node, right)| (node.0, right))
+            .collect())
+    }
+}
+
+/// circuit authentication path of a binary tree
+pub mod auth_path {
+    use super::*;
+
+    pub fn serialize<S: Serializer, F: PrimeField>(
+        value: &[Option<(F, bool)>],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            value
+                .iter()
+                .map(|e| e.as_ref().map(|(node, right)| (Ser(node), *right))),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, F: PrimeField>(
+        deserializer: D,
+    ) -> Result<Vec<Option<(F, bool)>>, D::Error> {
+        Ok(Vec::<Option<(De<F>, bool)>>::deserialize(deserializer)?
+            .into_iter()
+            .map(|e| e.map(|(node, right)| (node.0, right)))
+            .collect())
+    }
+}
+
+/// circuit authentication path of a quad tree
+pub mod quad_auth_path {
+    use super::*;
+
+    pub fn serialize<S: Serializer, F: PrimeField>(
+        value: &[Option<(Vec<F>, usize)>],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(value.iter().map(|e| {
+            e.as_ref()
+                .map(|(siblings, position)| (SerVec(&siblings[..]), *position))
+        }))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, F: PrimeField>(
+        deserializer: D,
+    ) -> Result<Vec<Option<(Vec<F>, usize)>>, D::Error> {
+        Ok(
+            Vec::<Option<(Vec<De<F>>, usize)>>::deserialize(deserializer)?
+                .into_iter()
+                .map(|e| {
+                    e.map(|(siblings, position)| {
+                        (siblings.into_iter().map(|s| s.0).collect(), position)
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::circuit::rln::RLNInputs;
+    use crate::poseidon::PoseidonParams;
+    use crate::public::RLNSignal;
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    use sapling_crypto::bellman::pairing::ff::PrimeField;
+
+    #[test]
+    fn test_serde_roundtrip() {
+        let signal = RLNSignal::<Bn256> {
+            epoch: Fr::from_str("3").unwrap(),
+            hash: Fr::from_str("5").unwrap(),
+            payload: b"rln".to_vec(),
+        };
+        let json = serde_json::to_string(&signal).unwrap();
+        let decoded: RLNSignal<Bn256> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.epoch, signal.epoch);
+        assert_eq!(decoded.hash, signal.hash);
+        assert_eq!(decoded.payload, signal.payload);
+
+        let mut inputs = RLNInputs::<Bn256>::empty(2);
+        inputs.root = Some(Fr::from_str("7").unwrap());
+        inputs.auth_path[1] = Some((Fr::from_str("9").unwrap(), true));
+        let json = serde_json::to_string(&inputs).unwrap();
+        let decoded: RLNInputs<Bn256> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.root, inputs.root);
+        assert_eq!(decoded.share_x, None);
+        assert_eq!(decoded.auth_path, inputs.auth_path);
+
+        let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+        let json = serde_json::to_string(&params).unwrap();
+        let decoded: PoseidonParams<Bn256> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.round_constant(7), params.round_constant(7));
+        assert_eq!(decoded.mds_matrix(), params.mds_matrix());
+    }
+}
@@ -0,0 +1,297 @@
+//! HTTP sidecar over a shared `RLN` instance.
+//!
+//! Request and response bodies are the raw byte layouts of the matching `RLN` methods
+//! * `POST /prove` takes `generate_proof` input and returns its output
+//! * `POST /verify` takes `verify` input and returns a single byte, 1 for a valid proof
+//! * `POST /keygen` returns `key_gen` output
+//! * `GET /root` returns the membership root in 32 bytes
+//! * `POST /members` takes a public key in 32 bytes and returns its index in 8 bytes
+//! * `DELETE /members/:index` removes the member at the index
+//!
+//! Proof generation takes member secrets and seconds of CPU, key generation and member
+//! updates change the group or hand out secrets, they need `Authorization: Bearer <token>`
+//! with the token of `Service::with_api_token` and are refused with a 403 when the service
+//! has no token.
+//! Failed requests get a 400 with the error message as the body, bodies longer than
+//! `max_signal_size` of the instance and room for the fixed fields get a 413.
+//! Requests are served by a fixed number of worker threads, see `set_workers`,
+//! the server is `tiny_http`, which builds with the toolchain of the crate.
+
+use crate::public::{DEFAULT_MAX_SIGNAL_SIZE, RLN};
+use bellman::pairing::bn256::Bn256;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+
+/// status code and body of a handled request
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+/// room for the fixed fields of `/prove` and `/verify` bodies next to the signal
+const BODY_OVERHEAD: u64 = 1024;
+
+/// worker threads of `Service::serve` unless changed with `set_workers`
+pub const DEFAULT_WORKERS: usize = 4;
+
+pub struct Service {
+    rln: Arc<RLN<Bn256>>,
+    api_token: Option<String>,
+    workers: usize,
+}
+
+impl Service {
+    /// service without a token, only `/verify` and `/root` are served
+    pub fn new(rln: Arc<RLN<Bn256>>) -> Service {
+        Service {
+            rln,
+            api_token: None,
+            workers: DEFAULT_WORKERS,
+        }
+    }
+
+    /// service that also serves proofs, key generation and member updates to holders of `api_token`
+    pub fn with_api_token(rln: Arc<RLN<Bn256>>, api_token: &str) -> Service {
+        Service {
+            rln,
+            api_token: Some(api_token.to_string()),
+            workers: DEFAULT_WORKERS,
+        }
+    }
+
+    /// sets the number of requests served at once, at least one
+    pub fn set_workers(&mut self, workers: usize) {
+        self.workers = workers.max(1);
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// longest request body accepted, `max_signal_size` of the instance or
+    /// `DEFAULT_MAX_SIGNAL_SIZE` without one, plus room for the fixed fields
+    pub fn max_body_size(&self) -> u64 {
+        self.rln
+            .max_signal_size()
+            .unwrap_or(DEFAULT_MAX_SIGNAL_SIZE)
+            .saturating_add(BODY_OVERHEAD)
+    }
+
+    /// routes a request, see the module docs for the layouts
+    /// * `authorization` is the value of the `Authorization` header if any
+    pub fn handle(
+        &self,
+        method: &str,
+        path: &str,
+        authorization: Option<&str>,
+        body: &[u8],
+    ) -> Response {
+        if body.len() as u64 > self.max_body_size() {
+            return response(413, b"request body is too large".to_vec());
+        }
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let result = match (method, &segments[..]) {
+            ("POST", ["prove"]) => match self.authorize(authorization) {
+                Ok(()) => self.prove(body),
+                Err(response) => return response,
+            },
+            ("POST", ["verify"]) => self.verify(body),
+            ("GET", ["root"]) => self.root(),
+            ("POST", ["keygen"]) => match self.authorize(authorization) {
+                Ok(()) => self.key_gen(),
+                Err(response) => return response,
+            },
+            ("POST", ["members"]) => match self.authorize(authorization) {
+                Ok(()) => self.add_member(body),
+                Err(response) => return response,
+            },
+            ("DELETE", ["members", index]) => match self.authorize(authorization) {
+                Ok(()) => self.delete_member(index),
+                Err(response) => return response,
+            },
+            _ => return response(404, b"not found".to_vec()),
+        };
+        match result {
+            Ok(body) => response(200, body),
+            Err(e) => response(400, e.to_string().into_bytes()),
+        }
+    }
+
+    /// serves requests on `workers` threads until accepting them fails
+    pub fn serve(self, addr: SocketAddr) -> io::Result<()> {
+        let server = tiny_http::Server::http(addr)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        let server = Arc::new(server);
+        let service = Arc::new(self);
+        let workers: Vec<thread::JoinHandle<io::Result<()>>> = (0..service.workers)
+            .map(|_| {
+                let server = server.clone();
+                let service = service.clone();
+                thread::spawn(move || loop {
+                    let request = server.recv()?;
+                    service.respond(request);
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker
+                .join()
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, "worker panicked"))??;
+        }
+        Ok(())
+    }
+
+    // bodies are read up to one byte past the limit, so `handle` sees oversized ones
+    fn respond(&self, mut request: tiny_http::Request) {
+        let authorization = request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv("Authorization"))
+            .map(|header| header.value.as_str().to_string());
+        let limit = self.max_body_size();
+        let declared = request.body_length().map_or(0, |length| length as u64);
+        let mut body: Vec<u8> = Vec::new();
+        let handled = if declared > limit {
+            response(413, b"request body is too large".to_vec())
+        } else {
+            match request.as_reader().take(limit + 1).read_to_end(&mut body) {
+                Ok(_) => self.handle(
+                    request.method().as_str(),
+                    request.url(),
+                    authorization.as_deref(),
+                    &body,
+                ),
+                Err(e) => response(400, e.to_string().into_bytes()),
+            }
+        };
+        let _ = request
+            .respond(tiny_http::Response::from_data(handled.body).with_status_code(handled.status));
+    }
+
+    fn authorize(&self, authorization: Option<&str>) -> Result<(), Response> {
+        let api_token = match &self.api_token {
+            Some(api_token) => api_token,
+            None => return Err(response(403, b"route is disabled".to_vec())),
+        };
+        let token = authorization.and_then(|value| value.strip_prefix("Bearer "));
+        match token {
+            Some(token) if constant_time_eq(token.as_bytes(), api_token.as_bytes()) => Ok(()),
+            _ => Err(response(401, b"unauthorized".to_vec())),
+        }
+    }
+
+    fn prove(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+        let mut proof: Vec<u8> = Vec::new();
+        self.rln.generate_proof(body, &mut proof)?;
+        Ok(proof)
+    }
+
+    fn verify(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+        let valid = self.rln.verify(body)?;
+        Ok(vec![valid as u8])
+    }
+
+    fn key_gen(&self) -> io::Result<Vec<u8>> {
+        let mut keypair: Vec<u8> = Vec::new();
+        self.rln.key_gen(&mut keypair)?;
+        Ok(keypair)
+    }
+
+    fn root(&self) -> io::Result<Vec<u8>> {
+        let mut root: Vec<u8> = Vec::new();
+        self.rln.get_root(&mut root)?;
+        Ok(root)
+    }
+
+    fn add_member(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+        let index = self.rln.update_next_member(body)?;
+        Ok(index.to_le_bytes().to_vec())
+    }
+
+    fn delete_member(&self, index: &str) -> io::Result<Vec<u8>> {
+        let index: u64 = index
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid member index"))?;
+        self.rln.delete_member(index)?;
+        Ok(vec![])
+    }
+}
+
+fn response(status: u16, body: Vec<u8>) -> Response {
+    Response { status, body }
+}
+
+// token comparison that does not stop at the first differing byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b.iter())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+#[test]
+fn test_service() {
+    use crate::merkle::TreeArity;
+
+    let rln = Arc::new(RLN::<Bn256>::new(3, TreeArity::Binary, None, None));
+    let open = Service::new(rln.clone());
+    let service = Service::with_api_token(rln.clone(), "secret token");
+    let auth = Some("Bearer secret token");
+
+    let root = service.handle("GET", "/root", None, &[]);
+    assert_eq!(root.status, 200);
+    assert_eq!(root.body.len(), 32);
+    assert_eq!(service.handle("GET", "/unknown", None, &[]).status, 404);
+    assert_eq!(service.handle("POST", "/verify", None, &[1, 2]).status, 400);
+    let oversized = vec![0u8; service.max_body_size() as usize + 1];
+    assert_eq!(
+        service.handle("POST", "/verify", None, &oversized).status,
+        413
+    );
+
+    // proofs and mutating routes need the token and are off without one
+    assert_eq!(open.handle("POST", "/prove", auth, &[]).status, 403);
+    assert_eq!(service.handle("POST", "/prove", None, &[]).status, 401);
+    assert_eq!(service.handle("POST", "/prove", auth, &[1, 2]).status, 400);
+    assert_eq!(open.handle("POST", "/keygen", auth, &[]).status, 403);
+    assert_eq!(service.handle("POST", "/keygen", None, &[]).status, 401);
+    assert_eq!(
+        service
+            .handle("POST", "/keygen", Some("Bearer wrong token!"), &[])
+            .status,
+        401
+    );
+    let keypair = service.handle("POST", "/keygen", auth, &[]);
+    assert_eq!(keypair.status, 200);
+    assert_eq!(keypair.body.len(), 64);
+
+    let public_key = &keypair.body[32..];
+    assert_eq!(
+        open.handle("POST", "/members", auth, public_key).status,
+        403
+    );
+    assert_eq!(
+        service.handle("POST", "/members", None, public_key).status,
+        401
+    );
+    let added = service.handle("POST", "/members", auth, public_key);
+    assert_eq!(added, response(200, 0u64.to_le_bytes().to_vec()));
+    assert_ne!(service.handle("GET", "/root", None, &[]).body, root.body);
+
+    assert_eq!(
+        service.handle("DELETE", "/members/0", None, &[]).status,
+        401
+    );
+    assert_eq!(
+        service.handle("DELETE", "/members/x", auth, &[]).status,
+        400
+    );
+    assert_eq!(
+        service.handle("DELETE", "/members/0", auth, &[]).status,
+        200
+    );
+}
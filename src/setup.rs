@@ -0,0 +1,295 @@
+//! Phase-2 style multi party contributions to circuit parameters.
+//!
+//! Each contribution rerandomizes `delta` of groth16 parameters with a secret `d`,
+//! scaling `delta_g1`, `delta_g2` by `d` and the `h` and `l` queries by `d^-1`,
+//! and publishes a proof of knowledge of `d`. Parameters are sound as long as one
+//! contributor destroys its `d`.
+//!
+//! Contributions only cover `delta`. `alpha`, `beta` and `tau` are fixed by
+//! whoever generated the initial parameters, so those must come from a trusted
+//! phase-1 (powers of tau) source for production deployments.
+
+use crate::utils::seeded_rng;
+use bellman::groth16::Parameters;
+use bellman::pairing::ff::{Field, PrimeField};
+use bellman::pairing::{CurveAffine, CurveProjective, EncodedPoint, Engine};
+use blake2::{Blake2b, Digest};
+use rand::{thread_rng, Rand, Rng};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+/// public record of a single contribution
+#[derive(Clone)]
+pub struct Contribution<E: Engine> {
+    pub delta_after_g1: E::G1Affine,
+    pub delta_after_g2: E::G2Affine,
+    // proof of knowledge of d, s_delta = s * d and r_delta = r * d
+    // where r is derived from the transcript
+    pub s: E::G1Affine,
+    pub s_delta: E::G1Affine,
+    pub r_delta: E::G2Affine,
+}
+
+/// ordered list of contributions applied to initial parameters
+#[derive(Clone)]
+pub struct Transcript<E: Engine> {
+    pub contributions: Vec<Contribution<E>>,
+}
+
+/// rerandomizes delta of the parameters in place,
+/// `entropy` is mixed with the os rng to derive the secret
+pub fn contribute<E: Engine>(params: &mut Parameters<E>, entropy: &[u8]) -> Contribution<E> {
+    let mut hasher = Blake2b::new();
+    hasher.input(entropy);
+    let os_entropy: [u8; 32] = thread_rng().gen();
+    hasher.input(&os_entropy[..]);
+    let mut rng = seeded_rng(&hasher.result()[..]);
+
+    let d = E::Fr::rand(&mut rng);
+    let d_inv = d.inverse().expect("d is non zero");
+
+    let delta_before_g1 = params.vk.delta_g1;
+    let s = E::G1::rand(&mut rng).into_affine();
+    let s_delta = s.mul(d).into_affine();
+    let r = transcript_g2::<E>(&delta_before_g1, &s, &s_delta);
+    let r_delta = r.mul(d).into_affine();
+
+    params.vk.delta_g1 = params.vk.delta_g1.mul(d).into_affine();
+    params.vk.delta_g2 = params.vk.delta_g2.mul(d).into_affine();
+    params.h = Arc::new(scale(&params.h, d_inv));
+    params.l = Arc::new(scale(&params.l, d_inv));
+
+    Contribution {
+        delta_after_g1: params.vk.delta_g1,
+        delta_after_g2: params.vk.delta_g2,
+        s,
+        s_delta,
+        r_delta,
+    }
+}
+
+/// checks that `after` is `before` with a single valid contribution applied
+pub fn verify_contribution<E: Engine>(
+    before: &Parameters<E>,
+    after: &Parameters<E>,
+    contribution: &Contribution<E>,
+) -> bool {
+    if !unchanged_queries(before, after) {
+        return false;
+    }
+    if contribution.delta_after_g1 != after.vk.delta_g1
+        || contribution.delta_after_g2 != after.vk.delta_g2
+    {
+        return false;
+    }
+    if !verify_delta_step(&before.vk.delta_g1, contribution) {
+        return false;
+    }
+    scaled_queries(before, after)
+}
+
+/// checks that `after` is `before` with every contribution of the transcript applied in order
+pub fn verify_transcript<E: Engine>(
+    before: &Parameters<E>,
+    after: &Parameters<E>,
+    transcript: &Transcript<E>,
+) -> bool {
+    if !unchanged_queries(before, after) {
+        return false;
+    }
+    let mut delta_g1 = before.vk.delta_g1;
+    for contribution in transcript.contributions.iter() {
+        if !verify_delta_step(&delta_g1, contribution) {
+            return false;
+        }
+        delta_g1 = contribution.delta_after_g1;
+    }
+    let last = match transcript.contributions.last() {
+        Some(last) => last,
+        None => return false,
+    };
+    if last.delta_after_g1 != after.vk.delta_g1 || last.delta_after_g2 != after.vk.delta_g2 {
+        return false;
+    }
+    scaled_queries(before, after)
+}
+
+impl<E: Engine> Contribution<E> {
+    /// * serialized as [ delta_after_g1<64> | delta_after_g2<128> | s<64> | s_delta<64> | r_delta<128> ] for bn256
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.delta_after_g1.into_uncompressed().as_ref())?;
+        writer.write_all(self.delta_after_g2.into_uncompressed().as_ref())?;
+        writer.write_all(self.s.into_uncompressed().as_ref())?;
+        writer.write_all(self.s_delta.into_uncompressed().as_ref())?;
+        writer.write_all(self.r_delta.into_uncompressed().as_ref())?;
+        Ok(())
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Contribution<E>> {
+        Ok(Contribution {
+            delta_after_g1: read_point::<_, E::G1Affine>(&mut reader)?,
+            delta_after_g2: read_point::<_, E::G2Affine>(&mut reader)?,
+            s: read_point::<_, E::G1Affine>(&mut reader)?,
+            s_delta: read_point::<_, E::G1Affine>(&mut reader)?,
+            r_delta: read_point::<_, E::G2Affine>(&mut reader)?,
+        })
+    }
+}
+
+impl<E: Engine> Default for Transcript<E> {
+    fn default() -> Self {
+        Transcript::new()
+    }
+}
+
+impl<E: Engine> Transcript<E> {
+    pub fn new() -> Transcript<E> {
+        Transcript {
+            contributions: vec![],
+        }
+    }
+
+    /// * serialized as [ n<4> | contribution * n ]
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&(self.contributions.len() as u32).to_le_bytes())?;
+        for contribution in self.contributions.iter() {
+            contribution.write(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Transcript<E>> {
+        let mut n = [0u8; 4];
+        reader.read_exact(&mut n)?;
+        let n = u32::from_le_bytes(n);
+        let mut contributions = Vec::new();
+        for _ in 0..n {
+            contributions.push(Contribution::read(&mut reader)?);
+        }
+        Ok(Transcript { contributions })
+    }
+}
+
+// r is bound to the state before the contribution and to s, s_delta
+fn transcript_g2<E: Engine>(
+    delta_before_g1: &E::G1Affine,
+    s: &E::G1Affine,
+    s_delta: &E::G1Affine,
+) -> E::G2Affine {
+    let mut hasher = Blake2b::new();
+    hasher.input(delta_before_g1.into_uncompressed().as_ref());
+    hasher.input(s.into_uncompressed().as_ref());
+    hasher.input(s_delta.into_uncompressed().as_ref());
+    let mut rng = seeded_rng(&hasher.result()[..]);
+    E::G2::rand(&mut rng).into_affine()
+}
+
+fn verify_delta_step<E: Engine>(
+    delta_before_g1: &E::G1Affine,
+    contribution: &Contribution<E>,
+) -> bool {
+    if contribution.s.is_zero() || contribution.s_delta.is_zero() {
+        return false;
+    }
+    let r = transcript_g2::<E>(delta_before_g1, &contribution.s, &contribution.s_delta);
+    // proof of knowledge of d
+    same_ratio::<E>(
+        (contribution.s, contribution.s_delta),
+        (r, contribution.r_delta),
+    )
+    // delta is multiplied by the same d
+    && same_ratio::<E>(
+        (*delta_before_g1, contribution.delta_after_g1),
+        (r, contribution.r_delta),
+    )
+    // delta in g1 and g2 agree
+    && same_ratio::<E>(
+        (E::G1Affine::one(), contribution.delta_after_g1),
+        (E::G2Affine::one(), contribution.delta_after_g2),
+    )
+}
+
+fn unchanged_queries<E: Engine>(before: &Parameters<E>, after: &Parameters<E>) -> bool {
+    before.vk.alpha_g1 == after.vk.alpha_g1
+        && before.vk.beta_g1 == after.vk.beta_g1
+        && before.vk.beta_g2 == after.vk.beta_g2
+        && before.vk.gamma_g2 == after.vk.gamma_g2
+        && before.vk.ic == after.vk.ic
+        && before.a == after.a
+        && before.b_g1 == after.b_g1
+        && before.b_g2 == after.b_g2
+        && before.h.len() == after.h.len()
+        && before.l.len() == after.l.len()
+}
+
+// h and l are scaled by the inverse of the overall delta change
+fn scaled_queries<E: Engine>(before: &Parameters<E>, after: &Parameters<E>) -> bool {
+    let mut rng = thread_rng();
+    let (h_before, h_after) = merge_pairs::<E, _>(&before.h, &after.h, &mut rng);
+    let (l_before, l_after) = merge_pairs::<E, _>(&before.l, &after.l, &mut rng);
+    same_ratio::<E>((h_before, h_after), (after.vk.delta_g2, before.vk.delta_g2))
+        && same_ratio::<E>((l_before, l_after), (after.vk.delta_g2, before.vk.delta_g2))
+}
+
+// e(g1.0, g2.1) == e(g1.1, g2.0)
+fn same_ratio<E: Engine>(g1: (E::G1Affine, E::G1Affine), g2: (E::G2Affine, E::G2Affine)) -> bool {
+    E::pairing(g1.0, g2.1) == E::pairing(g1.1, g2.0)
+}
+
+// random linear combination of both vectors with the same coefficients
+fn merge_pairs<E: Engine, R: Rng>(
+    v1: &[E::G1Affine],
+    v2: &[E::G1Affine],
+    rng: &mut R,
+) -> (E::G1Affine, E::G1Affine) {
+    let mut s1 = E::G1::zero();
+    let mut s2 = E::G1::zero();
+    for (p1, p2) in v1.iter().zip(v2.iter()) {
+        let rho = E::Fr::rand(rng);
+        s1.add_assign(&p1.mul(rho));
+        s2.add_assign(&p2.mul(rho));
+    }
+    (s1.into_affine(), s2.into_affine())
+}
+
+fn scale<G: CurveAffine>(points: &[G], by: G::Scalar) -> Vec<G> {
+    points.iter().map(|p| p.mul(by).into_affine()).collect()
+}
+
+fn read_point<R: Read, G: CurveAffine>(mut reader: R) -> io::Result<G> {
+    let mut repr = G::Uncompressed::empty();
+    reader.read_exact(repr.as_mut())?;
+    repr.into_affine()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[test]
+fn test_contributions() {
+    use crate::circuit::bench;
+    use crate::poseidon::PoseidonParams;
+    use bellman::pairing::bn256::Bn256;
+
+    let poseidon_params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let rln_test = bench::RLNTest::<Bn256>::new(3, Some(poseidon_params));
+    let mut raw_params: Vec<u8> = Vec::new();
+    rln_test.export_circuit_parameters(&mut raw_params).unwrap();
+    let initial = Parameters::<Bn256>::read(raw_params.as_slice(), false).unwrap();
+
+    let mut params = Parameters::<Bn256>::read(raw_params.as_slice(), false).unwrap();
+    let mut transcript = Transcript::<Bn256>::new();
+    for entropy in [&b"alice"[..], &b"bob"[..]].iter() {
+        let mut raw_before: Vec<u8> = Vec::new();
+        params.write(&mut raw_before).unwrap();
+        let before = Parameters::<Bn256>::read(raw_before.as_slice(), false).unwrap();
+        let contribution = contribute(&mut params, entropy);
+        assert!(verify_contribution(&before, &params, &contribution));
+        assert!(!verify_contribution(&initial, &initial, &contribution));
+        transcript.contributions.push(contribution);
+    }
+
+    let mut raw_transcript: Vec<u8> = Vec::new();
+    transcript.write(&mut raw_transcript).unwrap();
+    let transcript = Transcript::<Bn256>::read(raw_transcript.as_slice()).unwrap();
+    assert!(verify_transcript(&initial, &params, &transcript));
+    assert!(initial.vk.delta_g1 != params.vk.delta_g1);
+}
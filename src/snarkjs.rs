@@ -0,0 +1,260 @@
+//! Verification of groth16 proofs exported by snarkjs.
+//!
+//! Keys, proofs and public signals are the JSON files of `snarkjs zkey export verificationkey`
+//! and `snarkjs groth16 prove`, numbers in decimal strings and points in affine coordinates.
+//! Public signals keep the order of the circom circuit, `PublicInputLayout::circom().to_native`
+//! maps RLN signals to [ root, epoch, share_x, share_y, nullifier, rln_identifier ].
+//! Tests cover proofs of the native circuit in the snarkjs layout, output of the
+//! circom RLN circuit has not been checked against this module yet.
+
+use crate::verifier_core::checked_point;
+use bellman::groth16::{prepare_verifying_key, verify_proof, Proof, VerifyingKey};
+use bellman::pairing::bn256::{Bn256, Fq, Fq2, Fr, G1Affine, G2Affine};
+use bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr};
+use bellman::pairing::CurveAffine;
+use num_bigint::BigUint;
+use serde_json::Value;
+use std::io;
+
+/// verifies a snarkjs groth16 proof against its verification key and public signals
+pub fn verify_external(vk_json: &str, proof_json: &str, public_json: &str) -> io::Result<bool> {
+    let vk = read_verifying_key_json(vk_json)?;
+    let proof = read_proof_json(proof_json)?;
+    let public_signals = read_public_signals_json(public_json)?;
+    if public_signals.len() + 1 != vk.ic.len() {
+        return Err(invalid_data(
+            "public signals do not match the verification key",
+        ));
+    }
+    let vk = prepare_verifying_key(&vk);
+    verify_proof(&vk, &proof, &public_signals)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// reads `verification_key.json`
+/// * beta and delta in G1 are not exported by snarkjs, they are left as the point at infinity
+/// and the key only serves verification
+pub fn read_verifying_key_json(vk_json: &str) -> io::Result<VerifyingKey<Bn256>> {
+    let vk = parse(vk_json)?;
+    check_groth16_bn128(&vk)?;
+    let ic = field(&vk, "IC")?
+        .as_array()
+        .ok_or_else(|| invalid_data("IC is not an array"))?
+        .iter()
+        .map(g1)
+        .collect::<io::Result<Vec<G1Affine>>>()?;
+    if let Some(n_public) = vk.get("nPublic").and_then(Value::as_u64) {
+        if n_public as usize + 1 != ic.len() {
+            return Err(invalid_data("nPublic does not match IC"));
+        }
+    }
+    Ok(VerifyingKey {
+        alpha_g1: g1(field(&vk, "vk_alpha_1")?)?,
+        beta_g1: G1Affine::zero(),
+        beta_g2: g2(field(&vk, "vk_beta_2")?)?,
+        gamma_g2: g2(field(&vk, "vk_gamma_2")?)?,
+        delta_g1: G1Affine::zero(),
+        delta_g2: g2(field(&vk, "vk_delta_2")?)?,
+        ic,
+    })
+}
+
+/// reads `proof.json`
+/// * points at infinity and points outside the prime order subgroup are rejected,
+/// see `verifier_core::checked_point`
+pub fn read_proof_json(proof_json: &str) -> io::Result<Proof<Bn256>> {
+    let proof = parse(proof_json)?;
+    check_groth16_bn128(&proof)?;
+    Ok(Proof {
+        a: checked_point(g1(field(&proof, "pi_a")?)?)?,
+        b: checked_point(g2(field(&proof, "pi_b")?)?)?,
+        c: checked_point(g1(field(&proof, "pi_c")?)?)?,
+    })
+}
+
+/// reads `public.json`, the public signals in the order of the circuit
+pub fn read_public_signals_json(public_json: &str) -> io::Result<Vec<Fr>> {
+    parse(public_json)?
+        .as_array()
+        .ok_or_else(|| invalid_data("public signals are not an array"))?
+        .iter()
+        .map(decimal::<Fr>)
+        .collect()
+}
+
+fn parse(json: &str) -> io::Result<Value> {
+    serde_json::from_str(json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> io::Result<&'a Value> {
+    value
+        .get(name)
+        .ok_or_else(|| invalid_data(&format!("missing {}", name)))
+}
+
+// fields are optional in snarkjs output, other protocols and curves are rejected when given
+fn check_groth16_bn128(value: &Value) -> io::Result<()> {
+    if let Some(protocol) = value.get("protocol").and_then(Value::as_str) {
+        if protocol != "groth16" {
+            return Err(invalid_data("not a groth16 proof"));
+        }
+    }
+    if let Some(curve) = value.get("curve").and_then(Value::as_str) {
+        if curve != "bn128" {
+            return Err(invalid_data("not a bn128 proof"));
+        }
+    }
+    Ok(())
+}
+
+// canonical decimal, values above the modulus are rejected rather than reduced
+fn decimal<F: PrimeField>(value: &Value) -> io::Result<F> {
+    let digits = value
+        .as_str()
+        .ok_or_else(|| invalid_data("number is not a string"))?;
+    let n = BigUint::parse_bytes(digits.as_bytes(), 10)
+        .ok_or_else(|| invalid_data("number is not decimal"))?;
+    let mut bytes = n.to_bytes_le();
+    let mut repr = F::Repr::default();
+    let size = repr.as_ref().len() * 8;
+    if bytes.len() > size {
+        return Err(invalid_data("number is not a field element"));
+    }
+    bytes.resize(size, 0);
+    repr.read_le(bytes.as_slice())?;
+    F::from_repr(repr).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn coordinates(value: &Value, n: usize) -> io::Result<&Vec<Value>> {
+    match value.as_array() {
+        Some(coordinates) if coordinates.len() == n => Ok(coordinates),
+        _ => Err(invalid_data("unexpected point encoding")),
+    }
+}
+
+fn fq2(value: &Value) -> io::Result<Fq2> {
+    let c = coordinates(value, 2)?;
+    Ok(Fq2 {
+        c0: decimal(&c[0])?,
+        c1: decimal(&c[1])?,
+    })
+}
+
+// [ x, y, z ] with z either 1 or 0 for the point at infinity
+fn g1(value: &Value) -> io::Result<G1Affine> {
+    let c = coordinates(value, 3)?;
+    let z: Fq = decimal(&c[2])?;
+    if z.is_zero() {
+        return Ok(G1Affine::zero());
+    }
+    if z != Fq::one() {
+        return Err(invalid_data("point is not affine"));
+    }
+    G1Affine::from_xy_checked(decimal(&c[0])?, decimal(&c[1])?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn g2(value: &Value) -> io::Result<G2Affine> {
+    let c = coordinates(value, 3)?;
+    let z = fq2(&c[2])?;
+    if z.is_zero() {
+        return Ok(G2Affine::zero());
+    }
+    if z != Fq2::one() {
+        return Err(invalid_data("point is not affine"));
+    }
+    G2Affine::from_xy_checked(fq2(&c[0])?, fq2(&c[1])?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::merkle::TreeArity;
+    use crate::public::RLN;
+    use crate::utils::{read_proof, ProofFormat};
+    use serde_json::json;
+
+    fn repr_decimal<R: PrimeFieldRepr>(repr: R) -> Value {
+        let mut bytes = Vec::new();
+        repr.write_le(&mut bytes).unwrap();
+        json!(BigUint::from_bytes_le(&bytes).to_string())
+    }
+
+    fn to_decimal<F: PrimeField>(f: F) -> Value {
+        repr_decimal(f.into_repr())
+    }
+
+    fn g1_json(p: G1Affine) -> Value {
+        let (x, y) = p.into_xy_unchecked();
+        json!([to_decimal(x), to_decimal(y), "1"])
+    }
+
+    fn g2_json(p: G2Affine) -> Value {
+        let (x, y) = p.into_xy_unchecked();
+        json!([
+            [to_decimal(x.c0), to_decimal(x.c1)],
+            [to_decimal(y.c0), to_decimal(y.c1)],
+            ["1", "0"]
+        ])
+    }
+
+    // proof of the native circuit in the files snarkjs exports
+    #[test]
+    fn test_verify_external() {
+        let rln_identifier = Fr::from_str("7").unwrap();
+        let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, Some(rln_identifier));
+        let id_key = Fr::from_str("1001").unwrap();
+        let input_data = crate::test_utils::register_member(&rln, id_key, Fr::one(), b"interop");
+        let mut proof_data: Vec<u8> = Vec::new();
+        rln.generate_proof(input_data.as_slice(), &mut proof_data)
+            .unwrap();
+        let mut reader = proof_data.as_slice();
+        let proof = read_proof::<_, Bn256>(&mut reader, ProofFormat::Uncompressed).unwrap();
+        let mut public_signals =
+            crate::circuit::rln::RLNInputs::<Bn256>::read_public_inputs(reader).unwrap();
+        public_signals.push(rln_identifier);
+
+        let vk = rln.circuit_parameters().vk.clone();
+        let vk_json = json!({
+            "protocol": "groth16",
+            "curve": "bn128",
+            "nPublic": vk.ic.len() - 1,
+            "vk_alpha_1": g1_json(vk.alpha_g1),
+            "vk_beta_2": g2_json(vk.beta_g2),
+            "vk_gamma_2": g2_json(vk.gamma_g2),
+            "vk_delta_2": g2_json(vk.delta_g2),
+            "IC": vk.ic.iter().map(|p| g1_json(*p)).collect::<Vec<_>>(),
+        })
+        .to_string();
+        let proof_json = json!({
+            "pi_a": g1_json(proof.a),
+            "pi_b": g2_json(proof.b),
+            "pi_c": g1_json(proof.c),
+            "protocol": "groth16",
+            "curve": "bn128",
+        })
+        .to_string();
+        let public_json = |signals: &[Fr]| {
+            Value::Array(signals.iter().map(|s| to_decimal(*s)).collect()).to_string()
+        };
+
+        assert!(verify_external(&vk_json, &proof_json, &public_json(&public_signals)).unwrap());
+        let mut tampered = public_signals.clone();
+        tampered[1] = Fr::from_str("2").unwrap();
+        assert!(!verify_external(&vk_json, &proof_json, &public_json(&tampered)).unwrap());
+        assert!(verify_external(&vk_json, &proof_json, &public_json(&tampered[..5])).is_err());
+        assert!(verify_external(&vk_json, &proof_json, "[\"1\", \"x\"]").is_err());
+        let modulus = json!([repr_decimal(Fr::char())]).to_string();
+        assert!(read_public_signals_json(&modulus).is_err());
+
+        let mut infinity: Value = serde_json::from_str(&proof_json).unwrap();
+        infinity["pi_a"][2] = json!("0");
+        let infinity = read_proof_json(&infinity.to_string()).unwrap_err();
+        assert_eq!(infinity.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,52 @@
+//! Timing of the expensive steps of proving, verifying and tree updates.
+//!
+//! With the `telemetry` feature every step runs in a `tracing` span named `rln`
+//! with the step as a field, and emits a debug event with its duration when it ends.
+//! Without the feature `step` compiles to nothing. Bellman synthesizes the circuit
+//! and runs the multiexps in one call, so they are timed as a single step.
+
+#[cfg(feature = "telemetry")]
+use std::time::Instant;
+
+pub(crate) const PARAMETER_LOAD: &str = "parameter load";
+pub(crate) const WITNESS_BUILD: &str = "witness build";
+pub(crate) const SYNTHESIS_AND_MULTIEXP: &str = "synthesis and multiexp";
+pub(crate) const PAIRING_CHECK: &str = "pairing check";
+pub(crate) const TREE_UPDATE: &str = "tree update";
+
+/// ends the step when dropped
+#[cfg(feature = "telemetry")]
+pub(crate) struct Step {
+    name: &'static str,
+    start: Instant,
+    _span: tracing::span::EnteredSpan,
+}
+
+#[cfg(not(feature = "telemetry"))]
+pub(crate) struct Step;
+
+#[cfg(feature = "telemetry")]
+pub(crate) fn step(name: &'static str) -> Step {
+    Step {
+        name,
+        start: Instant::now(),
+        _span: tracing::debug_span!("rln", step = name).entered(),
+    }
+}
+
+#[cfg(not(feature = "telemetry"))]
+#[inline(always)]
+pub(crate) fn step(_name: &'static str) -> Step {
+    Step
+}
+
+#[cfg(feature = "telemetry")]
+impl Drop for Step {
+    fn drop(&mut self) {
+        tracing::debug!(
+            step = self.name,
+            elapsed_us = self.start.elapsed().as_micros() as u64,
+            "step done"
+        );
+    }
+}
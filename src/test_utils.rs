@@ -0,0 +1,218 @@
+//! Deterministic fixtures for tests of this crate and of downstream integrations.
+//!
+//! Members of fixture trees hold the secrets `fixture_secret(i)`, so trees, roots and
+//! inputs built from them are the same on every run. `golden` pins byte vectors
+//! over bn256 with the default binary poseidon parameters.
+
+use crate::circuit::rln::{external_nullifier, IdentityScheme, RLNInputs};
+use crate::merkle::{MerkleTree, TreeArity};
+use crate::poseidon::Poseidon;
+use crate::public::RLN;
+use byteorder::{LittleEndian, WriteBytesExt};
+use rand::{SeedableRng, XorShiftRng};
+use sapling_crypto::bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr};
+use sapling_crypto::bellman::pairing::Engine;
+use std::io::Write;
+
+pub const FIXTURE_SEED: [u32; 4] = [0x3dbe6258, 0x8d313d76, 0x3237db17, 0xe5bc0654];
+
+pub fn fixture_rng() -> XorShiftRng {
+    XorShiftRng::from_seed(FIXTURE_SEED)
+}
+
+/// secret of the i-th fixture member, `1001 + i`
+pub fn fixture_secret<E: Engine>(i: usize) -> E::Fr {
+    E::Fr::from_str(&format!("{}", 1001 + i)).unwrap()
+}
+
+/// tree whose first `members` leaves are public keys of fixture secrets
+pub fn fixture_tree<E: Engine>(
+    hasher: &Poseidon<E>,
+    depth: usize,
+    arity: TreeArity,
+    members: usize,
+) -> MerkleTree<E> {
+    let mut tree = MerkleTree::empty_with_arity(hasher.clone(), depth, arity);
+    for i in 0..members {
+        tree.update(i as u64, hasher.hash(vec![fixture_secret::<E>(i)]))
+            .unwrap();
+    }
+    tree
+}
+
+/// circuit inputs of the member at `index` holding `secret`,
+/// with `signal_hash` as the evaluation point
+pub fn valid_inputs<E: Engine>(
+    hasher: &Poseidon<E>,
+    tree: &MerkleTree<E>,
+    secret: E::Fr,
+    index: u64,
+    epoch: E::Fr,
+    signal_hash: E::Fr,
+    rln_identifier: E::Fr,
+) -> RLNInputs<E> {
+    let (auth_path, quad_auth_path) = match tree.arity {
+        TreeArity::Binary => (tree.get_witness(index).unwrap(), vec![]),
+        TreeArity::Quad => (vec![], tree.get_path(index).unwrap()),
+    };
+
+    let a_0 = secret;
+    let external_nullifier = external_nullifier(hasher, epoch, rln_identifier);
+    let a_1 = hasher.hash(vec![a_0, external_nullifier]);
+    let share_x = signal_hash;
+    let mut share_y = a_1;
+    share_y.mul_assign(&share_x);
+    share_y.add_assign(&a_0);
+    let nullifier = hasher.hash(vec![a_1]);
+
+    RLNInputs::<E> {
+        share_x: Some(share_x),
+        share_y: Some(share_y),
+        epoch: Some(epoch),
+        nullifier: Some(nullifier),
+        root: Some(tree.get_root()),
+        rln_identifier: Some(rln_identifier),
+        id_key: Some(secret),
+        id_nullifier: None,
+        identity_scheme: IdentityScheme::Single,
+        stake: None,
+        auth_path: auth_path.into_iter().map(Some).collect(),
+        quad_auth_path: quad_auth_path.into_iter().map(Some).collect(),
+    }
+}
+
+/// value `invalid_inputs` changes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Corruption {
+    Root,
+    Secret,
+    AuthPath,
+    ShareY,
+    Nullifier,
+}
+
+/// `inputs` with one value changed, so the circuit is no longer satisfied
+pub fn invalid_inputs<E: Engine>(inputs: &RLNInputs<E>, corruption: Corruption) -> RLNInputs<E> {
+    let mut inputs = inputs.clone();
+    let bump = |value: &mut Option<E::Fr>| {
+        if let Some(value) = value.as_mut() {
+            value.add_assign(&E::Fr::one());
+        }
+    };
+    match corruption {
+        Corruption::Root => bump(&mut inputs.root),
+        Corruption::Secret => bump(&mut inputs.id_key),
+        Corruption::ShareY => bump(&mut inputs.share_y),
+        Corruption::Nullifier => bump(&mut inputs.nullifier),
+        Corruption::AuthPath => {
+            if let Some(Some((sibling, _))) = inputs.auth_path.first_mut() {
+                sibling.add_assign(&E::Fr::one());
+            } else if let Some(Some((siblings, _))) = inputs.quad_auth_path.first_mut() {
+                siblings[0].add_assign(&E::Fr::one());
+            }
+        }
+    }
+    inputs
+}
+
+/// `generate_proof` input
+/// * [ id_key<32> | id_index<8> | epoch<32> | signal_len<8> | signal<var> ]
+pub fn proof_input<E: Engine>(id_key: E::Fr, index: u64, epoch: E::Fr, signal: &[u8]) -> Vec<u8> {
+    let mut input_data: Vec<u8> = Vec::new();
+    id_key.into_repr().write_le(&mut input_data).unwrap();
+    input_data.write_u64::<LittleEndian>(index).unwrap();
+    epoch.into_repr().write_le(&mut input_data).unwrap();
+    input_data
+        .write_u64::<LittleEndian>(signal.len() as u64)
+        .unwrap();
+    input_data.write_all(signal).unwrap();
+    input_data
+}
+
+/// adds the member holding `id_key` to the tree of `rln` and returns its `proof_input`
+pub fn register_member<E: Engine>(
+    rln: &RLN<E>,
+    id_key: E::Fr,
+    epoch: E::Fr,
+    signal: &[u8],
+) -> Vec<u8> {
+    let mut member: Vec<u8> = Vec::new();
+    rln.commitment(id_key)
+        .unwrap()
+        .into_repr()
+        .write_le(&mut member)
+        .unwrap();
+    let index = rln.update_next_member(member.as_slice()).unwrap();
+    proof_input::<E>(id_key, index, epoch, signal)
+}
+
+/// hex encoded, field elements in 32 little endian bytes
+pub mod golden {
+    /// public key of `fixture_secret(0)`
+    pub const PUBLIC_KEY_0: &str =
+        "a32df089eb9e3e385eec04ef36b557f4c412679bc441ccc03327409cf85b3417";
+
+    /// root of the binary `fixture_tree` of depth 3 with 4 members
+    pub const ROOT_DEPTH_3: &str =
+        "9ac3cb996a52de506548e3c849e9aaddf8d7c3e02f0c7fe331e17822054a8f0e";
+
+    /// `proof_input(fixture_secret(0), 0, 1, b"rln")`
+    pub const PROOF_INPUT: &str = concat!(
+        "e903000000000000000000000000000000000000000000000000000000000000",
+        "0000000000000000",
+        "0100000000000000000000000000000000000000000000000000000000000000",
+        "0300000000000000",
+        "726c6e",
+    );
+}
+
+#[test]
+fn test_fixtures() {
+    use crate::circuit::poseidon::PoseidonCircuit;
+    use crate::circuit::rln::RLNCircuit;
+    use crate::poseidon::PoseidonParams;
+    use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+    use sapling_crypto::bellman::Circuit;
+    use sapling_crypto::circuit::test::TestConstraintSystem;
+
+    let params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
+    let hasher = Poseidon::new(params.clone());
+    let tree = fixture_tree(&hasher, 3, TreeArity::Binary, 4);
+
+    let mut public_key: Vec<u8> = Vec::new();
+    hasher
+        .hash(vec![fixture_secret::<Bn256>(0)])
+        .into_repr()
+        .write_le(&mut public_key)
+        .unwrap();
+    assert_eq!(hex::encode(public_key), golden::PUBLIC_KEY_0);
+    let mut root: Vec<u8> = Vec::new();
+    tree.get_root().into_repr().write_le(&mut root).unwrap();
+    assert_eq!(hex::encode(root), golden::ROOT_DEPTH_3);
+    let input = proof_input::<Bn256>(fixture_secret::<Bn256>(0), 0, Fr::one(), b"rln");
+    assert_eq!(hex::encode(input), golden::PROOF_INPUT);
+
+    let satisfied = |inputs: RLNInputs<Bn256>| {
+        let mut cs = TestConstraintSystem::<Bn256>::new();
+        let circuit = RLNCircuit {
+            inputs,
+            hasher: PoseidonCircuit::from_hasher(&hasher),
+        };
+        circuit.synthesize(&mut cs).unwrap();
+        cs.is_satisfied()
+    };
+    let secret = fixture_secret::<Bn256>(2);
+    let inputs = valid_inputs(&hasher, &tree, secret, 2, Fr::one(), Fr::one(), Fr::zero());
+    assert!(satisfied(inputs.clone()));
+    for corruption in [
+        Corruption::Root,
+        Corruption::Secret,
+        Corruption::AuthPath,
+        Corruption::ShareY,
+        Corruption::Nullifier,
+    ]
+    .iter()
+    {
+        assert!(!satisfied(invalid_inputs(&inputs, *corruption)));
+    }
+}
@@ -1,17 +1,99 @@
-use bellman::groth16::Proof;
+use bellman::groth16::{Parameters, Proof, VerifyingKey};
 use bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr, ScalarEngine};
-use bellman::pairing::{CurveAffine, EncodedPoint, Engine};
+use bellman::pairing::{CurveAffine, CurveProjective, EncodedPoint, Engine};
+use blake2::{Blake2b, Blake2s, Digest};
 use byteorder::{LittleEndian, ReadBytesExt};
+use rand::chacha::ChaChaRng;
+use rand::{Rand, Rng, SeedableRng};
 use std::io::{self, Error, ErrorKind, Read, Write};
 
-use crate::hash_to_field::hash_to_field;
+use crate::hash_to_field::SignalHasher;
+pub use crate::verifier_core::ProofFormat;
+use crate::verifier_core::{decode_proof, is_in_subgroup};
 
-pub fn read_signal_hash<R: Read, E: Engine>(mut reader: R) -> io::Result<E::Fr> {
+/// reads [ signal_len<8> | signal<var> ] and hashes the signal
+pub fn read_signal_hash<R: Read, E: Engine>(
+    reader: R,
+    signal_hasher: SignalHasher,
+) -> io::Result<E::Fr> {
+    read_signal_hash_with_limit::<R, E>(reader, signal_hasher, None)
+}
+
+/// streams the signal into the hasher without buffering it
+/// * signals longer than `max_signal_size` are rejected before any of it is read
+pub fn read_signal_hash_with_limit<R: Read, E: Engine>(
+    mut reader: R,
+    signal_hasher: SignalHasher,
+    max_signal_size: Option<u64>,
+) -> io::Result<E::Fr> {
     let n = reader.read_u64::<LittleEndian>()?;
-    let mut buf: Vec<u8> = vec![0; n as usize];
-    reader.read(&mut buf[..])?;
+    if let Some(max_signal_size) = max_signal_size {
+        if n > max_signal_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "signal exceeds maximum size",
+            ));
+        }
+    }
+    signal_hasher.hash_reader::<E, _>(reader, n)
+}
+
+/// writes a field element in 32 big endian bytes, as solidity and most js libraries expect
+pub fn write_fr_be<W: Write, F: PrimeField>(fr: &F, writer: W) -> io::Result<()> {
+    fr.into_repr().write_be(writer)
+}
+
+/// reads a field element from 32 big endian bytes
+pub fn read_fr_be<R: Read, F: PrimeField>(reader: R) -> io::Result<F> {
+    let mut repr = F::Repr::default();
+    repr.read_be(reader)?;
+    fr_from_repr_strict(repr)
+}
+
+/// field element of a canonical repr, encodings of the modulus and above are rejected
+/// explicitly instead of relying on `from_repr`, as they come from untrusted peers
+pub fn fr_from_repr_strict<F: PrimeField>(repr: F::Repr) -> io::Result<F> {
+    if repr >= F::char() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "non canonical field element",
+        ));
+    }
+    F::from_repr(repr).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// epoch as an integer, `None` if it does not fit 64 bits
+pub fn epoch_number<F: PrimeField>(epoch: F) -> Option<u64> {
+    let repr = epoch.into_repr();
+    let limbs = repr.as_ref();
+    if limbs[1..].iter().all(|limb| *limb == 0) {
+        Some(limbs[0])
+    } else {
+        None
+    }
+}
+
+/// 0x prefixed big endian hex of a field element, always 64 digits
+pub fn fr_to_hex<F: PrimeField>(fr: &F) -> String {
+    let mut buf: Vec<u8> = Vec::new();
+    write_fr_be(fr, &mut buf).unwrap();
+    format!("0x{}", hex::encode(buf))
+}
 
-    Ok(hash_to_field::<E>(&buf[..]))
+/// parses big endian hex with or without the 0x prefix,
+/// shorter strings are left padded with zeros
+pub fn fr_from_hex<F: PrimeField>(s: &str) -> io::Result<F> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    let size = F::Repr::default().as_ref().len() * 16;
+    if digits.is_empty() || digits.len() > size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid field element hex length",
+        ));
+    }
+    let padded = format!("{:0>width$}", digits, width = size);
+    let buf = hex::decode(padded).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    read_fr_be(&buf[..])
 }
 
 pub fn read_fr<R: Read, E: Engine>(mut reader: R, n: usize) -> io::Result<Vec<E::Fr>> {
@@ -19,13 +101,48 @@ pub fn read_fr<R: Read, E: Engine>(mut reader: R, n: usize) -> io::Result<Vec<E:
     let mut buf = <E::Fr as PrimeField>::Repr::default();
     for _ in 0..n {
         buf.read_le(&mut reader)?;
-        let input =
-            E::Fr::from_repr(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        out.push(input);
+        out.push(fr_from_repr_strict(buf)?);
     }
     Ok(out)
 }
 
+/// writes a binary merkle witness as [ (direction<1> | node<32>) * depth ],
+/// direction is 1 if the sibling is on the right
+pub fn write_witness<W: Write, E: Engine>(
+    witness: &[(E::Fr, bool)],
+    mut writer: W,
+) -> io::Result<()> {
+    for (node, direction) in witness.iter() {
+        writer.write_all(&[*direction as u8])?;
+        node.into_repr().write_le(&mut writer)?;
+    }
+    Ok(())
+}
+
+pub fn read_witness<R: Read, E: Engine>(
+    mut reader: R,
+    depth: usize,
+) -> io::Result<Vec<(E::Fr, bool)>> {
+    let mut witness: Vec<(E::Fr, bool)> = Vec::with_capacity(depth);
+    let mut direction = [0u8; 1];
+    for _ in 0..depth {
+        reader.read_exact(&mut direction)?;
+        let direction = match direction[0] {
+            0u8 => false,
+            1u8 => true,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid path direction",
+                ))
+            }
+        };
+        let node = read_fr::<_, E>(&mut reader, 1)?[0];
+        witness.push((node, direction));
+    }
+    Ok(witness)
+}
+
 pub fn write_uncompressed_proof<W: Write, E: Engine>(
     proof: Proof<E>,
     mut writer: W,
@@ -36,54 +153,188 @@ pub fn write_uncompressed_proof<W: Write, E: Engine>(
     Ok(())
 }
 
-pub fn read_uncompressed_proof<R: Read, E: Engine>(mut reader: R) -> io::Result<Proof<E>> {
-    let mut g1_repr = <E::G1Affine as CurveAffine>::Uncompressed::empty();
-    let mut g2_repr = <E::G2Affine as CurveAffine>::Uncompressed::empty();
+pub fn read_uncompressed_proof<R: Read, E: Engine>(reader: R) -> io::Result<Proof<E>> {
+    read_proof(reader, ProofFormat::Uncompressed)
+}
 
-    reader.read_exact(g1_repr.as_mut())?;
-    let a = g1_repr
-        .into_affine()
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-        .and_then(|e| {
-            if e.is_zero() {
-                Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "point at infinity",
-                ))
-            } else {
-                Ok(e)
-            }
-        })?;
-
-    reader.read_exact(g2_repr.as_mut())?;
-    let b = g2_repr
-        .into_affine()
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-        .and_then(|e| {
-            if e.is_zero() {
-                Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "point at infinity",
-                ))
-            } else {
-                Ok(e)
-            }
-        })?;
-
-    reader.read_exact(g1_repr.as_mut())?;
-    let c = g1_repr
-        .into_affine()
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-        .and_then(|e| {
-            if e.is_zero() {
-                Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "point at infinity",
-                ))
-            } else {
-                Ok(e)
-            }
-        })?;
+pub fn write_compressed_proof<W: Write, E: Engine>(
+    proof: Proof<E>,
+    mut writer: W,
+) -> io::Result<()> {
+    writer.write_all(proof.a.into_compressed().as_ref())?;
+    writer.write_all(proof.b.into_compressed().as_ref())?;
+    writer.write_all(proof.c.into_compressed().as_ref())?;
+    Ok(())
+}
+
+pub fn read_compressed_proof<R: Read, E: Engine>(reader: R) -> io::Result<Proof<E>> {
+    read_proof(reader, ProofFormat::Compressed)
+}
+
+pub fn write_proof<W: Write, E: Engine>(
+    proof: Proof<E>,
+    format: ProofFormat,
+    writer: W,
+) -> io::Result<()> {
+    match format {
+        ProofFormat::Uncompressed => write_uncompressed_proof(proof, writer),
+        ProofFormat::Compressed => write_compressed_proof(proof, writer),
+    }
+}
+
+/// reads a proof of `format`, points are decoded by `verifier_core::decode_proof`
+pub fn read_proof<R: Read, E: Engine>(mut reader: R, format: ProofFormat) -> io::Result<Proof<E>> {
+    let mut data = vec![0u8; format.proof_size::<E>()];
+    reader.read_exact(&mut data)?;
+    let (proof, _) = decode_proof::<E>(&data, format)?;
+    Ok(proof)
+}
+
+/// rerandomizes a groth16 proof so it is unlinkable to the original,
+/// the result verifies against the same public inputs
+/// * A' = A / r1, B' = r1 * B + r1 * r2 * delta, C' = C + r2 * A
+pub fn rerandomize_proof<E: Engine, R: Rng>(
+    vk: &VerifyingKey<E>,
+    proof: &Proof<E>,
+    rng: &mut R,
+) -> Proof<E> {
+    let r1 = E::Fr::rand(rng);
+    let r2 = E::Fr::rand(rng);
+    let r1_inv = r1.inverse().expect("r1 is non zero");
+    let mut r1_r2 = r1;
+    r1_r2.mul_assign(&r2);
+
+    let a = proof.a.mul(r1_inv);
+    let mut b = proof.b.mul(r1);
+    b.add_assign(&vk.delta_g2.mul(r1_r2));
+    let mut c = proof.c.into_projective();
+    c.add_assign(&proof.a.mul(r2));
+
+    Proof {
+        a: a.into_affine(),
+        b: b.into_affine(),
+        c: c.into_affine(),
+    }
+}
+
+/// deterministic rng seeded with arbitrary bytes
+/// * the seed is hashed with blake2s into the 8 words chacha keys with,
+/// so every byte of it counts, trailing zeros included
+pub fn seeded_rng(seed: &[u8]) -> ChaChaRng {
+    let digest = Blake2s::digest(seed);
+    let key: Vec<u32> = digest
+        .chunks(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect();
+    ChaChaRng::from_seed(&key[..])
+}
+
+/// blake2b digest of the canonical serialization of circuit parameters
+pub fn parameters_fingerprint<E: Engine>(params: &Parameters<E>) -> io::Result<Vec<u8>> {
+    let mut writer = DigestWriter(Blake2b::new());
+    params.write(&mut writer)?;
+    Ok(writer.0.result().to_vec())
+}
+
+struct DigestWriter<D: Digest>(D);
+
+impl<D: Digest> Write for DigestWriter<D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.input(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_fr_hex() {
+    use bellman::pairing::bn256::Fr;
+    let one = Fr::one();
+    assert_eq!(
+        fr_to_hex(&one),
+        "0x0000000000000000000000000000000000000000000000000000000000000001"
+    );
+    assert_eq!(fr_from_hex::<Fr>("0x1").unwrap(), one);
+    assert_eq!(fr_from_hex::<Fr>("01").unwrap(), one);
+
+    let fr = Fr::from_str("1234567890123456789").unwrap();
+    assert_eq!(fr_from_hex::<Fr>(&fr_to_hex(&fr)).unwrap(), fr);
+    let mut be: Vec<u8> = Vec::new();
+    write_fr_be(&fr, &mut be).unwrap();
+    let mut le: Vec<u8> = Vec::new();
+    fr.into_repr().write_le(&mut le).unwrap();
+    le.reverse();
+    assert_eq!(be, le);
+    assert_eq!(read_fr_be::<_, Fr>(&be[..]).unwrap(), fr);
+
+    // modulus is not canonical
+    assert!(fr_from_hex::<Fr>(
+        "0x30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000001"
+    )
+    .is_err());
+    assert!(fr_from_hex::<Fr>("0xzz").is_err());
+}
+
+#[test]
+fn test_proof_serialization() {
+    use bellman::pairing::bn256::{Bn256, G1Affine, G2Affine};
+    let proof = Proof::<Bn256> {
+        a: G1Affine::one(),
+        b: G2Affine::one(),
+        c: G1Affine::one(),
+    };
+    for format in [ProofFormat::Uncompressed, ProofFormat::Compressed].iter() {
+        let mut buf: Vec<u8> = Vec::new();
+        write_proof(proof.clone(), *format, &mut buf).unwrap();
+        assert_eq!(buf.len(), format.proof_size::<Bn256>());
+        let decoded = read_proof::<_, Bn256>(buf.as_slice(), *format).unwrap();
+        assert!(decoded == proof);
+    }
+}
+
+#[test]
+fn test_strict_decoding() {
+    use bellman::pairing::bn256::{Bn256, Fr, G1Affine, G2Affine};
+    let modulus = Fr::char();
+    let mut raw: Vec<u8> = Vec::new();
+    modulus.write_le(&mut raw).unwrap();
+    assert!(read_fr::<_, Bn256>(raw.as_slice(), 1).is_err());
+    let mut below = modulus;
+    below.sub_noborrow(&<Fr as PrimeField>::Repr::from(1));
+    raw.clear();
+    below.write_le(&mut raw).unwrap();
+    let mut minus_one = Fr::one();
+    minus_one.negate();
+    assert_eq!(
+        read_fr::<_, Bn256>(raw.as_slice(), 1).unwrap()[0],
+        minus_one
+    );
+    raw.clear();
+    modulus.write_be(&mut raw).unwrap();
+    assert!(read_fr_be::<_, Fr>(raw.as_slice()).is_err());
+
+    assert!(is_in_subgroup(&G1Affine::one()));
+    assert!(is_in_subgroup(&G2Affine::one()));
+    let mut raw: Vec<u8> = Vec::new();
+    raw.extend_from_slice(G1Affine::zero().into_uncompressed().as_ref());
+    raw.extend_from_slice(G2Affine::one().into_uncompressed().as_ref());
+    raw.extend_from_slice(G1Affine::one().into_uncompressed().as_ref());
+    assert!(read_uncompressed_proof::<_, Bn256>(raw.as_slice()).is_err());
+}
 
-    Ok(Proof { a, b, c })
+#[test]
+fn test_seeded_rng() {
+    let sample = |seed: &[u8]| -> Vec<u32> {
+        let mut rng = seeded_rng(seed);
+        (0..4).map(|_| rng.gen()).collect()
+    };
+    assert_eq!(sample(b"seed"), sample(b"seed"));
+    assert_ne!(sample(b"a"), sample(b"a\0"));
+    let long = [7u8; 40];
+    let mut longer = long;
+    longer[39] = 8;
+    assert_ne!(sample(&long), sample(&longer));
 }
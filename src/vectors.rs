@@ -0,0 +1,393 @@
+//! Canonical test vectors over bn256 for implementations in other languages.
+//!
+//! Every value is derived from fixed seeds, so `TestVectors::generate` returns the same
+//! vectors on every run and platform. Field elements are 0x prefixed big endian hex with
+//! the `serde` feature, byte strings (inputs, bundles, keys) are plain hex of the layouts
+//! `RLN` reads and writes. `examples/export_test_vectors` writes them as JSON.
+//! `golden` pins the field elements, the bundles are only checked to verify.
+
+use crate::circuit::poseidon::PoseidonCircuit;
+use crate::circuit::rln::{external_nullifier, key_gen, IdentityScheme, RLNCircuit, RLNInputs};
+use crate::hash_to_field::SignalHasher;
+use crate::merkle::{MerkleTree, TreeArity};
+use crate::poseidon::{Poseidon, PoseidonParams};
+use crate::public::RLN;
+use bellman::groth16::generate_random_parameters;
+use bellman::pairing::bn256::{Bn256, Fr};
+use bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr};
+use byteorder::{LittleEndian, WriteBytesExt};
+use rand::{Rand, SeedableRng, XorShiftRng};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+const VECTORS_SEED: [u32; 4] = [0x3dbe6258, 0x8d313d76, 0x3237db17, 0xe5bc0654];
+
+// depths of the binary trees in `TestVectors::roots`
+const ROOT_DEPTHS: [usize; 5] = [1, 2, 3, 10, 20];
+
+// depth of the circuit `TestVectors::bundles` are proven with
+const BUNDLE_DEPTH: usize = 3;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PoseidonVector {
+    pub width: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr_vec"))]
+    pub inputs: Vec<Fr>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr"))]
+    pub output: Fr,
+}
+
+/// `key_gen` output, secrets are [ id_key ] or [ trapdoor, nullifier ]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct KeyVector {
+    pub identity_scheme: IdentityScheme,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr_vec"))]
+    pub secrets: Vec<Fr>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr"))]
+    pub commitment: Fr,
+}
+
+/// root of a binary tree with the single key commitments as its first leaves
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RootVector {
+    pub depth: usize,
+    pub members: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr"))]
+    pub root: Fr,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ShareVector {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr"))]
+    pub id_key: Fr,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr"))]
+    pub epoch: Fr,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr"))]
+    pub rln_identifier: Fr,
+    /// hex of the signal bytes
+    pub signal: String,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr"))]
+    pub external_nullifier: Fr,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr"))]
+    pub a_1: Fr,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr"))]
+    pub share_x: Fr,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr"))]
+    pub share_y: Fr,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::fr"))]
+    pub nullifier: Fr,
+}
+
+/// `generate_proof_bundle` output for `input` with proof randomness from a fixed seed
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BundleVector {
+    pub depth: usize,
+    /// hex of the `generate_proof` input
+    pub input: String,
+    /// hex of the bundle
+    pub bundle: String,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TestVectors {
+    pub poseidon: Vec<PoseidonVector>,
+    pub keys: Vec<KeyVector>,
+    pub roots: Vec<RootVector>,
+    pub shares: Vec<ShareVector>,
+    pub bundles: Vec<BundleVector>,
+    /// hex of the verifying key the bundles verify with, as `export_verifier_key` writes it
+    pub verifying_key: String,
+}
+
+impl TestVectors {
+    /// derives every vector, proves a circuit of depth 3 so takes a few seconds
+    pub fn generate() -> io::Result<TestVectors> {
+        let mut rng = XorShiftRng::from_seed(VECTORS_SEED);
+        let hasher = Poseidon::<Bn256>::new(PoseidonParams::new(8, 55, 3, None, None, None));
+        let quad_hasher = Poseidon::<Bn256>::new(PoseidonParams::new(8, 60, 5, None, None, None));
+        let fr = |n: u64| Fr::from_str(&n.to_string()).unwrap();
+
+        let poseidon = vec![
+            (&hasher, vec![fr(0)]),
+            (&hasher, vec![fr(1)]),
+            (&hasher, vec![fr(1), fr(2)]),
+            (&quad_hasher, vec![fr(1), fr(2), fr(3), fr(4)]),
+        ]
+        .into_iter()
+        .map(|(hasher, inputs)| PoseidonVector {
+            width: hasher.width(),
+            output: hasher.hash(inputs.clone()),
+            inputs,
+        })
+        .collect();
+
+        let mut keys: Vec<KeyVector> = (0..4)
+            .map(|_| {
+                let (secrets, commitment) =
+                    key_gen(&hasher, IdentityScheme::Single, &mut rng).unwrap();
+                KeyVector {
+                    identity_scheme: IdentityScheme::Single,
+                    secrets,
+                    commitment,
+                }
+            })
+            .collect();
+        let (secrets, commitment) = key_gen(&hasher, IdentityScheme::Semaphore, &mut rng).unwrap();
+        keys.push(KeyVector {
+            identity_scheme: IdentityScheme::Semaphore,
+            secrets,
+            commitment,
+        });
+        let members: Vec<Fr> = keys
+            .iter()
+            .filter(|key| key.identity_scheme == IdentityScheme::Single)
+            .map(|key| key.commitment)
+            .collect();
+
+        let mut roots = Vec::new();
+        for depth in ROOT_DEPTHS.iter() {
+            let mut tree = MerkleTree::empty_with_arity(hasher.clone(), *depth, TreeArity::Binary);
+            let capacity = 1usize << depth;
+            roots.push(RootVector {
+                depth: *depth,
+                members: 0,
+                root: tree.get_root(),
+            });
+            let n = members.len().min(capacity);
+            for (i, member) in members.iter().take(n).enumerate() {
+                tree.update(i as u64, *member)?;
+            }
+            roots.push(RootVector {
+                depth: *depth,
+                members: n,
+                root: tree.get_root(),
+            });
+        }
+
+        let signal_hasher = SignalHasher::default();
+        let shares = vec![
+            (keys[0].secrets[0], fr(1), Fr::zero(), b"".to_vec()),
+            (keys[0].secrets[0], fr(1), Fr::zero(), b"rln".to_vec()),
+            (keys[0].secrets[0], fr(2), Fr::zero(), b"rln".to_vec()),
+            (
+                keys[1].secrets[0],
+                Fr::rand(&mut rng),
+                fr(7),
+                b"hello world".to_vec(),
+            ),
+        ]
+        .into_iter()
+        .map(|(id_key, epoch, rln_identifier, signal)| {
+            let external_nullifier = external_nullifier(&hasher, epoch, rln_identifier);
+            let a_1 = hasher.hash(vec![id_key, external_nullifier]);
+            let share_x = signal_hasher.hash::<Bn256>(&signal).unwrap();
+            let mut share_y = a_1;
+            share_y.mul_assign(&share_x);
+            share_y.add_assign(&id_key);
+            ShareVector {
+                id_key,
+                epoch,
+                rln_identifier,
+                signal: hex::encode(signal),
+                external_nullifier,
+                a_1,
+                share_x,
+                share_y,
+                nullifier: hasher.hash(vec![a_1]),
+            }
+        })
+        .collect();
+
+        let circuit = RLNCircuit::<Bn256> {
+            inputs: RLNInputs::empty(BUNDLE_DEPTH),
+            hasher: PoseidonCircuit::from_hasher(&hasher),
+        };
+        let circuit_parameters = generate_random_parameters(circuit, &mut rng)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let rln = RLN::<Bn256>::new_with_shared_params(
+            BUNDLE_DEPTH,
+            TreeArity::Binary,
+            Arc::new(circuit_parameters),
+            None,
+        )?;
+        for member in members.iter() {
+            let mut leaf: Vec<u8> = Vec::new();
+            member.into_repr().write_le(&mut leaf)?;
+            rln.update_next_member(leaf.as_slice())?;
+        }
+        let mut verifying_key: Vec<u8> = Vec::new();
+        rln.export_verifier_key(&mut verifying_key)?;
+
+        let mut bundles = Vec::new();
+        let bundle_inputs = [(0usize, 1u64, &b"rln"[..]), (2, 3, &b"hello world"[..])];
+        for (index, epoch, signal) in bundle_inputs.iter() {
+            let mut input: Vec<u8> = Vec::new();
+            keys[*index].secrets[0].into_repr().write_le(&mut input)?;
+            input.write_u64::<LittleEndian>(*index as u64)?;
+            fr(*epoch).into_repr().write_le(&mut input)?;
+            input.write_u64::<LittleEndian>(signal.len() as u64)?;
+            input.write_all(signal)?;
+            let mut bundle: Vec<u8> = Vec::new();
+            rln.bundle_header()?.write(&mut bundle)?;
+            rln.generate_proof_with_rng(input.as_slice(), &mut bundle, &mut rng)?;
+            bundles.push(BundleVector {
+                depth: BUNDLE_DEPTH,
+                input: hex::encode(input),
+                bundle: hex::encode(bundle),
+            });
+        }
+
+        Ok(TestVectors {
+            poseidon,
+            keys,
+            roots,
+            shares,
+            bundles,
+            verifying_key: hex::encode(verifying_key),
+        })
+    }
+}
+
+/// expected values of `TestVectors::generate`, 0x prefixed big endian hex
+pub mod golden {
+    /// outputs of `TestVectors::poseidon`
+    pub const POSEIDON: [&str; 4] = [
+        "0x2ff267fd23782a5625e6d804f0a7fa700b8dc6084e2e7a5aff7cd4b1c506d30b",
+        "0x2413e13ec5f29412ed3f7c12ffd2629dcd38098b6749081406eb9c51ca505617",
+        "0x14721c8de33f6648cdd20051a2d0942b63038bfd80b569ed87c168526ff08eed",
+        "0x184d4e8c3962e375f91bf3f85ec4c15575e86a03a5569ce0a7834d7fec98e51a",
+    ];
+
+    /// secrets of `TestVectors::keys` in order, the semaphore key has two
+    pub const KEY_SECRETS: [&str; 6] = [
+        "0x115cb18c67f80ad73bb1096cd12244cb62c2ab7ba085fb205de15e729cec51b0",
+        "0x1f89f2f4f9a9600dc91b190f5175fff0fdf8e0ab65587350ccafd4daba83fb8e",
+        "0x1059d403f5e3c6169b009902ccde9d0b46fe3fce61952123cbb6dffbf9f47f87",
+        "0x18e1f93b50815253951290000dcc47df2ab01365d9a14e1fe7ee873f3bc1f467",
+        "0x241b1f4f6f168cb8cc09442c99a1cfa12360de5debcdcc18ff865265a42e362a",
+        "0x27d114d7da32d0ac3c12a17b0b5436debf0c3f96b65a48d49e04f3ebc9376c9e",
+    ];
+
+    /// commitments of `TestVectors::keys`
+    pub const KEY_COMMITMENTS: [&str; 5] = [
+        "0x18f42eda03cc77f00eb81b797f8618f508f38f09373265b36abc3a78d52b2575",
+        "0x218b6ee1460391ecca4807179bfbea10d881e868614b3943c8cf998ba6e54688",
+        "0x0e7d3df78063d671d14424ae76f2d3c91094d2273d59ef9f97aef8beafbcfb36",
+        "0x1a2e49fc77db323c00896e8b10821a0f329c43e3640e7423a347e1733f980ce7",
+        "0x1b2dbb7e1483f7e832153b4a2021ac707582f9e46a7fa08985c24e696c6dcdb6",
+    ];
+
+    /// roots of `TestVectors::roots`, empty and filled for each depth
+    pub const ROOTS: [&str; 10] = [
+        "0x2ff267fd23782a5625e6d804f0a7fa700b8dc6084e2e7a5aff7cd4b1c506d30b",
+        "0x1ddd75c65b8e508dc82d4eaac933e1d0b5224bde60ed3551d055a24335c375a0",
+        "0x07191352b37e1257205f09eaba526667cf18cb12e2cfeb2abc38e64bc0f0112e",
+        "0x1a6d47d244345e82c2ae45e8543b80fbbe856b7bf0f02d93ace86735c2aafa40",
+        "0x13fc18037da87a772cd576e98b3e6b503fa1c5025ea003da8e7ea8bbeced270f",
+        "0x1504b919307115822520868820d0db69610d17a70e03fbec9004952c08b9b19a",
+        "0x23e32f2d130a5775c21b2b3fbab442a5eb534d6647d0879bff6248905f12144b",
+        "0x213fb4a386a38e591d8b026539227c005e85a835c26e3e4618d6b22941bc8e03",
+        "0x18ca288533ec6604899f29c01ab46cceae35d599ed4d008a5d3893f6dba01c0b",
+        "0x03f200da6eddc2d964b5f26110160c7990513e4d764d51641b00696370c8a3a4",
+    ];
+
+    /// [ epoch, external_nullifier, a_1, share_x, share_y, nullifier ] of `TestVectors::shares`
+    pub const SHARES: [[&str; 6]; 4] = [
+        [
+            "0x0000000000000000000000000000000000000000000000000000000000000001",
+            "0x2413e13ec5f29412ed3f7c12ffd2629dcd38098b6749081406eb9c51ca505617",
+            "0x1a3208437fa16dfbdad888f41fa47a4f451555f8a2ef4409459cb66d67ade11e",
+            "0x15abe376e8d27da35baf653bc8d2ab777c4c8e54ff054e3eccc4850fb99db952",
+            "0x0ede17e292d6d2ed7029a8d2b42df1888169af98cab5e817e05ac651c60d7251",
+            "0x08a7b04385902d3247bb735c38d0c919c9ba97cbbe48a4c843e2b3dd37648524",
+        ],
+        [
+            "0x0000000000000000000000000000000000000000000000000000000000000001",
+            "0x2413e13ec5f29412ed3f7c12ffd2629dcd38098b6749081406eb9c51ca505617",
+            "0x1a3208437fa16dfbdad888f41fa47a4f451555f8a2ef4409459cb66d67ade11e",
+            "0x27ba7c147faa51c2a8bd3580a00e185d284995a951d5418e7636690cea365845",
+            "0x1544990fdad299a601949287f08f029119b1377792808ee9ab0ee8a3379c04c4",
+            "0x08a7b04385902d3247bb735c38d0c919c9ba97cbbe48a4c843e2b3dd37648524",
+        ],
+        [
+            "0x0000000000000000000000000000000000000000000000000000000000000002",
+            "0x2ade16979a7914d154ed8b68259ef47eebe16f1a2ac22aeb06e6d62bd938f8ef",
+            "0x21d8af7fa82a334562d588ee856031253a0a66a95e97046bfd6b3c1987376d3c",
+            "0x27ba7c147faa51c2a8bd3580a00e185d284995a951d5418e7636690cea365845",
+            "0x2e1123062394a1423337323186d4d6b8a4689b5e2148078b19d28d01e85bee1a",
+            "0x0c22c2bafa518df9ca3257cb4cdd4a5b7f69659bdcd9aa365fc2d4b22d08ed43",
+        ],
+        [
+            "0x1d4d5534eb1be5f4a2c58e40ac8771c94ab9f8480a32c1cc89c463c7567862fa",
+            "0x06478b394ca2cf00b4c2f13555e4353f38d70dddce4a88c94c642ab48e648b0f",
+            "0x2e18103727bb5d326d6080fdc37172702b17bce768c1d7d761c7109e5fc02f02",
+            "0x22d06a92bc8ee00a87e792c121864905614a12c275540e7107d8ab3449c7b4b0",
+            "0x115370a0b36ca3b8799f90e1c4c688d4ee995f78ac3112e0c03de5b421b3cff9",
+            "0x0a1b84b0aed24b9728095e826df89d9de19d1aee3fd9ab0677492ab830ced3fe",
+        ],
+    ];
+}
+
+#[test]
+fn test_vectors() {
+    use crate::bundle::BundleHeader;
+    use crate::public::RLNVerifier;
+    use crate::utils::fr_to_hex;
+
+    let vectors = TestVectors::generate().unwrap();
+    let hex = |frs: Vec<&Fr>| frs.into_iter().map(fr_to_hex).collect::<Vec<String>>();
+    assert_eq!(
+        hex(vectors.poseidon.iter().map(|v| &v.output).collect()),
+        golden::POSEIDON
+    );
+    assert_eq!(
+        hex(vectors.keys.iter().flat_map(|k| k.secrets.iter()).collect()),
+        golden::KEY_SECRETS
+    );
+    assert_eq!(
+        hex(vectors.keys.iter().map(|k| &k.commitment).collect()),
+        golden::KEY_COMMITMENTS
+    );
+    assert_eq!(
+        hex(vectors.roots.iter().map(|r| &r.root).collect()),
+        golden::ROOTS
+    );
+    for (share, expected) in vectors.shares.iter().zip(golden::SHARES.iter()) {
+        assert_eq!(fr_to_hex(&share.epoch), expected[0]);
+        assert_eq!(fr_to_hex(&share.external_nullifier), expected[1]);
+        assert_eq!(fr_to_hex(&share.a_1), expected[2]);
+        assert_eq!(fr_to_hex(&share.nullifier), expected[5]);
+        // share_x is the signal hashed with the default hash to field
+        #[cfg(not(feature = "legacy-hash-to-field"))]
+        {
+            assert_eq!(fr_to_hex(&share.share_x), expected[3]);
+            assert_eq!(fr_to_hex(&share.share_y), expected[4]);
+        }
+        let mut expected = share.a_1;
+        expected.mul_assign(&share.share_x);
+        expected.add_assign(&share.id_key);
+        assert_eq!(share.share_y, expected);
+    }
+
+    let raw_vk = hex::decode(&vectors.verifying_key).unwrap();
+    let verifier = RLNVerifier::<Bn256>::new_with_raw_vk(raw_vk.as_slice()).unwrap();
+    for (vector, signal) in vectors
+        .bundles
+        .iter()
+        .zip([&b"rln"[..], &b"hello world"[..]].iter())
+    {
+        let bundle = hex::decode(&vector.bundle).unwrap();
+        let mut reader = bundle.as_slice();
+        BundleHeader::read(&mut reader).unwrap();
+        let mut input_data = reader.to_vec();
+        input_data
+            .write_u64::<LittleEndian>(signal.len() as u64)
+            .unwrap();
+        input_data.write_all(signal).unwrap();
+        assert!(verifier.verify(input_data.as_slice()).unwrap());
+    }
+
+    let again = TestVectors::generate().unwrap();
+    assert_eq!(vectors.bundles[0].bundle, again.bundles[0].bundle);
+    assert_eq!(vectors.keys[4].commitment, again.keys[4].commitment);
+}
@@ -0,0 +1,385 @@
+//! Verification of RLN proofs from byte slices.
+//!
+//! Proof points and public inputs are decoded from byte slices and checked against a
+//! prepared verifying key, nothing here reads from `std::io`. `RLNVerifier` decodes
+//! proofs through this module, which is the only one built without default features.
+//! It still needs the standard library, as `bellman_ce` and `pairing` do.
+
+use bellman::groth16::{verify_proof, PreparedVerifyingKey, Proof};
+use bellman::pairing::ff::PrimeField;
+use bellman::pairing::{CurveAffine, CurveProjective, EncodedPoint, Engine};
+use std::fmt;
+
+/// public inputs that follow a proof, [ root, epoch, share_x, share_y, nullifier ]
+pub const PROOF_PUBLIC_INPUTS: usize = 5;
+
+/// encoding of the groth16 proof points
+/// * `Uncompressed` writes full affine coordinates, 256 bytes for bn256
+/// * `Compressed` writes x coordinates with a sign flag, 128 bytes for bn256
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProofFormat {
+    Uncompressed,
+    Compressed,
+}
+
+impl Default for ProofFormat {
+    fn default() -> Self {
+        ProofFormat::Uncompressed
+    }
+}
+
+impl ProofFormat {
+    pub fn proof_size<E: Engine>(&self) -> usize {
+        match self {
+            ProofFormat::Uncompressed => {
+                2 * <E::G1Affine as CurveAffine>::Uncompressed::size()
+                    + <E::G2Affine as CurveAffine>::Uncompressed::size()
+            }
+            ProofFormat::Compressed => {
+                2 * <E::G1Affine as CurveAffine>::Compressed::size()
+                    + <E::G2Affine as CurveAffine>::Compressed::size()
+            }
+        }
+    }
+}
+
+/// public inputs of the RLN circuit, in the order the native circuit allocates them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PublicInput {
+    Root,
+    Epoch,
+    ShareX,
+    ShareY,
+    Nullifier,
+    RlnIdentifier,
+    /// only with the `PublicStake` identity scheme
+    Stake,
+}
+
+impl PublicInput {
+    // position in [ root, epoch, share_x, share_y, nullifier, rln_identifier, stake ]
+    fn native_index(self) -> usize {
+        match self {
+            PublicInput::Root => 0,
+            PublicInput::Epoch => 1,
+            PublicInput::ShareX => 2,
+            PublicInput::ShareY => 3,
+            PublicInput::Nullifier => 4,
+            PublicInput::RlnIdentifier => 5,
+            PublicInput::Stake => 6,
+        }
+    }
+}
+
+/// order a verifying key takes the public inputs in
+/// * circuits of other toolchains allocate them differently, circom RLN declares
+/// the outputs `y`, `root` and `nullifier` before the inputs `x`, `epoch` and `rln_identifier`
+/// * proofs of this crate keep serializing [ root, epoch, share_x, share_y, nullifier ],
+/// the layout only rearranges them for the pairing check
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublicInputLayout {
+    order: Vec<PublicInput>,
+}
+
+impl PublicInputLayout {
+    /// the order of `RLNCircuit`
+    pub fn native() -> PublicInputLayout {
+        PublicInputLayout {
+            order: vec![
+                PublicInput::Root,
+                PublicInput::Epoch,
+                PublicInput::ShareX,
+                PublicInput::ShareY,
+                PublicInput::Nullifier,
+                PublicInput::RlnIdentifier,
+            ],
+        }
+    }
+
+    /// the order of snarkjs public signals of the circom RLN circuit,
+    /// [ y, root, nullifier, x, epoch, rln_identifier ]
+    pub fn circom() -> PublicInputLayout {
+        PublicInputLayout {
+            order: vec![
+                PublicInput::ShareY,
+                PublicInput::Root,
+                PublicInput::Nullifier,
+                PublicInput::ShareX,
+                PublicInput::Epoch,
+                PublicInput::RlnIdentifier,
+            ],
+        }
+    }
+
+    /// * every input appears at most once, the five inputs of a proof must appear
+    /// and a stake only after the rln identifier is in the layout
+    pub fn new(order: Vec<PublicInput>) -> Result<PublicInputLayout, VerifyError> {
+        let mut seen = [false; 7];
+        for input in order.iter() {
+            if seen[input.native_index()] {
+                return Err(VerifyError::InvalidLayout);
+            }
+            seen[input.native_index()] = true;
+        }
+        if seen[..PROOF_PUBLIC_INPUTS].iter().any(|seen| !seen) || (seen[6] && !seen[5]) {
+            return Err(VerifyError::InvalidLayout);
+        }
+        Ok(PublicInputLayout { order })
+    }
+
+    pub fn order(&self) -> &[PublicInput] {
+        &self.order
+    }
+
+    /// rearranges inputs of the native order,
+    /// [ root, epoch, share_x, share_y, nullifier, rln_identifier, stake ] or a prefix of it
+    pub fn arrange<F: Copy>(&self, native: &[F]) -> Result<Vec<F>, VerifyError> {
+        self.order
+            .iter()
+            .map(|input| native.get(input.native_index()).copied())
+            .collect::<Option<Vec<F>>>()
+            .ok_or(VerifyError::InputsMismatch)
+    }
+
+    /// inverse of `arrange`, e.g. for public signals of a circom proof,
+    /// returns as many native inputs as the layout has
+    pub fn to_native<F: Copy>(&self, arranged: &[F]) -> Result<Vec<F>, VerifyError> {
+        if arranged.len() != self.order.len() {
+            return Err(VerifyError::InputsMismatch);
+        }
+        let mut native = vec![arranged[0]; arranged.len()];
+        for (input, value) in self.order.iter().zip(arranged.iter()) {
+            native[input.native_index()] = *value;
+        }
+        Ok(native)
+    }
+}
+
+impl Default for PublicInputLayout {
+    fn default() -> Self {
+        PublicInputLayout::native()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyError {
+    /// data ends before the proof or its public inputs do
+    UnexpectedEnd,
+    /// bytes do not encode a curve point
+    InvalidPoint,
+    PointAtInfinity,
+    NotInSubgroup,
+    /// a public input is not below the field modulus
+    NonCanonicalField,
+    /// the number of public inputs does not match the verifying key
+    InputsMismatch,
+    /// a `PublicInputLayout` repeats or misses an input
+    InvalidLayout,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            VerifyError::UnexpectedEnd => "unexpected end of proof data",
+            VerifyError::InvalidPoint => "invalid curve point",
+            VerifyError::PointAtInfinity => "point at infinity",
+            VerifyError::NotInSubgroup => "point is not in the prime order subgroup",
+            VerifyError::NonCanonicalField => "non canonical field element",
+            VerifyError::InputsMismatch => "public inputs do not match the verifying key",
+            VerifyError::InvalidLayout => "invalid public input layout",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+impl From<VerifyError> for std::io::Error {
+    fn from(e: VerifyError) -> std::io::Error {
+        let kind = match e {
+            VerifyError::UnexpectedEnd => std::io::ErrorKind::UnexpectedEof,
+            VerifyError::InputsMismatch | VerifyError::InvalidLayout => {
+                std::io::ErrorKind::InvalidInput
+            }
+            _ => std::io::ErrorKind::InvalidData,
+        };
+        std::io::Error::new(kind, e)
+    }
+}
+
+/// decodes a proof from the start of `data`, returns it with the bytes that follow
+/// * points at infinity and points out of the prime order subgroup are rejected
+pub fn decode_proof<E: Engine>(
+    data: &[u8],
+    format: ProofFormat,
+) -> Result<(Proof<E>, &[u8]), VerifyError> {
+    match format {
+        ProofFormat::Uncompressed => decode_points::<
+            E,
+            <E::G1Affine as CurveAffine>::Uncompressed,
+            <E::G2Affine as CurveAffine>::Uncompressed,
+        >(data),
+        ProofFormat::Compressed => decode_points::<
+            E,
+            <E::G1Affine as CurveAffine>::Compressed,
+            <E::G2Affine as CurveAffine>::Compressed,
+        >(data),
+    }
+}
+
+fn decode_points<E, G1, G2>(data: &[u8]) -> Result<(Proof<E>, &[u8]), VerifyError>
+where
+    E: Engine,
+    G1: EncodedPoint<Affine = E::G1Affine>,
+    G2: EncodedPoint<Affine = E::G2Affine>,
+{
+    let (a, data) = decode_point::<G1>(data)?;
+    let (b, data) = decode_point::<G2>(data)?;
+    let (c, data) = decode_point::<G1>(data)?;
+    Ok((Proof { a, b, c }, data))
+}
+
+fn decode_point<P: EncodedPoint>(data: &[u8]) -> Result<(P::Affine, &[u8]), VerifyError> {
+    if data.len() < P::size() {
+        return Err(VerifyError::UnexpectedEnd);
+    }
+    let mut repr = P::empty();
+    repr.as_mut().copy_from_slice(&data[..P::size()]);
+    let point = repr.into_affine().map_err(|_| VerifyError::InvalidPoint)?;
+    Ok((checked_point(point)?, &data[P::size()..]))
+}
+
+// proof points have to be non zero and in the prime order subgroup,
+// checked here rather than trusting the decoder of the encoding to do it
+pub(crate) fn checked_point<G: CurveAffine>(point: G) -> Result<G, VerifyError> {
+    if point.is_zero() {
+        return Err(VerifyError::PointAtInfinity);
+    }
+    if !is_in_subgroup(&point) {
+        return Err(VerifyError::NotInSubgroup);
+    }
+    Ok(point)
+}
+
+pub(crate) fn is_in_subgroup<G: CurveAffine>(point: &G) -> bool {
+    point.mul(G::Scalar::char()).is_zero()
+}
+
+/// decodes `n` field elements of 32 little endian bytes each,
+/// returns them with the bytes that follow
+pub fn decode_fr<F: PrimeField>(data: &[u8], n: usize) -> Result<(Vec<F>, &[u8]), VerifyError> {
+    let mut data = data;
+    let mut out: Vec<F> = Vec::with_capacity(n);
+    for _ in 0..n {
+        let mut repr = F::Repr::default();
+        let size = repr.as_ref().len() * 8;
+        if data.len() < size {
+            return Err(VerifyError::UnexpectedEnd);
+        }
+        for (limb, bytes) in repr.as_mut().iter_mut().zip(data[..size].chunks(8)) {
+            let mut limb_bytes = [0u8; 8];
+            limb_bytes.copy_from_slice(bytes);
+            *limb = u64::from_le_bytes(limb_bytes);
+        }
+        if repr >= F::char() {
+            return Err(VerifyError::NonCanonicalField);
+        }
+        out.push(F::from_repr(repr).map_err(|_| VerifyError::NonCanonicalField)?);
+        data = &data[size..];
+    }
+    Ok((out, data))
+}
+
+/// checks the pairing equation of a proof over all of its public inputs
+pub fn verify_public_inputs<E: Engine>(
+    verifying_key: &PreparedVerifyingKey<E>,
+    proof: &Proof<E>,
+    public_inputs: &[E::Fr],
+) -> Result<bool, VerifyError> {
+    verify_proof(verifying_key, proof, public_inputs).map_err(|_| VerifyError::InputsMismatch)
+}
+
+/// verifies `generate_proof` output, the signal is not checked
+/// * `proof_data` is [ proof<256> | root<32> | epoch<32> | share_x<32> | share_y<32> | nullifier<32> ]
+/// * returns the public inputs of a valid proof, `None` if the proof does not hold
+pub fn verify_rln_proof<E: Engine>(
+    verifying_key: &PreparedVerifyingKey<E>,
+    proof_data: &[u8],
+    format: ProofFormat,
+    rln_identifier: E::Fr,
+) -> Result<Option<Vec<E::Fr>>, VerifyError> {
+    let (proof, rest) = decode_proof::<E>(proof_data, format)?;
+    let (mut public_inputs, _) = decode_fr::<E::Fr>(rest, PROOF_PUBLIC_INPUTS)?;
+    public_inputs.push(rln_identifier);
+    if !verify_public_inputs(verifying_key, &proof, &public_inputs)? {
+        return Ok(None);
+    }
+    public_inputs.pop();
+    Ok(Some(public_inputs))
+}
+
+#[cfg(feature = "prover")]
+#[test]
+fn test_verifier_core() {
+    use crate::merkle::TreeArity;
+    use crate::public::RLN;
+    use bellman::pairing::bn256::{Bn256, Fr};
+    use bellman::pairing::ff::Field;
+
+    let rln_identifier = Fr::from_str("7").unwrap();
+    let rln = RLN::<Bn256>::new(3, TreeArity::Binary, None, Some(rln_identifier));
+    let id_key = Fr::from_str("1001").unwrap();
+    let input_data = crate::test_utils::register_member(&rln, id_key, Fr::one(), b"core");
+    let mut proof: Vec<u8> = Vec::new();
+    rln.generate_proof(input_data.as_slice(), &mut proof)
+        .unwrap();
+
+    let verifying_key = rln.prepared_verifying_key();
+    let format = ProofFormat::Uncompressed;
+    let public_inputs = verify_rln_proof::<Bn256>(&verifying_key, &proof, format, rln_identifier)
+        .unwrap()
+        .unwrap();
+    assert_eq!(public_inputs.len(), PROOF_PUBLIC_INPUTS);
+    assert_eq!(public_inputs[1], Fr::one());
+    let result = verify_rln_proof::<Bn256>(&verifying_key, &proof, format, Fr::one()).unwrap();
+    assert!(result.is_none());
+    assert_eq!(
+        verify_rln_proof::<Bn256>(&verifying_key, &proof[..300], format, rln_identifier),
+        Err(VerifyError::UnexpectedEnd)
+    );
+    let mut infinity = proof.clone();
+    infinity[..64].copy_from_slice(&[0u8; 64]);
+    infinity[0] = 0x40;
+    assert!(verify_rln_proof::<Bn256>(&verifying_key, &infinity, format, rln_identifier).is_err());
+}
+
+#[test]
+fn test_public_input_layout() {
+    let native = [0u8, 1, 2, 3, 4, 5];
+    let circom = PublicInputLayout::circom();
+    let arranged = circom.arrange(&native).unwrap();
+    assert_eq!(arranged, vec![3, 0, 4, 2, 1, 5]);
+    assert_eq!(circom.to_native(&arranged).unwrap(), native.to_vec());
+    assert_eq!(
+        PublicInputLayout::native().arrange(&native).unwrap(),
+        native.to_vec()
+    );
+    assert_eq!(
+        circom.arrange(&native[..5]),
+        Err(VerifyError::InputsMismatch)
+    );
+
+    let mut order = PublicInputLayout::native().order().to_vec();
+    order.push(PublicInput::Stake);
+    assert!(PublicInputLayout::new(order.clone()).is_ok());
+    order.push(PublicInput::Root);
+    assert_eq!(
+        PublicInputLayout::new(order),
+        Err(VerifyError::InvalidLayout)
+    );
+    let missing = vec![PublicInput::Root, PublicInput::Epoch];
+    assert_eq!(
+        PublicInputLayout::new(missing),
+        Err(VerifyError::InvalidLayout)
+    );
+}
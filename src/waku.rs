@@ -0,0 +1,201 @@
+//! Waku RLN-RELAY `RateLimitProof` encoding.
+//!
+//! ```protobuf
+//! message RateLimitProof {
+//!   bytes proof = 1;
+//!   bytes merkle_root = 2;
+//!   bytes epoch = 3;
+//!   bytes share_x = 4;
+//!   bytes share_y = 5;
+//!   bytes nullifier = 6;
+//!   bytes rln_identifier = 7;
+//! }
+//! ```
+//!
+//! Field elements are 32 bytes little endian as in `generate_proof` output.
+//! `proof` is carried as is, so peers have to agree on the proof format.
+
+use crate::utils::{read_fr, ProofFormat};
+use bellman::pairing::ff::{PrimeField, PrimeFieldRepr};
+use bellman::pairing::Engine;
+use std::io::{self, Read, Write};
+
+#[derive(Clone)]
+pub struct RateLimitProof<E: Engine> {
+    pub proof: Vec<u8>,
+    pub merkle_root: E::Fr,
+    pub epoch: E::Fr,
+    pub share_x: E::Fr,
+    pub share_y: E::Fr,
+    pub nullifier: E::Fr,
+    pub rln_identifier: Option<E::Fr>,
+}
+
+impl<E: Engine> RateLimitProof<E> {
+    /// splits `generate_proof` output
+    /// * `proof_data` is [ proof<256>| root<32>| epoch<32>| share_x<32>| share_y<32>| nullifier<32> ]
+    pub fn from_proof_data<R: Read>(
+        mut proof_data: R,
+        proof_format: ProofFormat,
+        rln_identifier: Option<E::Fr>,
+    ) -> io::Result<RateLimitProof<E>> {
+        let mut proof = vec![0u8; proof_format.proof_size::<E>()];
+        proof_data.read_exact(&mut proof)?;
+        let inputs = read_fr::<_, E>(&mut proof_data, 5)?;
+        Ok(RateLimitProof {
+            proof,
+            merkle_root: inputs[0],
+            epoch: inputs[1],
+            share_x: inputs[2],
+            share_y: inputs[3],
+            nullifier: inputs[4],
+            rln_identifier,
+        })
+    }
+
+    /// writes back the `generate_proof` layout, ready for `verify_proof`
+    /// or for `verify` with the signal appended
+    pub fn write_proof_data<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.proof)?;
+        for fr in self.public_inputs().iter() {
+            fr.into_repr().write_le(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        write_field(&mut buf, 1, &self.proof);
+        let inputs = self.public_inputs();
+        // fields 2 to 7, the rln identifier last
+        for (i, fr) in inputs.iter().chain(self.rln_identifier.iter()).enumerate() {
+            let mut value: Vec<u8> = Vec::new();
+            fr.into_repr().write_le(&mut value).unwrap();
+            write_field(&mut buf, i as u64 + 2, &value);
+        }
+        buf
+    }
+
+    /// decodes a protobuf message, unknown fields are skipped
+    pub fn decode(mut buf: &[u8]) -> io::Result<RateLimitProof<E>> {
+        let mut proof: Option<Vec<u8>> = None;
+        let mut inputs: [Option<E::Fr>; 6] = [None; 6];
+        while !buf.is_empty() {
+            let key = read_varint(&mut buf)?;
+            let (field, wire_type) = (key >> 3, key & 7);
+            match wire_type {
+                0 => {
+                    read_varint(&mut buf)?;
+                }
+                2 => {
+                    let len = read_varint(&mut buf)? as usize;
+                    if len > buf.len() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "truncated rate limit proof",
+                        ));
+                    }
+                    let (value, rest) = buf.split_at(len);
+                    buf = rest;
+                    match field {
+                        1 => proof = Some(value.to_vec()),
+                        2..=7 => inputs[field as usize - 2] = Some(read_fr::<_, E>(value, 1)?[0]),
+                        _ => {}
+                    }
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unsupported protobuf wire type",
+                    ))
+                }
+            }
+        }
+        let missing =
+            || io::Error::new(io::ErrorKind::InvalidData, "missing rate limit proof field");
+        Ok(RateLimitProof {
+            proof: proof.ok_or_else(missing)?,
+            merkle_root: inputs[0].ok_or_else(missing)?,
+            epoch: inputs[1].ok_or_else(missing)?,
+            share_x: inputs[2].ok_or_else(missing)?,
+            share_y: inputs[3].ok_or_else(missing)?,
+            nullifier: inputs[4].ok_or_else(missing)?,
+            rln_identifier: inputs[5],
+        })
+    }
+
+    fn public_inputs(&self) -> [E::Fr; 5] {
+        [
+            self.merkle_root,
+            self.epoch,
+            self.share_x,
+            self.share_y,
+            self.nullifier,
+        ]
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(buf: &mut &[u8]) -> io::Result<u64> {
+    let mut value = 0u64;
+    for i in 0..10 {
+        let (byte, rest) = buf.split_first().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "truncated rate limit proof")
+        })?;
+        *buf = rest;
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "varint is too long",
+    ))
+}
+
+// length delimited field
+fn write_field(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+    write_varint(buf, field << 3 | 2);
+    write_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+#[test]
+fn test_rate_limit_proof() {
+    use bellman::pairing::bn256::{Bn256, Fr};
+    let inputs: Vec<Fr> = (1..6)
+        .map(|i| Fr::from_str(&format!("{}", i)).unwrap())
+        .collect();
+    let mut proof_data: Vec<u8> = vec![7u8; 128];
+    for fr in inputs.iter() {
+        fr.into_repr().write_le(&mut proof_data).unwrap();
+    }
+
+    let rate_limit_proof = RateLimitProof::<Bn256>::from_proof_data(
+        proof_data.as_slice(),
+        ProofFormat::Compressed,
+        Some(Fr::from_str("9").unwrap()),
+    )
+    .unwrap();
+    assert_eq!(rate_limit_proof.epoch, inputs[1]);
+    assert_eq!(rate_limit_proof.nullifier, inputs[4]);
+
+    let encoded = rate_limit_proof.encode();
+    // proof field header is the tag 0x0a followed by the length 128 as a two byte varint
+    assert_eq!(&encoded[..3], &[0x0a, 0x80, 0x01]);
+    let decoded = RateLimitProof::<Bn256>::decode(&encoded).unwrap();
+    assert_eq!(decoded.rln_identifier, rate_limit_proof.rln_identifier);
+    let mut written: Vec<u8> = Vec::new();
+    decoded.write_proof_data(&mut written).unwrap();
+    assert_eq!(written, proof_data);
+
+    assert!(RateLimitProof::<Bn256>::decode(&encoded[..encoded.len() - 1]).is_err());
+}
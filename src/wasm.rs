@@ -1,10 +1,25 @@
-use crate::public::RLN;
+use crate::hash_to_field::SignalHasher;
+use crate::merkle::TreeArity;
+use crate::nullifier::{LogStatus, NullifierLog};
+use crate::public::{RLNVerifier, RLN};
+use crate::utils::{fr_from_hex, fr_to_hex, read_fr, seeded_rng, ProofFormat};
+use crate::verifier_core::PublicInputLayout;
 
+use byteorder::{LittleEndian, ReadBytesExt};
+#[cfg(feature = "wasm-prover")]
+use rand::thread_rng;
+use std::cell::RefCell;
 use std::io::{self, Error, ErrorKind, Read, Write};
+use std::rc::Rc;
+use std::sync::Arc;
 use wasm_bindgen::prelude::*;
+#[cfg(feature = "wasm-prover")]
+use wasm_bindgen_futures::future_to_promise;
 
-use js_sys::Array;
+use js_sys::{Array, Function, Promise, Uint8Array};
+use sapling_crypto::bellman::groth16::Parameters;
 use sapling_crypto::bellman::pairing::bn256::{Bn256, Fr};
+use sapling_crypto::bellman::pairing::ff::{PrimeField, PrimeFieldRepr};
 
 pub fn set_panic_hook() {
     // When the `console_error_panic_hook` feature is enabled, we can call the
@@ -17,19 +32,60 @@ pub fn set_panic_hook() {
     console_error_panic_hook::set_once();
 }
 
+/// circuit parameters parsed once and shared by every `RLNWasm` built from them
+#[cfg(feature = "wasm-prover")]
+#[wasm_bindgen]
+pub struct RLNParamsHandle {
+    params: Arc<Parameters<Bn256>>,
+}
+
+#[cfg(feature = "wasm-prover")]
+#[wasm_bindgen]
+impl RLNParamsHandle {
+    #[wasm_bindgen]
+    pub fn new(raw_circuit_parameters: &[u8]) -> Result<RLNParamsHandle, JsValue> {
+        set_panic_hook();
+        let params = match Parameters::read(raw_circuit_parameters, true) {
+            Ok(params) => params,
+            Err(e) => return Err(to_js_error(e)),
+        };
+        Ok(RLNParamsHandle {
+            params: Arc::new(params),
+        })
+    }
+}
+
+#[cfg(feature = "wasm-prover")]
 #[wasm_bindgen]
 pub struct RLNWasm {
     api: RLN<Bn256>,
+    // leaves of rejected `persist_tree` writes, queued again by the next call
+    failed_writes: Rc<RefCell<Vec<u64>>>,
+}
+
+#[cfg(feature = "wasm-prover")]
+impl RLNWasm {
+    fn from_api(api: RLN<Bn256>) -> RLNWasm {
+        RLNWasm {
+            api,
+            failed_writes: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
 }
 
+#[cfg(feature = "wasm-prover")]
 #[wasm_bindgen]
 impl RLNWasm {
     #[wasm_bindgen]
-    pub fn new(merkle_depth: usize) -> RLNWasm {
+    pub fn new(merkle_depth: usize) -> Result<RLNWasm, JsValue> {
         set_panic_hook();
-        RLNWasm {
-            api: RLN::<Bn256>::new(merkle_depth, None),
-        }
+        check_merkle_depth(merkle_depth)?;
+        Ok(RLNWasm::from_api(RLN::<Bn256>::new(
+            merkle_depth,
+            TreeArity::Binary,
+            None,
+            None,
+        )))
     }
 
     #[wasm_bindgen]
@@ -38,18 +94,282 @@ impl RLNWasm {
         raw_circuit_parameters: &[u8],
     ) -> Result<RLNWasm, JsValue> {
         set_panic_hook();
-        let api = match RLN::new_with_raw_params(merkle_depth, raw_circuit_parameters, None) {
+        check_merkle_depth(merkle_depth)?;
+        let api = match RLN::new_with_raw_params(
+            merkle_depth,
+            TreeArity::Binary,
+            raw_circuit_parameters,
+            None,
+            None,
+        ) {
+            Ok(api) => api,
+            Err(e) => return Err(to_js_error(e)),
+        };
+        Ok(RLNWasm::from_api(api))
+    }
+
+    /// new instance with its own tree over shared circuit parameters
+    #[wasm_bindgen]
+    pub fn new_with_params_handle(
+        merkle_depth: usize,
+        params: &RLNParamsHandle,
+    ) -> Result<RLNWasm, JsValue> {
+        set_panic_hook();
+        check_merkle_depth(merkle_depth)?;
+        let api = match RLN::new_with_shared_params(
+            merkle_depth,
+            TreeArity::Binary,
+            params.params.clone(),
+            None,
+        ) {
             Ok(api) => api,
-            Err(e) => return Err(e.to_string().into()),
+            Err(e) => return Err(to_js_error(e)),
+        };
+        Ok(RLNWasm::from_api(api))
+    }
+
+    #[wasm_bindgen]
+    pub fn set_compressed_proofs(&mut self, compressed: bool) {
+        if compressed {
+            self.api.set_proof_format(ProofFormat::Compressed);
+        } else {
+            self.api.set_proof_format(ProofFormat::Uncompressed);
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_keccak_signal_hash(&mut self, keccak: bool) {
+        if keccak {
+            self.api.set_signal_hasher(SignalHasher::Keccak256);
+        } else {
+            self.api.set_signal_hasher(SignalHasher::Sha256);
+        }
+    }
+
+    /// zero accepts signals of any length
+    #[wasm_bindgen]
+    pub fn set_max_signal_size(&mut self, max_signal_size: u32) {
+        if max_signal_size == 0 {
+            self.api.set_max_signal_size(None);
+        } else {
+            self.api.set_max_signal_size(Some(max_signal_size as u64));
+        }
+    }
+
+    /// `rln_identifier` is a scalar field element in 32 bytes
+    #[wasm_bindgen]
+    pub fn set_rln_identifier(&mut self, rln_identifier: &[u8]) -> Result<(), JsValue> {
+        let rln_identifier = match read_fr::<_, Bn256>(rln_identifier, 1) {
+            Ok(fr) => fr[0],
+            Err(e) => return Err(to_js_error(e)),
         };
-        Ok(RLNWasm { api })
+        self.api.set_rln_identifier(rln_identifier);
+        Ok(())
+    }
+
+    /// same as `set_rln_identifier` with the big endian hex of the identifier
+    #[wasm_bindgen]
+    pub fn set_rln_identifier_hex(&mut self, rln_identifier: &str) -> Result<(), JsValue> {
+        let rln_identifier = fr_from_hex::<Fr>(rln_identifier).map_err(to_js_error)?;
+        self.api.set_rln_identifier(rln_identifier);
+        Ok(())
+    }
+
+    /// merkle root as a scalar field element in 32 little endian bytes
+    #[wasm_bindgen]
+    pub fn get_root(&self) -> Result<Vec<u8>, JsValue> {
+        let mut root: Vec<u8> = Vec::new();
+        self.api.get_root(&mut root).map_err(to_js_error)?;
+        Ok(root)
+    }
+
+    /// 0x prefixed big endian hex of the merkle root
+    #[wasm_bindgen]
+    pub fn get_root_hex(&self) -> Result<String, JsValue> {
+        hex_from_le(&self.get_root()?)
+    }
+
+    /// inserts the public key of a member in 32 little endian bytes, returns its leaf index
+    #[wasm_bindgen]
+    pub fn update_next_member(&self, public_key: &[u8]) -> Result<f64, JsValue> {
+        let index = self
+            .api
+            .update_next_member(public_key)
+            .map_err(to_js_error)?;
+        Ok(index as f64)
+    }
+
+    /// same as `update_next_member` with the big endian hex of the public key
+    #[wasm_bindgen]
+    pub fn update_next_member_hex(&self, public_key: &str) -> Result<f64, JsValue> {
+        let public_key = fr_from_hex::<Fr>(public_key).map_err(to_js_error)?;
+        let mut leaf: Vec<u8> = Vec::new();
+        public_key
+            .into_repr()
+            .write_le(&mut leaf)
+            .map_err(to_js_error)?;
+        self.update_next_member(&leaf)
+    }
+
+    /// fresh secrets followed by the public key, 32 little endian bytes each
+    #[wasm_bindgen]
+    pub fn key_gen(&self) -> Result<Vec<u8>, JsValue> {
+        let mut keys: Vec<u8> = Vec::new();
+        self.api.key_gen(&mut keys).map_err(to_js_error)?;
+        Ok(keys)
+    }
+
+    /// same as `key_gen` as an array of 0x prefixed big endian hex strings
+    #[wasm_bindgen]
+    pub fn key_gen_hex(&self) -> Result<Array, JsValue> {
+        let keys = Array::new();
+        for key in self.key_gen()?.chunks(32) {
+            keys.push(&hex_from_le(key)?.into());
+        }
+        Ok(keys)
+    }
+
+    /// records tree changes from now on for `persist_tree`
+    #[wasm_bindgen]
+    pub fn track_tree_changes(&self) {
+        self.api.track_tree_changes();
+    }
+
+    /// hands the leaves changed since the last call to an async key value store,
+    /// e.g. an IndexedDB object store, and resolves once every write is done
+    /// * `put(key, value)` is called with a leaf index and its 32 bytes,
+    /// or null for an emptied slot, then with "current_index" and the incremental index
+    /// * if a write is rejected, the leaves of the call are handed out again by the next one
+    #[wasm_bindgen]
+    pub fn persist_tree(&self, put: &Function) -> Result<Promise, JsValue> {
+        self.api
+            .requeue_tree_changes(&self.failed_writes.replace(Vec::new()));
+        let mut changes: Vec<u8> = Vec::new();
+        self.api
+            .take_tree_changes(&mut changes)
+            .map_err(to_js_error)?;
+        let mut changes = changes.as_slice();
+        let current_index = changes.read_u64::<LittleEndian>().map_err(to_js_error)?;
+        let n = changes.read_u64::<LittleEndian>().map_err(to_js_error)?;
+        let mut indexes: Vec<u64> = Vec::new();
+        let mut entries: Vec<(JsValue, JsValue)> = Vec::new();
+        for _ in 0..n {
+            let index = changes.read_u64::<LittleEndian>().map_err(to_js_error)?;
+            let (entry, rest) = changes.split_at(33);
+            changes = rest;
+            let value: JsValue = if entry[0] == 1 {
+                Uint8Array::from(&entry[1..]).into()
+            } else {
+                JsValue::NULL
+            };
+            indexes.push(index);
+            entries.push(((index as f64).into(), value));
+        }
+        entries.push(("current_index".into(), (current_index as f64).into()));
+
+        let writes = Array::new();
+        for (key, value) in entries.iter() {
+            match put.call2(&JsValue::NULL, key, value) {
+                Ok(write) => writes.push(&write),
+                Err(e) => {
+                    self.failed_writes.borrow_mut().extend(indexes);
+                    return Err(e);
+                }
+            };
+        }
+        let written = Promise::all(&writes);
+        // the caller still sees the rejection, this branch only queues the leaves again
+        let failed_writes = self.failed_writes.clone();
+        let requeue = Closure::once(move |_: JsValue| {
+            failed_writes.borrow_mut().extend(indexes);
+        });
+        let _ = written.catch(&requeue);
+        requeue.forget();
+        Ok(written)
+    }
+
+    /// rebuilds the tree from the persisted store
+    /// * `leaves` is an array of `[index, leaf]` pairs of the occupied slots
+    #[wasm_bindgen]
+    pub fn restore_tree(&self, current_index: f64, leaves: Array) -> Result<(), JsValue> {
+        let mut input_data: Vec<u8> = Vec::new();
+        input_data.extend_from_slice(&index_from_js(current_index)?.to_le_bytes());
+        input_data.extend_from_slice(&(leaves.length() as u64).to_le_bytes());
+        for entry in leaves.iter() {
+            let entry = Array::from(&entry);
+            let index = entry
+                .get(0)
+                .as_f64()
+                .ok_or_else(|| JsValue::from("leaf index is not a number"))?;
+            let leaf = Uint8Array::new(&entry.get(1)).to_vec();
+            if leaf.len() != 32 {
+                return Err("leaf is not 32 bytes".into());
+            }
+            input_data.extend_from_slice(&index_from_js(index)?.to_le_bytes());
+            input_data.extend_from_slice(&leaf);
+        }
+        self.api
+            .restore_tree(input_data.as_slice())
+            .map_err(to_js_error)?;
+        self.failed_writes.borrow_mut().clear();
+        Ok(())
     }
 
     #[wasm_bindgen]
     pub fn generate_proof(&self, input: &[u8]) -> Result<Vec<u8>, JsValue> {
-        let proof = match self.api.generate_proof(input) {
-            Ok(proof) => proof,
-            Err(e) => return Err(e.to_string().into()),
+        let mut proof: Vec<u8> = Vec::new();
+        match self.api.generate_proof(input, &mut proof) {
+            Ok(_) => (),
+            Err(e) => return Err(to_js_error(e)),
+        };
+        Ok(proof)
+    }
+
+    /// `generate_proof` returning a promise of the proof
+    /// * the input is read and the witness taken from the tree before returning,
+    /// proving runs once the caller yields, still on the calling thread,
+    /// so it belongs in a Web Worker, see the README
+    /// * `progress` if given is called with a phase and a percentage, see `ProofPhase`,
+    /// phase 0 goes from 0 to 100 while the witness is synthesized
+    /// and phase 1 is reported at 0 when the multiexps start and at 100 at the end
+    /// * the promise is rejected if `progress` throws
+    #[wasm_bindgen]
+    pub fn generate_proof_async(&self, input: &[u8], progress: Option<Function>) -> Promise {
+        let prepared = match self.api.prepare_proof(input) {
+            Ok(prepared) => prepared,
+            Err(e) => return Promise::reject(&to_js_error(e)),
+        };
+        future_to_promise(async move {
+            let mut proof: Vec<u8> = Vec::new();
+            let mut thrown: Option<JsValue> = None;
+            prepared
+                .prove_with_progress(&mut proof, &mut thread_rng(), |phase, percent| {
+                    if let (Some(progress), None) = (progress.as_ref(), thrown.as_ref()) {
+                        let phase = phase as u32;
+                        if let Err(e) =
+                            progress.call2(&JsValue::NULL, &phase.into(), &percent.into())
+                        {
+                            thrown = Some(e);
+                        }
+                    }
+                })
+                .map_err(to_js_error)?;
+            match thrown {
+                Some(e) => Err(e),
+                None => Ok(Uint8Array::from(proof.as_slice()).into()),
+            }
+        })
+    }
+
+    #[wasm_bindgen]
+    pub fn generate_proof_with_seed(&self, input: &[u8], seed: &[u8]) -> Result<Vec<u8>, JsValue> {
+        let mut proof: Vec<u8> = Vec::new();
+        match self
+            .api
+            .generate_proof_with_rng(input, &mut proof, &mut seeded_rng(seed))
+        {
+            Ok(_) => (),
+            Err(e) => return Err(to_js_error(e)),
         };
         Ok(proof)
     }
@@ -60,35 +380,311 @@ impl RLNWasm {
         uncompresed_proof: &[u8],
         raw_public_inputs: &[u8],
     ) -> Result<bool, JsValue> {
-        let success = match self.api.verify(uncompresed_proof, raw_public_inputs) {
+        let input_data = [uncompresed_proof, raw_public_inputs].concat();
+        let success = match self.api.verify_proof(input_data.as_slice()) {
             Ok(success) => success,
-            Err(e) => return Err(e.to_string().into()),
+            Err(e) => return Err(to_js_error(e)),
         };
         Ok(success)
     }
 
+    /// same as `verify` and also checks that share_x is the hash of the signal
+    #[wasm_bindgen]
+    pub fn verify_with_signal(
+        &self,
+        proof: &[u8],
+        raw_public_inputs: &[u8],
+        signal: &[u8],
+    ) -> Result<bool, JsValue> {
+        let input_data = proof_with_signal(proof, raw_public_inputs, signal);
+        match self.api.verify(input_data.as_slice()) {
+            Ok(success) => Ok(success),
+            Err(e) => Err(to_js_error(e)),
+        }
+    }
+
     #[wasm_bindgen]
     pub fn export_verifier_key(&self) -> Result<Vec<u8>, JsValue> {
         let mut output: Vec<u8> = Vec::new();
         match self.api.export_verifier_key(&mut output) {
             Ok(_) => (),
-            Err(e) => return Err(e.to_string().into()),
+            Err(e) => return Err(to_js_error(e)),
         };
         Ok(output)
     }
 
+    /// recovers a member secret from two proofs of the same epoch and nullifier
+    #[wasm_bindgen]
+    pub fn recover_secret(&self, share1: &[u8], share2: &[u8]) -> Result<Vec<u8>, JsValue> {
+        let mut secret: Vec<u8> = Vec::new();
+        match self.api.recover_secret(share1, share2, &mut secret) {
+            Ok(_) => (),
+            Err(e) => return Err(to_js_error(e)),
+        };
+        Ok(secret)
+    }
+
+    /// logs the share of a proof bundle and reports spam,
+    /// see `encode_log_status` for the result
+    #[wasm_bindgen]
+    pub fn log_and_detect(&self, proof_bundle: &[u8]) -> Result<Vec<u8>, JsValue> {
+        match self.api.nullifier_log_insert(proof_bundle) {
+            Ok(status) => Ok(encode_log_status(status)),
+            Err(e) => Err(to_js_error(e)),
+        }
+    }
+
     #[wasm_bindgen]
     pub fn export_circuit_parameters(&self) -> Result<Vec<u8>, JsValue> {
         let mut output: Vec<u8> = Vec::new();
         match self.api.export_circuit_parameters(&mut output) {
             Ok(_) => (),
-            Err(e) => return Err(e.to_string().into()),
+            Err(e) => return Err(to_js_error(e)),
         };
         Ok(output)
     }
 }
 
-#[cfg(test)]
+/// verifier only counterpart of `RLNWasm`, built with `wasm-verifier`
+/// without the prover so bundles that only check proofs stay small
+#[cfg(feature = "wasm-verifier")]
+#[wasm_bindgen]
+pub struct RLNWasmVerifier {
+    api: RLNVerifier<Bn256>,
+    nullifier_log: Option<NullifierLog<Bn256>>,
+}
+
+#[cfg(feature = "wasm-verifier")]
+#[wasm_bindgen]
+impl RLNWasmVerifier {
+    #[wasm_bindgen]
+    pub fn new(raw_verifying_key: &[u8]) -> Result<RLNWasmVerifier, JsValue> {
+        set_panic_hook();
+        let api = match RLNVerifier::new_with_raw_vk(raw_verifying_key) {
+            Ok(api) => api,
+            Err(e) => return Err(to_js_error(e)),
+        };
+        Ok(RLNWasmVerifier {
+            api,
+            nullifier_log: None,
+        })
+    }
+
+    #[wasm_bindgen]
+    pub fn set_compressed_proofs(&mut self, compressed: bool) {
+        if compressed {
+            self.api.set_proof_format(ProofFormat::Compressed);
+        } else {
+            self.api.set_proof_format(ProofFormat::Uncompressed);
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_keccak_signal_hash(&mut self, keccak: bool) {
+        if keccak {
+            self.api.set_signal_hasher(SignalHasher::Keccak256);
+        } else {
+            self.api.set_signal_hasher(SignalHasher::Sha256);
+        }
+    }
+
+    /// zero accepts signals of any length
+    #[wasm_bindgen]
+    pub fn set_max_signal_size(&mut self, max_signal_size: u32) {
+        if max_signal_size == 0 {
+            self.api.set_max_signal_size(None);
+        } else {
+            self.api.set_max_signal_size(Some(max_signal_size as u64));
+        }
+    }
+
+    /// `rln_identifier` is a scalar field element in 32 bytes
+    #[wasm_bindgen]
+    pub fn set_rln_identifier(&mut self, rln_identifier: &[u8]) -> Result<(), JsValue> {
+        let rln_identifier = match read_fr::<_, Bn256>(rln_identifier, 1) {
+            Ok(fr) => fr[0],
+            Err(e) => return Err(to_js_error(e)),
+        };
+        self.api.set_rln_identifier(rln_identifier);
+        Ok(())
+    }
+
+    /// takes the verifying key of a circom deployment, see `PublicInputLayout::circom`
+    #[wasm_bindgen]
+    pub fn set_circom_public_inputs(&mut self, circom: bool) {
+        if circom {
+            self.api
+                .set_public_input_layout(Some(PublicInputLayout::circom()));
+        } else {
+            self.api.set_public_input_layout(None);
+        }
+    }
+
+    /// verifies a proof against public signals in the order of the public input layout,
+    /// as snarkjs lists them, each a scalar field element in 32 bytes
+    /// * the rln identifier among the signals must be the one of this verifier
+    #[wasm_bindgen]
+    pub fn verify_public_signals(
+        &self,
+        proof: &[u8],
+        public_signals: &[u8],
+    ) -> Result<bool, JsValue> {
+        let layout = self.api.public_input_layout().cloned().unwrap_or_default();
+        let signals =
+            read_fr::<_, Bn256>(public_signals, public_signals.len() / 32).map_err(to_js_error)?;
+        let native = layout
+            .to_native(&signals)
+            .map_err(|e| to_js_error(e.into()))?;
+        if native.len() > 5 && native[5] != self.api.rln_identifier() {
+            return Ok(false);
+        }
+        let mut input_data = proof.to_vec();
+        for fr in native.iter().take(5) {
+            fr.into_repr()
+                .write_le(&mut input_data)
+                .map_err(to_js_error)?;
+        }
+        self.verify(&input_data[..], &[])
+    }
+
+    #[wasm_bindgen]
+    pub fn verify(&self, proof: &[u8], raw_public_inputs: &[u8]) -> Result<bool, JsValue> {
+        let input_data = [proof, raw_public_inputs].concat();
+        let success = match self.api.verify_proof(input_data.as_slice()) {
+            Ok(success) => success,
+            Err(e) => return Err(to_js_error(e)),
+        };
+        Ok(success)
+    }
+
+    /// same as `verify` and also checks that share_x is the hash of the signal
+    #[wasm_bindgen]
+    pub fn verify_with_signal(
+        &self,
+        proof: &[u8],
+        raw_public_inputs: &[u8],
+        signal: &[u8],
+    ) -> Result<bool, JsValue> {
+        let input_data = proof_with_signal(proof, raw_public_inputs, signal);
+        match self.api.verify(input_data.as_slice()) {
+            Ok(success) => Ok(success),
+            Err(e) => Err(to_js_error(e)),
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn recover_secret(&self, share1: &[u8], share2: &[u8]) -> Result<Vec<u8>, JsValue> {
+        let mut secret: Vec<u8> = Vec::new();
+        match self.api.recover_secret(share1, share2, &mut secret) {
+            Ok(_) => (),
+            Err(e) => return Err(to_js_error(e)),
+        };
+        Ok(secret)
+    }
+
+    /// keeps a nullifier log in this instance so `log_and_detect` can be used
+    #[wasm_bindgen]
+    pub fn enable_nullifier_log(&mut self) {
+        if self.nullifier_log.is_none() {
+            self.nullifier_log = Some(NullifierLog::new());
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn log_and_detect(&mut self, proof_bundle: &[u8]) -> Result<Vec<u8>, JsValue> {
+        let public_inputs = match self.api.read_proof_public_inputs(proof_bundle) {
+            Ok(public_inputs) => public_inputs,
+            Err(e) => return Err(to_js_error(e)),
+        };
+        let nullifier_log = match self.nullifier_log.as_mut() {
+            Some(nullifier_log) => nullifier_log,
+            None => return Err("nullifier log is not enabled".into()),
+        };
+        let status = nullifier_log.insert(
+            public_inputs[1],
+            public_inputs[4],
+            public_inputs[2],
+            public_inputs[3],
+        );
+        match status {
+            Ok(status) => Ok(encode_log_status(status)),
+            Err(e) => Err(to_js_error(e)),
+        }
+    }
+}
+
+// leaf indices are exchanged as js numbers, so have to be safe integers
+fn check_merkle_depth(merkle_depth: usize) -> Result<(), JsValue> {
+    if merkle_depth == 0 || merkle_depth > 53 {
+        return Err(format!("unsupported merkle depth {}", merkle_depth).into());
+    }
+    Ok(())
+}
+
+// 2^53 - 1
+const MAX_SAFE_INTEGER: f64 = 9007199254740991.0;
+
+fn index_from_js(index: f64) -> Result<u64, JsValue> {
+    if !(0.0..=MAX_SAFE_INTEGER).contains(&index) || index.fract() != 0.0 {
+        return Err(format!("invalid leaf index {}", index).into());
+    }
+    Ok(index as u64)
+}
+
+/// leaf of a member with the 32 byte `secret`, see `public::commitment`
+#[wasm_bindgen]
+pub fn commitment(secret: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let secret = read_fr::<_, Bn256>(secret, 1).map_err(to_js_error)?[0];
+    let mut output: Vec<u8> = Vec::new();
+    crate::public::commitment::<Bn256>(secret)
+        .into_repr()
+        .write_le(&mut output)
+        .map_err(to_js_error)?;
+    Ok(output)
+}
+
+/// same as `commitment` with hex of the secret and of the leaf
+#[wasm_bindgen]
+pub fn commitment_hex(secret: &str) -> Result<String, JsValue> {
+    let secret = fr_from_hex::<Fr>(secret).map_err(to_js_error)?;
+    Ok(fr_to_hex(&crate::public::commitment::<Bn256>(secret)))
+}
+
+// little endian bytes of a field element as the big endian hex js tooling expects
+fn hex_from_le(bytes: &[u8]) -> Result<String, JsValue> {
+    let fr = read_fr::<_, Bn256>(bytes, 1).map_err(to_js_error)?[0];
+    Ok(fr_to_hex(&fr))
+}
+
+// truncated inputs get a clearer message than the io one
+fn to_js_error(e: Error) -> JsValue {
+    match e.kind() {
+        ErrorKind::UnexpectedEof => format!("input is too short: {}", e).into(),
+        _ => e.to_string().into(),
+    }
+}
+
+// [ proof | public_inputs | signal_len<8> | signal<var> ] as read by `RLNVerifier::verify`
+#[cfg(feature = "wasm-verifier")]
+fn proof_with_signal(proof: &[u8], raw_public_inputs: &[u8], signal: &[u8]) -> Vec<u8> {
+    let mut input_data = [proof, raw_public_inputs].concat();
+    input_data.extend_from_slice(&(signal.len() as u64).to_le_bytes());
+    input_data.extend_from_slice(signal);
+    input_data
+}
+
+// [ status<1> | secret<32> ] where status is 0 new, 1 duplicate and 2 spam,
+// the recovered secret follows only for spam
+#[cfg(feature = "wasm-verifier")]
+fn encode_log_status(status: LogStatus<Bn256>) -> Vec<u8> {
+    let mut result = vec![status.code() as u8];
+    if let LogStatus::Breach(secret) = status {
+        secret.into_repr().write_le(&mut result).unwrap();
+    }
+    result
+}
+
+#[cfg(all(test, feature = "wasm-prover"))]
 mod test {
 
     use crate::circuit::bench;
@@ -98,35 +694,252 @@ mod test {
     use crate::circuit::rln::{RLNCircuit, RLNInputs};
     use crate::merkle::MerkleTree;
     use crate::poseidon::{Poseidon as PoseidonHasher, PoseidonParams};
+    use crate::utils::{fr_to_hex, read_fr};
     use bellman::groth16::{generate_random_parameters, Parameters, Proof};
     use bellman::pairing::bn256::{Bn256, Fr};
     use bellman::pairing::ff::{Field, PrimeField, PrimeFieldRepr};
+    use byteorder::{LittleEndian, WriteBytesExt};
     use rand::{Rand, SeedableRng, XorShiftRng};
+    use std::io::Write;
 
     #[wasm_bindgen_test]
     fn test_rln_wasm() {
         let merkle_depth = 3usize;
-        let poseidon_params = PoseidonParams::<Bn256>::new(8, 55, 3, None, None, None);
-        let rln_test = bench::RLNTest::<Bn256>::new(merkle_depth, Some(poseidon_params));
+        let mut rln_wasm = super::RLNWasm::new(merkle_depth).unwrap();
+        let (input_data, _) = gen_valid_inputs(&mut rln_wasm);
+
+        let proof = rln_wasm.generate_proof(input_data.as_slice()).unwrap();
+        let proof_len = proof.len() - 5 * 32;
+
+        assert_eq!(
+            rln_wasm
+                .verify(&proof[..proof_len], &proof[proof_len..])
+                .unwrap(),
+            true
+        );
+    }
 
-        let rln_wasm = super::RLNWasm::new(merkle_depth);
+    #[wasm_bindgen_test]
+    fn test_rln_wasm_compressed() {
+        let merkle_depth = 3usize;
+        let mut rln_wasm = super::RLNWasm::new(merkle_depth).unwrap();
+        rln_wasm.set_compressed_proofs(true);
+        let (input_data, _) = gen_valid_inputs(&mut rln_wasm);
 
-        let mut raw_inputs: Vec<u8> = Vec::new();
-        let inputs = rln_test.valid_inputs();
-        inputs.write(&mut raw_inputs);
+        let proof = rln_wasm.generate_proof(input_data.as_slice()).unwrap();
+        assert_eq!(proof.len(), 128 + 5 * 32);
 
-        // let now = Instant::now();
-        let proof = rln_wasm.generate_proof(raw_inputs.as_slice()).unwrap();
-        // let prover_time = now.elapsed().as_millis() as f64 / 1000.0;
+        assert_eq!(rln_wasm.verify(&proof[..128], &proof[128..]).unwrap(), true);
+    }
 
-        let mut raw_public_inputs: Vec<u8> = Vec::new();
-        inputs.write_public_inputs(&mut raw_public_inputs);
+    #[wasm_bindgen_test]
+    fn test_rln_wasm_verifier() {
+        let merkle_depth = 3usize;
+        let mut rln_wasm = super::RLNWasm::new(merkle_depth).unwrap();
+        let (input_data, _) = gen_valid_inputs(&mut rln_wasm);
+        let proof = rln_wasm.generate_proof(input_data.as_slice()).unwrap();
+        let proof_len = proof.len() - 5 * 32;
 
+        let raw_verifying_key = rln_wasm.export_verifier_key().unwrap();
+        let verifier = super::RLNWasmVerifier::new(raw_verifying_key.as_slice()).unwrap();
         assert_eq!(
-            rln_wasm
-                .verify(proof.as_slice(), raw_public_inputs.as_slice())
+            verifier
+                .verify(&proof[..proof_len], &proof[proof_len..])
                 .unwrap(),
             true
         );
     }
+
+    #[wasm_bindgen_test]
+    async fn test_rln_wasm_generate_proof_async() {
+        use js_sys::{Array, Function, Uint8Array};
+        use wasm_bindgen_futures::JsFuture;
+        let merkle_depth = 3usize;
+        let mut rln_wasm = super::RLNWasm::new(merkle_depth).unwrap();
+        let (input_data, _) = gen_valid_inputs(&mut rln_wasm);
+
+        let stages = Array::new();
+        let progress = Function::new_with_args("phase, percent", "this.push([phase, percent])");
+        let progress = progress.bind(&stages);
+        let pending = rln_wasm.generate_proof_async(&input_data, Some(progress.clone()));
+        // proving starts once the caller yields
+        assert_eq!(stages.length(), 0);
+        let proof = JsFuture::from(pending).await.unwrap();
+        let proof = Uint8Array::new(&proof).to_vec();
+        let proof_len = proof.len() - 5 * 32;
+        assert!(rln_wasm
+            .verify(&proof[..proof_len], &proof[proof_len..])
+            .unwrap());
+        let stage = |i: u32| {
+            let stage = Array::from(&stages.get(i));
+            (
+                stage.get(0).as_f64().unwrap(),
+                stage.get(1).as_f64().unwrap(),
+            )
+        };
+        let last = stages.length() - 1;
+        assert_eq!(stage(0), (0.0, 0.0));
+        assert_eq!(stage(last - 2), (0.0, 100.0));
+        assert_eq!(stage(last - 1), (1.0, 0.0));
+        assert_eq!(stage(last), (1.0, 100.0));
+
+        let truncated = rln_wasm.generate_proof_async(&input_data[..8], None);
+        assert!(JsFuture::from(truncated).await.is_err());
+        let throwing = Function::new_with_args("phase, percent", "throw 'cancelled'");
+        let cancelled = rln_wasm.generate_proof_async(&input_data, Some(throwing));
+        assert!(JsFuture::from(cancelled).await.is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_rln_wasm_persist_tree() {
+        use js_sys::{Function, Map, Uint8Array};
+        use wasm_bindgen_futures::JsFuture;
+        let rln_wasm = super::RLNWasm::new(3).unwrap();
+        rln_wasm.track_tree_changes();
+        let keypair = rln_wasm.key_gen().unwrap();
+        rln_wasm.update_next_member(&keypair[32..]).unwrap();
+
+        // a rejected write leaves the changes for the next call
+        let reject =
+            Function::new_with_args("key, value", "return Promise.reject('store is closed')");
+        let failed = rln_wasm.persist_tree(&reject).unwrap();
+        assert!(JsFuture::from(failed).await.is_err());
+
+        let store = Map::new();
+        let put = Function::new_with_args(
+            "key, value",
+            "this.set(key, value); return Promise.resolve()",
+        );
+        let put = put.bind(&store);
+        JsFuture::from(rln_wasm.persist_tree(&put).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(store.size(), 2);
+        assert_eq!(
+            Uint8Array::new(&store.get(&0f64.into())).to_vec(),
+            keypair[32..].to_vec()
+        );
+        assert_eq!(store.get(&"current_index".into()).as_f64(), Some(1f64));
+
+        store.clear();
+        JsFuture::from(rln_wasm.persist_tree(&put).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(store.size(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_rln_wasm_params_handle() {
+        let merkle_depth = 3usize;
+        let rln_wasm = super::RLNWasm::new(merkle_depth).unwrap();
+        let raw_params = rln_wasm.export_circuit_parameters().unwrap();
+        let handle = super::RLNParamsHandle::new(raw_params.as_slice()).unwrap();
+
+        let mut group_1 = super::RLNWasm::new_with_params_handle(merkle_depth, &handle).unwrap();
+        let group_2 = super::RLNWasm::new_with_params_handle(merkle_depth, &handle).unwrap();
+        assert_eq!(
+            group_1.export_verifier_key().unwrap(),
+            group_2.export_verifier_key().unwrap()
+        );
+        // members of one group do not show up in the other
+        let (input_data, _) = gen_valid_inputs(&mut group_1);
+        assert!(group_1.generate_proof(input_data.as_slice()).is_ok());
+        assert!(group_2.generate_proof(input_data.as_slice()).is_err());
+
+        assert!(super::RLNWasm::new_with_params_handle(merkle_depth + 1, &handle).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_rln_wasm_verify_with_signal() {
+        let merkle_depth = 3usize;
+        let mut rln_wasm = super::RLNWasm::new(merkle_depth).unwrap();
+        let (input_data, signal) = gen_valid_inputs(&mut rln_wasm);
+        let proof = rln_wasm.generate_proof(input_data.as_slice()).unwrap();
+        let proof_len = proof.len() - 5 * 32;
+        let (proof, public_inputs) = proof.split_at(proof_len);
+
+        assert!(rln_wasm
+            .verify_with_signal(proof, public_inputs, &signal)
+            .unwrap());
+        let raw_verifying_key = rln_wasm.export_verifier_key().unwrap();
+        let verifier = super::RLNWasmVerifier::new(raw_verifying_key.as_slice()).unwrap();
+        assert!(verifier
+            .verify_with_signal(proof, public_inputs, &signal)
+            .unwrap());
+        // a valid proof does not vouch for another signal
+        assert!(verifier
+            .verify_with_signal(proof, public_inputs, b"forged signal")
+            .is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_rln_wasm_recover_secret() {
+        let merkle_depth = 3usize;
+        let mut rln_wasm = super::RLNWasm::new(merkle_depth).unwrap();
+        let (input_data, signal) = gen_valid_inputs(&mut rln_wasm);
+        let proof_1 = rln_wasm.generate_proof(input_data.as_slice()).unwrap();
+
+        // same key and epoch, different signal
+        let mut input_data = input_data[..input_data.len() - signal.len() - 8].to_vec();
+        let signal = b"another signal";
+        input_data
+            .write_u64::<LittleEndian>(signal.len() as u64)
+            .unwrap();
+        input_data.write_all(&signal[..]).unwrap();
+        let proof_2 = rln_wasm.generate_proof(input_data.as_slice()).unwrap();
+
+        let secret = rln_wasm.recover_secret(&proof_1, &proof_2).unwrap();
+        assert_eq!(secret[..], input_data[..32]);
+
+        assert_eq!(rln_wasm.log_and_detect(&proof_1).unwrap(), vec![0u8]);
+        assert_eq!(rln_wasm.log_and_detect(&proof_1).unwrap(), vec![1u8]);
+        let result = rln_wasm.log_and_detect(&proof_2).unwrap();
+        assert_eq!(result[0], 2u8);
+        assert_eq!(result[1..], secret[..]);
+
+        let raw_verifying_key = rln_wasm.export_verifier_key().unwrap();
+        let mut verifier = super::RLNWasmVerifier::new(raw_verifying_key.as_slice()).unwrap();
+        assert!(verifier.log_and_detect(&proof_1).is_err());
+        verifier.enable_nullifier_log();
+        assert_eq!(verifier.log_and_detect(&proof_1).unwrap(), vec![0u8]);
+        assert_eq!(verifier.log_and_detect(&proof_2).unwrap()[1..], secret[..]);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_rln_wasm_hex() {
+        let rln_wasm = super::RLNWasm::new(3).unwrap();
+        let keys = rln_wasm.key_gen_hex().unwrap();
+        assert_eq!(keys.length(), 2);
+        let secret = keys.get(0).as_string().unwrap();
+        let public_key = keys.get(1).as_string().unwrap();
+        assert_eq!(super::commitment_hex(&secret).unwrap(), public_key);
+
+        assert_eq!(rln_wasm.update_next_member_hex(&public_key).unwrap(), 0.0);
+        let root = rln_wasm.get_root().unwrap();
+        let root_fr = read_fr::<_, Bn256>(root.as_slice(), 1).unwrap()[0];
+        assert_eq!(rln_wasm.get_root_hex().unwrap(), fr_to_hex(&root_fr));
+        assert!(rln_wasm.update_next_member_hex("0xzz").is_err());
+    }
+
+    // inserts a fresh member at index 0 and returns proof inputs for it
+    // serialized as [ id_key<32> | id_index<8> | epoch<32> | signal_len<8> | signal<var> ]
+    fn gen_valid_inputs(rln_wasm: &mut super::RLNWasm) -> (Vec<u8>, Vec<u8>) {
+        let mut rng = XorShiftRng::from_seed([0x3dbe6258, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let hasher = rln_wasm.api.hasher();
+        let id_key = Fr::rand(&mut rng);
+        let public_key = hasher.hash(vec![id_key]);
+
+        let mut pubkey_data: Vec<u8> = Vec::new();
+        public_key.into_repr().write_le(&mut pubkey_data).unwrap();
+        rln_wasm
+            .api
+            .update_next_member(pubkey_data.as_slice())
+            .unwrap();
+
+        let epoch = Fr::rand(&mut rng);
+        let signal = b"rln signal test xyz abc".to_vec();
+
+        let input_data = crate::test_utils::proof_input::<Bn256>(id_key, 0, epoch, &signal);
+        (input_data, signal)
+    }
 }